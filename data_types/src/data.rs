@@ -1,7 +1,7 @@
 //! This module contains helper methods for constructing replicated writes
 //! based on `DatabaseRules`.
 
-use crate::database_rules::DatabaseRules;
+use crate::database_rules::{DatabaseRules, Result};
 use crate::TIME_COLUMN_NAME;
 use generated_types::wal as wb;
 use influxdb_line_protocol::{FieldValue, ParsedLine};
@@ -12,6 +12,8 @@ use chrono::Utc;
 use crc32fast::Hasher;
 use flatbuffers::FlatBufferBuilder;
 
+pub mod verify;
+
 pub fn type_description(value: wb::ColumnValue) -> &'static str {
     use wb::ColumnValue::*;
 
@@ -33,6 +35,13 @@ pub struct ReplicatedWrite {
 }
 
 impl ReplicatedWrite {
+    /// Checks that the raw bytes are a well formed replicated write whose
+    /// payload matches its checksum. Bytes from another server must be
+    /// verified before any of the other methods are called on them.
+    pub fn verify(&self) -> Result<(), verify::Error> {
+        verify::verify_replicated_write(&self.data)
+    }
+
     /// Returns the Flatbuffers struct represented by the raw bytes.
     pub fn to_fb(&self) -> wb::ReplicatedWrite<'_> {
         flatbuffers::get_root::<wb::ReplicatedWrite<'_>>(&self.data)
@@ -74,6 +83,130 @@ impl ReplicatedWrite {
 
         0
     }
+
+    /// Returns the distinct partition keys of the entries in this replicated
+    /// write, in sorted order
+    pub fn partition_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<_> = self
+            .write_buffer_batch()
+            .and_then(|batch| batch.entries())
+            .into_iter()
+            .flatten()
+            .map(|entry| entry.partition_key().unwrap_or(""))
+            .collect();
+
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    /// Returns a new replicated write with the same writer and sequence
+    /// number that contains only the entries whose partition key passes
+    /// `partition_filter`. Returns `None` if no entries pass the filter.
+    pub fn filter_partitions(&self, partition_filter: impl Fn(&str) -> bool) -> Option<Self> {
+        self.filter_entries(partition_filter, |_| true, |_| true)
+    }
+
+    /// Returns a new replicated write with the same writer and sequence
+    /// number that contains only the tables for which `table_filter` returns
+    /// true and, within those, only the rows for which `row_filter` returns
+    /// true. Returns `None` if nothing in this write passes the filters.
+    pub fn filter(
+        &self,
+        table_filter: impl Fn(&str) -> bool,
+        row_filter: impl Fn(&wb::Row<'_>) -> bool,
+    ) -> Option<Self> {
+        self.filter_entries(|_| true, table_filter, row_filter)
+    }
+
+    fn filter_entries(
+        &self,
+        partition_filter: impl Fn(&str) -> bool,
+        table_filter: impl Fn(&str) -> bool,
+        row_filter: impl Fn(&wb::Row<'_>) -> bool,
+    ) -> Option<Self> {
+        let entries = self.write_buffer_batch()?.entries()?;
+
+        let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+        let mut filtered_entries = Vec::new();
+
+        for entry in entries {
+            if !partition_filter(entry.partition_key().unwrap_or("")) {
+                continue;
+            }
+
+            let mut table_batches = Vec::new();
+
+            for table in entry.table_batches().into_iter().flatten() {
+                let name = table.name().unwrap_or("");
+                if !table_filter(name) {
+                    continue;
+                }
+
+                let mut rows = Vec::new();
+                for row in table.rows().into_iter().flatten() {
+                    if row_filter(&row) {
+                        rows.push(copy_row(&mut fbb, &row));
+                    }
+                }
+
+                if !rows.is_empty() {
+                    let table_name = fbb.create_string(name);
+                    let rows = fbb.create_vector(&rows);
+                    table_batches.push(wb::TableWriteBatch::create(
+                        &mut fbb,
+                        &wb::TableWriteBatchArgs {
+                            name: Some(table_name),
+                            rows: Some(rows),
+                        },
+                    ));
+                }
+            }
+
+            let delete = entry
+                .delete()
+                .filter(|d| table_filter(d.table_name().unwrap_or("")))
+                .map(|d| copy_delete(&mut fbb, &d));
+
+            if table_batches.is_empty() && delete.is_none() {
+                continue;
+            }
+
+            let partition_key = entry.partition_key().map(|k| fbb.create_string(k));
+            let table_batches = fbb.create_vector(&table_batches);
+            filtered_entries.push(wb::WriteBufferEntry::create(
+                &mut fbb,
+                &wb::WriteBufferEntryArgs {
+                    partition_key,
+                    table_batches: Some(table_batches),
+                    delete,
+                },
+            ));
+        }
+
+        if filtered_entries.is_empty() {
+            return None;
+        }
+
+        let entries = fbb.create_vector(&filtered_entries);
+        let batch = wb::WriteBufferBatch::create(
+            &mut fbb,
+            &wb::WriteBufferBatchArgs {
+                entries: Some(entries),
+            },
+        );
+        fbb.finish(batch, None);
+
+        let (mut data, idx) = fbb.collapse();
+        let entry_bytes = data.split_off(idx);
+
+        let (writer, sequence) = self.writer_and_sequence();
+        Some(entry_bytes_to_replicated_write(
+            writer,
+            sequence,
+            &entry_bytes,
+        ))
+    }
 }
 
 impl From<&[u8]> for ReplicatedWrite {
@@ -165,19 +298,48 @@ pub fn lines_to_replicated_write(
     sequence: u64,
     lines: &[ParsedLine<'_>],
     rules: &DatabaseRules,
-) -> ReplicatedWrite {
+) -> Result<ReplicatedWrite> {
+    let entry_bytes = lines_to_entry_bytes(lines, rules)?;
+
+    Ok(entry_bytes_to_replicated_write(
+        writer,
+        sequence,
+        &entry_bytes,
+    ))
+}
+
+/// Splits `lines` into partitions using the partition template of `rules`
+/// and returns the Flatbuffers encoded `WriteBufferBatch` of them, which
+/// `entry_bytes_to_replicated_write` turns into a `ReplicatedWrite`.
+pub fn lines_to_entry_bytes(lines: &[ParsedLine<'_>], rules: &DatabaseRules) -> Result<Vec<u8>> {
     let default_time = Utc::now();
-    let entry_bytes = split_lines_into_write_entry_partitions(
-        |line| rules.partition_key(line, &default_time).unwrap(),
-        lines,
-    );
 
+    let mut partition_writes = BTreeMap::new();
+    for line in lines {
+        let key = rules.partition_key(line, &default_time)?;
+
+        partition_writes
+            .entry(key)
+            .or_insert_with(Vec::new)
+            .push(line);
+    }
+
+    Ok(partition_writes_to_entry_bytes(partition_writes))
+}
+
+/// Wraps the Flatbuffers encoded `WriteBufferBatch` in `entry_bytes` in a
+/// `ReplicatedWrite` from `writer` with the given sequence number.
+pub fn entry_bytes_to_replicated_write(
+    writer: u32,
+    sequence: u64,
+    entry_bytes: &[u8],
+) -> ReplicatedWrite {
     let mut hasher = Hasher::new();
-    hasher.update(&entry_bytes);
+    hasher.update(entry_bytes);
     let checksum = hasher.finalize();
 
     let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
-    let payload = fbb.create_vector_direct(&entry_bytes);
+    let payload = fbb.create_vector_direct(entry_bytes);
 
     let write = wb::ReplicatedWrite::create(
         &mut fbb,
@@ -201,8 +363,6 @@ pub fn split_lines_into_write_entry_partitions(
     partition_key_fn: impl Fn(&ParsedLine<'_>) -> String,
     lines: &[ParsedLine<'_>],
 ) -> Vec<u8> {
-    let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
-
     // split the lines into collections that go into partitions
     let mut partition_writes = BTreeMap::new();
 
@@ -215,6 +375,14 @@ pub fn split_lines_into_write_entry_partitions(
             .push(line);
     }
 
+    partition_writes_to_entry_bytes(partition_writes)
+}
+
+fn partition_writes_to_entry_bytes(
+    partition_writes: BTreeMap<String, Vec<&ParsedLine<'_>>>,
+) -> Vec<u8> {
+    let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+
     // create a WALEntry for each batch of lines going to a partition (one WALEntry
     // per partition)
     let entries = partition_writes
@@ -403,6 +571,16 @@ fn add_bool_value<'a>(
     add_value(fbb, column, wb::ColumnValue::BoolValue, bv.as_union_value())
 }
 
+fn add_u64_value<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    column: &str,
+    value: u64,
+) -> flatbuffers::WIPOffset<wb::Value<'a>> {
+    let uv = wb::U64Value::create(fbb, &wb::U64ValueArgs { value });
+
+    add_value(fbb, column, wb::ColumnValue::U64Value, uv.as_union_value())
+}
+
 fn add_value<'a>(
     fbb: &mut FlatBufferBuilder<'a>,
     column: &str,
@@ -420,3 +598,65 @@ fn add_value<'a>(
         },
     )
 }
+
+fn copy_row<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    row: &wb::Row<'_>,
+) -> flatbuffers::WIPOffset<wb::Row<'a>> {
+    let row_values = row
+        .values()
+        .into_iter()
+        .flatten()
+        .filter_map(|value| copy_value(fbb, &value))
+        .collect::<Vec<_>>();
+
+    let row_values = fbb.create_vector(&row_values);
+
+    wb::Row::create(
+        fbb,
+        &wb::RowArgs {
+            values: Some(row_values),
+        },
+    )
+}
+
+fn copy_value<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    value: &wb::Value<'_>,
+) -> Option<flatbuffers::WIPOffset<wb::Value<'a>>> {
+    let column = value.column().unwrap_or("");
+
+    let copied = match value.value_type() {
+        wb::ColumnValue::TagValue => {
+            add_tag_value(fbb, column, value.value_as_tag_value()?.value()?)
+        }
+        wb::ColumnValue::I64Value => add_i64_value(fbb, column, value.value_as_i64value()?.value()),
+        wb::ColumnValue::U64Value => add_u64_value(fbb, column, value.value_as_u64value()?.value()),
+        wb::ColumnValue::F64Value => add_f64_value(fbb, column, value.value_as_f64value()?.value()),
+        wb::ColumnValue::BoolValue => {
+            add_bool_value(fbb, column, value.value_as_bool_value()?.value())
+        }
+        wb::ColumnValue::StringValue => {
+            add_string_value(fbb, column, value.value_as_string_value()?.value()?)
+        }
+        wb::ColumnValue::NONE => return None,
+    };
+
+    Some(copied)
+}
+
+fn copy_delete<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    delete: &wb::WriteBufferDelete<'_>,
+) -> flatbuffers::WIPOffset<wb::WriteBufferDelete<'a>> {
+    let table_name = delete.table_name().map(|t| fbb.create_string(t));
+    let predicate = delete.predicate().map(|p| fbb.create_string(p));
+
+    wb::WriteBufferDelete::create(
+        fbb,
+        &wb::WriteBufferDeleteArgs {
+            table_name,
+            predicate,
+        },
+    )
+}
@@ -0,0 +1,354 @@
+//! This module checks that bytes received from another server are a well
+//! formed Flatbuffers `ReplicatedWrite`. The generated accessors trust the
+//! data they read, so they panic, or read the wrong data, if it isn't.
+//!
+//! The version of `flatbuffers` in use has no verifier, so this walks the
+//! `ReplicatedWrite`, `WriteBufferBatch` and everything in them as described
+//! in `wal.fbs`, checking that every table, vector and string is in bounds,
+//! that strings are UTF-8 and that bools and union types hold valid values.
+use crc32fast::Hasher;
+use generated_types::wal as wb;
+use snafu::{ensure, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid replicated write: {} at offset {}", message, offset))]
+    InvalidFlatbuffer {
+        message: &'static str,
+        offset: usize,
+    },
+
+    #[snafu(display(
+        "Replicated write checksum {} doesn't match the checksum of its payload {}",
+        checksum,
+        computed
+    ))]
+    ChecksumMismatch { checksum: u32, computed: u32 },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+const TAG_VALUE: u8 = wb::ColumnValue::TagValue as u8;
+const I64_VALUE: u8 = wb::ColumnValue::I64Value as u8;
+const U64_VALUE: u8 = wb::ColumnValue::U64Value as u8;
+const F64_VALUE: u8 = wb::ColumnValue::F64Value as u8;
+const BOOL_VALUE: u8 = wb::ColumnValue::BoolValue as u8;
+const STRING_VALUE: u8 = wb::ColumnValue::StringValue as u8;
+
+/// Checks that `data` is a well formed `ReplicatedWrite`, whose payload is a
+/// well formed `WriteBufferBatch` that matches its checksum.
+pub fn verify_replicated_write(data: &[u8]) -> Result<()> {
+    let mut verifier = Verifier::new(data);
+    let write = verifier.root()?;
+
+    // writer, sequence and checksum
+    verifier.field(&write, 0, 4)?;
+    verifier.field(&write, 1, 8)?;
+    let checksum = match verifier.field(&write, 2, 4)? {
+        Some(pos) => verifier.read_u32(pos)?,
+        None => 0,
+    };
+
+    let payload = match verifier.field(&write, 3, 4)? {
+        Some(pos) => {
+            let (start, len) = verifier.vector(verifier.follow(pos)?, 1)?;
+            Some(&data[start..start + len])
+        }
+        None => None,
+    };
+
+    let mut hasher = Hasher::new();
+    hasher.update(payload.unwrap_or_default());
+    let computed = hasher.finalize();
+    ensure!(
+        checksum == computed,
+        ChecksumMismatch { checksum, computed }
+    );
+
+    if let Some(payload) = payload {
+        let mut verifier = Verifier::new(payload);
+        let batch = verifier.root()?;
+        verifier.table_vector(&batch, 0, Verifier::write_buffer_entry)?;
+    }
+
+    Ok(())
+}
+
+/// The position of a table in the buffer and of its vtable
+#[derive(Debug)]
+struct Table {
+    pos: usize,
+    vtable: usize,
+    vtable_len: usize,
+    table_len: usize,
+}
+
+#[derive(Debug)]
+struct Verifier<'a> {
+    buf: &'a [u8],
+    /// The number of tables checked so far. Offsets can point many times at
+    /// the same table, so this bounds the work done for a small buffer.
+    tables: usize,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, tables: 0 }
+    }
+
+    fn root(&mut self) -> Result<Table> {
+        let pos = self.follow(0)?;
+        self.table(pos)
+    }
+
+    fn write_buffer_entry(&mut self, entry: Table) -> Result<()> {
+        // the mutable buffer expects every entry to have a partition key
+        if !self.string_field(&entry, 0)? {
+            return self.fail("missing partition key", entry.pos);
+        }
+        self.table_vector(&entry, 1, Self::table_write_batch)?;
+        if let Some(delete) = self.table_field(&entry, 2)? {
+            self.string_field(&delete, 0)?;
+            self.string_field(&delete, 1)?;
+        }
+        Ok(())
+    }
+
+    fn table_write_batch(&mut self, batch: Table) -> Result<()> {
+        self.string_field(&batch, 0)?;
+        self.table_vector(&batch, 1, Self::row)
+    }
+
+    fn row(&mut self, row: Table) -> Result<()> {
+        self.table_vector(&row, 0, Self::value)
+    }
+
+    fn value(&mut self, value: Table) -> Result<()> {
+        self.string_field(&value, 0)?;
+
+        let value_type = match self.field(&value, 1, 1)? {
+            Some(pos) => self.buf[pos],
+            None => 0,
+        };
+        if value_type == 0 {
+            return Ok(());
+        }
+
+        let union_value = match self.table_field(&value, 2)? {
+            Some(union_value) => union_value,
+            None => return self.fail("missing union value", value.pos),
+        };
+        match value_type {
+            TAG_VALUE | STRING_VALUE => self.string_field(&union_value, 0).map(|_| ()),
+            I64_VALUE | U64_VALUE | F64_VALUE => self.field(&union_value, 0, 8).map(|_| ()),
+            BOOL_VALUE => match self.field(&union_value, 0, 1)? {
+                Some(pos) if self.buf[pos] > 1 => self.fail("invalid bool", pos),
+                _ => Ok(()),
+            },
+            _ => self.fail("unknown union type", value.pos),
+        }
+    }
+
+    /// Checks the table at `pos` and its vtable
+    fn table(&mut self, pos: usize) -> Result<Table> {
+        self.tables += 1;
+        if self.tables > self.buf.len() {
+            return self.fail("too many tables", pos);
+        }
+
+        let soffset = i64::from(self.read_u32(pos)? as i32);
+        let vtable = pos as i64 - soffset;
+        if vtable < 0 {
+            return self.fail("vtable out of bounds", pos);
+        }
+        let vtable = vtable as usize;
+
+        let vtable_len = usize::from(self.read_u16(vtable)?);
+        let table_len = usize::from(self.read_u16(vtable + 2)?);
+        if vtable_len < 4 || vtable_len % 2 != 0 {
+            return self.fail("invalid vtable", vtable);
+        }
+        self.check_range(vtable, vtable_len)?;
+        if table_len < 4 {
+            return self.fail("invalid table size", pos);
+        }
+        self.check_range(pos, table_len)?;
+
+        Ok(Table {
+            pos,
+            vtable,
+            vtable_len,
+            table_len,
+        })
+    }
+
+    /// Returns the position of field `slot` of `table`, checking its `size`
+    /// bytes are within the table, or `None` if the field isn't set
+    fn field(&self, table: &Table, slot: usize, size: usize) -> Result<Option<usize>> {
+        let entry = 4 + 2 * slot;
+        if entry + 2 > table.vtable_len {
+            return Ok(None);
+        }
+
+        let offset = usize::from(self.read_u16(table.vtable + entry)?);
+        if offset == 0 {
+            return Ok(None);
+        }
+        if offset + size > table.table_len {
+            return self.fail("field out of its table", table.pos);
+        }
+
+        Ok(Some(table.pos + offset))
+    }
+
+    /// Checks the table that field `slot` of `table` points at, if it is set
+    fn table_field(&mut self, table: &Table, slot: usize) -> Result<Option<Table>> {
+        match self.field(table, slot, 4)? {
+            Some(pos) => {
+                let pos = self.follow(pos)?;
+                self.table(pos).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Checks the string that field `slot` of `table` points at, if it is
+    /// set, returning whether it is set
+    fn string_field(&self, table: &Table, slot: usize) -> Result<bool> {
+        if let Some(pos) = self.field(table, slot, 4)? {
+            let (start, len) = self.vector(self.follow(pos)?, 1)?;
+            // strings are followed by a null terminator
+            self.check_range(start, len + 1)?;
+            if self.buf[start + len] != 0 {
+                return self.fail("string not null terminated", start + len);
+            }
+            if std::str::from_utf8(&self.buf[start..start + len]).is_err() {
+                return self.fail("string not UTF-8", start);
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Checks the vector of tables that field `slot` of `table` points at,
+    /// if it is set, checking each table with `verify_table`
+    fn table_vector(
+        &mut self,
+        table: &Table,
+        slot: usize,
+        verify_table: fn(&mut Self, Table) -> Result<()>,
+    ) -> Result<()> {
+        if let Some(pos) = self.field(table, slot, 4)? {
+            let (start, len) = self.vector(self.follow(pos)?, 4)?;
+            for element in (start..start + 4 * len).step_by(4) {
+                let pos = self.follow(element)?;
+                let table = self.table(pos)?;
+                verify_table(self, table)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the vector at `pos`, returning the position of its first
+    /// element and its length
+    fn vector(&self, pos: usize, element_size: usize) -> Result<(usize, usize)> {
+        let len = self.read_u32(pos)? as usize;
+        match len.checked_mul(element_size) {
+            Some(size) => self.check_range(pos + 4, size)?,
+            None => return self.fail("vector too long", pos),
+        }
+        Ok((pos + 4, len))
+    }
+
+    /// Returns the position the offset at `pos` points to
+    fn follow(&self, pos: usize) -> Result<usize> {
+        let target = pos + self.read_u32(pos)? as usize;
+        if target >= self.buf.len() {
+            return self.fail("offset out of bounds", pos);
+        }
+        Ok(target)
+    }
+
+    fn read_u16(&self, pos: usize) -> Result<u16> {
+        self.check_range(pos, 2)?;
+        Ok(u16::from_le_bytes([self.buf[pos], self.buf[pos + 1]]))
+    }
+
+    fn read_u32(&self, pos: usize) -> Result<u32> {
+        self.check_range(pos, 4)?;
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.buf[pos..pos + 4]);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn check_range(&self, pos: usize, len: usize) -> Result<()> {
+        match pos.checked_add(len) {
+            Some(end) if end <= self.buf.len() => Ok(()),
+            _ => self.fail("out of bounds", pos),
+        }
+    }
+
+    fn fail<T>(&self, message: &'static str, offset: usize) -> Result<T> {
+        InvalidFlatbuffer { message, offset }.fail()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{lines_to_replicated_write, ReplicatedWrite};
+    use crate::database_rules::DatabaseRules;
+    use influxdb_line_protocol::parse_lines;
+
+    fn write() -> ReplicatedWrite {
+        let lp = "cpu,host=a usage=1.5,count=2i,up=true,msg=\"ok\" 10\nmem free=3u 20";
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        lines_to_replicated_write(1, 1, &lines, &DatabaseRules::default()).unwrap()
+    }
+
+    #[test]
+    fn valid_write() {
+        verify_replicated_write(&write().data).unwrap();
+    }
+
+    #[test]
+    fn truncated_writes_are_invalid() {
+        let data = write().data;
+        for len in 0..data.len() {
+            assert!(
+                verify_replicated_write(&data[..len]).is_err(),
+                "truncated to {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn payload_checksum() {
+        let write = write();
+        let payload = write.to_fb().payload().unwrap();
+        let offset = payload.as_ptr() as usize - write.data.as_ptr() as usize;
+
+        let mut data = write.data.clone();
+        data[offset + payload.len() / 2] ^= 1;
+        let err = verify_replicated_write(&data).unwrap_err();
+        assert!(matches!(err, Error::ChecksumMismatch { .. }), "{}", err);
+    }
+
+    #[test]
+    fn corrupt_writes_are_safe_to_read_once_verified() {
+        let data = write().data;
+        for i in 0..data.len() {
+            for bit in 0..8 {
+                let mut corrupt = data.clone();
+                corrupt[i] ^= 1 << bit;
+
+                let corrupt = ReplicatedWrite { data: corrupt };
+                if verify_replicated_write(&corrupt.data).is_ok() {
+                    corrupt.to_string();
+                    corrupt.partition_keys();
+                }
+            }
+        }
+    }
+}
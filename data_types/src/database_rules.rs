@@ -1,8 +1,16 @@
-use influxdb_line_protocol::ParsedLine;
+use crate::{data::ReplicatedWrite, row_predicate::RowPredicate};
+use influxdb_line_protocol::{FieldValue, ParsedLine};
+
+use std::{
+    collections::BTreeMap,
+    num::{NonZeroU32, NonZeroUsize},
+    sync::{Arc, RwLock},
+};
 
 use chrono::{DateTime, TimeZone, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use snafu::Snafu;
+use snafu::{ResultExt, Snafu};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -11,6 +19,19 @@ pub enum Error {
         source_module: &'static str,
         source: Box<dyn std::error::Error + Send + Sync + 'static>,
     },
+
+    #[snafu(display("Invalid regex '{}': {}", regex, source))]
+    InvalidRegex { regex: String, source: regex::Error },
+
+    #[snafu(display(
+        "Column {} has non-timestamp value '{}' used in partition template",
+        column,
+        value
+    ))]
+    NonTimestampValue { column: String, value: String },
+
+    #[snafu(display("Invalid matcher predicate: {}", source))]
+    InvalidMatcherPredicate { source: crate::row_predicate::Error },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -47,15 +68,23 @@ pub struct DatabaseRules {
     /// The minimum number of host groups to replicate a write to before success
     /// is returned. This can be overridden on a per request basis.
     /// Replication will continue to write to the other host groups in the
-    /// background.
+    /// background. If fewer host groups take the write, it has still been
+    /// applied and is queued for the rest, which is reported as pending
+    /// replication rather than as a failure.
     #[serde(default)]
     pub replication_count: u8,
     /// How long the replication queue can get before either rejecting writes or
     /// dropping missed writes. The queue is kept in memory on a
-    /// per-database basis. A queue size of zero means it will only try to
+    /// per-database basis, and also holds the writes that couldn't be pushed
+    /// to subscriptions. A queue size of zero means it will only try to
     /// replicate synchronously and drop any failures.
     #[serde(default)]
     pub replication_queue_max_size: usize,
+    /// What should happen when a write needs to be queued for background
+    /// replication, but the replication queue has reached
+    /// `replication_queue_max_size`.
+    #[serde(default)]
+    pub replication_queue_overflow: ReplicationQueueOverflow,
     /// `subscriptions` are used for query servers to get data via either push
     /// or pull as it arrives. They are separate from replication as they
     /// have a different purpose. They're for query servers or other clients
@@ -100,18 +129,199 @@ pub struct DatabaseRules {
     /// configuration.
     #[serde(default)]
     pub wal_buffer_config: Option<WalBufferConfig>,
+
+    /// When set, every write is appended to a WAL on the local disk of
+    /// the server, and synced, before it is acknowledged, and the WAL is
+    /// replayed into the mutable buffer when the server restarts. Entries
+    /// are deleted once their data has been snapshotted to object storage
+    /// (see `LifecycleRules::persist`).
+    #[serde(default)]
+    pub local_wal_config: Option<LocalWalConfig>,
+
+    /// Controls how the rows of a chunk are organised when the chunk is
+    /// loaded into the read buffer.
+    #[serde(default)]
+    pub read_buffer_config: ReadBufferConfig,
+
+    /// Controls when chunks are closed, moved to the read buffer, persisted
+    /// to object storage and dropped from memory.
+    #[serde(default)]
+    pub lifecycle_rules: LifecycleRules,
+
+    /// The regexes used by the partition template, compiled on first use.
+    /// This is not part of the configuration and is never serialized.
+    #[serde(skip)]
+    pub regex_cache: RegexCache,
 }
 
 impl DatabaseRules {
+    /// Checks the parts of the rules that can't be checked when they are
+    /// deserialized, such as the regexes of the partition template and the
+    /// matchers of the subscriptions, so that invalid rules are rejected
+    /// when the database is created rather than by the first write.
+    pub fn validate(&self) -> Result<()> {
+        for part in &self.partition_template.parts {
+            if let TemplatePart::RegexCapture(capture) = part {
+                self.regex_cache.get(&capture.regex)?;
+            }
+        }
+
+        for subscription in &self.subscriptions {
+            subscription.matcher.compile(&self.regex_cache)?;
+        }
+
+        Ok(())
+    }
+
     pub fn partition_key(
         &self,
         line: &ParsedLine<'_>,
         default_time: &DateTime<Utc>,
     ) -> Result<String> {
-        self.partition_template.partition_key(line, default_time)
+        self.partition_template
+            .partition_key_with_cache(line, default_time, &self.regex_cache)
+    }
+}
+
+/// `RegexCache` holds compiled regexes keyed by their pattern so that
+/// partition template regexes are only compiled once per `DatabaseRules`
+/// rather than once per line. Clones share the same cache. As it is derived
+/// entirely from the rules, two caches always compare as equal.
+#[derive(Debug, Default, Clone)]
+pub struct RegexCache {
+    regexes: Arc<RwLock<BTreeMap<String, Arc<Regex>>>>,
+}
+
+impl RegexCache {
+    /// Returns the compiled regex for `pattern`, compiling and caching it if
+    /// this is the first time it has been requested.
+    pub fn get(&self, pattern: &str) -> Result<Arc<Regex>> {
+        if let Some(regex) = self.regexes.read().expect("mutex poisoned").get(pattern) {
+            return Ok(Arc::clone(regex));
+        }
+
+        let regex = Arc::new(Regex::new(pattern).context(InvalidRegex { regex: pattern })?);
+        self.regexes
+            .write()
+            .expect("mutex poisoned")
+            .insert(pattern.to_string(), Arc::clone(&regex));
+
+        Ok(regex)
     }
 }
 
+impl PartialEq for RegexCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for RegexCache {}
+
+/// `ReplicationQueueOverflow` defines what should happen if a write needs to
+/// be queued for replication in the background, but the replication queue is
+/// already at its max size.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Copy)]
+pub enum ReplicationQueueOverflow {
+    /// Reject the incoming write and return an error. The client may retry
+    /// the request, which will succeed once the queue has drained.
+    RejectWrite,
+    /// Drop the oldest writes in the queue to make room. The host groups they
+    /// were queued for will miss those writes.
+    DropOldest,
+}
+
+impl Default for ReplicationQueueOverflow {
+    fn default() -> Self {
+        Self::RejectWrite
+    }
+}
+
+/// The default maximum number of rows in a read buffer row group.
+pub const DEFAULT_MAX_ROW_GROUP_ROWS: usize = 100_000;
+
+/// `ReadBufferConfig` defines how the rows of a chunk are sorted and split
+/// into row groups when the chunk is loaded into the read buffer. The read
+/// buffer compresses runs of repeated values and prunes row groups by their
+/// column ranges, so both depend heavily on row order.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ReadBufferConfig {
+    /// The columns to sort each table's rows by, keyed by table name. Columns
+    /// that don't exist in the table are ignored. Tables without a sort key
+    /// are sorted by their tag columns, in ascending order of cardinality,
+    /// followed by the time column.
+    #[serde(default)]
+    pub sort_keys: BTreeMap<String, Vec<String>>,
+    /// The maximum number of rows in a single row group. Tables with more
+    /// rows than this are split into several row groups.
+    #[serde(default = "default_max_row_group_rows")]
+    pub max_row_group_rows: usize,
+}
+
+impl Default for ReadBufferConfig {
+    fn default() -> Self {
+        Self {
+            sort_keys: BTreeMap::new(),
+            max_row_group_rows: DEFAULT_MAX_ROW_GROUP_ROWS,
+        }
+    }
+}
+
+fn default_max_row_group_rows() -> usize {
+    DEFAULT_MAX_ROW_GROUP_ROWS
+}
+
+impl ReadBufferConfig {
+    /// Returns the sort key configured for `table_name`, if any.
+    pub fn sort_key(&self, table_name: &str) -> Option<&[String]> {
+        self.sort_keys.get(table_name).map(|key| key.as_slice())
+    }
+}
+
+/// `LifecycleRules` define how chunks move through the system without any
+/// manual intervention: writes land in an open mutable buffer chunk, which is
+/// closed, moved to the read buffer, persisted to object storage and finally
+/// dropped from memory. A rule that is not set never triggers, so the default
+/// rules leave every chunk in the mutable buffer.
+#[derive(Debug, Default, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(default)]
+pub struct LifecycleRules {
+    /// Close the open chunk of a partition once no writes have been made to
+    /// it for this many seconds.
+    pub mutable_linger_seconds: Option<NonZeroU32>,
+    /// Close the open chunk of a partition this many seconds after the first
+    /// write to it, even if it is still receiving writes.
+    pub mutable_max_age_seconds: Option<NonZeroU32>,
+    /// Close the open chunk of a partition once its estimated size in bytes
+    /// reaches this threshold.
+    pub mutable_size_threshold: Option<NonZeroUsize>,
+    /// Once the estimated size in bytes of the mutable buffer exceeds this
+    /// limit, closed chunks are moved to the read buffer, oldest first,
+    /// regardless of `read_buffer_delay_seconds`.
+    pub mutable_buffer_max_size: Option<NonZeroUsize>,
+    /// Move closed chunks to the read buffer once they have been closed for
+    /// this many seconds. If not set, chunks are only moved to the read
+    /// buffer when the mutable buffer exceeds `mutable_buffer_max_size`.
+    pub read_buffer_delay_seconds: Option<u32>,
+    /// If set to `true`, closed chunks are snapshotted to object storage, and
+    /// are only dropped from the mutable buffer once the snapshot is
+    /// complete.
+    pub persist: bool,
+    /// If set to `true`, chunks that have been persisted to object storage
+    /// are dropped from the read buffer as well.
+    pub drop_persisted_from_read_buffer: bool,
+    /// Once the estimated memory used by the database's mutable buffer and
+    /// read buffer exceeds this soft limit, in bytes, open chunks are closed
+    /// and moved to the read buffer, and persisted chunks are evicted from
+    /// the read buffer until the database is back under the limit.
+    pub buffer_size_soft: Option<NonZeroUsize>,
+    /// While the estimated memory used by the database's mutable buffer and
+    /// read buffer exceeds this hard limit, in bytes, writes to the database
+    /// are rejected with an error that can be retried once the lifecycle
+    /// rules have freed some memory.
+    pub buffer_size_hard: Option<NonZeroUsize>,
+}
+
 /// WalBufferConfig defines the configuration for buffering data from the WAL in
 /// memory. This buffer is used for asynchronous replication and to collect
 /// segments before sending them to object storage.
@@ -147,6 +357,53 @@ pub struct WalBufferConfig {
     pub close_segment_after: Option<std::time::Duration>,
 }
 
+/// LocalWalConfig defines the configuration of the WAL a database keeps on
+/// the local disk of the server.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+#[serde(default)]
+pub struct LocalWalConfig {
+    /// The size in bytes of a WAL file that causes the next writes to go to
+    /// a new file. Only whole files are deleted once their entries are
+    /// snapshotted, so smaller files free disk space sooner.
+    pub file_rollover_size: u64,
+    /// When appended writes are synced to disk. Writes are only
+    /// acknowledged once they have been synced.
+    pub sync_policy: LocalWalSyncPolicy,
+}
+
+impl Default for LocalWalConfig {
+    fn default() -> Self {
+        Self {
+            file_rollover_size: 10 * 1024 * 1024,
+            sync_policy: LocalWalSyncPolicy::default(),
+        }
+    }
+}
+
+/// LocalWalSyncPolicy defines when the writes appended to a local WAL are
+/// synced to disk. Writes that arrive while the WAL is busy are always
+/// synced together with a single fsync.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Copy)]
+pub enum LocalWalSyncPolicy {
+    /// Sync as soon as all the writes queued up have been appended.
+    EveryWrite,
+    /// Sync once the oldest unsynced write has waited for this long.
+    Interval(std::time::Duration),
+    /// Sync once `bytes` bytes have been appended since the last sync, or
+    /// once the oldest unsynced write has waited for `max_latency`,
+    /// whichever comes first.
+    Bytes {
+        bytes: u64,
+        max_latency: std::time::Duration,
+    },
+}
+
+impl Default for LocalWalSyncPolicy {
+    fn default() -> Self {
+        Self::EveryWrite
+    }
+}
+
 /// WalBufferRollover defines the behavior of what should happen if a write
 /// comes in that would cause the buffer to exceed its max size AND the oldest
 /// segment can't be dropped because it has not yet been persisted.
@@ -177,30 +434,45 @@ pub struct PartitionTemplate {
 }
 
 impl PartitionTemplate {
+    /// Computes the partition key for `line`. Any regexes in the template are
+    /// compiled for this call only; use `DatabaseRules::partition_key` to
+    /// reuse compiled regexes across lines.
     pub fn partition_key(
         &self,
         line: &ParsedLine<'_>,
         default_time: &DateTime<Utc>,
     ) -> Result<String> {
-        let parts: Vec<_> = self
+        self.partition_key_with_cache(line, default_time, &RegexCache::default())
+    }
+
+    /// Computes the partition key for `line`, looking up compiled regexes in
+    /// `regex_cache`.
+    pub fn partition_key_with_cache(
+        &self,
+        line: &ParsedLine<'_>,
+        default_time: &DateTime<Utc>,
+        regex_cache: &RegexCache,
+    ) -> Result<String> {
+        let parts = self
             .parts
             .iter()
             .map(|p| match p {
-                TemplatePart::Table => line.series.measurement.to_string(),
-                TemplatePart::Column(column) => match line.tag_value(&column) {
+                TemplatePart::Table => Ok(line.series.measurement.to_string()),
+                TemplatePart::Column(column) => Ok(match line.tag_value(&column) {
                     Some(v) => format!("{}_{}", column, v),
                     None => match line.field_value(&column) {
                         Some(v) => format!("{}_{}", column, v),
                         None => "".to_string(),
                     },
-                },
-                TemplatePart::TimeFormat(format) => match line.timestamp {
+                }),
+                TemplatePart::TimeFormat(format) => Ok(match line.timestamp {
                     Some(t) => Utc.timestamp_nanos(t).format(&format).to_string(),
                     None => default_time.format(&format).to_string(),
-                },
-                _ => unimplemented!(),
+                }),
+                TemplatePart::RegexCapture(capture) => capture.partition_key(line, regex_cache),
+                TemplatePart::StrftimeColumn(strftime) => strftime.partition_key(line),
             })
-            .collect();
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(parts.join("-"))
     }
@@ -218,19 +490,78 @@ pub enum TemplatePart {
 }
 
 /// `RegexCapture` is for pulling parts of a string column into the partition
-/// key.
+/// key. If the regex has a capture group, the first group is used, otherwise
+/// the whole match is. Tags and string fields can be matched against; if the
+/// column is missing or the regex doesn't match, a blank value is output.
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct RegexCapture {
-    column: String,
-    regex: String,
+    pub column: String,
+    pub regex: String,
+}
+
+impl RegexCapture {
+    fn partition_key(&self, line: &ParsedLine<'_>, regex_cache: &RegexCache) -> Result<String> {
+        let regex = regex_cache.get(&self.regex)?;
+
+        let value = match line.tag_value(&self.column) {
+            Some(v) => v.as_str(),
+            None => match line.field_value(&self.column) {
+                Some(FieldValue::String(v)) => v.as_str(),
+                _ => return Ok("".to_string()),
+            },
+        };
+
+        let group = if regex.captures_len() > 1 { 1 } else { 0 };
+        let captured = regex.captures(value).and_then(|c| c.get(group));
+
+        Ok(match captured {
+            Some(m) => format!("{}_{}", self.column, m.as_str()),
+            None => "".to_string(),
+        })
+    }
 }
 
 /// `StrftimeColumn` can be used to create a time based partition key off some
-/// column other than the builtin `time` column.
+/// column other than the builtin `time` column. The column must hold either
+/// an integer of nanoseconds since the epoch or an RFC 3339 timestamp string.
+/// If the column is missing, a blank value is output.
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct StrftimeColumn {
-    column: String,
-    format: String,
+    pub column: String,
+    pub format: String,
+}
+
+impl StrftimeColumn {
+    fn partition_key(&self, line: &ParsedLine<'_>) -> Result<String> {
+        let time = match line.tag_value(&self.column) {
+            Some(v) => self.parse_timestamp(v.as_str())?,
+            None => match line.field_value(&self.column) {
+                Some(FieldValue::I64(v)) => Utc.timestamp_nanos(*v),
+                Some(FieldValue::String(v)) => self.parse_timestamp(v.as_str())?,
+                Some(v) => {
+                    return NonTimestampValue {
+                        column: &self.column,
+                        value: v.to_string(),
+                    }
+                    .fail()
+                }
+                None => return Ok("".to_string()),
+            },
+        };
+
+        Ok(time.format(&self.format).to_string())
+    }
+
+    fn parse_timestamp(&self, value: &str) -> Result<DateTime<Utc>> {
+        value
+            .parse::<i64>()
+            .map(|nanos| Utc.timestamp_nanos(nanos))
+            .or_else(|_| DateTime::parse_from_rfc3339(value).map(|t| t.with_timezone(&Utc)))
+            .map_err(|_| Error::NonTimestampValue {
+                column: self.column.clone(),
+                value: value.to_string(),
+            })
+    }
 }
 
 /// `PartitionId` is the object storage identifier for a specific partition. It
@@ -261,11 +592,69 @@ pub struct Subscription {
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct Matcher {
     pub tables: MatchTables,
-    // TODO: make this work with query::Predicate
+    /// An optional predicate that rows must match, such as
+    /// `host = 'a' AND usage > 90`. See `row_predicate` for the syntax.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub predicate: Option<String>,
 }
 
+impl Matcher {
+    /// Parses the predicate and compiles the table regex, through
+    /// `regex_cache`, of this rule, returning an error if either is invalid.
+    pub fn compile(&self, regex_cache: &RegexCache) -> Result<CompiledMatcher> {
+        let predicate = self
+            .predicate
+            .as_deref()
+            .map(RowPredicate::parse)
+            .transpose()
+            .context(InvalidMatcherPredicate)?;
+
+        let table_regex = match &self.tables {
+            MatchTables::Regex(regex) => Some(regex_cache.get(regex)?),
+            _ => None,
+        };
+
+        Ok(CompiledMatcher {
+            tables: self.tables.clone(),
+            table_regex,
+            predicate,
+        })
+    }
+}
+
+/// `CompiledMatcher` is a `Matcher` whose predicate has been parsed and
+/// whose table regex has been compiled, so that they are not redone for
+/// every write that is matched.
+#[derive(Debug, Clone)]
+pub struct CompiledMatcher {
+    tables: MatchTables,
+    table_regex: Option<Arc<Regex>>,
+    predicate: Option<RowPredicate>,
+}
+
+impl CompiledMatcher {
+    /// Returns the part of `write` that matches this rule as a new
+    /// `ReplicatedWrite` with the same writer ID and sequence number, or
+    /// `None` if nothing in the write matches.
+    pub fn filter_write(&self, write: &ReplicatedWrite) -> Option<ReplicatedWrite> {
+        if self.tables == MatchTables::All && self.predicate.is_none() {
+            return Some(write.clone());
+        }
+
+        write.filter(
+            |table| match &self.tables {
+                MatchTables::All => true,
+                MatchTables::Table(name) => name == table,
+                MatchTables::Regex(_) => self
+                    .table_regex
+                    .as_ref()
+                    .map_or(false, |r| r.is_match(table)),
+            },
+            |row| self.predicate.as_ref().map_or(true, |p| p.matches(row)),
+        )
+    }
+}
+
 /// `MatchTables` looks at the table name of a row to determine if it should
 /// match the rule.
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
@@ -444,6 +833,212 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn partition_key_with_regex_capture() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::RegexCapture(RegexCapture {
+                column: "host".to_string(),
+                regex: "^([a-z]+)-".to_string(),
+            })],
+        };
+
+        let line = parse_line("cpu,host=useast-web01 usage_user=23.2 10");
+        assert_eq!(
+            "host_useast",
+            template.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_regex_without_capture_group() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::RegexCapture(RegexCapture {
+                column: "host".to_string(),
+                regex: "[0-9]+".to_string(),
+            })],
+        };
+
+        let line = parse_line("cpu host=\"useast-web01\" 10");
+        assert_eq!(
+            "host_01",
+            template.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_regex_no_match_or_missing_column() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::RegexCapture(RegexCapture {
+                column: "host".to_string(),
+                regex: "^([a-z]+)-".to_string(),
+            })],
+        };
+
+        let line = parse_line("cpu,host=web01 usage_user=23.2 10");
+        assert_eq!("", template.partition_key(&line, &Utc::now()).unwrap());
+
+        let line = parse_line("cpu,region=west usage_user=23.2 10");
+        assert_eq!("", template.partition_key(&line, &Utc::now()).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_invalid_regex() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::RegexCapture(RegexCapture {
+                column: "host".to_string(),
+                regex: "([a-z]+".to_string(),
+            })],
+        };
+
+        let line = parse_line("cpu,host=useast-web01 usage_user=23.2 10");
+        let err = template.partition_key(&line, &Utc::now()).unwrap_err();
+        assert!(matches!(err, Error::InvalidRegex { .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_rejects_invalid_partition_template_regex() -> Result {
+        let mut rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::RegexCapture(RegexCapture {
+                    column: "host".to_string(),
+                    regex: "^([a-z]+)-".to_string(),
+                })],
+            },
+            ..Default::default()
+        };
+        rules.validate()?;
+
+        rules.partition_template.parts = vec![TemplatePart::RegexCapture(RegexCapture {
+            column: "host".to_string(),
+            regex: "([a-z]+".to_string(),
+        })];
+        let err = rules.validate().unwrap_err();
+        assert!(matches!(err, Error::InvalidRegex { .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_rejects_invalid_subscription_matchers() -> Result {
+        let subscription = |tables, predicate: Option<&str>| Subscription {
+            name: "sub".to_string(),
+            host_group_id: "az1".to_string(),
+            matcher: Matcher {
+                tables,
+                predicate: predicate.map(str::to_string),
+            },
+        };
+
+        let mut rules = DatabaseRules {
+            subscriptions: vec![subscription(
+                MatchTables::Regex("^cpu".to_string()),
+                Some("host = 'a' AND usage > 1e-5"),
+            )],
+            ..Default::default()
+        };
+        rules.validate()?;
+
+        rules.subscriptions = vec![subscription(MatchTables::Regex("(cpu".to_string()), None)];
+        let err = rules.validate().unwrap_err();
+        assert!(matches!(err, Error::InvalidRegex { .. }));
+
+        rules.subscriptions = vec![subscription(MatchTables::All, Some("host = "))];
+        let err = rules.validate().unwrap_err();
+        assert!(matches!(err, Error::InvalidMatcherPredicate { .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_strftime_column() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::StrftimeColumn(StrftimeColumn {
+                column: "event_time".to_string(),
+                format: "%Y-%m-%d".to_string(),
+            })],
+        };
+
+        let line = parse_line("cpu event_time=1602338097000000000i 10");
+        assert_eq!(
+            "2020-10-10",
+            template.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        let line = parse_line("cpu,event_time=2020-10-11T01:02:03Z usage_user=1 10");
+        assert_eq!(
+            "2020-10-11",
+            template.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        let line = parse_line("cpu usage_user=1 10");
+        assert_eq!("", template.partition_key(&line, &Utc::now()).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_strftime_column_non_timestamp() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::StrftimeColumn(StrftimeColumn {
+                column: "event_time".to_string(),
+                format: "%Y-%m-%d".to_string(),
+            })],
+        };
+
+        let line = parse_line("cpu event_time=1.5 10");
+        let err = template.partition_key(&line, &Utc::now()).unwrap_err();
+        assert!(matches!(err, Error::NonTimestampValue { .. }));
+
+        let line = parse_line("cpu event_time=\"yesterday\" 10");
+        let err = template.partition_key(&line, &Utc::now()).unwrap_err();
+        assert!(matches!(err, Error::NonTimestampValue { .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_rules_cache_compiled_regexes() -> Result {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![
+                    TemplatePart::Table,
+                    TemplatePart::RegexCapture(RegexCapture {
+                        column: "host".to_string(),
+                        regex: "^([a-z]+)-".to_string(),
+                    }),
+                ],
+            },
+            ..Default::default()
+        };
+
+        let line = parse_line("cpu,host=useast-web01 usage_user=23.2 10");
+        assert_eq!(
+            "cpu-host_useast",
+            rules.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        let cached = rules.regex_cache.get("^([a-z]+)-").unwrap();
+        let line = parse_line("cpu,host=uswest-web02 usage_user=23.2 10");
+        assert_eq!(
+            "cpu-host_uswest",
+            rules.partition_key(&line, &Utc::now()).unwrap()
+        );
+        assert!(Arc::ptr_eq(
+            &cached,
+            &rules.regex_cache.get("^([a-z]+)-").unwrap()
+        ));
+
+        Ok(())
+    }
+
     fn parsed_lines(lp: &str) -> Vec<ParsedLine<'_>> {
         parse_lines(lp).map(|l| l.unwrap()).collect()
     }
@@ -9,13 +9,14 @@
     clippy::use_self
 )]
 
-pub use schema::TIME_COLUMN_NAME;
+pub use schema::{MEASUREMENT_COLUMN_NAME, TIME_COLUMN_NAME};
 
 pub mod data;
 pub mod database_rules;
 pub mod error;
 pub mod names;
 pub mod partition_metadata;
+pub mod row_predicate;
 pub mod schema;
 pub mod selection;
 
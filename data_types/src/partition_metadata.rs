@@ -1,7 +1,10 @@
 //! This module contains structs that describe the metadata for a partition
 //! including schema, summary statistics, and file locations in storage.
 
-use std::fmt::{Debug, Display};
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -14,17 +17,65 @@ pub struct Partition {
     pub key: String,
     /// The tables in this partition
     pub tables: Vec<Table>,
+    /// The id of the chunk these tables were snapshotted from, if any
+    #[serde(default)]
+    pub chunk_id: Option<u32>,
+    /// The sequence numbers, by writer id, of the writes contained in this
+    /// partition's data
+    #[serde(default)]
+    pub writer_sequences: BTreeMap<u32, SequenceRange>,
+}
+
+/// An inclusive range of sequence numbers from a single writer.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceRange {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl SequenceRange {
+    pub fn new(sequence: u64) -> Self {
+        Self {
+            min: sequence,
+            max: sequence,
+        }
+    }
+
+    /// Extends the range, if necessary, to include `sequence`.
+    pub fn update(&mut self, sequence: u64) {
+        self.min = self.min.min(sequence);
+        self.max = self.max.max(sequence);
+    }
+
+    /// Returns true if `sequence` falls within this range.
+    pub fn contains(&self, sequence: u64) -> bool {
+        self.min <= sequence && sequence <= self.max
+    }
 }
 
 /// Metadata and statistics information for a table.
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Table {
     pub name: String,
-    pub columns: Vec<Column>,
+    pub columns: Vec<ColumnSummary>,
+}
+
+impl Table {
+    /// Returns the summary of the column named `name`, if any
+    pub fn column(&self, name: &str) -> Option<&ColumnSummary> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+}
+
+/// The name and statistics of a column.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ColumnSummary {
+    pub name: String,
+    pub stats: Column,
 }
 
 /// Statistics and type information for a column.
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub enum Column {
     I64(Statistics<i64>),
     U64(Statistics<u64>),
@@ -133,6 +184,20 @@ mod tests {
         assert_eq!(stat.count, 4);
     }
 
+    #[test]
+    fn sequence_range() {
+        let mut range = SequenceRange::new(5);
+        assert!(range.contains(5));
+        assert!(!range.contains(4));
+
+        range.update(9);
+        range.update(2);
+        assert_eq!(range, SequenceRange { min: 2, max: 9 });
+        assert!(range.contains(2));
+        assert!(range.contains(9));
+        assert!(!range.contains(10));
+    }
+
     #[test]
     fn update_string() {
         let mut stat = Statistics::new("bbb".to_string());
@@ -0,0 +1,437 @@
+//! This module contains the predicates used to select individual rows of a
+//! `ReplicatedWrite`, such as the rows sent to a subscription by its
+//! `Matcher`.
+//!
+//! Predicates are SQL boolean expressions, parsed with `sqlparser` as the
+//! `WHERE` clause of a query, that compare columns against literal values:
+//!
+//! ```text
+//! host = 'server01' AND (usage_user > 90 OR usage_system >= 50.5)
+//! NOT region = 'us-west' OR "error code" IS NOT NULL
+//! ```
+//!
+//! They are converted to DataFusion `Expr`s, so only the subset of SQL that
+//! can be evaluated against a single row is accepted. Comparisons against a
+//! column that is missing from a row, or whose value has a different type
+//! than the literal, evaluate to false.
+use arrow_deps::datafusion::{
+    logical_plan::{Expr, Operator},
+    scalar::ScalarValue,
+};
+use generated_types::wal as wb;
+use snafu::{ResultExt, Snafu};
+use sqlparser::{
+    ast::{self, BinaryOperator, SetExpr, Statement, UnaryOperator, Value},
+    dialect::GenericDialect,
+    parser::{Parser, ParserError},
+};
+
+use std::cmp::Ordering;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid row predicate '{}': {}", predicate, source))]
+    InvalidPredicate {
+        predicate: String,
+        source: ParserError,
+    },
+
+    #[snafu(display("Unsupported row predicate '{}': {}", predicate, message))]
+    UnsupportedPredicate { predicate: String, message: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A parsed row predicate, which can be evaluated against the rows of a
+/// `ReplicatedWrite`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowPredicate {
+    expr: Expr,
+}
+
+/// The value of a column in a row
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RowValue<'a> {
+    String(&'a str),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Bool(bool),
+}
+
+impl RowPredicate {
+    /// Parses `predicate` into a `RowPredicate`
+    pub fn parse(predicate: &str) -> Result<Self> {
+        let unsupported = |message: &str| UnsupportedPredicate { predicate, message }.fail();
+
+        // the predicate is parsed as the WHERE clause of a query, which is
+        // then checked for anything that followed the predicate
+        let sql = format!("SELECT * FROM t WHERE {}", predicate);
+        let mut statements =
+            Parser::parse_sql(&GenericDialect {}, &sql).context(InvalidPredicate { predicate })?;
+        if statements.len() != 1 {
+            return unsupported("expected a single expression");
+        }
+
+        let query = match statements.pop() {
+            Some(Statement::Query(query)) => *query,
+            _ => return unsupported("expected a single expression"),
+        };
+        let selection = match query.body {
+            SetExpr::Select(select)
+                if query.ctes.is_empty()
+                    && query.order_by.is_empty()
+                    && query.limit.is_none()
+                    && query.offset.is_none()
+                    && query.fetch.is_none()
+                    && select.group_by.is_empty()
+                    && select.having.is_none() =>
+            {
+                select.selection
+            }
+            _ => return unsupported("expected a single expression"),
+        };
+
+        match selection {
+            Some(selection) => match to_expr(&selection) {
+                Ok(expr) => Ok(Self { expr }),
+                Err(message) => unsupported(&message),
+            },
+            None => unsupported("expected a single expression"),
+        }
+    }
+
+    /// Returns true if `row` matches this predicate
+    pub fn matches(&self, row: &wb::Row<'_>) -> bool {
+        evaluate(&self.expr, &|column| row_value(row, column))
+    }
+}
+
+/// Converts the parsed SQL `expr` to an `Expr`, returning a description of
+/// the first part of it that can't be evaluated against a row.
+fn to_expr(expr: &ast::Expr) -> Result<Expr, String> {
+    match expr {
+        ast::Expr::Nested(expr) => to_expr(expr),
+        ast::Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => Ok(Expr::Not(Box::new(to_expr(expr)?))),
+        ast::Expr::IsNull(expr) => Ok(Expr::IsNull(Box::new(to_column(expr)?))),
+        ast::Expr::IsNotNull(expr) => Ok(Expr::IsNotNull(Box::new(to_column(expr)?))),
+        ast::Expr::BinaryOp { left, op, right } => {
+            let op = match op {
+                BinaryOperator::And => Operator::And,
+                BinaryOperator::Or => Operator::Or,
+                BinaryOperator::Eq => Operator::Eq,
+                BinaryOperator::NotEq => Operator::NotEq,
+                BinaryOperator::Lt => Operator::Lt,
+                BinaryOperator::LtEq => Operator::LtEq,
+                BinaryOperator::Gt => Operator::Gt,
+                BinaryOperator::GtEq => Operator::GtEq,
+                _ => return Err(format!("unsupported operator {}", op)),
+            };
+
+            let (left, right) = match op {
+                Operator::And | Operator::Or => (to_expr(left)?, to_expr(right)?),
+                _ => (to_column(left)?, to_literal(right)?),
+            };
+
+            Ok(Expr::BinaryExpr {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            })
+        }
+        _ => Err(format!("unsupported expression {}", expr)),
+    }
+}
+
+fn to_column(expr: &ast::Expr) -> Result<Expr, String> {
+    match expr {
+        ast::Expr::Identifier(ident) => Ok(Expr::Column(ident.value.clone())),
+        _ => Err(format!("expected a column name, found {}", expr)),
+    }
+}
+
+fn to_literal(expr: &ast::Expr) -> Result<Expr, String> {
+    let value = match expr {
+        ast::Expr::Value(Value::SingleQuotedString(s)) => ScalarValue::Utf8(Some(s.clone())),
+        ast::Expr::Value(Value::Boolean(b)) => ScalarValue::Boolean(Some(*b)),
+        ast::Expr::Value(Value::Number(n)) => number(n)?,
+        ast::Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match expr.as_ref() {
+            ast::Expr::Value(Value::Number(n)) => number(&format!("-{}", n))?,
+            _ => return Err(format!("expected a literal value, found {}", expr)),
+        },
+        _ => return Err(format!("expected a literal value, found {}", expr)),
+    };
+
+    Ok(Expr::Literal(value))
+}
+
+fn number(n: &str) -> Result<ScalarValue, String> {
+    match n.parse::<i64>() {
+        Ok(i) => Ok(ScalarValue::Int64(Some(i))),
+        Err(_) => match n.parse::<f64>() {
+            Ok(f) => Ok(ScalarValue::Float64(Some(f))),
+            Err(_) => Err(format!("invalid number {}", n)),
+        },
+    }
+}
+
+/// Evaluates `expr`, which was built by `to_expr`, against the row whose
+/// column values are returned by `value_of`
+fn evaluate<'a>(expr: &Expr, value_of: &dyn Fn(&str) -> Option<RowValue<'a>>) -> bool {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => evaluate(left, value_of) && evaluate(right, value_of),
+        Expr::BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        } => evaluate(left, value_of) || evaluate(right, value_of),
+        Expr::Not(expr) => !evaluate(expr, value_of),
+        Expr::IsNull(column) => column_value(column, value_of).is_none(),
+        Expr::IsNotNull(column) => column_value(column, value_of).is_some(),
+        Expr::BinaryExpr { left, op, right } => {
+            match (column_value(left, value_of), right.as_ref()) {
+                (Some(row_value), Expr::Literal(literal)) => match compare(row_value, literal) {
+                    Some(ordering) => accepts(*op, ordering),
+                    None => false,
+                },
+                _ => false,
+            }
+        }
+        _ => unreachable!("expression not built by to_expr: {:?}", expr),
+    }
+}
+
+fn column_value<'a>(
+    column: &Expr,
+    value_of: &dyn Fn(&str) -> Option<RowValue<'a>>,
+) -> Option<RowValue<'a>> {
+    match column {
+        Expr::Column(name) => value_of(name),
+        _ => unreachable!("expression not built by to_expr: {:?}", column),
+    }
+}
+
+/// Returns true if a comparison with `op` holds for values with `ordering`
+fn accepts(op: Operator, ordering: Ordering) -> bool {
+    match op {
+        Operator::Eq => ordering == Ordering::Equal,
+        Operator::NotEq => ordering != Ordering::Equal,
+        Operator::Lt => ordering == Ordering::Less,
+        Operator::LtEq => ordering != Ordering::Greater,
+        Operator::Gt => ordering == Ordering::Greater,
+        Operator::GtEq => ordering != Ordering::Less,
+        _ => unreachable!("comparison not built by to_expr: {:?}", op),
+    }
+}
+
+/// Compares a row value to a literal, returning `None` if their types are
+/// not comparable.
+fn compare(row_value: RowValue<'_>, literal: &ScalarValue) -> Option<Ordering> {
+    match (row_value, literal) {
+        (RowValue::String(v), ScalarValue::Utf8(Some(l))) => Some(v.cmp(l.as_str())),
+        (RowValue::Bool(v), ScalarValue::Boolean(Some(l))) => Some(v.cmp(l)),
+        (RowValue::I64(v), ScalarValue::Int64(Some(l))) => Some(v.cmp(l)),
+        (RowValue::U64(v), ScalarValue::Int64(Some(l))) => Some(i128::from(v).cmp(&i128::from(*l))),
+        (RowValue::I64(v), ScalarValue::Float64(Some(l))) => (v as f64).partial_cmp(l),
+        (RowValue::U64(v), ScalarValue::Float64(Some(l))) => (v as f64).partial_cmp(l),
+        (RowValue::F64(v), ScalarValue::Int64(Some(l))) => v.partial_cmp(&(*l as f64)),
+        (RowValue::F64(v), ScalarValue::Float64(Some(l))) => v.partial_cmp(l),
+        _ => None,
+    }
+}
+
+/// Returns the value of `column` in `row`, if present
+fn row_value<'a>(row: &wb::Row<'a>, column: &str) -> Option<RowValue<'a>> {
+    let value = row
+        .values()?
+        .into_iter()
+        .find(|v| v.column() == Some(column))?;
+
+    match value.value_type() {
+        wb::ColumnValue::TagValue => value.value_as_tag_value()?.value().map(RowValue::String),
+        wb::ColumnValue::StringValue => {
+            value.value_as_string_value()?.value().map(RowValue::String)
+        }
+        wb::ColumnValue::I64Value => Some(RowValue::I64(value.value_as_i64value()?.value())),
+        wb::ColumnValue::U64Value => Some(RowValue::U64(value.value_as_u64value()?.value())),
+        wb::ColumnValue::F64Value => Some(RowValue::F64(value.value_as_f64value()?.value())),
+        wb::ColumnValue::BoolValue => Some(RowValue::Bool(value.value_as_bool_value()?.value())),
+        wb::ColumnValue::NONE => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::datafusion::logical_plan::{col, lit};
+
+    use std::collections::BTreeMap;
+
+    fn evaluate_row(predicate: &str, row: &BTreeMap<&str, RowValue<'_>>) -> bool {
+        let predicate = RowPredicate::parse(predicate).unwrap();
+        evaluate(&predicate.expr, &|column| row.get(column).copied())
+    }
+
+    fn test_row() -> BTreeMap<&'static str, RowValue<'static>> {
+        let mut row = BTreeMap::new();
+        row.insert("host", RowValue::String("server01"));
+        row.insert("region", RowValue::String("us-west"));
+        row.insert("usage_user", RowValue::F64(92.5));
+        row.insert("count", RowValue::I64(10));
+        row.insert("bytes", RowValue::U64(u64::MAX));
+        row.insert("active", RowValue::Bool(true));
+        row
+    }
+
+    fn binary(left: Expr, op: Operator, right: Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
+    #[test]
+    fn parse_precedence() {
+        let parsed = RowPredicate::parse("a = 1 OR b = 2 AND NOT c = 3").unwrap();
+
+        let expected = binary(
+            col("a").eq(lit(1_i64)),
+            Operator::Or,
+            binary(
+                col("b").eq(lit(2_i64)),
+                Operator::And,
+                Expr::Not(Box::new(col("c").eq(lit(3_i64)))),
+            ),
+        );
+        assert_eq!(parsed, RowPredicate { expr: expected });
+    }
+
+    #[test]
+    fn parse_literals() {
+        let cases = vec![
+            ("-5", ScalarValue::Int64(Some(-5))),
+            ("2.5", ScalarValue::Float64(Some(2.5))),
+            ("-0.5", ScalarValue::Float64(Some(-0.5))),
+            ("'a'", ScalarValue::Utf8(Some("a".to_string()))),
+            ("true", ScalarValue::Boolean(Some(true))),
+        ];
+
+        for (literal, expected) in cases {
+            let parsed = RowPredicate::parse(&format!("a > {}", literal)).unwrap();
+            let expected = binary(col("a"), Operator::Gt, Expr::Literal(expected));
+            assert_eq!(
+                parsed,
+                RowPredicate { expr: expected },
+                "literal: {}",
+                literal
+            );
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let invalid = vec!["", "host = ", "host = 'a", "(host = 'a'", "host ! 'a'"];
+        for predicate in invalid {
+            let err = RowPredicate::parse(predicate).unwrap_err();
+            assert!(
+                matches!(err, Error::InvalidPredicate { .. }),
+                "predicate: {}, error: {}",
+                predicate,
+                err
+            );
+        }
+
+        let unsupported = vec![
+            "host",
+            "host = 'a' ORDER BY host",
+            "host = 'a' LIMIT 1",
+            "host = 'a'; SELECT 1",
+            "host = region",
+            "'a' = host",
+            "count + 1 = 2",
+            "host LIKE 'a%'",
+            "lower(host) = 'a'",
+            "1 IS NULL",
+        ];
+        for predicate in unsupported {
+            let err = RowPredicate::parse(predicate).unwrap_err();
+            assert!(
+                matches!(err, Error::UnsupportedPredicate { .. }),
+                "predicate: {}, error: {}",
+                predicate,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn evaluate_comparisons() {
+        let row = test_row();
+
+        assert!(evaluate_row("host = 'server01'", &row));
+        assert!(!evaluate_row("host != 'server01'", &row));
+        assert!(evaluate_row("host <> 'server02'", &row));
+        assert!(evaluate_row("region >= 'us' AND region < 'us-x'", &row));
+        assert!(evaluate_row("usage_user > 90", &row));
+        assert!(evaluate_row("usage_user <= 92.5", &row));
+        assert!(evaluate_row("count = 10", &row));
+        assert!(evaluate_row("count < 10.5", &row));
+        assert!(evaluate_row("bytes > 9223372036854775807", &row));
+        assert!(evaluate_row("active = TRUE", &row));
+        assert!(evaluate_row("\"usage_user\" > -1000", &row));
+    }
+
+    #[test]
+    fn evaluate_missing_and_mismatched_columns() {
+        let row = test_row();
+
+        assert!(!evaluate_row("not_here = 'a'", &row));
+        assert!(!evaluate_row("not_here != 'a'", &row));
+        assert!(evaluate_row("NOT not_here = 'a'", &row));
+        assert!(!evaluate_row("host = 1", &row));
+        assert!(!evaluate_row("active = 'true'", &row));
+    }
+
+    #[test]
+    fn evaluate_null_checks() {
+        let row = test_row();
+
+        assert!(evaluate_row("not_here IS NULL", &row));
+        assert!(!evaluate_row("not_here is not null", &row));
+        assert!(evaluate_row("host IS NOT NULL", &row));
+    }
+
+    #[test]
+    fn evaluate_boolean_logic() {
+        let row = test_row();
+
+        assert!(evaluate_row(
+            "host = 'server02' OR (region = 'us-west' AND count > 5)",
+            &row
+        ));
+        assert!(!evaluate_row(
+            "(host = 'server02' OR region = 'us-west') AND count > 50",
+            &row
+        ));
+        assert!(evaluate_row("NOT (host = 'server02')", &row));
+    }
+
+    #[test]
+    fn quoted_strings() {
+        let mut row = BTreeMap::new();
+        row.insert("error code", RowValue::String("it's"));
+
+        assert!(evaluate_row("\"error code\" = 'it''s'", &row));
+    }
+}
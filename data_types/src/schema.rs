@@ -14,6 +14,10 @@ use arrow_deps::arrow::datatypes::{
 
 pub const TIME_COLUMN_NAME: &str = "time";
 
+/// The name of the pseudo column whose values are the names of the tables
+/// (measurements) rows belong to
+pub const MEASUREMENT_COLUMN_NAME: &str = "_measurement";
+
 pub mod builder;
 
 /// Database schema creation / validation errors.
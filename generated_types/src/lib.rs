@@ -15,6 +15,16 @@ include!(concat!(
 ));
 include!(concat!(env!("OUT_DIR"), "/wal_generated.rs"));
 
+/// The gRPC service IOx servers use to replicate writes to each other
+pub mod replication {
+    pub mod v1 {
+        include!(concat!(
+            env!("OUT_DIR"),
+            "/influxdata.iox.replication.v1.rs"
+        ));
+    }
+}
+
 // Can't implement `Default` because `prost::Message` implements `Default`
 impl TimestampRange {
     pub fn max() -> Self {
@@ -12,10 +12,13 @@ use arrow_deps::{
 
 use chrono::{DateTime, Utc};
 use generated_types::wal as wb;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use data_types::{
-    partition_metadata::Table as TableStats, schema::Schema, selection::Selection, TIME_COLUMN_NAME,
+    partition_metadata::{SequenceRange, Table as TableStats},
+    schema::Schema,
+    selection::Selection,
+    TIME_COLUMN_NAME,
 };
 
 use query::{
@@ -107,6 +110,33 @@ pub struct Chunk {
 
     /// map of the dictionary ID for the table name to the table
     pub tables: HashMap<u32, Table>,
+
+    /// The range of sequence numbers, by writer id, of the writes that
+    /// have been applied to this chunk
+    pub writer_sequences: BTreeMap<u32, SequenceRange>,
+}
+
+/// Summary information about a chunk, which can be used to make decisions
+/// about the chunk without copying its data
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkSummary {
+    /// The key of the partition containing this chunk
+    pub partition_key: String,
+
+    /// The id of this chunk
+    pub id: u32,
+
+    /// The estimated size in bytes of the data in this chunk
+    pub size: usize,
+
+    /// Time at which the first data was written into this chunk
+    pub time_of_first_write: Option<DateTime<Utc>>,
+
+    /// Most recent time at which data write was initiated into this chunk
+    pub time_of_last_write: Option<DateTime<Utc>>,
+
+    /// Time at which this chunk was closed, or None if it is still open
+    pub time_closed: Option<DateTime<Utc>>,
 }
 
 /// Describes the result of translating a set of strings into
@@ -213,9 +243,19 @@ impl Chunk {
             time_of_first_write: None,
             time_of_last_write: None,
             time_closed: None,
+            writer_sequences: BTreeMap::new(),
         }
     }
 
+    /// Records that the write with `sequence` from `writer_id` has been
+    /// applied to this chunk
+    pub fn record_sequence(&mut self, writer_id: u32, sequence: u64) {
+        self.writer_sequences
+            .entry(writer_id)
+            .and_modify(|range| range.update(sequence))
+            .or_insert_with(|| SequenceRange::new(sequence));
+    }
+
     pub fn write_entry(&mut self, entry: &wb::WriteBufferEntry<'_>) -> Result<()> {
         if let Some(table_batches) = entry.table_batches() {
             let now = Utc::now();
@@ -395,6 +435,26 @@ impl Chunk {
         self.id
     }
 
+    /// Returns the estimated size in bytes of the data in this chunk,
+    /// including its dictionary
+    pub fn size(&self) -> usize {
+        let tables: usize = self.tables.values().map(|t| t.size()).sum();
+        self.dictionary.size() + tables
+    }
+
+    /// Returns a summary of this chunk, which is stored in the partition
+    /// with key `partition_key`
+    pub fn summary(&self, partition_key: &str) -> ChunkSummary {
+        ChunkSummary {
+            partition_key: partition_key.to_string(),
+            id: self.id,
+            size: self.size(),
+            time_of_first_write: self.time_of_first_write,
+            time_of_last_write: self.time_of_last_write,
+            time_closed: self.time_closed,
+        }
+    }
+
     /// Convert the table specified in this chunk into some number of
     /// record batches, appended to dst
     pub fn table_to_arrow(
@@ -426,7 +486,7 @@ impl Chunk {
                         chunk: self.id,
                     })?;
 
-            let columns = table.stats();
+            let columns = table.stats(self);
 
             stats.push(TableStats {
                 name: name.to_string(),
@@ -5,6 +5,7 @@ use crate::dictionary::Dictionary;
 use data_types::{data::type_description, partition_metadata::Statistics};
 
 use arrow_deps::arrow::datatypes::DataType as ArrowDataType;
+use std::mem;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -117,6 +118,21 @@ impl Column {
         self.len() == 0
     }
 
+    /// The estimated size in bytes of the values stored in this column.
+    /// Tag values are stored in the chunk's dictionary and are not included.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::F64(v, _) => mem::size_of::<Option<f64>>() * v.len(),
+            Self::I64(v, _) => mem::size_of::<Option<i64>>() * v.len(),
+            Self::String(v, _) => {
+                let string_bytes: usize = v.iter().flatten().map(|s| s.len()).sum();
+                mem::size_of::<Option<String>>() * v.len() + string_bytes
+            }
+            Self::Bool(v, _) => mem::size_of::<Option<bool>>() * v.len(),
+            Self::Tag(v, _) => mem::size_of::<Option<u32>>() * v.len(),
+        }
+    }
+
     pub fn type_description(&self) -> &'static str {
         match self {
             Self::F64(_, _) => "f64",
@@ -284,6 +300,18 @@ mod tests {
     type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
     type Result<T = (), E = TestError> = std::result::Result<T, E>;
 
+    #[test]
+    fn test_size() {
+        let col = Column::I64(vec![Some(1), None, Some(2)], Statistics::new(1));
+        assert_eq!(col.size(), 3 * mem::size_of::<Option<i64>>());
+
+        let col = Column::String(
+            vec![Some("foo".to_string()), None],
+            Statistics::new("foo".to_string()),
+        );
+        assert_eq!(col.size(), 2 * mem::size_of::<Option<String>>() + 3);
+    }
+
     #[test]
     fn test_has_i64_range() -> Result {
         let mut stats = Statistics::new(1);
@@ -11,7 +11,7 @@ use query::{
 use crate::column::Column;
 use crate::table::Table;
 use crate::{
-    chunk::{Chunk, ChunkPredicate},
+    chunk::{Chunk, ChunkPredicate, ChunkSummary},
     partition::Partition,
 };
 
@@ -19,7 +19,7 @@ use std::collections::{BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
 
 use arrow_deps::datafusion::{error::DataFusionError, logical_plan::LogicalPlan};
-use data_types::data::ReplicatedWrite;
+use data_types::{data::ReplicatedWrite, MEASUREMENT_COLUMN_NAME};
 
 use crate::dictionary::Error as DictionaryError;
 
@@ -53,6 +53,13 @@ pub enum Error {
         source: DictionaryError,
     },
 
+    #[snafu(display("Table ID {} not found in dictionary of chunk {}", table_id, chunk))]
+    TableIdNotFoundInDictionary {
+        table_id: u32,
+        chunk: u64,
+        source: DictionaryError,
+    },
+
     #[snafu(display("Column ID {} not found in dictionary of chunk {}", column_id, chunk))]
     ColumnIdNotFoundInDictionary {
         column_id: u32,
@@ -140,8 +147,14 @@ impl MutableBufferDb {
         }
     }
 
-    /// Directs the writes from batch into the appropriate partitions
-    async fn write_entries_to_partitions(&self, batch: &wal::WriteBufferBatch<'_>) -> Result<()> {
+    /// Directs the writes from batch into the appropriate partitions,
+    /// recording the writer and sequence of the write in each of them
+    async fn write_entries_to_partitions(
+        &self,
+        batch: &wal::WriteBufferBatch<'_>,
+        writer_id: u32,
+        sequence: u64,
+    ) -> Result<()> {
         if let Some(entries) = batch.entries() {
             for entry in entries {
                 let key = entry
@@ -150,7 +163,8 @@ impl MutableBufferDb {
 
                 let partition = self.get_partition(key).await;
                 let mut partition = partition.write().await;
-                partition.write_entry(&entry)?
+                partition.write_entry(&entry)?;
+                partition.record_sequence(writer_id, sequence);
             }
         }
 
@@ -164,6 +178,18 @@ impl MutableBufferDb {
         Ok(partition.rollover_chunk())
     }
 
+    /// Ensures that the open chunk of the specified partition, and any chunks
+    /// created after it, have ids of at least `next_id`. Used when
+    /// recovering, so that new chunks do not reuse the ids of chunks that
+    /// were persisted before a restart.
+    pub async fn reserve_chunk_ids(&self, partition_key: &str, next_id: u32) {
+        self.get_partition(partition_key)
+            .await
+            .write()
+            .await
+            .reserve_chunk_ids(next_id)
+    }
+
     /// return the specified chunk from the partition
     /// Returns None if no such chunk exists.
     pub async fn get_chunk(&self, partition_key: &str, chunk_id: u32) -> Option<Arc<Chunk>> {
@@ -184,31 +210,34 @@ impl MutableBufferDb {
             .drop_chunk(chunk_id)
             .context(DroppingChunk { partition_key })
     }
-}
 
-#[async_trait]
-impl Database for MutableBufferDb {
-    type Error = Error;
-    type Chunk = Chunk;
-
-    async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
-        match write.write_buffer_batch() {
-            Some(b) => self.write_entries_to_partitions(&b).await?,
-            None => {
-                return MissingPayload {
-                    writer: write.to_fb().writer(),
-                }
-                .fail()
-            }
-        };
+    /// Returns summaries of every chunk, open and closed, in every partition
+    /// of this database
+    pub async fn chunk_summaries(&self) -> Vec<ChunkSummary> {
+        let mut summaries = vec![];
+        for partition in self.partition_snapshot().await {
+            let partition = partition.read().await;
+            summaries.extend(partition.iter().map(|chunk| chunk.summary(partition.key())));
+        }
+        summaries
+    }
 
-        Ok(())
+    /// Returns the estimated size in bytes of all the data in this database
+    pub async fn size(&self) -> usize {
+        self.chunk_summaries().await.iter().map(|c| c.size).sum()
     }
 
-    // return all column names in this database, while applying optional predicates
-    async fn tag_column_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
+    /// As `tag_column_names`, but does not plan any of the chunks in
+    /// `skip_chunks`, identified by (partition key, chunk id). This is used
+    /// when the names in those chunks are found from another copy of their
+    /// data, such as the read buffer.
+    pub async fn tag_column_names_skipping_chunks(
+        &self,
+        predicate: Predicate,
+        skip_chunks: &BTreeSet<(String, u32)>,
+    ) -> Result<StringSetPlan> {
         let has_exprs = predicate.has_exprs();
-        let mut filter = ChunkTableFilter::new(predicate);
+        let mut filter = ChunkTableFilter::new(predicate).skip_chunks(skip_chunks);
 
         if has_exprs {
             let mut visitor = NamePredVisitor::new();
@@ -221,24 +250,45 @@ impl Database for MutableBufferDb {
         }
     }
 
-    /// return all field names in this database, while applying optional
-    /// predicates
-    async fn field_column_names(&self, predicate: Predicate) -> Result<FieldListPlan, Self::Error> {
-        let mut filter = ChunkTableFilter::new(predicate);
+    /// As `field_column_names`, but does not plan any of the chunks in
+    /// `skip_chunks`, identified by (partition key, chunk id). This is used
+    /// when the fields in those chunks are found from another copy of their
+    /// data, such as the read buffer.
+    pub async fn field_column_names_skipping_chunks(
+        &self,
+        predicate: Predicate,
+        skip_chunks: &BTreeSet<(String, u32)>,
+    ) -> Result<FieldListPlan> {
+        let mut filter = ChunkTableFilter::new(predicate).skip_chunks(skip_chunks);
         let mut visitor = TableFieldPredVisitor::new();
         self.accept(&mut filter, &mut visitor).await?;
         Ok(visitor.into_fieldlist_plan())
     }
 
-    /// return all column values in this database, while applying optional
-    /// predicates
-    async fn column_values(
+    /// As `column_values`, but does not plan any of the chunks in
+    /// `skip_chunks`, identified by (partition key, chunk id). This is used
+    /// when the values in those chunks are found from another copy of their
+    /// data, such as the read buffer.
+    pub async fn column_values_skipping_chunks(
         &self,
         column_name: &str,
         predicate: Predicate,
-    ) -> Result<StringSetPlan, Self::Error> {
+        skip_chunks: &BTreeSet<(String, u32)>,
+    ) -> Result<StringSetPlan> {
         let has_exprs = predicate.has_exprs();
-        let mut filter = ChunkTableFilter::new(predicate);
+        let mut filter = ChunkTableFilter::new(predicate).skip_chunks(skip_chunks);
+
+        if column_name == MEASUREMENT_COLUMN_NAME {
+            return if has_exprs {
+                let mut visitor = TableNamePredVisitor::new();
+                self.accept(&mut filter, &mut visitor).await?;
+                Ok(visitor.plans.into())
+            } else {
+                let mut visitor = TableNameVisitor::new();
+                self.accept(&mut filter, &mut visitor).await?;
+                Ok(visitor.table_names.into())
+            };
+        }
 
         if has_exprs {
             let mut visitor = ValuePredVisitor::new(column_name);
@@ -251,19 +301,17 @@ impl Database for MutableBufferDb {
         }
     }
 
-    async fn query_series(&self, predicate: Predicate) -> Result<SeriesSetPlans, Self::Error> {
-        let mut filter = ChunkTableFilter::new(predicate);
-        let mut visitor = SeriesVisitor::new();
-        self.accept(&mut filter, &mut visitor).await?;
-        Ok(visitor.plans.into())
-    }
-
-    async fn query_groups(
+    /// As `query_groups`, but does not plan any of the chunks in
+    /// `skip_chunks`, identified by (partition key, chunk id). This is used
+    /// when those chunks are queried from another copy of their data, such as
+    /// the read buffer.
+    pub async fn query_groups_skipping_chunks(
         &self,
         predicate: Predicate,
         gby_agg: GroupByAndAggregate,
-    ) -> Result<SeriesSetPlans, Self::Error> {
-        let mut filter = ChunkTableFilter::new(predicate);
+        skip_chunks: &BTreeSet<(String, u32)>,
+    ) -> Result<SeriesSetPlans> {
+        let mut filter = ChunkTableFilter::new(predicate).skip_chunks(skip_chunks);
 
         match gby_agg {
             GroupByAndAggregate::Columns { agg, group_columns } => {
@@ -282,6 +330,83 @@ impl Database for MutableBufferDb {
         }
     }
 
+    /// As `query_series`, but does not plan any of the chunks in
+    /// `skip_chunks`, identified by (partition key, chunk id). This is used
+    /// when those chunks are queried from another copy of their data, such as
+    /// the read buffer.
+    pub async fn query_series_skipping_chunks(
+        &self,
+        predicate: Predicate,
+        skip_chunks: &BTreeSet<(String, u32)>,
+    ) -> Result<SeriesSetPlans> {
+        let mut filter = ChunkTableFilter::new(predicate).skip_chunks(skip_chunks);
+        let mut visitor = SeriesVisitor::new();
+        self.accept(&mut filter, &mut visitor).await?;
+        Ok(visitor.plans.into())
+    }
+}
+
+#[async_trait]
+impl Database for MutableBufferDb {
+    type Error = Error;
+    type Chunk = Chunk;
+
+    async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
+        match write.write_buffer_batch() {
+            Some(b) => {
+                let (writer_id, sequence) = write.writer_and_sequence();
+                self.write_entries_to_partitions(&b, writer_id, sequence)
+                    .await?
+            }
+            None => {
+                return MissingPayload {
+                    writer: write.to_fb().writer(),
+                }
+                .fail()
+            }
+        };
+
+        Ok(())
+    }
+
+    // return all column names in this database, while applying optional predicates
+    async fn tag_column_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
+        self.tag_column_names_skipping_chunks(predicate, &BTreeSet::new())
+            .await
+    }
+
+    /// return all field names in this database, while applying optional
+    /// predicates
+    async fn field_column_names(&self, predicate: Predicate) -> Result<FieldListPlan, Self::Error> {
+        self.field_column_names_skipping_chunks(predicate, &BTreeSet::new())
+            .await
+    }
+
+    /// return all column values in this database, while applying optional
+    /// predicates
+    async fn column_values(
+        &self,
+        column_name: &str,
+        predicate: Predicate,
+    ) -> Result<StringSetPlan, Self::Error> {
+        self.column_values_skipping_chunks(column_name, predicate, &BTreeSet::new())
+            .await
+    }
+
+    async fn query_series(&self, predicate: Predicate) -> Result<SeriesSetPlans, Self::Error> {
+        self.query_series_skipping_chunks(predicate, &BTreeSet::new())
+            .await
+    }
+
+    async fn query_groups(
+        &self,
+        predicate: Predicate,
+        gby_agg: GroupByAndAggregate,
+    ) -> Result<SeriesSetPlans, Self::Error> {
+        self.query_groups_skipping_chunks(predicate, gby_agg, &BTreeSet::new())
+            .await
+    }
+
     /// Return the partition keys for data in this DB
     async fn partition_keys(&self) -> Result<Vec<String>, Self::Error> {
         let partitions = self.partitions.read().await;
@@ -437,6 +562,10 @@ impl MutableBufferDb {
 
             if filter.should_visit_partition(&partition)? {
                 for chunk in partition.iter() {
+                    if !filter.should_visit_chunk(&partition, chunk) {
+                        continue;
+                    }
+
                     visitor.pre_visit_chunk(chunk)?;
                     filter.pre_visit_chunk(chunk)?;
 
@@ -486,6 +615,10 @@ struct ChunkTableFilter {
     /// A 'compiled' version of the predicate to evaluate on tables /
     /// columns in a particular chunk during the walk
     chunk_predicate: Option<ChunkPredicate>,
+
+    /// Chunks, identified by (partition key, chunk id), that will not be
+    /// visited
+    skip_chunks: BTreeSet<(String, u32)>,
 }
 
 impl ChunkTableFilter {
@@ -494,9 +627,16 @@ impl ChunkTableFilter {
             predicate,
             additional_required_columns: None,
             chunk_predicate: None,
+            skip_chunks: BTreeSet::new(),
         }
     }
 
+    /// adds the specified chunks to the chunks that will not be visited
+    fn skip_chunks(mut self, chunks: &BTreeSet<(String, u32)>) -> Self {
+        self.skip_chunks.extend(chunks.iter().cloned());
+        self
+    }
+
     /// adds the specified columns to a list of columns that must be
     /// present in a table.
     fn add_required_columns(mut self, column_names: &[String]) -> Self {
@@ -539,6 +679,13 @@ impl ChunkTableFilter {
         Ok(table.could_match_predicate(self.chunk_predicate())?)
     }
 
+    /// If returns false, skips visiting the chunk and all its tables
+    fn should_visit_chunk(&self, partition: &Partition, chunk: &Chunk) -> bool {
+        !self
+            .skip_chunks
+            .contains(&(partition.key().to_string(), chunk.id()))
+    }
+
     /// If returns false, skips visiting partition
     fn should_visit_partition(&mut self, partition: &Partition) -> Result<bool> {
         match &self.predicate.partition_key {
@@ -815,6 +962,68 @@ impl<'a> Visitor for ValuePredVisitor<'a> {
     }
 }
 
+/// return the names of all tables in this database with rows that
+/// could pass the timestamp range
+struct TableNameVisitor {
+    table_names: StringSet,
+}
+
+impl TableNameVisitor {
+    fn new() -> Self {
+        Self {
+            table_names: StringSet::new(),
+        }
+    }
+}
+
+impl Visitor for TableNameVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        chunk: &Chunk,
+        _filter: &mut ChunkTableFilter,
+    ) -> Result<()> {
+        let table_name =
+            chunk
+                .dictionary
+                .lookup_id(table.id)
+                .context(TableIdNotFoundInDictionary {
+                    table_id: table.id,
+                    chunk: chunk.id,
+                })?;
+
+        if !self.table_names.contains(table_name) {
+            self.table_names.insert(table_name.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// return the names of all tables in this database with rows that
+/// pass a general purpose predicate
+struct TableNamePredVisitor {
+    plans: Vec<LogicalPlan>,
+}
+
+impl TableNamePredVisitor {
+    fn new() -> Self {
+        Self { plans: Vec::new() }
+    }
+}
+
+impl Visitor for TableNamePredVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        chunk: &Chunk,
+        filter: &mut ChunkTableFilter,
+    ) -> Result<()> {
+        self.plans
+            .push(table.table_name_values_plan(filter.chunk_predicate(), chunk)?);
+        Ok(())
+    }
+}
+
 /// Return DataFusion plans to calculate which series pass the
 /// specified predicate.
 struct SeriesVisitor {
@@ -953,6 +1162,38 @@ mod tests {
         v.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>()
     }
 
+    #[tokio::test]
+    async fn chunk_summaries() -> Result {
+        let db = MutableBufferDb::new("mydb");
+        assert_eq!(db.size().await, 0);
+
+        let lines: Vec<_> = parse_lines("cpu,region=west user=23.2 10")
+            .map(|l| l.unwrap())
+            .collect();
+        write_lines(&db, &lines).await;
+
+        let partition_key = "1970-01-01T00";
+        let summaries = db.chunk_summaries().await;
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].partition_key, partition_key);
+        assert_eq!(summaries[0].id, 0);
+        assert!(summaries[0].size > 0);
+        assert!(summaries[0].time_of_first_write.is_some());
+        assert!(summaries[0].time_closed.is_none());
+        assert_eq!(db.size().await, summaries[0].size);
+
+        db.rollover_partition(partition_key).await?;
+        let summaries = db.chunk_summaries().await;
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].id, 0);
+        assert!(summaries[0].time_closed.is_some());
+        assert_eq!(summaries[1].id, 1);
+        assert_eq!(summaries[1].size, 0);
+        assert!(summaries[1].time_closed.is_none());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn missing_tags_are_null() -> Result {
         let db = MutableBufferDb::new("mydb");
@@ -1303,6 +1544,46 @@ mod tests {
                     .build(),
                 expected_column_values: Ok(vec![]),
             },
+            TestCase {
+                description: "No predicates, measurement names",
+                column_name: MEASUREMENT_COLUMN_NAME,
+                predicate: PredicateBuilder::default().build(),
+                expected_column_values: Ok(vec!["h2o", "o2"]),
+            },
+            TestCase {
+                description: "Restrictions: timestamp, measurement names",
+                column_name: MEASUREMENT_COLUMN_NAME,
+                predicate: PredicateBuilder::default()
+                    .timestamp_range(250, 301)
+                    .build(),
+                expected_column_values: Ok(vec!["h2o", "o2"]),
+            },
+            TestCase {
+                description: "Restrictions: predicate, measurement names",
+                column_name: MEASUREMENT_COLUMN_NAME,
+                predicate: PredicateBuilder::default()
+                    .add_expr(col("state").eq(lit("NY"))) // state=NY
+                    .build(),
+                expected_column_values: Ok(vec!["o2"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name and predicate, measurement names",
+                column_name: MEASUREMENT_COLUMN_NAME,
+                predicate: PredicateBuilder::default()
+                    .table("h2o")
+                    .add_expr(col("state").eq(lit("MA"))) // state=MA
+                    .build(),
+                expected_column_values: Ok(vec!["h2o"]),
+            },
+            TestCase {
+                description: "Restrictions: timestamp and predicate, measurement names: no match",
+                column_name: MEASUREMENT_COLUMN_NAME,
+                predicate: PredicateBuilder::default()
+                    .timestamp_range(1, 300) // filters out the NY row
+                    .add_expr(col("state").eq(lit("NY"))) // state=NY
+                    .build(),
+                expected_column_values: Ok(vec![]),
+            },
         ];
 
         for test_case in test_cases.into_iter() {
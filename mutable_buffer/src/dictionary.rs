@@ -17,9 +17,11 @@ pub enum Error {
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Debug, Clone)]
-pub struct Dictionary(
-    StringInterner<DefaultSymbol, StringBackend<DefaultSymbol>, DefaultHashBuilder>,
-);
+pub struct Dictionary {
+    interner: StringInterner<DefaultSymbol, StringBackend<DefaultSymbol>, DefaultHashBuilder>,
+    /// The total size in bytes of the interned strings
+    values_size: usize,
+}
 
 impl Default for Dictionary {
     fn default() -> Self {
@@ -29,13 +31,26 @@ impl Default for Dictionary {
 
 impl Dictionary {
     pub fn new() -> Self {
-        Self(StringInterner::new())
+        Self {
+            interner: StringInterner::new(),
+            values_size: 0,
+        }
     }
 
     /// Returns the id corresponding to value, adding an entry for the
     /// id if it is not yet present in the dictionary.
     pub fn lookup_value_or_insert(&mut self, value: &str) -> u32 {
-        symbol_to_u32(self.0.get_or_intern(value))
+        let len = self.interner.len();
+        let symbol = self.interner.get_or_intern(value);
+        if self.interner.len() > len {
+            self.values_size += value.len();
+        }
+        symbol_to_u32(symbol)
+    }
+
+    /// Returns the estimated size in bytes of the dictionary
+    pub fn size(&self) -> usize {
+        self.values_size + self.interner.len() * std::mem::size_of::<DefaultSymbol>()
     }
 
     /// Returns the ID in self.dictionary that corresponds to `value`, if any.
@@ -49,7 +64,7 @@ impl Dictionary {
     /// if any. No error is returned to avoid an allocation when no value is
     /// present
     pub fn id(&self, value: &str) -> Option<u32> {
-        self.0.get(value).map(symbol_to_u32)
+        self.interner.get(value).map(symbol_to_u32)
     }
 
     /// Returns the str in self.dictionary that corresponds to `id`,
@@ -57,7 +72,7 @@ impl Dictionary {
     pub fn lookup_id(&self, id: u32) -> Result<&str> {
         let symbol =
             Symbol::try_from_usize(id as usize).expect("to be able to convert u32 to symbol");
-        self.0
+        self.interner
             .resolve(symbol)
             .context(DictionaryIdLookupError { id })
     }
@@ -108,6 +108,22 @@ impl Partition {
             })
     }
 
+    /// Records that the write with `sequence` from `writer_id` has been
+    /// applied to the open chunk
+    pub fn record_sequence(&mut self, writer_id: u32, sequence: u64) {
+        self.open_chunk.record_sequence(writer_id, sequence)
+    }
+
+    /// Ensures that the open chunk, and any chunks created after it, have ids
+    /// of at least `next_id`. The open chunk is only renumbered if it does
+    /// not yet contain any data.
+    pub fn reserve_chunk_ids(&mut self, next_id: u32) {
+        if self.open_chunk.is_empty() && self.open_chunk.id() < next_id {
+            self.open_chunk = Chunk::new(next_id);
+        }
+        self.id_generator = self.id_generator.max(next_id.saturating_add(1));
+    }
+
     /// Return the list of chunks, in order of id, in this
     /// partition). A Snapshot of the currently active chunk is
     /// returned. The snapshot will not be affected by future inserts
@@ -254,7 +270,10 @@ impl<'a> Iterator for ChunkIter<'a> {
 mod tests {
     use super::*;
     use chrono::Utc;
-    use data_types::{data::split_lines_into_write_entry_partitions, selection::Selection};
+    use data_types::{
+        data::split_lines_into_write_entry_partitions, partition_metadata::SequenceRange,
+        selection::Selection,
+    };
 
     use arrow_deps::{
         arrow::record_batch::RecordBatch, assert_table_eq, test_util::sort_record_batch,
@@ -415,6 +434,35 @@ mod tests {
         assert_eq!(all_ids_with_data(&partition), vec![1, 2]);
     }
 
+    #[tokio::test]
+    async fn test_reserve_chunk_ids() {
+        let mut partition = Partition::new("a_key");
+
+        // An empty open chunk is renumbered
+        partition.reserve_chunk_ids(5);
+        load_data(&mut partition, &["h2o,state=MA,city=Boston temp=70.4 100"]).await;
+        partition.record_sequence(1, 7);
+
+        // Reserving ids does not renumber an open chunk with data
+        partition.reserve_chunk_ids(6);
+        let chunk = partition.rollover_chunk();
+        assert_eq!(chunk.id(), 5);
+        assert_eq!(
+            chunk.writer_sequences.get(&1),
+            Some(&SequenceRange { min: 7, max: 7 })
+        );
+
+        // but subsequent chunks start after the reserved id
+        let chunk = partition.rollover_chunk();
+        assert_eq!(chunk.id(), 7);
+        assert!(chunk.writer_sequences.is_empty());
+
+        // Reserving lower ids has no effect
+        partition.reserve_chunk_ids(2);
+        let chunk = partition.rollover_chunk();
+        assert_eq!(chunk.id(), 8);
+    }
+
     #[tokio::test]
     async fn test_rollover_chunk_drop_data_is_gone() {
         let mut partition = Partition::new("a_key");
@@ -787,7 +835,7 @@ mod tests {
         let stats = chunk.table_stats().unwrap();
         for s in &stats {
             if s.name == table_name {
-                return s.columns[0].count();
+                return s.column("time").unwrap().stats.count();
             }
         }
         0
@@ -17,10 +17,10 @@ use crate::{
     dictionary::{Dictionary, Error as DictionaryError},
 };
 use data_types::{
-    partition_metadata::Column as ColumnStats,
+    partition_metadata::{Column as ColumnStats, ColumnSummary},
     schema::{builder::SchemaBuilder, Schema},
     selection::Selection,
-    TIME_COLUMN_NAME,
+    MEASUREMENT_COLUMN_NAME, TIME_COLUMN_NAME,
 };
 use snafu::{OptionExt, ResultExt, Snafu};
 
@@ -216,6 +216,11 @@ impl Table {
         self.columns.first().map_or(0, |v| v.len())
     }
 
+    /// The estimated size in bytes of the data in this table
+    pub fn size(&self) -> usize {
+        self.columns.iter().map(|c| c.size()).sum()
+    }
+
     /// Returns a reference to the specified column
     fn column(&self, column_id: u32) -> Result<&Column> {
         Ok(self
@@ -383,6 +388,33 @@ impl Table {
             .context(BuildingPlan)
     }
 
+    /// Creates a DataFusion LogicalPlan that returns the name of this table
+    /// once for each row that matches the predicate, as a single column of
+    /// Strings named `MEASUREMENT_COLUMN_NAME`
+    ///
+    /// The created plan looks like:
+    ///
+    ///    Projection
+    ///        Filter(predicate)
+    ///          InMemoryScan
+    pub fn table_name_values_plan(
+        &self,
+        chunk_predicate: &ChunkPredicate,
+        chunk: &Chunk,
+    ) -> Result<LogicalPlan> {
+        // Scan and Filter
+        let plan_builder = self.scan_with_predicates(chunk_predicate, chunk)?;
+
+        let table_name = self.table_name(chunk);
+        let select_exprs = vec![lit(table_name.as_str()).alias(MEASUREMENT_COLUMN_NAME)];
+
+        plan_builder
+            .project(select_exprs)
+            .context(BuildingPlan)?
+            .build()
+            .context(BuildingPlan)
+    }
+
     /// Creates a SeriesSet plan that produces an output table with rows that
     /// match the predicate
     ///
@@ -1097,18 +1129,36 @@ impl Table {
         }
     }
 
-    pub fn stats(&self) -> Vec<ColumnStats> {
-        self.columns
+    /// Returns the name and statistics of each column in this table, in
+    /// order of name
+    pub fn stats(&self, chunk: &Chunk) -> Vec<ColumnSummary> {
+        let mut summaries: Vec<_> = self
+            .column_id_to_index
             .iter()
-            .map(|c| match c {
-                Column::F64(_, stats) => ColumnStats::F64(stats.clone()),
-                Column::I64(_, stats) => ColumnStats::I64(stats.clone()),
-                Column::Bool(_, stats) => ColumnStats::Bool(stats.clone()),
-                Column::String(_, stats) | Column::Tag(_, stats) => {
-                    ColumnStats::String(stats.clone())
+            .map(|(&column_id, &column_index)| {
+                let name = chunk
+                    .dictionary
+                    .lookup_id(column_id)
+                    .expect("Find column name in dictionary");
+
+                let stats = match &self.columns[column_index] {
+                    Column::F64(_, stats) => ColumnStats::F64(stats.clone()),
+                    Column::I64(_, stats) => ColumnStats::I64(stats.clone()),
+                    Column::Bool(_, stats) => ColumnStats::Bool(stats.clone()),
+                    Column::String(_, stats) | Column::Tag(_, stats) => {
+                        ColumnStats::String(stats.clone())
+                    }
+                };
+
+                ColumnSummary {
+                    name: name.to_string(),
+                    stats,
                 }
             })
-            .collect()
+            .collect();
+
+        summaries.sort_by(|a, b| a.name.cmp(&b.name));
+        summaries
     }
 }
 
@@ -1,10 +1,23 @@
 use std::sync::Arc;
 
-use snafu::{ResultExt, Snafu};
+use snafu::{ensure, ResultExt, Snafu};
 
-use crate::{exec::Executor, Database, PartitionChunk};
-use arrow_deps::datafusion::{
-    datasource::MemTable, error::DataFusionError, physical_plan::ExecutionPlan,
+use crate::{
+    exec::Executor,
+    frontend::influxrpc::InfluxRPCPlanner,
+    predicate::{Predicate, TimestampRange},
+    provider::ProviderBuilder,
+    util::make_scan_plan,
+    Database, PartitionChunk,
+};
+use arrow_deps::{
+    arrow::{
+        array::{ArrayRef, StringArray},
+        datatypes::{DataType, Field, Schema as ArrowSchema},
+        record_batch::RecordBatch,
+    },
+    datafusion::{datasource::TableProvider, physical_plan::ExecutionPlan},
+    util::str_iter_to_batch,
 };
 use data_types::selection::Selection;
 
@@ -25,117 +38,504 @@ pub enum Error {
         statement: Box<Statement>,
     },
 
-    #[snafu(display("Internal Error creating memtable for table {}: {}", table, source))]
-    InternalMemTableCreation {
-        table: String,
-        source: DataFusionError,
+    #[snafu(display("Expected a single SQL statement in query {}, found {}", query, count))]
+    NotSingleStatement { query: String, count: usize },
+
+    #[snafu(display("Error listing partitions: {}", source))]
+    ListingPartitions {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Error listing tables: {}", source))]
+    ListingTables {
+        source: Box<dyn std::error::Error + Send + Sync>,
     },
 
-    #[snafu(display("Internal error converting table to arrow {}: {}", table, source))]
-    InternalTableConversion {
+    #[snafu(display("Table {} not found", table))]
+    TableNotFound { table: String },
+
+    #[snafu(display("Internal error getting schema of table {}: {}", table, source))]
+    InternalTableSchema {
         table: String,
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
-    #[snafu(display("No rows found in table {} while executing '{}'", table, query))]
-    InternalNoRowsInTable { table: String, query: String },
+    #[snafu(display(
+        "Internal error creating table provider for table {}: {}",
+        table,
+        source
+    ))]
+    InternalProviderCreation {
+        table: String,
+        source: crate::provider::Error,
+    },
+
+    #[snafu(display("Internal error creating results of {}: {}", statement, source))]
+    InternalShowResults {
+        statement: String,
+        source: arrow_deps::arrow::error::ArrowError,
+    },
+
+    #[snafu(display("Internal error planning {}: {}", statement, source))]
+    InternalShowPlan {
+        statement: String,
+        source: arrow_deps::datafusion::error::DataFusionError,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// This struct can create plans for running SQL queries against databases
 #[derive(Debug, Default)]
-pub struct SQLQueryPlanner {}
+pub struct SQLQueryPlanner {
+    timestamp_range: Option<TimestampRange>,
+}
 
 impl SQLQueryPlanner {
-    /// Plan a SQL query against the data in `database`, and return a
-    /// DataFusion physical execution plan. The plan can then be
-    /// executed using `executor` in a streaming fashion.
+    /// Restricts every table the planned queries read to the rows
+    /// whose time is in `range`
+    pub fn with_timestamp_range(mut self, range: TimestampRange) -> Self {
+        self.timestamp_range = Some(range);
+        self
+    }
+
+    /// Plan a SQL query, which must be a single statement, against the
+    /// data in `database`, and return a DataFusion physical execution
+    /// plan. The plan can then be executed using `executor` in a
+    /// streaming fashion.
     pub async fn query<D: Database>(
         &self,
         database: &D,
         query: &str,
         executor: &Executor,
     ) -> Result<Arc<dyn ExecutionPlan>> {
-        let mut ctx = executor.new_context();
+        let mut plans = self.query_statements(database, query, executor).await?;
+
+        ensure!(
+            plans.len() == 1,
+            NotSingleStatement {
+                query,
+                count: plans.len()
+            }
+        );
+        Ok(plans.pop().expect("one plan"))
+    }
+
+    /// Plan each of the (`;` separated) statements of a SQL query
+    /// against the data in `database`, returning a physical execution
+    /// plan for each statement, in order.
+    ///
+    /// Besides queries, `EXPLAIN`, `SHOW TABLES` and `SHOW COLUMNS
+    /// FROM <table>` statements are supported.
+    pub async fn query_statements<D: Database>(
+        &self,
+        database: &D,
+        query: &str,
+        executor: &Executor,
+    ) -> Result<Vec<Arc<dyn ExecutionPlan>>> {
+        let dialect = GenericDialect {};
+        let statements = Parser::parse_sql(&dialect, query).context(InvalidSqlQuery { query })?;
+        let single_statement = statements.len() == 1;
+
+        let mut plans = Vec::with_capacity(statements.len());
+        for statement in statements {
+            let plan = match &statement {
+                Statement::ShowVariable { variable }
+                    if variable.value.eq_ignore_ascii_case("tables") =>
+                {
+                    self.show_tables(database, executor).await?
+                }
+                Statement::ShowColumns {
+                    table_name,
+                    filter: None,
+                    ..
+                } => {
+                    self.show_columns(database, &table_name.to_string(), executor)
+                        .await?
+                }
+                Statement::Query(_) | Statement::Explain { .. } => {
+                    // DataFusion plans one statement at a time, so
+                    // when there are several each is planned from its
+                    // SQL text
+                    let sql = if single_statement {
+                        query.to_string()
+                    } else {
+                        statement.to_string()
+                    };
+                    self.plan_statement(database, &statement, &sql, executor)
+                        .await?
+                }
+                _ => {
+                    return UnsupportedStatement {
+                        query,
+                        statement: statement.clone(),
+                    }
+                    .fail()
+                }
+            };
+            plans.push(plan);
+        }
 
-        // figure out the table names that appear in the sql
-        let table_names = table_names(query)?;
+        Ok(plans)
+    }
 
-        let partition_keys = database.partition_keys().await.unwrap();
+    /// Plans `statement`, whose SQL text is `sql`, with DataFusion
+    async fn plan_statement<D: Database>(
+        &self,
+        database: &D,
+        statement: &Statement,
+        sql: &str,
+        executor: &Executor,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let mut ctx = executor.new_context();
 
         // Register a table provider for each table so DataFusion
         // knows what the schema of that table is and how to obtain
-        // its data when needed.
-        for table in &table_names {
-            let mut data = Vec::new();
-            for partition_key in &partition_keys {
-                for chunk in database.chunks(partition_key).await {
-                    chunk
-                        .table_to_arrow(&mut data, &table, Selection::All)
-                        .map_err(|e| Box::new(e) as _)
-                        .context(InternalTableConversion { table })?
-                }
+        // its data when needed. The data of each chunk is only read
+        // when the plan is executed.
+        for table in table_names(statement) {
+            let mut builder = table_provider(database, &table).await?;
+            if let Some(range) = self.timestamp_range {
+                builder = builder.timestamp_range(range);
             }
 
-            // TODO: make our own struct that implements
-            // TableProvider, type so we can take advantage of
-            // datafusion predicate and selection pushdown. For now,
-            // use a Memtable provider (which requires materializing
-            // the entire table here)
-
-            // if the table was reported to exist, it should not be empty (eventually we
-            // should get the schema and table data separtely)
-            if data.is_empty() {
-                return InternalNoRowsInTable { table, query }.fail();
+            // tables without any chunks are not registered, so
+            // DataFusion reports them as not found
+            if !builder.is_empty() {
+                ctx.inner_mut()
+                    .register_table(&table, Box::new(builder.build()));
             }
+        }
 
-            let schema = data[0].schema().clone();
-            let provider = Box::new(
-                MemTable::try_new(schema, vec![data])
-                    .context(InternalMemTableCreation { table })?,
-            );
+        ctx.prepare_sql(sql).await.context(Preparing)
+    }
 
-            ctx.inner_mut().register_table(&table, provider);
-        }
+    /// Returns a plan that produces the names of the tables in
+    /// `database`, in a `table_name` column
+    async fn show_tables<D: Database>(
+        &self,
+        database: &D,
+        executor: &Executor,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let plan = InfluxRPCPlanner::new()
+            .table_names(database, Predicate::default())
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(ListingTables)?;
+        let table_names = executor
+            .to_string_set(plan)
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(ListingTables)?;
+
+        let batch = str_iter_to_batch("table_name", table_names.iter().map(Some)).context(
+            InternalShowResults {
+                statement: "SHOW TABLES",
+            },
+        )?;
+        let plan = make_scan_plan(batch).context(InternalShowPlan {
+            statement: "SHOW TABLES",
+        })?;
+
+        executor
+            .new_context()
+            .prepare_plan(&plan)
+            .await
+            .context(Preparing)
+    }
+
+    /// Returns a plan that produces the names and types of the columns
+    /// of `table` in `database`, in `column_name` and `data_type`
+    /// columns
+    async fn show_columns<D: Database>(
+        &self,
+        database: &D,
+        table: &str,
+        executor: &Executor,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let builder = table_provider(database, table).await?;
+        ensure!(!builder.is_empty(), TableNotFound { table });
+        let schema = builder.build().schema();
+
+        let statement = format!("SHOW COLUMNS FROM {}", table);
+        let column_names: StringArray = schema
+            .fields()
+            .iter()
+            .map(|field| Some(field.name().as_str()))
+            .collect();
+        let data_types: StringArray = schema
+            .fields()
+            .iter()
+            .map(|field| Some(format!("{:?}", field.data_type())))
+            .collect();
+        let batch = RecordBatch::try_new(
+            Arc::new(ArrowSchema::new(vec![
+                Field::new("column_name", DataType::Utf8, false),
+                Field::new("data_type", DataType::Utf8, false),
+            ])),
+            vec![
+                Arc::new(column_names) as ArrayRef,
+                Arc::new(data_types) as ArrayRef,
+            ],
+        )
+        .context(InternalShowResults {
+            statement: &statement,
+        })?;
+        let plan = make_scan_plan(batch).context(InternalShowPlan {
+            statement: &statement,
+        })?;
+
+        executor
+            .new_context()
+            .prepare_plan(&plan)
+            .await
+            .context(Preparing)
+    }
+}
+
+/// Returns a builder for a provider of `table`, with every chunk of
+/// `database` that has data for the table added to it
+async fn table_provider<D: Database>(
+    database: &D,
+    table: &str,
+) -> Result<ProviderBuilder<D::Chunk>> {
+    let partition_keys = database
+        .partition_keys()
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(ListingPartitions)?;
+
+    let mut builder = ProviderBuilder::new(table);
+    for partition_key in &partition_keys {
+        for chunk in database.chunks(partition_key).await {
+            if !chunk.has_table(table).await {
+                continue;
+            }
 
-        ctx.prepare_sql(query).await.context(Preparing)
+            let schema = chunk
+                .table_schema(table, Selection::All)
+                .await
+                .map_err(|e| Box::new(e) as _)
+                .context(InternalTableSchema { table })?;
+            builder = builder
+                .add_chunk(chunk, schema.into())
+                .context(InternalProviderCreation { table })?;
+        }
     }
+
+    Ok(builder)
 }
 
 use sqlparser::{
-    ast::{SetExpr, Statement, TableFactor},
+    ast::{Expr, JoinConstraint, JoinOperator, Query, SelectItem, SetExpr, Statement, TableFactor},
     dialect::GenericDialect,
     parser::Parser,
 };
 
-/// return a list of table names that appear in the query
-/// TODO find some way to avoid using sql parser direcly here
-fn table_names(query: &str) -> Result<Vec<String>> {
-    let mut tables = vec![];
+/// Returns the names of the tables referenced anywhere in
+/// `statement` (including joins, subqueries and set operations), each
+/// once. Names that refer to common table expressions (`WITH`
+/// clauses) are not included.
+fn table_names(statement: &Statement) -> Vec<String> {
+    let mut finder = TableNameFinder::default();
+    finder.statement(statement);
+    finder.tables
+}
 
-    let dialect = GenericDialect {};
-    let ast = Parser::parse_sql(&dialect, query).context(InvalidSqlQuery { query })?;
+/// Walks a SQL syntax tree collecting the table names in it
+#[derive(Debug, Default)]
+struct TableNameFinder {
+    tables: Vec<String>,
+    /// The names of the common table expressions in scope
+    ctes: Vec<String>,
+}
 
-    for statement in ast {
+impl TableNameFinder {
+    fn statement(&mut self, statement: &Statement) {
         match statement {
-            Statement::Query(q) => {
-                if let SetExpr::Select(q) = q.body {
-                    for item in q.from {
-                        if let TableFactor::Table { name, .. } = item.relation {
-                            tables.push(name.to_string());
+            Statement::Query(query) => self.query(query),
+            Statement::Explain { statement, .. } => self.statement(statement),
+            _ => {}
+        }
+    }
+
+    fn query(&mut self, query: &Query) {
+        // a common table expression is in scope in the ones after it
+        // and in the body of the query
+        let num_ctes = self.ctes.len();
+        for cte in &query.ctes {
+            self.query(&cte.query);
+            self.ctes.push(cte.alias.name.to_string());
+        }
+
+        self.set_expr(&query.body);
+        for order_by in &query.order_by {
+            self.expr(&order_by.expr);
+        }
+
+        self.ctes.truncate(num_ctes);
+    }
+
+    fn set_expr(&mut self, set_expr: &SetExpr) {
+        match set_expr {
+            SetExpr::Select(select) => {
+                for item in &select.from {
+                    self.table_factor(&item.relation);
+                    for join in &item.joins {
+                        self.table_factor(&join.relation);
+                        match &join.join_operator {
+                            JoinOperator::Inner(JoinConstraint::On(expr))
+                            | JoinOperator::LeftOuter(JoinConstraint::On(expr))
+                            | JoinOperator::RightOuter(JoinConstraint::On(expr))
+                            | JoinOperator::FullOuter(JoinConstraint::On(expr)) => self.expr(expr),
+                            _ => {}
+                        }
+                    }
+                }
+                for item in &select.projection {
+                    match item {
+                        SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                            self.expr(expr)
                         }
+                        _ => {}
+                    }
+                }
+                if let Some(selection) = &select.selection {
+                    self.expr(selection);
+                }
+                for expr in &select.group_by {
+                    self.expr(expr);
+                }
+                if let Some(having) = &select.having {
+                    self.expr(having);
+                }
+            }
+            SetExpr::Query(query) => self.query(query),
+            SetExpr::SetOperation { left, right, .. } => {
+                self.set_expr(left);
+                self.set_expr(right);
+            }
+            SetExpr::Values(values) => {
+                for row in &values.0 {
+                    for expr in row {
+                        self.expr(expr);
                     }
                 }
             }
-            _ => {
-                return UnsupportedStatement {
-                    query: query.to_string(),
-                    statement,
+        }
+    }
+
+    fn table_factor(&mut self, table_factor: &TableFactor) {
+        match table_factor {
+            TableFactor::Table { name, .. } => {
+                let name = name.to_string();
+                if !self.ctes.contains(&name) && !self.tables.contains(&name) {
+                    self.tables.push(name);
+                }
+            }
+            TableFactor::Derived { subquery, .. } => self.query(subquery),
+            TableFactor::NestedJoin(table_with_joins) => {
+                self.table_factor(&table_with_joins.relation);
+                for join in &table_with_joins.joins {
+                    self.table_factor(&join.relation);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Subquery(query) | Expr::Exists(query) => self.query(query),
+            Expr::InSubquery { expr, subquery, .. } => {
+                self.expr(expr);
+                self.query(subquery);
+            }
+            Expr::InList { expr, list, .. } => {
+                self.expr(expr);
+                for expr in list {
+                    self.expr(expr);
+                }
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                self.expr(expr);
+                self.expr(low);
+                self.expr(high);
+            }
+            Expr::BinaryOp { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::UnaryOp { expr, .. }
+            | Expr::Nested(expr)
+            | Expr::Cast { expr, .. }
+            | Expr::IsNull(expr)
+            | Expr::IsNotNull(expr)
+            | Expr::Extract { expr, .. }
+            | Expr::Collate { expr, .. } => self.expr(expr),
+            Expr::Function(function) => {
+                for arg in &function.args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Case {
+                operand,
+                conditions,
+                results,
+                else_result,
+            } => {
+                for expr in operand.iter().chain(else_result) {
+                    self.expr(expr);
+                }
+                for expr in conditions.iter().chain(results) {
+                    self.expr(expr);
                 }
-                .fail()
             }
+            _ => {}
         }
     }
-    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(sql: &str) -> Vec<String> {
+        let dialect = GenericDialect {};
+        let statements = Parser::parse_sql(&dialect, sql).unwrap();
+        assert_eq!(statements.len(), 1);
+        table_names(&statements[0])
+    }
+
+    #[test]
+    fn test_table_names() {
+        assert_eq!(names("SELECT * FROM cpu"), vec!["cpu"]);
+        assert_eq!(
+            names("SELECT * FROM cpu JOIN mem ON cpu.host = mem.host JOIN cpu AS c2 ON true"),
+            vec!["cpu", "mem"]
+        );
+        assert_eq!(
+            names("SELECT * FROM (SELECT * FROM cpu) AS c, (disk CROSS JOIN net)"),
+            vec!["cpu", "disk", "net"]
+        );
+        assert_eq!(
+            names("SELECT * FROM cpu WHERE host IN (SELECT host FROM mem) OR EXISTS (SELECT 1 FROM disk)"),
+            vec!["cpu", "mem", "disk"]
+        );
+        assert_eq!(
+            names("SELECT host FROM cpu UNION ALL SELECT host FROM mem"),
+            vec!["cpu", "mem"]
+        );
+        assert_eq!(
+            names("WITH c AS (SELECT * FROM cpu), d AS (SELECT * FROM c) SELECT * FROM d, mem"),
+            vec!["cpu", "mem"]
+        );
+        assert_eq!(
+            names("SELECT (SELECT max(usage) FROM mem) AS m FROM cpu"),
+            vec!["cpu", "mem"]
+        );
+        assert_eq!(names("EXPLAIN SELECT * FROM cpu"), vec!["cpu"]);
+        assert_eq!(names("SHOW TABLES"), Vec::<String>::new());
+    }
 }
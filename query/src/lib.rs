@@ -5,7 +5,13 @@
     clippy::use_self
 )]
 
-use arrow_deps::{arrow::record_batch::RecordBatch, datafusion::logical_plan::LogicalPlan};
+use arrow_deps::{
+    arrow::record_batch::RecordBatch,
+    datafusion::{
+        logical_plan::LogicalPlan,
+        physical_plan::{common::SizedRecordBatchStream, SendableRecordBatchStream},
+    },
+};
 use async_trait::async_trait;
 use data_types::{
     data::ReplicatedWrite, partition_metadata::Table as TableStats, schema::Schema,
@@ -20,6 +26,7 @@ pub mod frontend;
 pub mod func;
 pub mod group_by;
 pub mod predicate;
+pub mod provider;
 pub mod util;
 
 use self::{group_by::GroupByAndAggregate, predicate::Predicate};
@@ -35,7 +42,7 @@ use self::{group_by::GroupByAndAggregate, predicate::Predicate};
 #[async_trait]
 pub trait Database: Debug + Send + Sync {
     type Error: std::error::Error + Send + Sync + 'static;
-    type Chunk: PartitionChunk;
+    type Chunk: PartitionChunk + 'static;
 
     /// Stores the replicated write into the database.
     async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error>;
@@ -67,6 +74,10 @@ pub trait Database: Debug + Send + Sync {
     /// Returns a plan which finds the distinct values in the
     /// `column_name` column of this database which pass the
     /// conditions specified by `predicate`.
+    ///
+    /// If `column_name` is `MEASUREMENT_COLUMN_NAME`, the plan finds
+    /// the names of the tables with at least one row which passes
+    /// `predicate`.
     async fn column_values(
         &self,
         column_name: &str,
@@ -149,6 +160,28 @@ pub trait PartitionChunk: Debug + Send + Sync {
         table_name: &str,
         selection: Selection<'_>,
     ) -> Result<(), Self::Error>;
+
+    /// Returns a stream of the data in a table in this chunk, with the
+    /// specified column selection. Unlike `table_to_arrow`, this can be
+    /// used with chunks whose data must be fetched before it is read.
+    ///
+    /// The default implementation uses `table_to_arrow`.
+    async fn read_table(
+        &self,
+        table_name: &str,
+        selection: Selection<'_>,
+    ) -> Result<SendableRecordBatchStream, Self::Error> {
+        let schema = self.table_schema(table_name, selection.clone()).await?;
+
+        let mut batches = Vec::new();
+        self.table_to_arrow(&mut batches, table_name, selection)?;
+        let batches = batches.into_iter().map(Arc::new).collect();
+
+        Ok(Box::pin(SizedRecordBatchStream::new(
+            schema.into(),
+            batches,
+        )))
+    }
 }
 
 #[async_trait]
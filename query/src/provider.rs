@@ -0,0 +1,642 @@
+//! This module contains an implementation of a DataFusion
+//! `TableProvider` that reads a table from the chunks of a
+//! `Database`, so queries only read the chunks (and the columns) they
+//! need, as they need them.
+
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use arrow_deps::{
+    arrow::{
+        array::{new_null_array, Array, BooleanArray, Int64Array},
+        compute::filter_record_batch,
+        datatypes::{DataType, Field, Schema as ArrowSchema, SchemaRef},
+        error::{ArrowError, Result as ArrowResult},
+        record_batch::RecordBatch,
+    },
+    datafusion::{
+        datasource::{
+            datasource::{Statistics, TableProviderFilterPushDown},
+            TableProvider,
+        },
+        error::{DataFusionError, Result as DataFusionResult},
+        logical_plan::{Expr, Operator},
+        physical_plan::{
+            Distribution, ExecutionPlan, Partitioning, RecordBatchStream, SendableRecordBatchStream,
+        },
+        scalar::ScalarValue,
+    },
+};
+use async_trait::async_trait;
+use data_types::{selection::Selection, TIME_COLUMN_NAME};
+use futures::{Stream, StreamExt};
+use snafu::{ensure, Snafu};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    predicate::{Predicate, PredicateBuilder, TimestampRange},
+    PartitionChunk,
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Column {} of table {} has type {:?} in one chunk and {:?} in another",
+        column_name,
+        table_name,
+        existing_type,
+        new_type
+    ))]
+    IncompatibleColumnTypes {
+        table_name: String,
+        column_name: String,
+        existing_type: DataType,
+        new_type: DataType,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A chunk that has data for the table, along with the schema of
+/// that data
+#[derive(Debug)]
+struct ChunkInfo<C: PartitionChunk + 'static> {
+    chunk: Arc<C>,
+    schema: SchemaRef,
+}
+
+// Implemented by hand, as deriving would require `C: Clone`
+impl<C: PartitionChunk + 'static> Clone for ChunkInfo<C> {
+    fn clone(&self) -> Self {
+        Self {
+            chunk: Arc::clone(&self.chunk),
+            schema: Arc::clone(&self.schema),
+        }
+    }
+}
+
+/// Builds a `ChunkTableProvider` for a table from the chunks that
+/// have data for it. The schema of the table is the union of the
+/// schemas of the table in each chunk, with the columns in
+/// lexicographic order (the order of `Selection::All`).
+#[derive(Debug)]
+pub struct ProviderBuilder<C: PartitionChunk + 'static> {
+    table_name: Arc<String>,
+    fields: BTreeMap<String, Field>,
+    metadata: HashMap<String, String>,
+    chunks: Vec<ChunkInfo<C>>,
+    timestamp_range: Option<TimestampRange>,
+}
+
+impl<C: PartitionChunk + 'static> ProviderBuilder<C> {
+    pub fn new(table_name: impl Into<String>) -> Self {
+        Self {
+            table_name: Arc::new(table_name.into()),
+            fields: BTreeMap::new(),
+            metadata: HashMap::new(),
+            chunks: Vec::new(),
+            timestamp_range: None,
+        }
+    }
+
+    /// Restricts the table to the rows whose time is in `range`, as
+    /// if every scan of it were filtered on that range
+    pub fn timestamp_range(mut self, range: TimestampRange) -> Self {
+        self.timestamp_range = Some(range);
+        self
+    }
+
+    /// Adds `chunk`, whose data for the table has the schema `schema`,
+    /// to the provider. Returns an error if a column in `schema` has a
+    /// different type than the same column in a chunk added earlier.
+    pub fn add_chunk(mut self, chunk: Arc<C>, schema: SchemaRef) -> Result<Self> {
+        for field in schema.fields() {
+            match self.fields.get(field.name()) {
+                Some(existing) => {
+                    ensure!(
+                        existing.data_type() == field.data_type(),
+                        IncompatibleColumnTypes {
+                            table_name: self.table_name.as_str(),
+                            column_name: field.name(),
+                            existing_type: existing.data_type().clone(),
+                            new_type: field.data_type().clone(),
+                        }
+                    );
+                    if field.is_nullable() && !existing.is_nullable() {
+                        self.fields
+                            .insert(field.name().clone(), nullable_field(field));
+                    }
+                }
+                None => {
+                    self.fields.insert(field.name().clone(), field.clone());
+                }
+            }
+        }
+        self.metadata.extend(
+            schema
+                .metadata()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+
+        self.chunks.push(ChunkInfo { chunk, schema });
+        Ok(self)
+    }
+
+    /// Returns true if no chunks have been added
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Creates the provider, consuming this builder
+    pub fn build(self) -> ChunkTableProvider<C> {
+        let Self {
+            table_name,
+            fields,
+            metadata,
+            chunks,
+            timestamp_range,
+        } = self;
+
+        // columns that are missing from some chunks are null in the
+        // rows of those chunks
+        let fields = fields
+            .into_iter()
+            .map(|(name, field)| {
+                let in_all_chunks = chunks
+                    .iter()
+                    .all(|info| info.schema.column_with_name(&name).is_some());
+                if in_all_chunks {
+                    field
+                } else {
+                    nullable_field(&field)
+                }
+            })
+            .collect();
+
+        ChunkTableProvider {
+            table_name,
+            schema: Arc::new(ArrowSchema::new_with_metadata(fields, metadata)),
+            chunks,
+            timestamp_range,
+        }
+    }
+}
+
+fn nullable_field(field: &Field) -> Field {
+    Field::new(field.name(), field.data_type().clone(), true)
+}
+
+/// A DataFusion `TableProvider` for a table stored in `PartitionChunk`s.
+///
+/// Filters are pushed down to the provider to prune the chunks that
+/// can't have matching rows (using `PartitionChunk::might_pass_predicate`),
+/// and only the projected columns of the remaining chunks are read when the
+/// plan is executed. The chunks are read one after another into a single
+/// DataFusion partition (see `IOxReadFilterNode`).
+#[derive(Debug)]
+pub struct ChunkTableProvider<C: PartitionChunk + 'static> {
+    table_name: Arc<String>,
+    schema: SchemaRef,
+    chunks: Vec<ChunkInfo<C>>,
+    timestamp_range: Option<TimestampRange>,
+}
+
+impl<C: PartitionChunk + 'static> ChunkTableProvider<C> {
+    /// Returns the predicate used to prune the chunks that can't have
+    /// any rows that pass all of `filters`
+    fn pruning_predicate(&self, filters: &[Expr]) -> Predicate {
+        let mut range = self
+            .timestamp_range
+            .unwrap_or_else(|| TimestampRange::new(i64::MIN, i64::MAX));
+        let mut builder = PredicateBuilder::default().table(self.table_name.as_str());
+        for filter in filters {
+            narrow_time_range(filter, &mut range);
+            builder = builder.add_expr(filter.clone());
+        }
+
+        if range.start != i64::MIN || range.end != i64::MAX {
+            builder = builder.timestamp_range(range.start, range.end);
+        }
+        builder.build()
+    }
+}
+
+impl<C: PartitionChunk + 'static> TableProvider for ChunkTableProvider<C> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+        filters: &[Expr],
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let schema = match projection {
+            Some(indices) => {
+                let fields = indices
+                    .iter()
+                    .map(|&i| self.schema.field(i).clone())
+                    .collect();
+                Arc::new(ArrowSchema::new_with_metadata(
+                    fields,
+                    self.schema.metadata().clone(),
+                ))
+            }
+            None => Arc::clone(&self.schema),
+        };
+
+        let predicate = self.pruning_predicate(filters);
+        let chunks = self
+            .chunks
+            .iter()
+            .filter(|info| info.chunk.might_pass_predicate(&predicate))
+            .cloned()
+            .collect();
+
+        Ok(Arc::new(IOxReadFilterNode {
+            table_name: Arc::clone(&self.table_name),
+            schema,
+            chunks,
+            timestamp_range: self.timestamp_range,
+        }))
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics::default()
+    }
+
+    fn supports_filter_pushdown(
+        &self,
+        _filter: &Expr,
+    ) -> DataFusionResult<TableProviderFilterPushDown> {
+        // the filters are only used to skip chunks, so DataFusion must
+        // still apply them to the rows of the chunks that are read
+        Ok(TableProviderFilterPushDown::Inexact)
+    }
+}
+
+/// Narrows `range` to the values of the time column that can pass
+/// `expr`, for comparisons of the time column with integer literals
+/// (possibly combined with `AND`)
+fn narrow_time_range(expr: &Expr, range: &mut TimestampRange) {
+    if let Expr::BinaryExpr { left, op, right } = expr {
+        match (left.as_ref(), op, right.as_ref()) {
+            (left, Operator::And, right) => {
+                narrow_time_range(left, range);
+                narrow_time_range(right, range);
+            }
+            (Expr::Column(name), op, Expr::Literal(ScalarValue::Int64(Some(value))))
+                if name == TIME_COLUMN_NAME =>
+            {
+                narrow_time_range_by(op, *value, range)
+            }
+            // `value op time` is the same as `time op' value` with op
+            // reversed
+            (Expr::Literal(ScalarValue::Int64(Some(value))), op, Expr::Column(name))
+                if name == TIME_COLUMN_NAME =>
+            {
+                let op = match op {
+                    Operator::Lt => Operator::Gt,
+                    Operator::LtEq => Operator::GtEq,
+                    Operator::Gt => Operator::Lt,
+                    Operator::GtEq => Operator::LtEq,
+                    Operator::Eq => Operator::Eq,
+                    _ => return,
+                };
+                narrow_time_range_by(&op, *value, range)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Narrows `range` to the times that pass `time op value`
+fn narrow_time_range_by(op: &Operator, value: i64, range: &mut TimestampRange) {
+    let (start, end) = match op {
+        Operator::Eq => (value, value.saturating_add(1)),
+        Operator::Gt => (value.saturating_add(1), i64::MAX),
+        Operator::GtEq => (value, i64::MAX),
+        Operator::Lt => (i64::MIN, value),
+        Operator::LtEq => (i64::MIN, value.saturating_add(1)),
+        _ => return,
+    };
+    range.start = range.start.max(start);
+    range.end = range.end.min(end);
+}
+
+/// A DataFusion `ExecutionPlan` that reads the columns of `schema`
+/// from the table in each of `chunks`.
+///
+/// The chunks are read one after another, in order, into a single
+/// output partition. A chunk is only read once the rows of the
+/// previous chunks have been consumed. If there is a `timestamp_range`,
+/// only the rows whose time is in it are produced.
+#[derive(Debug)]
+pub struct IOxReadFilterNode<C: PartitionChunk + 'static> {
+    table_name: Arc<String>,
+    schema: SchemaRef,
+    chunks: Vec<ChunkInfo<C>>,
+    timestamp_range: Option<TimestampRange>,
+}
+
+// Implemented by hand, as deriving would require `C: Clone`
+impl<C: PartitionChunk + 'static> Clone for IOxReadFilterNode<C> {
+    fn clone(&self) -> Self {
+        Self {
+            table_name: Arc::clone(&self.table_name),
+            schema: Arc::clone(&self.schema),
+            chunks: self.chunks.clone(),
+            timestamp_range: self.timestamp_range,
+        }
+    }
+}
+
+impl<C: PartitionChunk + 'static> IOxReadFilterNode<C> {
+    /// Sends the rows of each chunk, converted to `schema`, to `tx`
+    async fn read_chunks(self, tx: mpsc::Sender<ArrowResult<RecordBatch>>) {
+        for info in &self.chunks {
+            let mut columns: Vec<_> = self
+                .schema
+                .fields()
+                .iter()
+                .map(|field| field.name().as_str())
+                .filter(|name| info.schema.column_with_name(name).is_some())
+                .collect();
+            let has_time = info.schema.column_with_name(TIME_COLUMN_NAME).is_some();
+            if self.timestamp_range.is_some() {
+                // none of the rows of a chunk without times are in range
+                if !has_time {
+                    continue;
+                }
+                // the time column is needed to filter the rows
+                if !columns.contains(&TIME_COLUMN_NAME) {
+                    columns.push(TIME_COLUMN_NAME);
+                }
+            }
+            // Read the time column from chunks without any of the columns,
+            // so their rows are still returned (with all columns null)
+            if columns.is_empty() && has_time {
+                columns.push(TIME_COLUMN_NAME);
+            }
+
+            let mut input = match info
+                .chunk
+                .read_table(&self.table_name, Selection::Some(&columns))
+                .await
+            {
+                Ok(input) => input,
+                Err(e) => {
+                    let e = DataFusionError::Execution(format!(
+                        "Error reading table {} from chunk {}: {}",
+                        self.table_name,
+                        info.chunk.id(),
+                        e
+                    ));
+                    // the receiver may have been dropped, in which case
+                    // nobody is interested in the error
+                    tx.send(Err(ArrowError::ExternalError(Box::new(e))))
+                        .await
+                        .ok();
+                    return;
+                }
+            };
+
+            while let Some(batch) = input.next().await {
+                let batch = batch
+                    .and_then(|batch| match &self.timestamp_range {
+                        Some(range) => filter_timestamp_range(batch, range),
+                        None => Ok(batch),
+                    })
+                    .and_then(|batch| adapt_batch(&self.schema, batch));
+                if tx.send(batch).await.is_err() {
+                    // the output stream was dropped
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<C: PartitionChunk + 'static> ExecutionPlan for IOxReadFilterNode<C> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn required_child_distribution(&self) -> Distribution {
+        Distribution::UnspecifiedDistribution
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        if children.is_empty() {
+            Ok(Arc::new(self.clone()))
+        } else {
+            Err(DataFusionError::Internal(format!(
+                "IOxReadFilterNode has no children, got {}",
+                children.len()
+            )))
+        }
+    }
+
+    async fn execute(&self, partition: usize) -> DataFusionResult<SendableRecordBatchStream> {
+        if 0 != partition {
+            return Err(DataFusionError::Internal(format!(
+                "IOxReadFilterNode invalid partition {}",
+                partition
+            )));
+        }
+
+        // A capacity of 1 means at most one batch is read ahead of
+        // the consumer of the stream
+        let (tx, rx) = mpsc::channel(1);
+        let reader = tokio::task::spawn(self.clone().read_chunks(tx.clone()));
+
+        // if reading panics the stream ends with an error, rather than
+        // early as if every row had been read
+        tokio::task::spawn(async move {
+            if let Err(e) = reader.await {
+                let e = DataFusionError::Execution(format!("Error reading chunks: {}", e));
+                tx.send(Err(ArrowError::ExternalError(Box::new(e))))
+                    .await
+                    .ok();
+            }
+        });
+
+        Ok(Box::pin(ChunkBatchStream {
+            schema: Arc::clone(&self.schema),
+            input: ReceiverStream::new(rx),
+        }))
+    }
+}
+
+/// Returns the rows of `batch` whose time is in `range`
+fn filter_timestamp_range(batch: RecordBatch, range: &TimestampRange) -> ArrowResult<RecordBatch> {
+    let time_index = match batch.schema().column_with_name(TIME_COLUMN_NAME) {
+        Some((i, _)) => i,
+        None => return Ok(batch),
+    };
+    let times = batch
+        .column(time_index)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .ok_or_else(|| {
+            ArrowError::ComputeError(format!(
+                "Expected the {} column to be Int64",
+                TIME_COLUMN_NAME
+            ))
+        })?;
+
+    let in_range: Vec<bool> = (0..times.len())
+        .map(|i| !times.is_null(i) && range.contains(times.value(i)))
+        .collect();
+    filter_record_batch(&batch, &BooleanArray::from(in_range))
+}
+
+/// Converts `batch` to `schema`, reordering its columns by name and
+/// filling in the missing ones with nulls
+fn adapt_batch(schema: &SchemaRef, batch: RecordBatch) -> ArrowResult<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(
+            |field| match batch.schema().column_with_name(field.name()) {
+                Some((i, _)) => Arc::clone(batch.column(i)),
+                None => new_null_array(field.data_type(), batch.num_rows()),
+            },
+        )
+        .collect();
+
+    RecordBatch::try_new(Arc::clone(schema), columns)
+}
+
+/// The stream of the batches read from the chunks by an
+/// `IOxReadFilterNode`
+#[derive(Debug)]
+struct ChunkBatchStream {
+    schema: SchemaRef,
+    input: ReceiverStream<ArrowResult<RecordBatch>>,
+}
+
+impl Stream for ChunkBatchStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.input.poll_next_unpin(cx)
+    }
+}
+
+impl RecordBatchStream for ChunkBatchStream {
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestChunk;
+    use arrow_deps::datafusion::physical_plan::common::collect;
+
+    fn time_range(filter: Expr) -> TimestampRange {
+        let mut range = TimestampRange::new(i64::MIN, i64::MAX);
+        narrow_time_range(&filter, &mut range);
+        range
+    }
+
+    fn time() -> Expr {
+        Expr::Column(TIME_COLUMN_NAME.to_string())
+    }
+
+    fn lit(value: i64) -> Expr {
+        Expr::Literal(ScalarValue::Int64(Some(value)))
+    }
+
+    #[test]
+    fn test_narrow_time_range() {
+        assert_eq!(
+            time_range(time().gt(lit(10))),
+            TimestampRange::new(11, i64::MAX)
+        );
+        assert_eq!(
+            time_range(time().gt_eq(lit(10))),
+            TimestampRange::new(10, i64::MAX)
+        );
+        assert_eq!(
+            time_range(time().lt(lit(10))),
+            TimestampRange::new(i64::MIN, 10)
+        );
+        assert_eq!(
+            time_range(time().lt_eq(lit(10))),
+            TimestampRange::new(i64::MIN, 11)
+        );
+        assert_eq!(time_range(time().eq(lit(10))), TimestampRange::new(10, 11));
+        assert_eq!(
+            time_range(lit(10).lt(time())),
+            TimestampRange::new(11, i64::MAX)
+        );
+        assert_eq!(
+            time_range(time().gt_eq(lit(10)).and(time().lt(lit(20)))),
+            TimestampRange::new(10, 20)
+        );
+
+        // comparisons of other columns, or with other types of values,
+        // don't narrow the range
+        assert_eq!(
+            time_range(Expr::Column("foo".to_string()).gt(lit(10))),
+            TimestampRange::new(i64::MIN, i64::MAX)
+        );
+        assert_eq!(
+            time_range(time().gt(Expr::Literal(ScalarValue::Float64(Some(10.0))))),
+            TimestampRange::new(i64::MIN, i64::MAX)
+        );
+        assert_eq!(
+            time_range(time().gt(lit(10)).or(time().lt(lit(5)))),
+            TimestampRange::new(i64::MIN, i64::MAX)
+        );
+    }
+
+    #[tokio::test]
+    async fn reader_panics_end_the_stream_with_an_error() {
+        let schema = Arc::new(ArrowSchema::new(vec![Field::new(
+            TIME_COLUMN_NAME,
+            DataType::Int64,
+            false,
+        )]));
+        // reading a `TestChunk` panics
+        let provider = ProviderBuilder::new("cpu")
+            .add_chunk(Arc::new(TestChunk::new(0)), schema)
+            .unwrap()
+            .build();
+
+        let plan = provider.scan(&None, 1024, &[]).unwrap();
+        let stream = plan.execute(0).await.unwrap();
+        assert!(collect(stream).await.is_err());
+    }
+}
@@ -593,7 +593,8 @@ impl TestLPWriter {
             ..Default::default()
         };
 
-        let write = lines_to_replicated_write(self.writer_id, self.sequence_number, &lines, &rules);
+        let write = lines_to_replicated_write(self.writer_id, self.sequence_number, &lines, &rules)
+            .expect("computing partition keys");
         self.sequence_number += 1;
         database
             .store_replicated_write(&write)
@@ -4,13 +4,13 @@ use std::{
 };
 
 use data_types::selection::Selection;
-use snafu::Snafu;
+use snafu::{ResultExt, Snafu};
 
 use crate::row_group::RowGroup;
 use crate::row_group::{ColumnName, Predicate};
-use crate::schema::AggregateType;
+use crate::schema::{AggregateType, ResultSchema};
 use crate::table;
-use crate::table::Table;
+use crate::table::{ColumnSize, Table};
 
 type TableName = String;
 
@@ -18,6 +18,12 @@ type TableName = String;
 pub enum Error {
     #[snafu(display("table '{}' does not exist", table_name))]
     TableNotFound { table_name: String },
+
+    #[snafu(display("error processing table '{}': {}", table_name, source))]
+    TableError {
+        table_name: String,
+        source: table::Error,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -90,6 +96,18 @@ impl Chunk {
         self.chunk_data.read().unwrap().size
     }
 
+    /// The size of each column of each table in this chunk, along with the
+    /// encodings used to store the column.
+    pub fn column_sizes(&self) -> BTreeMap<String, BTreeMap<String, ColumnSize>> {
+        self.chunk_data
+            .read()
+            .unwrap()
+            .data
+            .iter()
+            .map(|(name, table)| (name.clone(), table.column_sizes()))
+            .collect()
+    }
+
     /// The total number of rows in all row groups in all tables in this chunk.
     pub fn rows(&self) -> u64 {
         self.chunk_data.read().unwrap().rows
@@ -159,6 +177,26 @@ impl Chunk {
         }
     }
 
+    /// Returns the schema of the specified columns of the provided table.
+    /// Returns an error if the specified table does not exist.
+    pub fn table_schema(
+        &self,
+        table_name: &str,
+        columns: &Selection<'_>,
+    ) -> Result<ResultSchema, Error> {
+        // read lock on chunk.
+        let chunk_data = self.chunk_data.read().unwrap();
+
+        let table = chunk_data
+            .data
+            .get(table_name)
+            .ok_or(Error::TableNotFound {
+                table_name: table_name.to_owned(),
+            })?;
+
+        Ok(table.schema(columns))
+    }
+
     /// Returns an iterator of lazily executed `read_filter` operations on the
     /// provided table for the specified column selections.
     ///
@@ -210,6 +248,36 @@ impl Chunk {
             .map(|table| table.read_aggregate(predicate, group_columns, aggregates))
     }
 
+    /// Returns an iterable collection of windowed aggregates, grouped by the
+    /// provided group columns and fixed-duration windows of time. Results are
+    /// merged across all row groups within the returned table.
+    ///
+    /// Returns `None` if the table no longer exists within the chunk, and an
+    /// error if any of the group columns is not a tag column.
+    pub fn read_window_aggregate(
+        &self,
+        table_name: &str,
+        predicate: Predicate,
+        group_columns: &Selection<'_>,
+        aggregates: &[(ColumnName<'_>, AggregateType)],
+        every: i64,
+        offset: i64,
+    ) -> Result<Option<table::ReadWindowAggregateResults>> {
+        // read lock on chunk.
+        let chunk_data = self.chunk_data.read().unwrap();
+
+        // Lookup table by name and dispatch execution.
+        chunk_data
+            .data
+            .get(table_name)
+            .map(|table| {
+                table
+                    .read_window_aggregate(predicate, group_columns, aggregates, every, offset)
+                    .context(TableError { table_name })
+            })
+            .transpose()
+    }
+
     //
     // ---- Schema API queries
     //
@@ -272,25 +340,30 @@ impl Chunk {
     }
 
     /// Returns the distinct set of tag values (column values) for each provided
-    /// tag key, where each returned value lives in a row matching the provided
-    /// optional predicates and time range.
+    /// tag column in the table, where each returned value lives in a row
+    /// matching the provided predicate. Values are added to `dst`, which
+    /// may already contain values found in other chunks.
+    ///
+    /// As a special case, if `columns` is `Selection::All` then all distinct
+    /// values for all tag columns are returned for the table.
     ///
-    /// As a special case, if `tag_keys` is empty then all distinct values for
-    /// all columns (tag keys) are returned for the chunk.
-    pub fn tag_values(
+    /// `dst` is returned unchanged if the table doesn't exist within the
+    /// chunk.
+    pub fn column_values(
         &self,
-        table_name: String,
-        predicate: Predicate,
-        tag_keys: &[ColumnName<'_>],
-        found_tag_values: &BTreeMap<ColumnName<'_>, BTreeSet<&String>>,
-    ) -> BTreeMap<ColumnName<'_>, BTreeSet<&String>> {
-        // Lookup table by name and dispatch execution to the table for that
-        // if the chunk's time range overlaps the requested time range.
-        //
-        // This method also has the ability to short-circuit execution against
-        // columns if those columns only contain values that have already been
-        // found.
-        todo!();
+        table_name: &str,
+        predicate: &Predicate,
+        columns: &Selection<'_>,
+        dst: BTreeMap<String, BTreeSet<String>>,
+    ) -> BTreeMap<String, BTreeSet<String>> {
+        // read lock on chunk.
+        let chunk_data = self.chunk_data.read().unwrap();
+
+        // Lookup table by name and dispatch execution.
+        match chunk_data.data.get(table_name) {
+            Some(table) => table.column_values(predicate, columns, dst),
+            None => dst,
+        }
     }
 }
 
@@ -1,3 +1,4 @@
+pub mod binary;
 pub mod bool;
 pub mod cmp;
 pub mod dictionary;
@@ -16,16 +17,6 @@ use arrow_deps::{arrow, arrow::array::Array};
 
 use crate::schema::{AggregateType, LogicalDataType};
 
-// Edd's totally made up magic constant. This determines whether we would use
-// a run-length encoded dictionary encoding or just a plain dictionary encoding.
-// I have ideas about how to build heuristics to do this in a much better way
-// than an arbitrary constant but for now it's this...
-//
-// FWIW it's not the cardinality of the column that should drive the decision
-// it's how many run-lengths would be produced in an RLE column and whether that
-// compression is worth the memory and compute costs to work on it.
-pub const TEMP_CARDINALITY_DICTIONARY_ENCODING_LIMIT: usize = 100_000;
-
 /// The possible logical types that column values can have. All values in a
 /// column have the same physical type.
 pub enum Column {
@@ -46,8 +37,8 @@ pub enum Column {
     // A column of boolean values.
     Bool(MetaData<bool>, BooleanEncoding),
 
-    // These are TODO
-    ByteArray(MetaData<Vec<u8>>, StringEncoding), // TODO - arbitrary bytes
+    // A column of arbitrary byte arrays.
+    ByteArray(MetaData<Vec<u8>>, ByteArrayEncoding),
 }
 
 impl Column {
@@ -77,8 +68,28 @@ impl Column {
         }
     }
 
+    /// The estimated size in bytes of the column's encoded data.
     pub fn size(&self) -> u64 {
-        0
+        match self {
+            Column::String(meta, _) => meta.size,
+            Column::Float(meta, _) => meta.size,
+            Column::Integer(meta, _) => meta.size,
+            Column::Unsigned(meta, _) => meta.size,
+            Column::Bool(meta, _) => meta.size,
+            Column::ByteArray(meta, _) => meta.size,
+        }
+    }
+
+    /// The name of the physical encoding used to store the column's values.
+    pub fn encoding_name(&self) -> &'static str {
+        match self {
+            Column::String(_, data) => data.debug_name(),
+            Column::Float(_, data) => data.debug_name(),
+            Column::Integer(_, data) => data.debug_name(),
+            Column::Unsigned(_, data) => data.debug_name(),
+            Column::Bool(_, data) => data.debug_name(),
+            Column::ByteArray(_, data) => data.debug_name(),
+        }
     }
 
     /// Returns the (min, max)  values stored in this column
@@ -116,7 +127,38 @@ impl Column {
                 Some(range) => Some((OwnedValue::Boolean(range.0), OwnedValue::Boolean(range.1))),
                 None => None,
             },
-            Column::ByteArray(_, _) => todo!(),
+            Column::ByteArray(meta, _) => match &meta.range {
+                Some(range) => Some((
+                    OwnedValue::ByteArray(range.0.clone()),
+                    OwnedValue::ByteArray(range.1.clone()),
+                )),
+                None => None,
+            },
+        }
+    }
+
+    /// Determines if the column contains any NULL values.
+    pub fn contains_null(&self) -> bool {
+        match &self {
+            Column::String(_, data) => data.contains_null(),
+            Column::Float(_, data) => data.contains_null(),
+            Column::Integer(_, data) => data.contains_null(),
+            Column::Unsigned(_, data) => data.contains_null(),
+            Column::Bool(_, data) => data.contains_null(),
+            Column::ByteArray(_, data) => data.contains_null(),
+        }
+    }
+
+    // Determines if every value in the column is NULL, in which case there is
+    // no range of values on the column's meta-data.
+    fn all_null(&self) -> bool {
+        match &self {
+            Column::String(meta, _) => meta.range.is_none(),
+            Column::Float(meta, _) => meta.range.is_none(),
+            Column::Integer(meta, _) => meta.range.is_none(),
+            Column::Unsigned(meta, _) => meta.range.is_none(),
+            Column::Bool(meta, _) => meta.range.is_none(),
+            Column::ByteArray(meta, _) => meta.range.is_none(),
         }
     }
 
@@ -160,7 +202,7 @@ impl Column {
             Column::Integer(_, data) => data.value(row_id),
             Column::Unsigned(_, data) => data.value(row_id),
             Column::Bool(_, data) => data.value(row_id),
-            Column::ByteArray(_, _) => todo!(),
+            Column::ByteArray(_, data) => data.value(row_id),
         }
     }
 
@@ -181,7 +223,7 @@ impl Column {
             Column::Integer(_, data) => data.values(row_ids),
             Column::Unsigned(_, data) => data.values(row_ids),
             Column::Bool(_, data) => data.values(row_ids),
-            Column::ByteArray(_, _) => todo!(),
+            Column::ByteArray(_, data) => data.values(row_ids),
         }
     }
 
@@ -193,7 +235,7 @@ impl Column {
             Column::Integer(_, data) => data.all_values(),
             Column::Unsigned(_, data) => data.all_values(),
             Column::Bool(_, data) => data.all_values(),
-            Column::ByteArray(_, _) => todo!(),
+            Column::ByteArray(_, data) => data.all_values(),
         }
     }
 
@@ -201,7 +243,6 @@ impl Column {
     pub fn decode_id(&self, encoded_id: u32) -> Value<'_> {
         match &self {
             Column::String(_, data) => data.decode_id(encoded_id),
-            Column::ByteArray(_, _) => todo!(),
             _ => panic!("unsupported operation"),
         }
     }
@@ -219,7 +260,7 @@ impl Column {
 
         match &self {
             Column::String(_, data) => data.distinct_values(row_ids),
-            Column::ByteArray(_, _) => todo!(),
+            Column::ByteArray(_, data) => data.distinct_values(row_ids),
             _ => unimplemented!("distinct values is not implemented for this type"),
         }
     }
@@ -279,6 +320,10 @@ impl Column {
     //
 
     /// Determine the set of row ids that satisfy the predicate.
+    ///
+    /// A `NULL` value can only be compared using the `=` and `!=` operators,
+    /// which are interpreted as `IS NULL` and `IS NOT NULL` respectively. No
+    /// rows satisfy other comparisons with `NULL`.
     pub fn row_ids_filter(
         &self,
         op: &cmp::Operator,
@@ -294,13 +339,16 @@ impl Column {
         }
 
         // Check the column for all rows that satisfy the predicate.
-        let row_ids = match &self {
-            Column::String(_, data) => data.row_ids_filter(op, value.string(), dst),
-            Column::Float(_, data) => data.row_ids_filter(op, value.scalar(), dst),
-            Column::Integer(_, data) => data.row_ids_filter(op, value.scalar(), dst),
-            Column::Unsigned(_, data) => data.row_ids_filter(op, value.scalar(), dst),
-            Column::Bool(_, data) => data.row_ids_filter(op, value.bool(), dst),
-            Column::ByteArray(_, data) => todo!(),
+        let row_ids = match (&self, value) {
+            // Predicates on NULL that couldn't be answered using the meta-data
+            // are either `IS NULL` or `IS NOT NULL`.
+            (_, Value::Null) => self.row_ids_is_null(matches!(op, cmp::Operator::Equal), dst),
+            (Column::String(_, data), _) => data.row_ids_filter(op, value.string(), dst),
+            (Column::Float(_, data), _) => data.row_ids_filter(op, value.scalar(), dst),
+            (Column::Integer(_, data), _) => data.row_ids_filter(op, value.scalar(), dst),
+            (Column::Unsigned(_, data), _) => data.row_ids_filter(op, value.scalar(), dst),
+            (Column::Bool(_, data), _) => data.row_ids_filter(op, value.bool(), dst),
+            (Column::ByteArray(_, data), _) => data.row_ids_filter(op, value.bytes(), dst),
         };
 
         if row_ids.is_empty() {
@@ -347,19 +395,26 @@ impl Column {
         // TODO(edd): figure out pooling of these
         let dst = RowIDs::Bitmap(Bitmap::create());
 
-        let low_scalar = (&low.0, low.1.scalar());
-        let high_scalar = (&high.0, high.1.scalar());
-
         // Check the column for all rows that satisfy the predicate.
         let row_ids = match &self {
-            Column::String(_, data) => unimplemented!("not supported on string columns yet"),
-            Column::Float(_, data) => data.row_ids_filter_range(low_scalar, high_scalar, dst),
-            Column::Integer(_, data) => data.row_ids_filter_range(low_scalar, high_scalar, dst),
+            Column::String(_, data) => {
+                data.row_ids_filter_range((&low.0, low.1.string()), (&high.0, high.1.string()), dst)
+            }
+            Column::Float(_, data) => {
+                data.row_ids_filter_range((&low.0, low.1.scalar()), (&high.0, high.1.scalar()), dst)
+            }
+            Column::Integer(_, data) => {
+                data.row_ids_filter_range((&low.0, low.1.scalar()), (&high.0, high.1.scalar()), dst)
+            }
             Column::Unsigned(_, data) => {
                 data.row_ids_filter_range((&low.0, low.1.scalar()), (&high.0, high.1.scalar()), dst)
             }
-            Column::Bool(_, data) => unimplemented!("filter_range not supported on boolean column"),
-            Column::ByteArray(_, data) => todo!(),
+            Column::Bool(_, data) => {
+                data.row_ids_filter_range((&low.0, low.1.bool()), (&high.0, high.1.bool()), dst)
+            }
+            Column::ByteArray(_, data) => {
+                data.row_ids_filter_range((&low.0, low.1.bytes()), (&high.0, high.1.bytes()), dst)
+            }
         };
 
         if row_ids.is_empty() {
@@ -368,6 +423,33 @@ impl Column {
         RowIDsOption::Some(row_ids)
     }
 
+    // The row ids of all the rows that are NULL (`is_null == true`) or not NULL
+    // (`is_null == false`).
+    fn row_ids_is_null(&self, is_null: bool, mut dst: RowIDs) -> RowIDs {
+        if !self.contains_null() {
+            dst.clear();
+            if !is_null {
+                dst.add_range(0, self.num_rows());
+            }
+            return dst;
+        }
+
+        match (&self, is_null) {
+            (Column::String(_, data), true) => data.row_ids_null(dst),
+            (Column::String(_, data), false) => data.row_ids_not_null(dst),
+            (Column::Float(_, data), true) => data.row_ids_null(dst),
+            (Column::Float(_, data), false) => data.row_ids_not_null(dst),
+            (Column::Integer(_, data), true) => data.row_ids_null(dst),
+            (Column::Integer(_, data), false) => data.row_ids_not_null(dst),
+            (Column::Unsigned(_, data), true) => data.row_ids_null(dst),
+            (Column::Unsigned(_, data), false) => data.row_ids_not_null(dst),
+            (Column::Bool(_, data), true) => data.row_ids_null(dst),
+            (Column::Bool(_, data), false) => data.row_ids_not_null(dst),
+            (Column::ByteArray(_, data), true) => data.row_ids_null(dst),
+            (Column::ByteArray(_, data), false) => data.row_ids_not_null(dst),
+        }
+    }
+
     // Helper function to determine if the predicate matches either no rows or
     // all the rows in a column. This is determined by looking at the metadata
     // on the column.
@@ -375,6 +457,10 @@ impl Column {
     // `None` indicates that the column may contain some matching rows and the
     // predicate should be directly applied to the column.
     fn evaluate_predicate_on_meta(&self, op: &cmp::Operator, value: &Value<'_>) -> PredicateMatch {
+        if value.is_null() {
+            return self.evaluate_null_predicate_on_meta(op);
+        }
+
         match op {
             // When the predicate is == and the metadata range indicates the column
             // can't contain `value` then the column doesn't need to be read.
@@ -411,9 +497,36 @@ impl Column {
         PredicateMatch::SomeMaybe
     }
 
-    // Helper method to determine if the column possibly contains this value.
-    //
-    // TODO(edd): currently this only handles non-null values.
+    // Helper function to determine if a predicate on `NULL` matches either no
+    // rows or all the rows in a column. `=` and `!=` are interpreted as
+    // `IS NULL` and `IS NOT NULL`, and no rows can match any other comparison
+    // with `NULL`.
+    fn evaluate_null_predicate_on_meta(&self, op: &cmp::Operator) -> PredicateMatch {
+        match op {
+            cmp::Operator::Equal => {
+                if !self.contains_null() {
+                    PredicateMatch::None
+                } else if self.all_null() {
+                    PredicateMatch::All
+                } else {
+                    PredicateMatch::SomeMaybe
+                }
+            }
+            cmp::Operator::NotEqual => {
+                if !self.contains_null() {
+                    PredicateMatch::All
+                } else if self.all_null() {
+                    PredicateMatch::None
+                } else {
+                    PredicateMatch::SomeMaybe
+                }
+            }
+            _ => PredicateMatch::None,
+        }
+    }
+
+    // Helper method to determine if the column possibly contains this
+    // non-null value.
     fn might_contain_value(&self, value: &Value<'_>) -> bool {
         match &self {
             Column::String(meta, _) => {
@@ -448,14 +561,16 @@ impl Column {
                 Value::Boolean(b) => meta.might_contain_value(*b),
                 v => panic!("cannot compare boolean to {:?}", v),
             },
-            Column::ByteArray(meta, _) => todo!(),
+            Column::ByteArray(meta, _) => match value {
+                Value::Null => false,
+                Value::ByteArray(other) => meta.might_contain_value(*other),
+                v => panic!("cannot compare byte array to {:?}", v),
+            },
         }
     }
 
-    // Helper method to determine if the predicate matches all the values in
-    // the column.
-    //
-    // TODO(edd): this doesn't handle matching on NULL yet.
+    // Helper method to determine if the predicate on a non-null value matches
+    // all the values in the column.
     fn predicate_matches_all_values(&self, op: &cmp::Operator, value: &Value<'_>) -> bool {
         match &self {
             Column::String(meta, data) => {
@@ -517,7 +632,17 @@ impl Column {
                     v => panic!("cannot compare on boolean column using {:?}", v),
                 }
             }
-            Column::ByteArray(meta, _) => todo!(),
+            Column::ByteArray(meta, data) => {
+                if data.contains_null() {
+                    return false;
+                }
+
+                match value {
+                    Value::Null => false,
+                    Value::ByteArray(other) => meta.might_match_all_values(op, *other),
+                    v => panic!("cannot compare on byte array column using {:?}", v),
+                }
+            }
         }
     }
 
@@ -541,7 +666,7 @@ impl Column {
             Column::Integer(meta, data) => meta.match_no_values(op, value.scalar().as_i64()),
             Column::Unsigned(meta, data) => meta.match_no_values(op, value.scalar().as_u64()),
             Column::Bool(meta, data) => meta.match_no_values(op, value.bool()),
-            Column::ByteArray(meta, _) => todo!(),
+            Column::ByteArray(meta, _) => meta.match_no_values(op, value.bytes()),
         }
     }
 
@@ -559,7 +684,7 @@ impl Column {
             Column::Integer(_, data) => data.min(row_ids),
             Column::Unsigned(_, data) => data.min(row_ids),
             Column::Bool(_, data) => data.min(row_ids),
-            Column::ByteArray(_, _) => todo!(),
+            Column::ByteArray(_, data) => data.min(row_ids),
         }
     }
 
@@ -573,7 +698,7 @@ impl Column {
             Column::Integer(_, data) => data.max(row_ids),
             Column::Unsigned(_, data) => data.max(row_ids),
             Column::Bool(_, data) => data.max(row_ids),
-            Column::ByteArray(_, _) => todo!(),
+            Column::ByteArray(_, data) => data.max(row_ids),
         }
     }
 
@@ -603,7 +728,7 @@ impl Column {
             Column::Integer(_, data) => data.count(row_ids),
             Column::Unsigned(_, data) => data.count(row_ids),
             Column::Bool(_, data) => data.count(row_ids),
-            Column::ByteArray(_, _) => todo!(),
+            Column::ByteArray(_, data) => data.count(row_ids),
         }
     }
 
@@ -619,8 +744,14 @@ impl Column {
 
     /// Determines if the column contains other values than those provided in
     /// `values`.
+    ///
+    /// Only string columns can be checked against a set of strings. Any other
+    /// column may contain other values, so `true` is returned for them.
     pub fn contains_other_values(&self, values: &BTreeSet<Option<&String>>) -> bool {
-        todo!()
+        match &self {
+            Column::String(_, data) => data.contains_other_values(values),
+            _ => true,
+        }
     }
 }
 
@@ -721,6 +852,14 @@ pub enum StringEncoding {
 /// This implementation is concerned with how to produce string columns with
 /// different encodings.
 impl StringEncoding {
+    /// The name of the encoding, which is reported via column size APIs.
+    pub fn debug_name(&self) -> &'static str {
+        match self {
+            Self::RLEDictionary(_) => "RLE",
+            Self::Dictionary(_) => "Dictionary",
+        }
+    }
+
     /// Determines if the column contains a NULL value.
     pub fn contains_null(&self) -> bool {
         match &self {
@@ -787,6 +926,15 @@ impl StringEncoding {
         }
     }
 
+    /// Determines if the column contains values other than those provided in
+    /// `values`. A `None` in `values` stands for the NULL value.
+    pub fn contains_other_values(&self, values: &BTreeSet<Option<&String>>) -> bool {
+        match &self {
+            Self::RLEDictionary(c) => c.contains_other_values(values),
+            Self::Dictionary(c) => c.contains_other_values(values),
+        }
+    }
+
     /// Returns the row ids that satisfy the provided predicate.
     pub fn row_ids_filter(&self, op: &cmp::Operator, value: &str, dst: RowIDs) -> RowIDs {
         match &self {
@@ -795,6 +943,38 @@ impl StringEncoding {
         }
     }
 
+    /// Returns the row ids that satisfy both the provided predicates.
+    pub fn row_ids_filter_range(
+        &self,
+        low: (&cmp::Operator, &str),
+        high: (&cmp::Operator, &str),
+        dst: RowIDs,
+    ) -> RowIDs {
+        let mut dst = self.row_ids_filter(low.0, low.1, dst);
+        if dst.is_empty() {
+            return dst;
+        }
+
+        dst.intersect(&self.row_ids_filter(high.0, high.1, RowIDs::new_bitmap()));
+        dst
+    }
+
+    /// Returns the row ids of all rows that contain NULL values.
+    pub fn row_ids_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::RLEDictionary(c) => c.row_ids_null(dst),
+            Self::Dictionary(c) => c.row_ids_null(dst),
+        }
+    }
+
+    /// Returns the row ids of all rows that contain non-NULL values.
+    pub fn row_ids_not_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::RLEDictionary(c) => c.row_ids_not_null(dst),
+            Self::Dictionary(c) => c.row_ids_not_null(dst),
+        }
+    }
+
     /// The lexicographic minimum non-null value at the rows specified, or the
     /// NULL value if the column only contains NULL values at the provided row
     /// ids.
@@ -844,21 +1024,31 @@ impl StringEncoding {
     }
 
     fn from_arrow_string_array(arr: &arrow::array::StringArray) -> Self {
-        // build a sorted dictionary.
+        let value = |i: usize| {
+            if arr.is_null(i) {
+                None
+            } else {
+                Some(arr.value(i))
+            }
+        };
+
+        // build a sorted dictionary, and determine how many runs of identical
+        // values the column has in its current sort order.
         let mut dictionary = BTreeSet::new();
+        let mut runs = 0;
 
         for i in 0..arr.len() {
-            if !arr.is_null(i) {
-                dictionary.insert(arr.value(i).to_string());
+            if i == 0 || value(i) != value(i - 1) {
+                runs += 1;
+            }
+
+            if let Some(v) = value(i) {
+                dictionary.insert(v.to_string());
             }
         }
 
-        let mut data: dictionary::Encoding =
-            if dictionary.len() > TEMP_CARDINALITY_DICTIONARY_ENCODING_LIMIT {
-                dictionary::Encoding::Plain(dictionary::Plain::with_dictionary(dictionary))
-            } else {
-                dictionary::Encoding::RLE(dictionary::RLE::with_dictionary(dictionary))
-            };
+        let stats = dictionary::ColumnStats::new(arr.len() as u32, runs, &dictionary);
+        let mut data = dictionary::Encoding::with_dictionary_for_stats(dictionary, &stats);
 
         let mut prev = if !arr.is_null(0) {
             Some(arr.value(0))
@@ -1046,6 +1236,7 @@ impl StringEncoding {
                 };
 
                 MetaData {
+                    size: data.size(),
                     rows: data.num_rows(),
                     range,
                     ..MetaData::default()
@@ -1086,12 +1277,32 @@ pub enum IntegerEncoding {
 }
 
 impl IntegerEncoding {
+    /// The name of the encoding, which is reported via column size APIs.
+    pub fn debug_name(&self) -> &'static str {
+        match self {
+            Self::I64I64(_) => "I64I64",
+            Self::I64I32(_) => "I64I32",
+            Self::I64U32(_) => "I64U32",
+            Self::I64I16(_) => "I64I16",
+            Self::I64U16(_) => "I64U16",
+            Self::I64I8(_) => "I64I8",
+            Self::I64U8(_) => "I64U8",
+            Self::U64U64(_) => "U64U64",
+            Self::U64U32(_) => "U64U32",
+            Self::U64U16(_) => "U64U16",
+            Self::U64U8(_) => "U64U8",
+            Self::I64I64N(_) => "I64I64N",
+            Self::U64U64N(_) => "U64U64N",
+        }
+    }
+
     /// Determines if the column contains a NULL value.
     pub fn contains_null(&self) -> bool {
-        if let Self::I64I64N(c) = &self {
-            return c.contains_null();
+        match &self {
+            Self::I64I64N(c) => c.contains_null(),
+            Self::U64U64N(c) => c.contains_null(),
+            _ => false,
         }
-        false
     }
 
     /// Returns the logical value found at the provided row id.
@@ -1292,8 +1503,36 @@ impl IntegerEncoding {
                 c.row_ids_filter_range((low.1.as_u8(), low.0), (high.1.as_u8(), high.0), dst)
             }
 
-            Self::I64I64N(c) => todo!(),
-            Self::U64U64N(c) => todo!(),
+            Self::I64I64N(c) => {
+                c.row_ids_filter_range((low.1.as_i64(), *low.0), (high.1.as_i64(), *high.0), dst)
+            }
+            Self::U64U64N(c) => {
+                c.row_ids_filter_range((low.1.as_u64(), *low.0), (high.1.as_u64(), *high.0), dst)
+            }
+        }
+    }
+
+    /// Returns the row ids of all rows that contain NULL values.
+    ///
+    /// Note: it is the caller's responsibility to ensure the column can
+    /// contain NULL values.
+    pub fn row_ids_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::I64I64N(c) => c.row_ids_null(dst),
+            Self::U64U64N(c) => c.row_ids_null(dst),
+            _ => unreachable!("encoding does not support NULL values"),
+        }
+    }
+
+    /// Returns the row ids of all rows that contain non-NULL values.
+    ///
+    /// Note: it is the caller's responsibility to ensure the column can
+    /// contain NULL values.
+    pub fn row_ids_not_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::I64I64N(c) => c.row_ids_not_null(dst),
+            Self::U64U64N(c) => c.row_ids_not_null(dst),
+            _ => unreachable!("encoding does not support NULL values"),
         }
     }
 
@@ -1394,6 +1633,14 @@ pub enum FloatEncoding {
 }
 
 impl FloatEncoding {
+    /// The name of the encoding, which is reported via column size APIs.
+    pub fn debug_name(&self) -> &'static str {
+        match self {
+            Self::Fixed64(_) => "Fixed64",
+            Self::FixedNull64(_) => "FixedNull64",
+        }
+    }
+
     /// Determines if the column contains a NULL value.
     pub fn contains_null(&self) -> bool {
         match self {
@@ -1460,7 +1707,31 @@ impl FloatEncoding {
             FloatEncoding::Fixed64(c) => {
                 c.row_ids_filter_range((low.1.as_f64(), &low.0), (high.1.as_f64(), &high.0), dst)
             }
-            FloatEncoding::FixedNull64(c) => todo!(),
+            FloatEncoding::FixedNull64(c) => {
+                c.row_ids_filter_range((low.1.as_f64(), *low.0), (high.1.as_f64(), *high.0), dst)
+            }
+        }
+    }
+
+    /// Returns the row ids of all rows that contain NULL values.
+    ///
+    /// Note: it is the caller's responsibility to ensure the column can
+    /// contain NULL values.
+    pub fn row_ids_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            FloatEncoding::Fixed64(_) => unreachable!("encoding does not support NULL values"),
+            FloatEncoding::FixedNull64(c) => c.row_ids_null(dst),
+        }
+    }
+
+    /// Returns the row ids of all rows that contain non-NULL values.
+    ///
+    /// Note: it is the caller's responsibility to ensure the column can
+    /// contain NULL values.
+    pub fn row_ids_not_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            FloatEncoding::Fixed64(_) => unreachable!("encoding does not support NULL values"),
+            FloatEncoding::FixedNull64(c) => c.row_ids_not_null(dst),
         }
     }
 
@@ -1508,6 +1779,13 @@ pub enum BooleanEncoding {
 }
 
 impl BooleanEncoding {
+    /// The name of the encoding, which is reported via column size APIs.
+    pub fn debug_name(&self) -> &'static str {
+        match self {
+            Self::BooleanNull(_) => "BooleanNull",
+        }
+    }
+
     /// Determines if the column contains a NULL value.
     pub fn contains_null(&self) -> bool {
         match self {
@@ -1554,6 +1832,36 @@ impl BooleanEncoding {
         }
     }
 
+    /// Returns the row ids that satisfy both the provided predicates.
+    pub fn row_ids_filter_range(
+        &self,
+        low: (&cmp::Operator, bool),
+        high: (&cmp::Operator, bool),
+        dst: RowIDs,
+    ) -> RowIDs {
+        let mut dst = self.row_ids_filter(low.0, low.1, dst);
+        if dst.is_empty() {
+            return dst;
+        }
+
+        dst.intersect(&self.row_ids_filter(high.0, high.1, RowIDs::new_bitmap()));
+        dst
+    }
+
+    /// Returns the row ids of all rows that contain NULL values.
+    pub fn row_ids_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::BooleanNull(c) => c.row_ids_null(dst),
+        }
+    }
+
+    /// Returns the row ids of all rows that contain non-NULL values.
+    pub fn row_ids_not_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::BooleanNull(c) => c.row_ids_not_null(dst),
+        }
+    }
+
     pub fn min(&self, row_ids: &[u32]) -> Value<'_> {
         match &self {
             Self::BooleanNull(c) => match c.min(row_ids) {
@@ -1579,9 +1887,188 @@ impl BooleanEncoding {
     }
 }
 
-// Converts an Arrow `StringArray` into a column, currently using the RLE
-// encoding scheme. Other encodings can be supported and added to this
-// implementation.
+pub enum ByteArrayEncoding {
+    Plain(binary::Plain),
+    Dictionary(binary::Dictionary),
+}
+
+impl ByteArrayEncoding {
+    /// The name of the encoding, which is reported via column size APIs.
+    pub fn debug_name(&self) -> &'static str {
+        match self {
+            Self::Plain(_) => "Plain",
+            Self::Dictionary(_) => "Dictionary",
+        }
+    }
+
+    /// Determines if the column contains a NULL value.
+    pub fn contains_null(&self) -> bool {
+        match self {
+            Self::Plain(enc) => enc.contains_null(),
+            Self::Dictionary(enc) => enc.contains_null(),
+        }
+    }
+
+    /// The total size in bytes of the encoded column.
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::Plain(enc) => enc.size(),
+            Self::Dictionary(enc) => enc.size(),
+        }
+    }
+
+    /// The number of rows in the column.
+    pub fn num_rows(&self) -> u32 {
+        match self {
+            Self::Plain(enc) => enc.num_rows(),
+            Self::Dictionary(enc) => enc.num_rows(),
+        }
+    }
+
+    /// Returns the logical value found at the provided row id.
+    pub fn value(&self, row_id: u32) -> Value<'_> {
+        let v = match &self {
+            Self::Plain(c) => c.value(row_id),
+            Self::Dictionary(c) => c.value(row_id),
+        };
+
+        match v {
+            Some(v) => Value::ByteArray(v),
+            None => Value::Null,
+        }
+    }
+
+    /// Returns the logical values found at the provided row ids.
+    ///
+    /// TODO(edd): perf - pooling of destination vectors.
+    pub fn values(&self, row_ids: &[u32]) -> Values<'_> {
+        match &self {
+            Self::Plain(c) => Values::ByteArray(c.values(row_ids, vec![])),
+            Self::Dictionary(c) => Values::ByteArray(c.values(row_ids, vec![])),
+        }
+    }
+
+    /// Returns all logical values in the column.
+    ///
+    /// TODO(edd): perf - pooling of destination vectors.
+    pub fn all_values(&self) -> Values<'_> {
+        match &self {
+            Self::Plain(c) => Values::ByteArray(c.all_values(vec![])),
+            Self::Dictionary(c) => Values::ByteArray(c.all_values(vec![])),
+        }
+    }
+
+    /// Returns the distinct set of values found at the provided row ids.
+    ///
+    /// TODO(edd): perf - pooling of destination sets.
+    pub fn distinct_values(&self, row_ids: &[u32]) -> ValueSet<'_> {
+        match &self {
+            Self::Plain(c) => ValueSet::ByteArray(c.distinct_values(row_ids, BTreeSet::new())),
+            Self::Dictionary(c) => ValueSet::ByteArray(c.distinct_values(row_ids, BTreeSet::new())),
+        }
+    }
+
+    /// Returns the row ids that satisfy the provided predicate.
+    pub fn row_ids_filter(&self, op: &cmp::Operator, value: &[u8], dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::Plain(c) => c.row_ids_filter(value, op, dst),
+            Self::Dictionary(c) => c.row_ids_filter(value, op, dst),
+        }
+    }
+
+    /// Returns the row ids that satisfy both the provided predicates.
+    pub fn row_ids_filter_range(
+        &self,
+        low: (&cmp::Operator, &[u8]),
+        high: (&cmp::Operator, &[u8]),
+        dst: RowIDs,
+    ) -> RowIDs {
+        let mut dst = self.row_ids_filter(low.0, low.1, dst);
+        if dst.is_empty() {
+            return dst;
+        }
+
+        dst.intersect(&self.row_ids_filter(high.0, high.1, RowIDs::new_bitmap()));
+        dst
+    }
+
+    /// Returns the row ids of all rows that contain NULL values.
+    pub fn row_ids_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::Plain(c) => c.row_ids_null(dst),
+            Self::Dictionary(c) => c.row_ids_null(dst),
+        }
+    }
+
+    /// Returns the row ids of all rows that contain non-NULL values.
+    pub fn row_ids_not_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::Plain(c) => c.row_ids_not_null(dst),
+            Self::Dictionary(c) => c.row_ids_not_null(dst),
+        }
+    }
+
+    pub fn min(&self, row_ids: &[u32]) -> Value<'_> {
+        let v = match &self {
+            Self::Plain(c) => c.min(row_ids),
+            Self::Dictionary(c) => c.min(row_ids),
+        };
+
+        match v {
+            Some(v) => Value::ByteArray(v),
+            None => Value::Null,
+        }
+    }
+
+    pub fn max(&self, row_ids: &[u32]) -> Value<'_> {
+        let v = match &self {
+            Self::Plain(c) => c.max(row_ids),
+            Self::Dictionary(c) => c.max(row_ids),
+        };
+
+        match v {
+            Some(v) => Value::ByteArray(v),
+            None => Value::Null,
+        }
+    }
+
+    pub fn count(&self, row_ids: &[u32]) -> u32 {
+        match &self {
+            Self::Plain(c) => c.count(row_ids),
+            Self::Dictionary(c) => c.count(row_ids),
+        }
+    }
+
+    // Builds the column meta-data from the encoded data.
+    fn meta_from_data(data: &Self) -> MetaData<Vec<u8>> {
+        let row_ids = (0..data.num_rows()).collect::<Vec<_>>();
+        let range = match (data.min(&row_ids), data.max(&row_ids)) {
+            (Value::ByteArray(min), Value::ByteArray(max)) => Some((min.to_vec(), max.to_vec())),
+            (Value::Null, Value::Null) => None,
+            _ => unreachable!("min/max must both be Some or None"),
+        };
+
+        MetaData {
+            size: data.size(),
+            rows: data.num_rows(),
+            range,
+            ..MetaData::default()
+        }
+    }
+}
+
+impl std::fmt::Display for ByteArrayEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Plain(data) => write!(f, "{}", data),
+            Self::Dictionary(data) => write!(f, "{}", data),
+        }
+    }
+}
+
+// Converts an Arrow `StringArray` into a column, using whichever of the RLE or
+// plain dictionary encodings is estimated to be cheaper given the number of
+// runs of repeated values in the array.
 //
 // Note: this currently runs through the array and builds the dictionary before
 // creating the encoding. There is room for performance improvement here but
@@ -1614,6 +2101,35 @@ impl From<&[&str]> for Column {
     }
 }
 
+/// Converts an Arrow `BinaryArray` into a column, choosing whichever of the
+/// plain or dictionary encodings would be smaller for the data.
+impl From<arrow::array::BinaryArray> for Column {
+    fn from(arr: arrow::array::BinaryArray) -> Self {
+        let values = (0..arr.len())
+            .map(|i| {
+                if arr.is_null(i) {
+                    None
+                } else {
+                    Some(arr.value(i))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let data = if binary::prefer_dictionary(&values) {
+            ByteArrayEncoding::Dictionary(binary::Dictionary::from(values.as_slice()))
+        } else {
+            ByteArrayEncoding::Plain(binary::Plain::from(arr))
+        };
+        Column::ByteArray(ByteArrayEncoding::meta_from_data(&data), data)
+    }
+}
+
+impl From<&[Option<&[u8]>]> for Column {
+    fn from(arr: &[Option<&[u8]>]) -> Self {
+        Self::from(arrow::array::BinaryArray::from(arr.to_vec()))
+    }
+}
+
 /// Converts a slice of u64 values into the most compact fixed-width physical
 /// encoding.
 impl From<&[u64]> for Column {
@@ -2125,7 +2641,31 @@ impl<'a> AggregateResult<'a> {
                 (_, Value::Scalar(b)) => *v += b,
                 (_, _) => unreachable!("not a possible variant combination"),
             },
-            _ => unimplemented!("First and Last aggregates not implemented yet"),
+            _ => unimplemented!("First and Last aggregates require a timestamp"),
+        }
+    }
+
+    /// Updates the aggregate with a value from a row having the timestamp
+    /// `time`. The timestamp is used by the `First` and `Last` selectors;
+    /// all other aggregates are updated as per `update`.
+    pub fn update_with_time(&mut self, other: Value<'a>, time: i64) {
+        if other.is_null() {
+            // a NULL value has no effect on aggregates
+            return;
+        }
+
+        match self {
+            Self::First(v) => {
+                if v.map_or(true, |(first_time, _)| time < first_time) {
+                    *v = Some((time, other));
+                }
+            }
+            Self::Last(v) => {
+                if v.map_or(true, |(last_time, _)| time > last_time) {
+                    *v = Some((time, other));
+                }
+            }
+            _ => self.update(other),
         }
     }
 
@@ -2144,6 +2684,20 @@ impl<'a> AggregateResult<'a> {
                     *this = *that;
                 }
             }
+            (AggregateResult::First(this), AggregateResult::First(that)) => {
+                if let Some((that_time, _)) = that {
+                    if this.map_or(true, |(this_time, _)| *that_time < this_time) {
+                        *this = *that;
+                    }
+                }
+            }
+            (AggregateResult::Last(this), AggregateResult::Last(that)) => {
+                if let Some((that_time, _)) = that {
+                    if this.map_or(true, |(this_time, _)| *that_time > this_time) {
+                        *this = *that;
+                    }
+                }
+            }
             (a, b) => unimplemented!("merging {:?} into {:?} not yet implemented", b, a),
         }
     }
@@ -2160,8 +2714,11 @@ impl<'a> AggregateResult<'a> {
                 Value::String(s) => Some(s),
                 v => panic!("cannot convert {:?} to &str", v),
             },
-            AggregateResult::First(_) => panic!("cannot convert first tuple to &str"),
-            AggregateResult::Last(_) => panic!("cannot convert last tuple to &str"),
+            AggregateResult::First(v) | AggregateResult::Last(v) => match v {
+                None | Some((_, Value::Null)) => None,
+                Some((_, Value::String(s))) => Some(s),
+                Some((_, v)) => panic!("cannot convert {:?} to &str", v),
+            },
             AggregateResult::Sum(v) => panic!("cannot convert {:?} to &str", v),
             AggregateResult::Count(_) => panic!("cannot convert count to &str"),
         }
@@ -2179,8 +2736,11 @@ impl<'a> AggregateResult<'a> {
                 Value::ByteArray(s) => Some(s),
                 v => panic!("cannot convert {:?} to &[u8]", v),
             },
-            AggregateResult::First(_) => panic!("cannot convert first tuple to &[u8]"),
-            AggregateResult::Last(_) => panic!("cannot convert last tuple to &[u8]"),
+            AggregateResult::First(v) | AggregateResult::Last(v) => match v {
+                None | Some((_, Value::Null)) => None,
+                Some((_, Value::ByteArray(s))) => Some(s),
+                Some((_, v)) => panic!("cannot convert {:?} to &[u8]", v),
+            },
             AggregateResult::Sum(v) => panic!("cannot convert {:?} to &[u8]", v),
             AggregateResult::Count(_) => panic!("cannot convert count to &[u8]"),
         }
@@ -2198,8 +2758,11 @@ impl<'a> AggregateResult<'a> {
                 Value::Boolean(s) => Some(*s),
                 v => panic!("cannot convert {:?} to bool", v),
             },
-            AggregateResult::First(_) => panic!("cannot convert first tuple to bool"),
-            AggregateResult::Last(_) => panic!("cannot convert last tuple to bool"),
+            AggregateResult::First(v) | AggregateResult::Last(v) => match v {
+                None | Some((_, Value::Null)) => None,
+                Some((_, Value::Boolean(b))) => Some(*b),
+                Some((_, v)) => panic!("cannot convert {:?} to bool", v),
+            },
             AggregateResult::Sum(v) => panic!("cannot convert {:?} to bool", v),
             AggregateResult::Count(_) => panic!("cannot convert count to bool"),
         }
@@ -2230,8 +2793,11 @@ impl<'a> AggregateResult<'a> {
                 },
                 v => panic!("cannot convert {:?} to i64", v),
             },
-            AggregateResult::First(_) => panic!("cannot convert first tuple to scalar"),
-            AggregateResult::Last(_) => panic!("cannot convert last tuple to scalar"),
+            AggregateResult::First(v) | AggregateResult::Last(v) => match v {
+                None | Some((_, Value::Null)) | Some((_, Value::Scalar(Scalar::Null))) => None,
+                Some((_, Value::Scalar(Scalar::I64(v)))) => Some(*v),
+                Some((_, v)) => panic!("cannot convert {:?} to i64", v),
+            },
             AggregateResult::Count(_) => panic!("cannot represent count as i64"),
         }
     }
@@ -2262,8 +2828,11 @@ impl<'a> AggregateResult<'a> {
                 },
                 v => panic!("cannot convert {:?} to u64", v),
             },
-            AggregateResult::First(_) => panic!("cannot convert first tuple to scalar"),
-            AggregateResult::Last(_) => panic!("cannot convert last tuple to scalar"),
+            AggregateResult::First(v) | AggregateResult::Last(v) => match v {
+                None | Some((_, Value::Null)) | Some((_, Value::Scalar(Scalar::Null))) => None,
+                Some((_, Value::Scalar(Scalar::U64(v)))) => Some(*v),
+                Some((_, v)) => panic!("cannot convert {:?} to u64", v),
+            },
         }
     }
 
@@ -2292,8 +2861,11 @@ impl<'a> AggregateResult<'a> {
                 },
                 v => panic!("cannot convert {:?} to f64", v),
             },
-            AggregateResult::First(_) => panic!("cannot convert first tuple to scalar"),
-            AggregateResult::Last(_) => panic!("cannot convert last tuple to scalar"),
+            AggregateResult::First(v) | AggregateResult::Last(v) => match v {
+                None | Some((_, Value::Null)) | Some((_, Value::Scalar(Scalar::Null))) => None,
+                Some((_, Value::Scalar(Scalar::F64(v)))) => Some(*v),
+                Some((_, v)) => panic!("cannot convert {:?} to f64", v),
+            },
             AggregateResult::Count(_) => panic!("cannot represent count as f64"),
         }
     }
@@ -2492,6 +3064,7 @@ impl PartialEq<Value<'_>> for OwnedValue {
     fn eq(&self, other: &Value<'_>) -> bool {
         match (&self, other) {
             (OwnedValue::String(a), Value::String(b)) => a == b,
+            (OwnedValue::ByteArray(a), Value::ByteArray(b)) => a == b,
             (OwnedValue::Scalar(a), Value::Scalar(b)) => a == b,
             _ => false,
         }
@@ -2502,6 +3075,7 @@ impl PartialOrd<Value<'_>> for OwnedValue {
     fn partial_cmp(&self, other: &Value<'_>) -> Option<std::cmp::Ordering> {
         match (&self, other) {
             (OwnedValue::String(a), Value::String(b)) => Some(a.as_str().cmp(b)),
+            (OwnedValue::ByteArray(a), Value::ByteArray(b)) => Some(a.as_slice().cmp(b)),
             (OwnedValue::Scalar(a), Value::Scalar(b)) => a.partial_cmp(b),
             _ => None,
         }
@@ -2546,6 +3120,13 @@ impl Value<'_> {
         panic!("cannot unwrap Value to String");
     }
 
+    pub fn bytes(&self) -> &[u8] {
+        if let Self::ByteArray(b) = self {
+            return b;
+        }
+        panic!("cannot unwrap Value to ByteArray");
+    }
+
     pub fn bool(&self) -> bool {
         if let Self::Boolean(b) = self {
             return *b;
@@ -2945,7 +3526,7 @@ impl RowIDs {
 #[cfg(test)]
 mod test {
     use super::*;
-    use arrow_deps::arrow::array::{Int64Array, StringArray};
+    use arrow_deps::arrow::array::{BooleanArray, Float64Array, Int64Array, StringArray};
 
     #[test]
     fn row_ids_intersect() {
@@ -2972,16 +3553,18 @@ mod test {
         let input = vec![None, Some("world"), None, Some("hello")];
         let arr = StringArray::from(input);
 
+        // There are no repeated values so a plain dictionary is cheaper.
         let col = Column::from(arr);
-        if let Column::String(meta, StringEncoding::RLEDictionary(enc)) = col {
+        assert_eq!(col.encoding_name(), "Dictionary");
+        if let Column::String(meta, StringEncoding::Dictionary(enc)) = col {
             assert_eq!(
                 meta,
                 super::MetaData::<String> {
-                    size: 317,
+                    size: 147,
                     rows: 4,
                     range: Some(("hello".to_string(), "world".to_string())),
                     properties: ColumnProperties {
-                        has_pre_computed_row_ids: true
+                        has_pre_computed_row_ids: false
                     }
                 }
             );
@@ -2995,6 +3578,24 @@ mod test {
         } else {
             panic!("invalid type");
         }
+
+        // Long runs of repeated values are cheaper to run-length encode.
+        let mut input = vec![None; 10];
+        input.extend(vec![Some("east"); 50]);
+        input.extend(vec![Some("west"); 40]);
+        let arr = StringArray::from(input.clone());
+
+        let col = Column::from(arr);
+        assert_eq!(col.encoding_name(), "RLE");
+        if let Column::String(meta, StringEncoding::RLEDictionary(enc)) = col {
+            assert_eq!(meta.size, enc.size());
+            assert_eq!(meta.rows, 100);
+            assert_eq!(meta.range, Some(("east".to_string(), "west".to_string())));
+            assert!(meta.properties.has_pre_computed_row_ids);
+            assert_eq!(enc.all_values(vec![]), input);
+        } else {
+            panic!("invalid type");
+        }
     }
 
     #[test]
@@ -3210,6 +3811,29 @@ mod test {
         assert_eq!(col.distinct_values(&[0, 1, 2, 3, 4]), ValueSet::String(exp));
     }
 
+    #[test]
+    fn contains_other_values() {
+        let input = &[Some("hello"), None, Some("world"), Some("hello")];
+        let col = Column::from(&input[..]);
+
+        let hello = "hello".to_string();
+        let world = "world".to_string();
+
+        let mut values = BTreeSet::new();
+        values.insert(Some(&hello));
+        assert!(col.contains_other_values(&values));
+
+        values.insert(Some(&world));
+        assert!(col.contains_other_values(&values)); // NULL not in `values`
+
+        values.insert(None);
+        assert!(!col.contains_other_values(&values));
+
+        // only string columns can be checked
+        let col = Column::from(&[0_i64, 1, 200][..]);
+        assert!(col.contains_other_values(&values));
+    }
+
     #[test]
     fn encoded_values() {
         let input = &[
@@ -3562,6 +4186,93 @@ mod test {
         assert!(matches!(row_ids, RowIDsOption::All(_)));
     }
 
+    #[test]
+    fn row_ids_filter_byte_array() {
+        let input = &[
+            Some(&b"west"[..]),
+            None,
+            Some(&b"east"[..]),
+            Some(&b"west"[..]),
+        ];
+
+        let col = Column::from(&input[..]);
+        let mut row_ids = col.row_ids_filter(
+            &cmp::Operator::Equal,
+            &Value::ByteArray(b"west"),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![0, 3]);
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::Equal,
+            &Value::ByteArray(b"north"),
+            RowIDs::new_bitmap(),
+        );
+        assert!(matches!(row_ids, RowIDsOption::None(_)));
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::NotEqual,
+            &Value::ByteArray(b"west"),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![2]);
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::LT,
+            &Value::ByteArray(b"f"),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![2]);
+    }
+
+    #[test]
+    fn byte_array_column() {
+        // low cardinality data is dictionary encoded.
+        let input = vec![Some(&b"a"[..]), None, Some(&b"bb"[..]), Some(&b"a"[..])]
+            .into_iter()
+            .cycle()
+            .take(100)
+            .collect::<Vec<_>>();
+        let col = Column::from(input.as_slice());
+        assert!(matches!(
+            col,
+            Column::ByteArray(_, ByteArrayEncoding::Dictionary(_))
+        ));
+        assert_eq!(
+            col.column_range(),
+            Some((
+                OwnedValue::ByteArray(b"a".to_vec()),
+                OwnedValue::ByteArray(b"bb".to_vec())
+            ))
+        );
+        assert_eq!(col.value(1), Value::Null);
+        assert_eq!(col.value(2), Value::ByteArray(b"bb"));
+        assert_eq!(col.min(&[1, 2, 3]), Value::ByteArray(b"a"));
+        assert_eq!(col.max(&[0, 1]), Value::ByteArray(b"a"));
+        assert_eq!(col.count(&[0, 1, 2, 3]), 3);
+
+        // high cardinality data is stored as is.
+        let values = (0..100_u8).map(|i| vec![i; 16]).collect::<Vec<_>>();
+        let input = values
+            .iter()
+            .map(|v| Some(v.as_slice()))
+            .collect::<Vec<_>>();
+        let col = Column::from(input.as_slice());
+        assert!(matches!(
+            col,
+            Column::ByteArray(_, ByteArrayEncoding::Plain(_))
+        ));
+        assert_eq!(col.min(&[3, 10, 4]), Value::ByteArray(&[3; 16]));
+        assert_eq!(col.max(&[3, 10, 4]), Value::ByteArray(&[10; 16]));
+
+        match col.values(&[0, 99]) {
+            Values::ByteArray(values) => {
+                assert_eq!(values, vec![Some(&[0_u8; 16][..]), Some(&[99_u8; 16][..])])
+            }
+            v => panic!("unexpected values {:?}", v),
+        }
+    }
+
     #[test]
     fn row_ids_range() {
         let input = &[100_i64, 200, 300, 2, 200, 22, 30];
@@ -3624,6 +4335,107 @@ mod test {
         assert_eq!(row_ids.unwrap().to_vec(), vec![0, 1, 2, 3, 4, 5, 6]);
     }
 
+    #[test]
+    fn row_ids_range_string() {
+        let input = &[
+            Some("host-a"),
+            Some("host-m"),
+            None,
+            Some("host-c"),
+            Some("host-z"),
+        ];
+
+        let col = Column::from(&input[..]);
+        let mut row_ids = col.row_ids_filter_range(
+            &(cmp::Operator::GTE, Value::from("host-a")),
+            &(cmp::Operator::LT, Value::from("host-m")),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![0, 3]);
+
+        row_ids = col.row_ids_filter_range(
+            &(cmp::Operator::GT, Value::from("host-a")),
+            &(cmp::Operator::LTE, Value::from("host-m")),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![1, 3]);
+
+        row_ids = col.row_ids_filter_range(
+            &(cmp::Operator::GT, Value::from("host-b")),
+            &(cmp::Operator::LT, Value::from("host-d")),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![3]);
+
+        row_ids = col.row_ids_filter_range(
+            &(cmp::Operator::GT, Value::from("host-z")),
+            &(cmp::Operator::LT, Value::from("zzz")),
+            RowIDs::new_bitmap(),
+        );
+        assert!(matches!(row_ids, RowIDsOption::None(_)));
+    }
+
+    #[test]
+    fn row_ids_range_bool() {
+        let input = vec![Some(true), None, Some(false), Some(true)];
+
+        let col = Column::from(BooleanArray::from(input));
+        let row_ids = col.row_ids_filter_range(
+            &(cmp::Operator::GTE, Value::Boolean(false)),
+            &(cmp::Operator::LT, Value::Boolean(true)),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![2]);
+    }
+
+    #[test]
+    fn row_ids_filter_null() {
+        // A column without any NULL values can be answered from meta-data.
+        let col = Column::from(&[100_i64, 200][..]);
+        let row_ids = col.row_ids_filter(&cmp::Operator::Equal, &Value::Null, RowIDs::new_bitmap());
+        assert!(matches!(row_ids, RowIDsOption::None(_)));
+        let row_ids =
+            col.row_ids_filter(&cmp::Operator::NotEqual, &Value::Null, RowIDs::new_bitmap());
+        assert!(matches!(row_ids, RowIDsOption::All(_)));
+
+        // As can a column only containing NULL values.
+        let input: &[Option<&str>] = &[None, None];
+        let col = Column::from(input);
+        let row_ids = col.row_ids_filter(&cmp::Operator::Equal, &Value::Null, RowIDs::new_bitmap());
+        assert!(matches!(row_ids, RowIDsOption::All(_)));
+        let row_ids =
+            col.row_ids_filter(&cmp::Operator::NotEqual, &Value::Null, RowIDs::new_bitmap());
+        assert!(matches!(row_ids, RowIDsOption::None(_)));
+
+        let cols = vec![
+            Column::from(&[Some("a"), None, Some("b"), None][..]),
+            Column::from(Int64Array::from(vec![Some(1), None, Some(2), None])),
+            Column::from(Float64Array::from(vec![Some(1.0), None, Some(2.0), None])),
+            Column::from(BooleanArray::from(vec![
+                Some(true),
+                None,
+                Some(false),
+                None,
+            ])),
+            Column::from(&[Some(&b"a"[..]), None, Some(&b"b"[..]), None][..]),
+        ];
+
+        for col in cols {
+            let row_ids =
+                col.row_ids_filter(&cmp::Operator::Equal, &Value::Null, RowIDs::new_bitmap());
+            assert_eq!(row_ids.unwrap().to_vec(), vec![1, 3]);
+
+            let row_ids =
+                col.row_ids_filter(&cmp::Operator::NotEqual, &Value::Null, RowIDs::new_bitmap());
+            assert_eq!(row_ids.unwrap().to_vec(), vec![0, 2]);
+
+            // No values are ordered relative to NULL.
+            let row_ids =
+                col.row_ids_filter(&cmp::Operator::GT, &Value::Null, RowIDs::new_bitmap());
+            assert!(matches!(row_ids, RowIDsOption::None(_)));
+        }
+    }
+
     #[test]
     fn might_contain_value() {
         let input = &[100_i64, 200, 300, 2, 200, 22, 30, -1228282828282];
@@ -3921,4 +4733,25 @@ mod test {
         res.update(Value::Scalar(Scalar::Null));
         assert!(matches!(res, AggregateResult::Sum(Scalar::I64(15))));
     }
+
+    #[test]
+    fn aggregate_result_first_last() {
+        let mut first = AggregateResult::First(None);
+        let mut last = AggregateResult::Last(None);
+        for (time, v) in &[(20, 1.0), (10, 2.0), (30, 3.0), (10, 4.0)] {
+            first.update_with_time(Value::Scalar(Scalar::F64(*v)), *time);
+            last.update_with_time(Value::Scalar(Scalar::F64(*v)), *time);
+        }
+        first.update_with_time(Value::Null, 0);
+        last.update_with_time(Value::Null, 100);
+
+        assert_eq!(first.try_as_f64_scalar(), Some(2.0));
+        assert_eq!(last.try_as_f64_scalar(), Some(3.0));
+
+        first.merge(&AggregateResult::First(Some((5, Value::String("a")))));
+        assert_eq!(first, AggregateResult::First(Some((5, Value::String("a")))));
+        last.merge(&AggregateResult::Last(Some((30, Value::String("a")))));
+        last.merge(&AggregateResult::Last(None));
+        assert_eq!(last.try_as_f64_scalar(), Some(3.0));
+    }
 }
@@ -0,0 +1,643 @@
+//! Encodings for nullable byte arrays (arbitrary binary data).
+//!
+//! `Plain` stores each row's value in an Arrow array, which suits
+//! high-cardinality data such as serialised messages, whilst `Dictionary`
+//! stores each distinct value once and encodes rows as ids into the sorted
+//! dictionary, which suits low-cardinality data.
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+use arrow_deps::arrow::array::{Array, BinaryArray};
+
+use crate::column::{cmp, RowIDs};
+
+// `NULL_ID` is the encoded id reserved for NULL values in a `Dictionary`
+// encoding.
+const NULL_ID: u32 = 0;
+
+// Determines if the ordering of a value relative to the predicate's value
+// satisfies the operator.
+fn ordering_satisfies(ord: Ordering, op: &cmp::Operator) -> bool {
+    match op {
+        cmp::Operator::Equal => ord == Ordering::Equal,
+        cmp::Operator::NotEqual => ord != Ordering::Equal,
+        cmp::Operator::GT => ord == Ordering::Greater,
+        cmp::Operator::GTE => ord != Ordering::Less,
+        cmp::Operator::LT => ord == Ordering::Less,
+        cmp::Operator::LTE => ord != Ordering::Greater,
+    }
+}
+
+#[derive(Debug)]
+pub struct Plain {
+    arr: BinaryArray,
+
+    // The total size in bytes of the non-null values.
+    value_bytes: u64,
+}
+
+impl std::fmt::Display for Plain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[Plain] rows: {:?}, nulls: {:?}, size: {}",
+            self.arr.len(),
+            self.arr.null_count(),
+            self.size()
+        )
+    }
+}
+
+impl Plain {
+    pub fn num_rows(&self) -> u32 {
+        self.arr.len() as u32
+    }
+
+    pub fn contains_null(&self) -> bool {
+        self.arr.null_count() > 0
+    }
+
+    /// Returns the total size in bytes of the encoded data: the values, their
+    /// offsets and the null bitmap.
+    pub fn size(&self) -> u64 {
+        Self::estimated_size(self.arr.len(), self.value_bytes)
+    }
+
+    // The size of a plain encoding of `rows` values with a total of
+    // `value_bytes` bytes.
+    fn estimated_size(rows: usize, value_bytes: u64) -> u64 {
+        value_bytes + (rows as u64 + 1) * std::mem::size_of::<i32>() as u64 + (rows as u64 + 7) / 8
+    }
+
+    //
+    //
+    // ---- Methods for getting decoded values.
+    //
+    //
+
+    /// Return the logical value at the provided row ID. A NULL value
+    /// is represented by None.
+    pub fn value(&self, row_id: u32) -> Option<&[u8]> {
+        if self.arr.is_null(row_id as usize) {
+            return None;
+        }
+        Some(self.arr.value(row_id as usize))
+    }
+
+    /// Returns the logical values for the provided row IDs.
+    ///
+    /// NULL values are represented by None.
+    pub fn values<'a>(
+        &'a self,
+        row_ids: &[u32],
+        mut dst: Vec<Option<&'a [u8]>>,
+    ) -> Vec<Option<&'a [u8]>> {
+        dst.clear();
+        dst.reserve(row_ids.len());
+
+        for &row_id in row_ids {
+            dst.push(self.value(row_id));
+        }
+        dst
+    }
+
+    /// Returns the logical values for all the rows in the column.
+    ///
+    /// NULL values are represented by None.
+    pub fn all_values<'a>(&'a self, mut dst: Vec<Option<&'a [u8]>>) -> Vec<Option<&'a [u8]>> {
+        dst.clear();
+        dst.reserve(self.arr.len());
+
+        for row_id in 0..self.num_rows() {
+            dst.push(self.value(row_id));
+        }
+        dst
+    }
+
+    /// Returns the distinct set of values found at the provided row ids.
+    pub fn distinct_values<'a>(
+        &'a self,
+        row_ids: &[u32],
+        mut dst: BTreeSet<Option<&'a [u8]>>,
+    ) -> BTreeSet<Option<&'a [u8]>> {
+        dst.clear();
+
+        for &row_id in row_ids {
+            if row_id >= self.num_rows() {
+                break; // all other row ids beyond column.
+            }
+            dst.insert(self.value(row_id));
+        }
+        dst
+    }
+
+    //
+    //
+    // ---- Methods for aggregation.
+    //
+    //
+
+    /// Returns the count of the non-null values for the provided
+    /// row IDs.
+    pub fn count(&self, row_ids: &[u32]) -> u32 {
+        if !self.contains_null() {
+            return row_ids.len() as u32;
+        }
+
+        row_ids
+            .iter()
+            .filter(|&&row_id| !self.arr.is_null(row_id as usize))
+            .count() as u32
+    }
+
+    /// Returns the minimum non-null value from the provided row IDs.
+    pub fn min(&self, row_ids: &[u32]) -> Option<&[u8]> {
+        row_ids
+            .iter()
+            .filter_map(|&row_id| self.value(row_id))
+            .min()
+    }
+
+    /// Returns the maximum non-null value from the provided row IDs.
+    pub fn max(&self, row_ids: &[u32]) -> Option<&[u8]> {
+        row_ids
+            .iter()
+            .filter_map(|&row_id| self.value(row_id))
+            .max()
+    }
+
+    //
+    //
+    // ---- Methods for filtering via operators.
+    //
+    //
+
+    /// Returns the set of row ids that satisfy a binary operator on a logical
+    /// value. Values are ordered lexicographically by their bytes.
+    ///
+    /// Essentially, this supports `value {=, !=, >, >=, <, <=} x`.
+    ///
+    /// NULL values never satisfy the operator.
+    pub fn row_ids_filter(&self, value: &[u8], op: &cmp::Operator, mut dst: RowIDs) -> RowIDs {
+        dst.clear();
+
+        for row_id in 0..self.num_rows() {
+            if let Some(v) = self.value(row_id) {
+                if ordering_satisfies(v.cmp(value), op) {
+                    dst.add(row_id);
+                }
+            }
+        }
+        dst
+    }
+
+    /// Returns the set of row ids for rows that contain a NULL value.
+    pub fn row_ids_null(&self, dst: RowIDs) -> RowIDs {
+        self.row_ids_is_null(true, dst)
+    }
+
+    /// Returns the set of row ids for rows that contain a non-NULL value.
+    pub fn row_ids_not_null(&self, dst: RowIDs) -> RowIDs {
+        self.row_ids_is_null(false, dst)
+    }
+
+    // All row ids that have either NULL or not NULL values. For performance
+    // reasons ranges of matching values are collected up and added in bulk to
+    // the bitmap.
+    fn row_ids_is_null(&self, is_null: bool, mut dst: RowIDs) -> RowIDs {
+        dst.clear();
+
+        let mut found = false;
+        let mut count = 0;
+        for i in 0..self.num_rows() as usize {
+            if self.arr.is_null(i) != is_null && found {
+                let (min, max) = (i as u32 - count, i as u32);
+                dst.add_range(min, max);
+                found = false;
+                count = 0;
+                continue;
+            } else if self.arr.is_null(i) != is_null {
+                continue;
+            }
+
+            if !found {
+                found = true;
+            }
+            count += 1;
+        }
+
+        // add any remaining range.
+        if found {
+            let (min, max) = (self.num_rows() - count, self.num_rows());
+            dst.add_range(min, max);
+        }
+        dst
+    }
+}
+
+impl From<BinaryArray> for Plain {
+    fn from(arr: BinaryArray) -> Self {
+        let value_bytes = (0..arr.len())
+            .filter(|&i| !arr.is_null(i))
+            .map(|i| arr.value(i).len() as u64)
+            .sum();
+        Self { arr, value_bytes }
+    }
+}
+
+impl From<&[Option<&[u8]>]> for Plain {
+    fn from(v: &[Option<&[u8]>]) -> Self {
+        Self::from(BinaryArray::from(v.to_vec()))
+    }
+}
+
+#[derive(Debug)]
+pub struct Dictionary {
+    // The sorted set of logical values that are contained within this column
+    // encoding. Entries always contains None, which is used to reserve the
+    // encoded id of `0` for NULL values.
+    entries: Vec<Option<Vec<u8>>>,
+
+    // A vector of encoded ids used to represent logical values within the
+    // column encoding.
+    encoded_data: Vec<u32>,
+
+    // marker indicating if the encoding contains a NULL value in one or more
+    // rows.
+    contains_null: bool,
+}
+
+impl std::fmt::Display for Dictionary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[Dictionary] rows: {:?}, cardinality: {}, size: {}",
+            self.num_rows(),
+            self.entries.len() - 1,
+            self.size()
+        )
+    }
+}
+
+impl Dictionary {
+    pub fn num_rows(&self) -> u32 {
+        self.encoded_data.len() as u32
+    }
+
+    pub fn contains_null(&self) -> bool {
+        self.contains_null
+    }
+
+    /// Returns the total size in bytes of the encoded data: the dictionary
+    /// and the encoded ids.
+    pub fn size(&self) -> u64 {
+        let entry_bytes = self
+            .entries
+            .iter()
+            .flatten()
+            .map(|entry| entry.len() as u64)
+            .sum();
+        Self::estimated_size(self.encoded_data.len(), self.entries.len() - 1, entry_bytes)
+    }
+
+    // The size of a dictionary encoding of `rows` values with `cardinality`
+    // distinct values, totalling `entry_bytes` bytes.
+    fn estimated_size(rows: usize, cardinality: usize, entry_bytes: u64) -> u64 {
+        let entry_overhead = std::mem::size_of::<Option<Vec<u8>>>() as u64;
+        entry_bytes
+            + (cardinality as u64 + 1) * entry_overhead
+            + rows as u64 * std::mem::size_of::<u32>() as u64
+    }
+
+    /// The sorted distinct set of non-null values in the column.
+    pub fn dictionary(&self) -> Vec<&[u8]> {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|v| v.as_slice())
+            .collect()
+    }
+
+    //
+    //
+    // ---- Methods for getting decoded values.
+    //
+    //
+
+    /// Return the logical value at the provided row ID. A NULL value
+    /// is represented by None.
+    pub fn value(&self, row_id: u32) -> Option<&[u8]> {
+        self.entries[self.encoded_data[row_id as usize] as usize].as_deref()
+    }
+
+    /// Returns the logical values for the provided row IDs.
+    ///
+    /// NULL values are represented by None.
+    pub fn values<'a>(
+        &'a self,
+        row_ids: &[u32],
+        mut dst: Vec<Option<&'a [u8]>>,
+    ) -> Vec<Option<&'a [u8]>> {
+        dst.clear();
+        dst.reserve(row_ids.len());
+
+        for &row_id in row_ids {
+            dst.push(self.value(row_id));
+        }
+        dst
+    }
+
+    /// Returns the logical values for all the rows in the column.
+    ///
+    /// NULL values are represented by None.
+    pub fn all_values<'a>(&'a self, mut dst: Vec<Option<&'a [u8]>>) -> Vec<Option<&'a [u8]>> {
+        dst.clear();
+        dst.reserve(self.encoded_data.len());
+
+        for &id in &self.encoded_data {
+            dst.push(self.entries[id as usize].as_deref());
+        }
+        dst
+    }
+
+    /// Returns the distinct set of values found at the provided row ids.
+    pub fn distinct_values<'a>(
+        &'a self,
+        row_ids: &[u32],
+        mut dst: BTreeSet<Option<&'a [u8]>>,
+    ) -> BTreeSet<Option<&'a [u8]>> {
+        dst.clear();
+
+        // Used to mark off when a decoded value has been added to the result
+        // set.
+        let mut found_ids = vec![false; self.entries.len()];
+        for &row_id in row_ids {
+            let id = match self.encoded_data.get(row_id as usize) {
+                Some(&id) => id as usize,
+                None => break, // all other row ids beyond column.
+            };
+
+            if !found_ids[id] {
+                found_ids[id] = true;
+                dst.insert(self.entries[id].as_deref());
+            }
+        }
+        dst
+    }
+
+    //
+    //
+    // ---- Methods for aggregation.
+    //
+    //
+
+    /// Returns the count of the non-null values for the provided
+    /// row IDs.
+    pub fn count(&self, row_ids: &[u32]) -> u32 {
+        if !self.contains_null {
+            return row_ids.len() as u32;
+        }
+
+        row_ids
+            .iter()
+            .filter(|&&row_id| self.encoded_data[row_id as usize] != NULL_ID)
+            .count() as u32
+    }
+
+    /// Returns the minimum non-null value from the provided row IDs. The
+    /// dictionary is sorted so the minimum value has the smallest id.
+    pub fn min(&self, row_ids: &[u32]) -> Option<&[u8]> {
+        row_ids
+            .iter()
+            .map(|&row_id| self.encoded_data[row_id as usize])
+            .filter(|&id| id != NULL_ID)
+            .min()
+            .and_then(|id| self.entries[id as usize].as_deref())
+    }
+
+    /// Returns the maximum non-null value from the provided row IDs. The
+    /// dictionary is sorted so the maximum value has the largest id.
+    pub fn max(&self, row_ids: &[u32]) -> Option<&[u8]> {
+        row_ids
+            .iter()
+            .map(|&row_id| self.encoded_data[row_id as usize])
+            .max()
+            .and_then(|id| self.entries[id as usize].as_deref())
+    }
+
+    //
+    //
+    // ---- Methods for filtering via operators.
+    //
+    //
+
+    /// Returns the set of row ids that satisfy a binary operator on a logical
+    /// value. Values are ordered lexicographically by their bytes.
+    ///
+    /// Essentially, this supports `value {=, !=, >, >=, <, <=} x`.
+    ///
+    /// NULL values never satisfy the operator.
+    pub fn row_ids_filter(&self, value: &[u8], op: &cmp::Operator, mut dst: RowIDs) -> RowIDs {
+        dst.clear();
+
+        // Evaluate the operator once per dictionary entry rather than once
+        // per row.
+        let matching_ids = self
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                Some(v) => ordering_satisfies(v.as_slice().cmp(value), op),
+                None => false,
+            })
+            .collect::<Vec<_>>();
+
+        if !matching_ids.iter().any(|&matches| matches) {
+            return dst;
+        }
+
+        for (row_id, &id) in self.encoded_data.iter().enumerate() {
+            if matching_ids[id as usize] {
+                dst.add(row_id as u32);
+            }
+        }
+        dst
+    }
+
+    /// Returns the set of row ids for rows that contain a NULL value.
+    pub fn row_ids_null(&self, dst: RowIDs) -> RowIDs {
+        self.row_ids_is_null(true, dst)
+    }
+
+    /// Returns the set of row ids for rows that contain a non-NULL value.
+    pub fn row_ids_not_null(&self, dst: RowIDs) -> RowIDs {
+        self.row_ids_is_null(false, dst)
+    }
+
+    // All row ids that have either NULL or not NULL values.
+    fn row_ids_is_null(&self, is_null: bool, mut dst: RowIDs) -> RowIDs {
+        dst.clear();
+
+        for (row_id, &id) in self.encoded_data.iter().enumerate() {
+            if (id == NULL_ID) == is_null {
+                dst.add(row_id as u32);
+            }
+        }
+        dst
+    }
+}
+
+impl<'a> From<&[Option<&'a [u8]>]> for Dictionary {
+    fn from(values: &[Option<&'a [u8]>]) -> Self {
+        let dictionary = values.iter().flatten().collect::<BTreeSet<_>>();
+
+        let mut entries = Vec::with_capacity(dictionary.len() + 1);
+        entries.push(None);
+        let mut ids = BTreeMap::new();
+        for (i, &v) in dictionary.into_iter().enumerate() {
+            ids.insert(v, i as u32 + 1);
+            entries.push(Some(v.to_vec()));
+        }
+
+        let encoded_data = values
+            .iter()
+            .map(|v| match v {
+                Some(v) => ids[v],
+                None => NULL_ID,
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            entries,
+            encoded_data,
+            contains_null: values.iter().any(Option::is_none),
+        }
+    }
+}
+
+/// Returns true if a `Dictionary` encoding of the provided values would be
+/// smaller than a `Plain` encoding of them.
+pub fn prefer_dictionary(values: &[Option<&[u8]>]) -> bool {
+    let distinct = values.iter().flatten().collect::<BTreeSet<_>>();
+    let entry_bytes = distinct.iter().map(|v| v.len() as u64).sum();
+    let value_bytes = values.iter().flatten().map(|v| v.len() as u64).sum();
+
+    Dictionary::estimated_size(values.len(), distinct.len(), entry_bytes)
+        < Plain::estimated_size(values.len(), value_bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::cmp::Operator;
+    use super::*;
+
+    const A: &[u8] = &[0x0a];
+    const B: &[u8] = &[0x0b, 0x00];
+    const C: &[u8] = &[0xff, 0x01, 0x02];
+
+    fn input() -> Vec<Option<&'static [u8]>> {
+        vec![Some(B), None, Some(A), Some(C), Some(B), None]
+    }
+
+    #[test]
+    fn value() {
+        let plain = Plain::from(input().as_slice());
+        let dict = Dictionary::from(input().as_slice());
+
+        for row_id in 0..6 {
+            assert_eq!(plain.value(row_id), input()[row_id as usize]);
+            assert_eq!(dict.value(row_id), input()[row_id as usize]);
+        }
+
+        assert_eq!(
+            plain.values(&[0, 1, 3], vec![]),
+            vec![Some(B), None, Some(C)]
+        );
+        assert_eq!(
+            dict.values(&[0, 1, 3], vec![]),
+            vec![Some(B), None, Some(C)]
+        );
+        assert_eq!(plain.all_values(vec![]), input());
+        assert_eq!(dict.all_values(vec![]), input());
+        assert_eq!(dict.dictionary(), vec![A, B, C]);
+    }
+
+    #[test]
+    fn distinct_values() {
+        let plain = Plain::from(input().as_slice());
+        let dict = Dictionary::from(input().as_slice());
+
+        let exp = vec![None, Some(A), Some(B)]
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        assert_eq!(plain.distinct_values(&[0, 1, 2, 4], BTreeSet::new()), exp);
+        assert_eq!(dict.distinct_values(&[0, 1, 2, 4], BTreeSet::new()), exp);
+
+        assert!(plain.distinct_values(&[100], BTreeSet::new()).is_empty());
+        assert!(dict.distinct_values(&[100], BTreeSet::new()).is_empty());
+    }
+
+    #[test]
+    fn aggregates() {
+        let plain = Plain::from(input().as_slice());
+        let dict = Dictionary::from(input().as_slice());
+
+        assert_eq!(plain.count(&[0, 1, 2, 3, 4, 5]), 4);
+        assert_eq!(dict.count(&[0, 1, 2, 3, 4, 5]), 4);
+        assert_eq!(plain.count(&[1, 5]), 0);
+        assert_eq!(dict.count(&[1, 5]), 0);
+
+        assert_eq!(plain.min(&[0, 1, 2, 3]), Some(A));
+        assert_eq!(dict.min(&[0, 1, 2, 3]), Some(A));
+        assert_eq!(plain.min(&[1, 3, 4]), Some(B));
+        assert_eq!(dict.min(&[1, 3, 4]), Some(B));
+        assert_eq!(plain.min(&[1, 5]), None);
+        assert_eq!(dict.min(&[1, 5]), None);
+
+        assert_eq!(plain.max(&[0, 1, 2, 3]), Some(C));
+        assert_eq!(dict.max(&[0, 1, 2, 3]), Some(C));
+        assert_eq!(plain.max(&[1, 2]), Some(A));
+        assert_eq!(dict.max(&[1, 2]), Some(A));
+        assert_eq!(plain.max(&[5]), None);
+        assert_eq!(dict.max(&[5]), None);
+    }
+
+    #[test]
+    fn row_ids_filter() {
+        let plain = Plain::from(input().as_slice());
+        let dict = Dictionary::from(input().as_slice());
+
+        let cases: Vec<(&[u8], Operator, Vec<u32>)> = vec![
+            (B, Operator::Equal, vec![0, 4]),
+            (&[0x0b], Operator::Equal, vec![]),
+            (B, Operator::NotEqual, vec![2, 3]),
+            (B, Operator::GT, vec![3]),
+            (B, Operator::GTE, vec![0, 3, 4]),
+            (B, Operator::LT, vec![2]),
+            (B, Operator::LTE, vec![0, 2, 4]),
+            (&[0x0b], Operator::GT, vec![0, 3, 4]),
+        ];
+
+        for (value, op, exp) in cases {
+            let row_ids = plain.row_ids_filter(value, &op, RowIDs::new_vector());
+            assert_eq!(row_ids.to_vec(), exp, "plain {:?} {:?}", op, value);
+
+            let row_ids = dict.row_ids_filter(value, &op, RowIDs::new_vector());
+            assert_eq!(row_ids.to_vec(), exp, "dictionary {:?} {:?}", op, value);
+        }
+    }
+
+    #[test]
+    fn prefer_dictionary() {
+        // repeated values are cheaper to store once
+        let repeated = vec![Some(C); 100];
+        assert!(super::prefer_dictionary(&repeated));
+
+        // unique values are cheaper to store inline
+        let unique = (0..100_u8).map(|i| vec![i; 10]).collect::<Vec<_>>();
+        let unique = unique
+            .iter()
+            .map(|v| Some(v.as_slice()))
+            .collect::<Vec<_>>();
+        assert!(!super::prefer_dictionary(&unique));
+    }
+}
@@ -14,12 +14,53 @@ use crate::column::{cmp, RowIDs};
 /// The encoded id for a NULL value.
 pub const NULL_ID: u32 = 0;
 
+/// Statistics about a column's values, in the order they are to be encoded,
+/// which are used to estimate the cost of each dictionary encoding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ColumnStats {
+    /// The number of rows in the column.
+    pub rows: u32,
+
+    /// The number of runs of identical values (including NULL) in the column.
+    pub runs: u32,
+
+    /// The number of distinct non-null values in the column.
+    pub cardinality: u32,
+
+    /// The total length in bytes of all distinct non-null values.
+    pub dictionary_bytes: usize,
+}
+
+impl ColumnStats {
+    pub fn new(rows: u32, runs: u32, dictionary: &BTreeSet<String>) -> Self {
+        Self {
+            rows,
+            runs,
+            cardinality: dictionary.len() as u32,
+            dictionary_bytes: dictionary.iter().map(|v| v.len()).sum(),
+        }
+    }
+}
+
 pub enum Encoding {
     RLE(RLE),
     Plain(Plain),
 }
 
 impl Encoding {
+    /// Initialises whichever of the `RLE` or `Plain` encodings is estimated to
+    /// be cheaper for a column described by `stats`. In general columns with
+    /// long runs of repeated values are cheaper to store and scan run-length
+    /// encoded, whilst columns with few repeated values are cheaper to store
+    /// as a plain vector of encoded ids.
+    pub fn with_dictionary_for_stats(dictionary: BTreeSet<String>, stats: &ColumnStats) -> Self {
+        if RLE::estimated_cost(stats) <= Plain::estimated_cost(stats) {
+            Self::RLE(RLE::with_dictionary(dictionary))
+        } else {
+            Self::Plain(Plain::with_dictionary(dictionary))
+        }
+    }
+
     pub fn debug_name(&self) -> &'static str {
         match &self {
             Encoding::RLE(_) => "RLE encoder",
@@ -302,6 +343,32 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn with_dictionary_for_stats() {
+        let dictionary = vec!["east", "north", "south", "west"]
+            .into_iter()
+            .map(String::from)
+            .collect::<BTreeSet<_>>();
+
+        // few long runs of each value
+        let stats = ColumnStats::new(100_000, 4, &dictionary);
+        let enc = Encoding::with_dictionary_for_stats(dictionary.clone(), &stats);
+        assert!(matches!(enc, Encoding::RLE(_)));
+
+        // values are unsorted so no value repeats in the next row
+        let stats = ColumnStats::new(100_000, 100_000, &dictionary);
+        let enc = Encoding::with_dictionary_for_stats(dictionary, &stats);
+        assert!(matches!(enc, Encoding::Plain(_)));
+
+        // high cardinality column with a unique value in every row
+        let dictionary = (0..10_000)
+            .map(|i| format!("host-{}", i))
+            .collect::<BTreeSet<_>>();
+        let stats = ColumnStats::new(10_000, 10_000, &dictionary);
+        let enc = Encoding::with_dictionary_for_stats(dictionary, &stats);
+        assert!(matches!(enc, Encoding::Plain(_)));
+    }
+
     #[test]
     fn push() {
         let encodings = vec![
@@ -968,7 +1035,27 @@ mod test {
 
     #[test]
     fn distinct_values() {
-        let mut enc = Encoding::RLE(RLE::default());
+        let encodings = vec![
+            Encoding::RLE(RLE::default()),
+            Encoding::Plain(Plain::default()),
+        ];
+
+        for enc in encodings {
+            _distinct_values(enc);
+        }
+
+        let encodings = vec![
+            Encoding::RLE(RLE::default()),
+            Encoding::Plain(Plain::default()),
+        ];
+
+        for enc in encodings {
+            _distinct_values_single_value(enc);
+        }
+    }
+
+    fn _distinct_values_single_value(mut enc: Encoding) {
+        let name = enc.debug_name();
         enc.push_additional(Some("east".to_string()), 100);
 
         let values = enc.distinct_values((0..100).collect::<Vec<_>>().as_slice(), BTreeSet::new());
@@ -976,10 +1063,14 @@ mod test {
             values,
             vec![Some(&"east".to_string())]
                 .into_iter()
-                .collect::<BTreeSet<_>>()
+                .collect::<BTreeSet<_>>(),
+            "{}",
+            name,
         );
+    }
 
-        enc = Encoding::RLE(RLE::default());
+    fn _distinct_values(mut enc: Encoding) {
+        let name = enc.debug_name();
         enc.push_additional(Some("east".to_string()), 3); // 0, 1, 2
         enc.push_additional(Some("north".to_string()), 1); // 3
         enc.push_additional(Some("east".to_string()), 5); // 4, 5, 6, 7, 8
@@ -996,7 +1087,9 @@ mod test {
                 Some(&"south".to_string()),
             ]
             .into_iter()
-            .collect::<BTreeSet<_>>()
+            .collect::<BTreeSet<_>>(),
+            "{}",
+            name,
         );
 
         let values = enc.distinct_values((0..4).collect::<Vec<_>>().as_slice(), BTreeSet::new());
@@ -1004,7 +1097,9 @@ mod test {
             values,
             vec![Some(&"east".to_string()), Some(&"north".to_string()),]
                 .into_iter()
-                .collect::<BTreeSet<_>>()
+                .collect::<BTreeSet<_>>(),
+            "{}",
+            name,
         );
 
         let values = enc.distinct_values(&[3, 10], BTreeSet::new());
@@ -1012,16 +1107,29 @@ mod test {
             values,
             vec![Some(&"north".to_string()), Some(&"south".to_string()),]
                 .into_iter()
-                .collect::<BTreeSet<_>>()
+                .collect::<BTreeSet<_>>(),
+            "{}",
+            name,
         );
 
         let values = enc.distinct_values(&[100], BTreeSet::new());
-        assert!(values.is_empty());
+        assert!(values.is_empty(), "{}", name);
     }
 
     #[test]
     fn contains_other_values() {
-        let mut enc = Encoding::RLE(RLE::default());
+        let encodings = vec![
+            Encoding::RLE(RLE::default()),
+            Encoding::Plain(Plain::default()),
+        ];
+
+        for enc in encodings {
+            _contains_other_values(enc);
+        }
+    }
+
+    fn _contains_other_values(mut enc: Encoding) {
+        let name = enc.debug_name();
         enc.push_additional(Some("east".to_string()), 3); // 0, 1, 2
         enc.push_additional(Some("north".to_string()), 1); // 3
         enc.push_additional(Some("east".to_string()), 5); // 4, 5, 6, 7, 8
@@ -1036,44 +1144,65 @@ mod test {
         others.insert(Some(east));
         others.insert(Some(north));
 
-        assert!(enc.contains_other_values(&others));
+        assert!(enc.contains_other_values(&others), "{}", name);
 
         let f1 = "foo".to_string();
         others.insert(Some(&f1));
-        assert!(enc.contains_other_values(&others));
+        assert!(enc.contains_other_values(&others), "{}", name);
 
         others.insert(Some(&south));
         others.insert(None);
-        assert!(!enc.contains_other_values(&others));
+        assert!(!enc.contains_other_values(&others), "{}", name);
 
         let f2 = "bar".to_string();
         others.insert(Some(&f2));
-        assert!(!enc.contains_other_values(&others));
+        assert!(!enc.contains_other_values(&others), "{}", name);
 
-        assert!(enc.contains_other_values(&BTreeSet::new()));
+        assert!(enc.contains_other_values(&BTreeSet::new()), "{}", name);
     }
 
     #[test]
     fn has_non_null_value() {
-        let mut enc = Encoding::RLE(RLE::default());
+        let encodings = vec![
+            Encoding::RLE(RLE::default()),
+            Encoding::Plain(Plain::default()),
+        ];
+
+        for enc in encodings {
+            _has_non_null_value(enc);
+        }
+
+        let encodings = vec![
+            Encoding::RLE(RLE::default()),
+            Encoding::Plain(Plain::default()),
+        ];
+
+        for enc in encodings {
+            _has_non_null_value_all_null(enc);
+        }
+    }
+
+    fn _has_non_null_value(mut enc: Encoding) {
+        let name = enc.debug_name();
         enc.push_additional(Some("east".to_string()), 3); // 0, 1, 2
         enc.push_additional(Some("north".to_string()), 1); // 3
         enc.push_additional(Some("east".to_string()), 5); // 4, 5, 6, 7, 8
         enc.push_additional(Some("south".to_string()), 2); // 9, 10
         enc.push_none(); // 11
 
-        assert!(enc.has_non_null_value(&[0]));
-        assert!(enc.has_non_null_value(&[0, 1, 2]));
-        assert!(enc.has_non_null_value(&[10]));
+        assert!(enc.has_non_null_value(&[0]), "{}", name);
+        assert!(enc.has_non_null_value(&[0, 1, 2]), "{}", name);
+        assert!(enc.has_non_null_value(&[10]), "{}", name);
 
-        assert!(!enc.has_non_null_value(&[11]));
-        assert!(!enc.has_non_null_value(&[11, 12, 100]));
+        assert!(!enc.has_non_null_value(&[11]), "{}", name);
+        assert!(!enc.has_non_null_value(&[11, 12, 100]), "{}", name);
+    }
 
-        // Pure NULL column...
-        enc = Encoding::RLE(RLE::default());
+    fn _has_non_null_value_all_null(mut enc: Encoding) {
+        let name = enc.debug_name();
         enc.push_additional(None, 10);
-        assert!(!enc.has_non_null_value(&[0]));
-        assert!(!enc.has_non_null_value(&[4, 7]));
+        assert!(!enc.has_non_null_value(&[0]), "{}", name);
+        assert!(!enc.has_non_null_value(&[4, 7]), "{}", name);
     }
 
     #[test]
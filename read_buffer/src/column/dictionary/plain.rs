@@ -13,7 +13,7 @@ use std::mem::size_of;
 
 use arrow_deps::arrow::array::{Array, StringArray};
 
-use crate::column::dictionary::NULL_ID;
+use crate::column::dictionary::{ColumnStats, NULL_ID};
 use crate::column::{cmp, RowIDs};
 
 pub struct Plain {
@@ -78,6 +78,24 @@ impl Plain {
         (entries_size + encoded_ids_size + 1) as u64
     }
 
+    /// An estimation of the cost of encoding a column described by `stats`
+    /// with a `Plain` encoding, without having to build it. The cost is the
+    /// estimated on-heap size of the encoding plus the number of bytes scanned
+    /// when evaluating a predicate against every row in the column.
+    pub fn estimated_cost(stats: &ColumnStats) -> u64 {
+        // + 1 for the reserved NULL entry
+        let entries_size = size_of::<Vec<Option<String>>>()
+            + (size_of::<Option<String>>() * (stats.cardinality as usize + 1))
+            + stats.dictionary_bytes;
+        let encoded_ids_size = size_of::<Vec<u32>>() + (size_of::<u32>() * stats.rows as usize);
+
+        // A full scan of the column visits every encoded id.
+        let scan_size = size_of::<u32>() * stats.rows as usize;
+
+        // + 1 for contains_null field
+        (entries_size + encoded_ids_size + scan_size + 1) as u64
+    }
+
     /// The number of distinct logical values in this column encoding.
     pub fn cardinality(&self) -> u32 {
         if self.contains_null {
@@ -607,7 +625,37 @@ impl Plain {
         // totally ordered.
         dst.clear();
 
-        todo!()
+        // Used to mark off when a decoded value has been added to the result
+        // set. TODO(perf) - this might benefit from being pooled somehow.
+        let mut encoded_values = vec![false; self.entries.len()];
+
+        let mut found = 0;
+        // if the encoding doesn't contain any NULL values then we can mark
+        // NULL off as "found"
+        if !self.contains_null {
+            encoded_values[NULL_ID as usize] = true;
+            found += 1;
+        }
+
+        for &row_id in row_ids {
+            let encoded_id = match self.encoded_data.get(row_id as usize) {
+                Some(&encoded_id) => encoded_id as usize,
+                None => break, // all other row ids beyond column.
+            };
+
+            if !encoded_values[encoded_id] {
+                dst.insert(self.entries[encoded_id].as_ref());
+                encoded_values[encoded_id] = true;
+                found += 1;
+            }
+
+            if found == encoded_values.len() {
+                // all distinct values have been read
+                break;
+            }
+        }
+
+        dst
     }
 
     //
@@ -661,7 +709,23 @@ impl Plain {
     /// argument to `contains_other_values`) columns can be short-circuited when
     /// they only contain values that have already been discovered.
     pub fn contains_other_values(&self, values: &BTreeSet<Option<&String>>) -> bool {
-        todo!()
+        let mut encoded_values = self.entries.len();
+        if !self.contains_null {
+            encoded_values -= 1; // this column doesn't encode NULL
+        }
+
+        if encoded_values > values.len() {
+            return true;
+        }
+
+        // skip the reserved NULL entry
+        for entry in self.entries.iter().skip(1) {
+            if !values.contains(&entry.as_ref()) {
+                return true;
+            }
+        }
+
+        self.contains_null && !values.contains(&None)
     }
 
     /// Determines if the column contains at least one non-null value at
@@ -670,13 +734,23 @@ impl Plain {
     /// It is the caller's responsibility to ensure row ids are a monotonically
     /// increasing set.
     pub fn has_non_null_value(&self, row_ids: &[u32]) -> bool {
-        todo!()
+        if self.contains_null {
+            return self.find_non_null_value(row_ids);
+        }
+
+        // There are no NULL entries in this column so just find a row id
+        // that falls on any row in the column.
+        row_ids
+            .iter()
+            .any(|&id| (id as usize) < self.encoded_data.len())
     }
 
     // Returns true if there exists an encoded non-null value at any of the row
     // ids.
     fn find_non_null_value(&self, row_ids: &[u32]) -> bool {
-        todo!()
+        row_ids.iter().any(|&id| {
+            matches!(self.encoded_data.get(id as usize), Some(&encoded_id) if encoded_id != NULL_ID)
+        })
     }
 }
 
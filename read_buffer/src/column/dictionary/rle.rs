@@ -7,9 +7,13 @@ use croaring::Bitmap;
 
 use arrow_deps::arrow::array::{Array, StringArray};
 
-use crate::column::dictionary::NULL_ID;
+use crate::column::dictionary::{ColumnStats, NULL_ID};
 use crate::column::{cmp, RowIDs};
 
+// The approximate number of bytes a roaring bitmap needs to store a single run
+// of consecutive row ids.
+const BITMAP_RUN_SIZE: usize = 4;
+
 // `RLE` is a run-length encoding for dictionary columns, where all dictionary
 // entries are utf-8 valid strings.
 pub struct RLE {
@@ -98,6 +102,44 @@ impl RLE {
         (entry_index_size + index_entry_size + index_row_ids_size + run_lengths_size + 1 + 4) as u64
     }
 
+    /// An estimation of the cost of encoding a column described by `stats`
+    /// with an `RLE` encoding, without having to build it. The cost is the
+    /// estimated on-heap size of the encoding plus the number of bytes scanned
+    /// when evaluating a predicate against every run in the column.
+    pub fn estimated_cost(stats: &ColumnStats) -> u64 {
+        let cardinality = stats.cardinality as usize;
+        let runs = stats.runs as usize;
+
+        let entry_index_size = size_of::<BTreeMap<String, u32>>()
+            + ((size_of::<String>() + size_of::<u32>()) * cardinality)
+            + stats.dictionary_bytes;
+
+        // + 1 for the reserved NULL entry
+        let index_entry_size = size_of::<Vec<String>>()
+            + (size_of::<String>() * (cardinality + 1))
+            + stats.dictionary_bytes;
+
+        // Unlike `size` this accounts for the contents of each bitmap. Each run
+        // in the column contributes roughly one run container entry to the
+        // bitmap of the run's value.
+        let index_row_ids_size = size_of::<BTreeMap<u32, Bitmap>>()
+            + ((size_of::<u32>() + size_of::<Bitmap>()) * (cardinality + 1))
+            + (BITMAP_RUN_SIZE * runs);
+
+        let run_lengths_size = size_of::<Vec<(u32, u32)>>() + (size_of::<(u32, u32)>() * runs);
+
+        // A full scan of the column visits every run-length.
+        let scan_size = size_of::<(u32, u32)>() * runs;
+
+        (entry_index_size
+            + index_entry_size
+            + index_row_ids_size
+            + run_lengths_size
+            + scan_size
+            + 1
+            + 4) as u64
+    }
+
     /// The number of distinct logical values in this column encoding.
     pub fn cardinality(&self) -> u32 {
         if self.contains_null {
@@ -275,7 +275,7 @@ where
     ///
     /// Essentially, this supports `value {=, !=, >, >=, <, <=} x`.
     ///
-    /// The equivalent of `IS NULL` is not currently supported via this method.
+    /// The equivalent of `IS NULL` is supported via `row_ids_null`.
     pub fn row_ids_filter(&self, value: T::Native, op: &cmp::Operator, dst: RowIDs) -> RowIDs {
         match op {
             cmp::Operator::GT => self.row_ids_cmp_order(value, Self::ord_from_op(&op), dst),
@@ -473,6 +473,49 @@ where
         }
         dst
     }
+
+    /// Returns the set of row ids for rows that contain a NULL value.
+    pub fn row_ids_null(&self, dst: RowIDs) -> RowIDs {
+        self.row_ids_is_null(true, dst)
+    }
+
+    /// Returns the set of row ids for rows that contain a non-NULL value.
+    pub fn row_ids_not_null(&self, dst: RowIDs) -> RowIDs {
+        self.row_ids_is_null(false, dst)
+    }
+
+    // All row ids that have either NULL or not NULL values. For performance
+    // reasons ranges of matching values are collected up and added in bulk to
+    // the bitmap.
+    fn row_ids_is_null(&self, is_null: bool, mut dst: RowIDs) -> RowIDs {
+        dst.clear();
+
+        let mut found = false;
+        let mut count = 0;
+        for i in 0..self.num_rows() as usize {
+            if self.arr.is_null(i) != is_null && found {
+                let (min, max) = (i as u32 - count, i as u32);
+                dst.add_range(min, max);
+                found = false;
+                count = 0;
+                continue;
+            } else if self.arr.is_null(i) != is_null {
+                continue;
+            }
+
+            if !found {
+                found = true;
+            }
+            count += 1;
+        }
+
+        // add any remaining range.
+        if found {
+            let (min, max) = (self.num_rows() - count, self.num_rows());
+            dst.add_range(min, max);
+        }
+        dst
+    }
 }
 
 // This macro implements the From trait for slices of various logical types.
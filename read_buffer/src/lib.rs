@@ -25,6 +25,7 @@ use snafu::{ensure, OptionExt, ResultExt, Snafu};
 // Identifiers that are exported as part of the public API.
 pub use row_group::{BinaryExpr, Predicate};
 pub use schema::*;
+pub use table::ColumnSize;
 
 use chunk::Chunk;
 use row_group::{ColumnName, RowGroup};
@@ -191,6 +192,30 @@ impl Database {
         self.data.read().unwrap().size
     }
 
+    /// Returns the size of every column of every table in the chunk, keyed by
+    /// table name and then column name. Each size includes a breakdown of
+    /// the encodings chosen for the column.
+    pub fn column_sizes(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+    ) -> Result<BTreeMap<String, BTreeMap<String, ColumnSize>>> {
+        let partition_data = self.data.read().unwrap();
+
+        let partition = partition_data
+            .partitions
+            .get(partition_key)
+            .context(PartitionNotFound { key: partition_key })?;
+
+        let chunk_data = partition.data.read().unwrap();
+        let chunk = chunk_data
+            .chunks
+            .get(&chunk_id)
+            .context(ChunkNotFound { id: chunk_id })?;
+
+        Ok(chunk.column_sizes())
+    }
+
     pub fn rows(&self) -> u64 {
         self.data.read().unwrap().rows
     }
@@ -230,6 +255,47 @@ impl Database {
         }
     }
 
+    /// Returns the union of the schemas of the specified columns in the
+    /// provided table, for the specified partition key and chunks within that
+    /// partition. The schema is found from the tables' meta data, so no data
+    /// is read.
+    pub fn table_schema(
+        &self,
+        partition_key: &str,
+        table_name: &str,
+        chunk_ids: &[u32],
+        select_columns: Selection<'_>,
+    ) -> Result<Schema> {
+        // Get read lock on database's partitions.
+        let partition_data = self.data.read().unwrap();
+
+        let partition = partition_data
+            .partitions
+            .get(partition_key)
+            .context(PartitionNotFound { key: partition_key })?;
+
+        // Get read lock on partition's chunks.
+        let chunk_data = partition.data.read().unwrap();
+
+        let builder = chunk_ids
+            .iter()
+            .try_fold(SchemaMerger::new(), |builder, chunk_id| {
+                let chunk = chunk_data
+                    .chunks
+                    .get(chunk_id)
+                    .context(ChunkNotFound { id: *chunk_id })?;
+
+                let table_schema = chunk
+                    .table_schema(table_name, &select_columns)
+                    .context(ChunkError)?;
+                let schema: Schema = (&table_schema).try_into().context(BuildingSchema)?;
+
+                builder.merge(schema).context(BuildingSchema)
+            })?;
+
+        builder.build().context(BuildingSchema)
+    }
+
     /// Returns rows for the specified columns in the provided table, for the
     /// specified partition key and chunks within that partition.
     ///
@@ -365,43 +431,91 @@ impl Database {
     /// that the caller has already provided an appropriately pruned
     /// collection of chunks.
     ///
-    /// Currently, only grouping by string (tag key) columns is supported.
+    /// Currently, only grouping by string (tag key) columns is supported, and
+    /// an error is returned for any other group column.
     /// Required aggregates are specified via a tuple comprising a column name
     /// and the type of aggregation required. Multiple aggregations can be
     /// applied to the same column.
     ///
     /// `window` should be a positive value indicating a duration in
-    /// nanoseconds.
-    pub fn read_window_aggregate(
+    /// nanoseconds. Windows are aligned to the epoch shifted by `offset`
+    /// nanoseconds, and the time column of each result holds the exclusive
+    /// upper bound of its window.
+    pub fn read_window_aggregate<'input>(
         &self,
         partition_key: &str,
-        table_name: &str,
+        table_name: &'input str,
         chunk_ids: &[u32],
         predicate: Predicate,
-        group_columns: Selection<'_>,
-        aggregates: Vec<(ColumnName<'_>, AggregateType)>,
+        group_columns: Selection<'input>,
+        aggregates: Vec<(ColumnName<'input>, AggregateType)>,
         window: u64,
+        offset: i64,
     ) -> Result<ReadWindowAggregateResults> {
-        Err(Error::UnsupportedOperation {
-            msg: "`read_aggregate_window` not yet implemented".to_owned(),
-        })
+        ensure!(
+            window > 0 && window <= i64::MAX as u64,
+            UnsupportedOperation {
+                msg: format!("invalid window duration {}ns", window),
+            }
+        );
+
+        // get read lock on database
+        let partition_data = self.data.read().unwrap();
+        let mut chunk_table_results = vec![];
+
+        let partition = partition_data
+            .partitions
+            .get(partition_key)
+            .context(PartitionNotFound { key: partition_key })?;
+
+        for chunk_id in chunk_ids {
+            // Get read lock on partition's chunks.
+            let chunk_data = partition.data.read().unwrap();
+
+            let chunk = chunk_data
+                .chunks
+                .get(chunk_id)
+                .context(ChunkNotFound { id: *chunk_id })?;
+
+            ensure!(chunk.has_table(table_name), TableNotFound { table_name });
+
+            // Get all relevant row groups for this chunk's table. This
+            // is cheap because it doesn't execute the read operation,
+            // but just gets references to the needed to data to do so.
+            if let Some(table_results) = chunk
+                .read_window_aggregate(
+                    table_name,
+                    predicate.clone(),
+                    &group_columns,
+                    &aggregates,
+                    window as i64,
+                    offset,
+                )
+                .context(ChunkError)?
+            {
+                chunk_table_results.push(table_results);
+            }
+        }
+
+        Ok(ReadWindowAggregateResults::new(chunk_table_results))
     }
 
+    /// Returns the distinct set of tag values (column values) for each provided
+    /// column, which *must* be considered a tag key, limited to the specified
+    /// partition key, table name and chunk ids.
     ///
-    /// NOTE: this is going to contain a specialised execution path for
-    /// essentially doing:
+    /// This is a specialised execution path for essentially doing:
     ///
     /// SELECT DISTINCT(column_name) WHERE XYZ
     ///
-    /// In the future the `ReadBuffer` should just probably just add this
-    /// special execution to read_filter queries with `DISTINCT` expressions
-    /// on the selector columns.
+    /// Row groups that cannot satisfy the predicate are pruned, and the
+    /// values are read from the columns' dictionaries where possible. Columns
+    /// only containing values already found in other chunks or row groups
+    /// are not read. NULL values are not returned.
     ///
-    /// Returns the distinct set of tag values (column values) for each provided
-    /// column, which *must* be considered a tag key.
-    ///
-    /// As a special case, if `tag_keys` is empty then all distinct values for
-    /// all columns (tag keys) are returned for the provided chunks.
+    /// As a special case, if `select_columns` is `Selection::All` then all
+    /// distinct values for all tag columns are returned for the provided
+    /// chunks.
     pub fn tag_values(
         &self,
         partition_key: &str,
@@ -409,10 +523,29 @@ impl Database {
         chunk_ids: &[u32],
         predicate: Predicate,
         select_columns: Selection<'_>,
-    ) -> Result<TagValuesResults> {
-        Err(Error::UnsupportedOperation {
-            msg: "`tag_values` call not yet hooked up".to_owned(),
-        })
+    ) -> Result<BTreeMap<String, BTreeSet<String>>> {
+        // get read lock on database
+        let partition_data = self.data.read().unwrap();
+
+        let partition = partition_data
+            .partitions
+            .get(partition_key)
+            .context(PartitionNotFound { key: partition_key })?;
+
+        // Get read lock on partition's chunks.
+        let chunk_data = partition.data.read().unwrap();
+
+        let mut values = BTreeMap::new();
+        for chunk_id in chunk_ids {
+            let chunk = chunk_data
+                .chunks
+                .get(chunk_id)
+                .context(ChunkNotFound { id: *chunk_id })?;
+
+            values = chunk.column_values(table_name, &predicate, &select_columns, values);
+        }
+
+        Ok(values)
     }
 
     //
@@ -731,29 +864,44 @@ impl Iterator for ReadAggregateResults {
 
 /// An iterable set of results for calls to `read_window_aggregate`.
 ///
-/// There may be some internal buffering and merging of results before a record
-/// batch is emitted from the iterator.
-pub struct ReadWindowAggregateResults {}
-
-impl Iterator for ReadWindowAggregateResults {
-    type Item = RecordBatch;
+/// The iterator lazily executes against each chunk on a call to `next`. As
+/// with `ReadAggregateResults`, all row group results inside the chunk's table
+/// are merged before a record batch is returned, so the caller can expect at
+/// most one record batch to be yielded for each chunk. Rows within each record
+/// batch are ordered by group key and then by window.
+pub struct ReadWindowAggregateResults {
+    // The table results for all chunks being executed against
+    all_chunks_table_results: Vec<table::ReadWindowAggregateResults>,
+    next_chunk: usize,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        None
+impl ReadWindowAggregateResults {
+    fn new(results: Vec<table::ReadWindowAggregateResults>) -> Self {
+        Self {
+            all_chunks_table_results: results,
+            next_chunk: 0,
+        }
     }
 }
 
-/// An iterable set of results for calls to `tag_values`.
-///
-/// There may be some internal buffering and merging of results before a record
-/// batch is emitted from the iterator.
-pub struct TagValuesResults {}
-
-impl Iterator for TagValuesResults {
+impl Iterator for ReadWindowAggregateResults {
     type Item = RecordBatch;
 
     fn next(&mut self) -> Option<Self::Item> {
-        None
+        if self.next_chunk == self.all_chunks_table_results.len() {
+            return None;
+        }
+
+        let table_result_itr = &mut self.all_chunks_table_results[self.next_chunk];
+        match table_result_itr.next() {
+            Some(rb) => Some(rb),
+            None => {
+                // no more row group results for the table in the chunk. Try
+                // next chunk.
+                self.next_chunk += 1;
+                self.next()
+            }
+        }
     }
 }
 
@@ -766,7 +914,7 @@ mod test {
         array::{
             ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, StringArray, UInt64Array,
         },
-        datatypes::DataType::{Boolean, Float64, Int64, UInt64},
+        datatypes::DataType::{Binary, Boolean, Float64, Int64, UInt64, Utf8},
     };
 
     use column::Values;
@@ -903,6 +1051,63 @@ mod test {
             .expect_err("expected partition not found error");
     }
 
+    #[test]
+    fn column_sizes() {
+        let schema = SchemaBuilder::new()
+            .non_null_tag("region")
+            .non_null_tag("host")
+            .timestamp()
+            .build()
+            .unwrap()
+            .into();
+
+        // region is sorted so has two long runs of values, whilst every host
+        // value is unique.
+        let rows = 1000;
+        let regions = (0..rows)
+            .map(|i| if i < rows / 2 { "east" } else { "west" })
+            .collect::<Vec<_>>();
+        let hosts = (0..rows).map(|i| format!("host-{}", i)).collect::<Vec<_>>();
+        let data: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(regions)),
+            Arc::new(StringArray::from(
+                hosts.iter().map(|h| h.as_str()).collect::<Vec<_>>(),
+            )),
+            Arc::new(Int64Array::from((0..rows as i64).collect::<Vec<_>>())),
+        ];
+        let rb = RecordBatch::try_new(schema, data).unwrap();
+
+        let mut db = Database::new();
+        db.upsert_partition("hour_1", 22, "a_table", rb);
+
+        let sizes = db.column_sizes("hour_1", 22).unwrap();
+        let table_sizes = sizes.get("a_table").unwrap();
+        assert_eq!(
+            table_sizes.keys().collect::<Vec<_>>(),
+            vec!["host", "region", "time"]
+        );
+
+        let region = table_sizes.get("region").unwrap();
+        assert_eq!(region.encodings.keys().collect::<Vec<_>>(), vec![&"RLE"]);
+        assert!(region.size > 0);
+
+        let host = table_sizes.get("host").unwrap();
+        assert_eq!(
+            host.encodings.keys().collect::<Vec<_>>(),
+            vec![&"Dictionary"]
+        );
+        assert!(host.size > region.size);
+
+        // all column sizes make up the total size of the database
+        let total: u64 = table_sizes.values().map(|c| c.size).sum();
+        assert_eq!(total, db.size());
+
+        assert!(matches!(
+            db.column_sizes("hour_1", 29),
+            Err(Error::ChunkNotFound { id: 29 })
+        ));
+    }
+
     // Helper function to assert the contents of a column on a record batch.
     fn assert_rb_column_equals(rb: &RecordBatch, col_name: &str, exp: &Values<'_>) {
         let got_column = rb.column(rb.schema().index_of(col_name).unwrap());
@@ -1042,6 +1247,77 @@ mod test {
         );
     }
 
+    #[test]
+    fn read_filter_byte_array() {
+        let mut db = Database::new();
+
+        let schema = SchemaBuilder::new()
+            .non_null_tag("region")
+            .field("payload", Binary)
+            .field("msg", Utf8)
+            .timestamp()
+            .build()
+            .unwrap();
+
+        let data: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec!["west", "west", "east"])),
+            Arc::new(BinaryArray::from(vec![
+                Some(&b"\x00\x01"[..]),
+                None,
+                Some(&b"\xff"[..]),
+            ])),
+            Arc::new(StringArray::from(vec![Some("hello"), Some("world"), None])),
+            Arc::new(Int64Array::from(vec![100, 200, 300])),
+        ];
+
+        let rb = RecordBatch::try_new(schema.into(), data).unwrap();
+        db.upsert_partition("hour_1", 22, "Coolverine", rb);
+
+        // SELECT * FROM "Coolverine" WHERE "payload" = '\xff'
+        let predicate = Predicate::new(vec![row_group::BinaryExpr::new(
+            "payload",
+            column::cmp::Operator::Equal,
+            row_group::Literal::ByteArray(b"\xff".to_vec()),
+        )]);
+
+        let mut itr = db
+            .read_filter("hour_1", "Coolverine", &[22], predicate, Selection::All)
+            .unwrap();
+
+        let row_group = itr.next().unwrap();
+        assert_rb_column_equals(&row_group, "region", &Values::String(vec![Some("east")]));
+        assert_rb_column_equals(
+            &row_group,
+            "payload",
+            &Values::ByteArray(vec![Some(&b"\xff"[..])]),
+        );
+        assert_rb_column_equals(&row_group, "msg", &Values::String(vec![None]));
+        assert!(itr.next().is_none());
+
+        // Byte array columns can be read in their entirety too.
+        let mut itr = db
+            .read_filter(
+                "hour_1",
+                "Coolverine",
+                &[22],
+                Predicate::default(),
+                Selection::Some(&["payload", "msg"]),
+            )
+            .unwrap();
+
+        let row_group = itr.next().unwrap();
+        assert_rb_column_equals(
+            &row_group,
+            "payload",
+            &Values::ByteArray(vec![Some(&b"\x00\x01"[..]), None, Some(&b"\xff"[..])]),
+        );
+        assert_rb_column_equals(
+            &row_group,
+            "msg",
+            &Values::String(vec![Some("hello"), Some("world"), None]),
+        );
+    }
+
     #[test]
     fn read_filter_single_chunk() {
         let mut db = Database::new();
@@ -1124,6 +1400,47 @@ mod test {
         assert!(itr.next().is_none());
     }
 
+    #[test]
+    fn table_schema() {
+        let mut db = Database::new();
+        db.upsert_partition("hour_1", 22, "Coolverine", gen_recordbatch());
+
+        let column_names = |schema: Schema| -> Vec<String> {
+            schema
+                .iter()
+                .map(|(_, field)| field.name().clone())
+                .collect()
+        };
+
+        let schema = db
+            .table_schema("hour_1", "Coolverine", &[22], Selection::All)
+            .unwrap();
+        assert_eq!(
+            column_names(schema),
+            vec!["active", "counter", "region", "sketchy_sensor", "time"]
+        );
+
+        // columns not in the table are ignored
+        let schema = db
+            .table_schema(
+                "hour_1",
+                "Coolverine",
+                &[22],
+                Selection::Some(&["region", "counter", "not_a_column"]),
+            )
+            .unwrap();
+        assert_eq!(column_names(schema), vec!["region", "counter"]);
+
+        assert!(matches!(
+            db.table_schema("hour_1", "not_a_table", &[22], Selection::All),
+            Err(Error::ChunkError { .. })
+        ));
+        assert!(matches!(
+            db.table_schema("hour_1", "Coolverine", &[23], Selection::All),
+            Err(Error::ChunkNotFound { id: 23 })
+        ));
+    }
+
     #[test]
     fn read_filter_multiple_chunks() {
         let mut db = Database::new();
@@ -1317,6 +1634,196 @@ mod test {
         assert_rb_column_equals(&result, "counter_sum", &Values::U64(vec![15000, 12000]));
         assert_rb_column_equals(&result, "counter_count", &Values::U64(vec![3, 6]));
     }
+
+    #[test]
+    fn read_window_aggregate() {
+        let mut db = Database::new();
+
+        // Add a bunch of row groups to a single table in a single chunk
+        for &i in &[100, 200, 300] {
+            let schema = SchemaBuilder::new()
+                .non_null_tag("env")
+                .non_null_tag("region")
+                .non_null_field("temp", Float64)
+                .non_null_field("counter", UInt64)
+                .timestamp()
+                .build()
+                .unwrap();
+
+            let data: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from(vec!["prod", "dev", "prod"])),
+                Arc::new(StringArray::from(vec!["west", "west", "east"])),
+                Arc::new(Float64Array::from(vec![10.0, 30000.0, 4500.0])),
+                Arc::new(UInt64Array::from(vec![1000, 3000, 5000])),
+                Arc::new(Int64Array::from(vec![i, 20 + i, 30 + i])),
+            ];
+
+            // Add a record batch to a single partition
+            let rb = RecordBatch::try_new(schema.into(), data).unwrap();
+            // The row group gets added to the same chunk each time.
+            db.upsert_partition("hour_1", 1, "table1", rb);
+        }
+
+        //
+        //   QUERY:
+        //
+        //   SELECT FIRST("temp"), LAST("temp"), SUM("counter")
+        //   FROM "table_1"
+        //   GROUP BY "region", window(100ns)
+        //
+
+        let itr = db
+            .read_window_aggregate(
+                "hour_1",
+                "table1",
+                &[1],
+                Predicate::default(),
+                Selection::Some(&["region"]),
+                vec![
+                    ("temp", AggregateType::First),
+                    ("temp", AggregateType::Last),
+                    ("counter", AggregateType::Sum),
+                ],
+                100,
+                0,
+            )
+            .unwrap();
+        let result = itr.collect::<Vec<RecordBatch>>();
+        assert_eq!(result.len(), 1);
+        let result = &result[0];
+
+        assert_rb_column_equals(
+            &result,
+            "region",
+            &Values::String(vec![
+                Some("east"),
+                Some("east"),
+                Some("east"),
+                Some("west"),
+                Some("west"),
+                Some("west"),
+            ]),
+        );
+        assert_rb_column_equals(
+            &result,
+            "time",
+            &Values::I64(vec![200, 300, 400, 200, 300, 400]),
+        );
+        assert_rb_column_equals(
+            &result,
+            "temp_first",
+            &Values::F64(vec![4500.0, 4500.0, 4500.0, 10.0, 10.0, 10.0]),
+        );
+        assert_rb_column_equals(
+            &result,
+            "temp_last",
+            &Values::F64(vec![4500.0, 4500.0, 4500.0, 30000.0, 30000.0, 30000.0]),
+        );
+        assert_rb_column_equals(
+            &result,
+            "counter_sum",
+            &Values::U64(vec![5000, 5000, 5000, 4000, 4000, 4000]),
+        );
+
+        // windows must have a duration
+        assert!(matches!(
+            db.read_window_aggregate(
+                "hour_1",
+                "table1",
+                &[1],
+                Predicate::default(),
+                Selection::Some(&["region"]),
+                vec![("counter", AggregateType::Sum)],
+                0,
+                0,
+            ),
+            Err(Error::UnsupportedOperation { .. })
+        ));
+    }
+
+    #[test]
+    fn tag_values() {
+        let mut db = Database::new();
+
+        // Add a row group to each of two chunks in the same partition
+        for (chunk_id, env, region, i) in &[(1, "prod", "west", 100), (2, "dev", "east", 200)] {
+            let schema = SchemaBuilder::new()
+                .non_null_tag("env")
+                .tag("region")
+                .non_null_field("counter", UInt64)
+                .timestamp()
+                .build()
+                .unwrap();
+
+            let data: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from(vec![*env, *env, "stage"])),
+                Arc::new(StringArray::from(vec![Some(*region), Some("north"), None])),
+                Arc::new(UInt64Array::from(vec![1000, 3000, 5000])),
+                Arc::new(Int64Array::from(vec![*i, 20 + *i, 30 + *i])),
+            ];
+
+            let rb = RecordBatch::try_new(schema.into(), data).unwrap();
+            db.upsert_partition("hour_1", *chunk_id, "table1", rb);
+        }
+
+        let to_set = |values: &[&str]| {
+            values
+                .iter()
+                .map(ToString::to_string)
+                .collect::<BTreeSet<_>>()
+        };
+
+        // all tag columns across both chunks
+        let values = db
+            .tag_values(
+                "hour_1",
+                "table1",
+                &[1, 2],
+                Predicate::default(),
+                Selection::All,
+            )
+            .unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["env"], to_set(&["dev", "prod", "stage"]));
+        assert_eq!(values["region"], to_set(&["east", "north", "west"]));
+
+        // with a predicate only matching rows in the second chunk
+        let values = db
+            .tag_values(
+                "hour_1",
+                "table1",
+                &[1, 2],
+                Predicate::new(vec![BinaryExpr::from(("time", ">=", 200_i64))]),
+                Selection::Some(&["region"]),
+            )
+            .unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values["region"], to_set(&["east", "north"]));
+
+        // unknown table
+        let values = db
+            .tag_values(
+                "hour_1",
+                "table2",
+                &[1, 2],
+                Predicate::default(),
+                Selection::All,
+            )
+            .unwrap();
+        assert!(values.is_empty());
+
+        // unknown chunk
+        assert!(matches!(
+            db.tag_values(
+                "hour_1",
+                "table1",
+                &[3],
+                Predicate::default(),
+                Selection::All,
+            ),
+            Err(Error::ChunkNotFound { id: 3 })
+        ));
+    }
 }
 
 /// THIS MODULE SHOULD ONLY BE IMPORTED FOR BENCHMARKS.
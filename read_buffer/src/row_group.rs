@@ -1,7 +1,7 @@
 use std::{
     borrow::Cow,
     cmp::Ordering,
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     convert::{TryFrom, TryInto},
     sync::Arc,
 };
@@ -13,7 +13,7 @@ use snafu::{ResultExt, Snafu};
 
 use crate::column::{
     cmp::Operator, AggregateResult, Column, EncodedValues, OwnedValue, RowIDs, RowIDsOption,
-    Scalar, Value, Values, ValuesIterator,
+    Scalar, Value, ValueSet, Values, ValuesIterator,
 };
 use crate::schema;
 use crate::schema::{AggregateType, LogicalDataType, ResultSchema};
@@ -138,6 +138,15 @@ impl RowGroup {
         self.meta.size
     }
 
+    /// The name, chosen encoding and size in bytes of each column in the
+    /// `RowGroup`.
+    pub fn column_sizes(&self) -> impl Iterator<Item = (&str, &'static str, u64)> + '_ {
+        self.all_columns_by_name.iter().map(move |(name, &idx)| {
+            let col = &self.columns[idx];
+            (name.as_str(), col.encoding_name(), col.size())
+        })
+    }
+
     /// The number of rows in the `RowGroup` (all columns have the same number
     /// of rows).
     pub fn rows(&self) -> u32 {
@@ -327,7 +336,11 @@ impl RowGroup {
 
         for expr in predicate.iter() {
             // N.B column should always exist because validation of predicates
-            // should happen at the `Table` level.
+            // should happen at the `Table` level. The exception is `IS NULL`,
+            // which all rows satisfy when the column doesn't exist.
+            if expr.matches_null() && !self.all_columns_by_name.contains_key(expr.column()) {
+                continue;
+            }
             let (col_name, col) = self.column_name_and_column(expr.column());
 
             // Explanation of how this buffer pattern works. The idea is that
@@ -502,6 +515,123 @@ impl RowGroup {
         result
     }
 
+    /// Materialises a collection of data in group columns and aggregate
+    /// columns, where rows are additionally grouped into fixed-duration windows
+    /// of time. Results are optionally filtered by the provided predicate.
+    ///
+    /// Windows are `every` nanoseconds wide and aligned to the Unix epoch
+    /// shifted by `offset` nanoseconds. The group key for each result row
+    /// comprises the values of `group_columns` followed by the exclusive upper
+    /// bound (`stop`) of the row's window, which is emitted as the time column.
+    ///
+    /// Note: `read_window_aggregate` currently only supports "tag" columns.
+    /// Note: `read_window_aggregate` does not order results.
+    pub fn read_window_aggregate(
+        &self,
+        predicate: &Predicate,
+        group_columns: &[ColumnName<'_>],
+        aggregates: &[(ColumnName<'_>, AggregateType)],
+        every: i64,
+        offset: i64,
+    ) -> ReadAggregateResult<'_> {
+        assert!(every > 0, "window duration must be positive");
+
+        let mut group_columns_schema = self.meta.schema_for_column_names(group_columns);
+        group_columns_schema.extend(self.meta.schema_for_column_names(&[TIME_COLUMN_NAME]));
+
+        let mut result = ReadAggregateResult {
+            schema: ResultSchema {
+                select_columns: vec![],
+                group_columns: group_columns_schema,
+                aggregate_columns: self.meta.schema_for_aggregate_column_names(aggregates),
+            },
+            ..ReadAggregateResult::default()
+        };
+
+        let row_ids = match self.row_ids_from_predicate(predicate) {
+            RowIDsOption::None(_) => return result, // no matching rows
+            RowIDsOption::Some(row_ids) => row_ids.to_vec(),
+            RowIDsOption::All(_) => (0..self.rows()).collect::<Vec<_>>(),
+        };
+
+        // materialise the *encoded* values for each tag column being grouped
+        // on, and the timestamp for each row.
+        let groupby_encoded_ids = group_columns
+            .iter()
+            .map(|&name| {
+                let col = self.column_by_name(name);
+                col.encoded_values(&row_ids, EncodedValues::with_capacity_u32(row_ids.len()))
+                    .take_u32()
+            })
+            .collect::<Vec<_>>();
+
+        let timestamps = match self.time_column().values(&row_ids) {
+            Values::I64(values) => values,
+            Values::I64N(values) => values
+                .into_iter()
+                .map(|v| v.expect("time column should not contain NULL values"))
+                .collect(),
+            _ => unreachable!("time column must contain i64 values"),
+        };
+
+        // Materialise values in aggregate columns.
+        let aggregate_columns_data = result
+            .schema
+            .aggregate_column_names_iter()
+            .map(|name| self.column_by_name(name).values(&row_ids))
+            .collect::<Vec<_>>();
+
+        // The group key for each row is the encoded id of each tag column
+        // value followed by the window bound the row falls in.
+        let mut groups: HashMap<Vec<i64>, Vec<AggregateResult<'_>>> = HashMap::default();
+        let mut key_buf = vec![0_i64; group_columns.len() + 1];
+
+        for (row, &timestamp) in timestamps.iter().enumerate() {
+            for (j, col_ids) in groupby_encoded_ids.iter().enumerate() {
+                key_buf[j] = col_ids[row] as i64;
+            }
+            key_buf[group_columns.len()] = window_stop(timestamp, every, offset);
+
+            let aggs = match groups.raw_entry_mut().from_key(&key_buf) {
+                hash_map::RawEntryMut::Occupied(entry) => entry.into_mut(),
+                hash_map::RawEntryMut::Vacant(entry) => {
+                    let group_key_aggs = result
+                        .schema
+                        .aggregate_columns
+                        .iter()
+                        .map(|(_, agg_type, _)| AggregateResult::from(agg_type))
+                        .collect::<Vec<_>>();
+
+                    entry.insert(key_buf.clone(), group_key_aggs).1
+                }
+            };
+
+            for (i, values) in aggregate_columns_data.iter().enumerate() {
+                aggs[i].update_with_time(values.value(row), timestamp);
+            }
+        }
+
+        // Finally, build results set. Each encoded group key needs to be
+        // materialised into a logical group key
+        let columns = group_columns
+            .iter()
+            .map(|&name| self.column_by_name(name))
+            .collect::<Vec<_>>();
+
+        for (group_key, aggs) in groups.into_iter() {
+            let mut logical_key = Vec::with_capacity(group_key.len());
+            for (col_idx, column) in columns.iter().enumerate() {
+                logical_key.push(column.decode_id(group_key[col_idx] as u32));
+            }
+            logical_key.push(Value::Scalar(Scalar::I64(group_key[columns.len()])));
+
+            result.group_keys.push(GroupKey(logical_key));
+            result.aggregates.push(AggregateResults(aggs));
+        }
+
+        result
+    }
+
     // read_group_hash executes a read-group-aggregate operation on the
     // `RowGroup` using a hashmap to build up a collection of group keys and
     // aggregates.
@@ -922,6 +1052,73 @@ impl RowGroup {
         }
         dst.aggregates.push(AggregateResults(aggregate_row)); // write the row
     }
+
+    //
+    // ---- Schema API queries
+    //
+
+    /// Adds to `dst` the distinct set of non-null values for each of the
+    /// provided tag columns, where each value lives in a row satisfying the
+    /// predicate. Columns that don't exist in the row group or aren't tag
+    /// columns are ignored.
+    ///
+    /// The values already present in `dst` are used to short-circuit
+    /// execution: a column is only read if its dictionary contains values
+    /// that have not yet been found.
+    pub fn column_values(
+        &self,
+        predicate: &Predicate,
+        columns: &[ColumnName<'_>],
+        mut dst: BTreeMap<String, BTreeSet<String>>,
+    ) -> BTreeMap<String, BTreeSet<String>> {
+        let columns = columns
+            .iter()
+            .filter(|&&name| {
+                matches!(
+                    self.meta.columns.get(name),
+                    Some(ColumnMeta {
+                        typ: schema::ColumnType::Tag(_),
+                        ..
+                    })
+                )
+            })
+            .filter(|&&name| {
+                let mut found = dst
+                    .get(name)
+                    .into_iter()
+                    .flat_map(|values| values.iter().map(Some))
+                    .collect::<BTreeSet<_>>();
+                found.insert(None); // NULL values are never returned
+                self.column_by_name(name).contains_other_values(&found)
+            })
+            .collect::<Vec<_>>();
+
+        if columns.is_empty() {
+            return dst;
+        }
+
+        let row_ids = match self.row_ids_from_predicate(predicate) {
+            RowIDsOption::None(_) => return dst, // no matching rows
+            RowIDsOption::Some(row_ids) => row_ids.to_vec(),
+            RowIDsOption::All(_) => (0..self.rows()).collect::<Vec<_>>(),
+        };
+
+        for name in columns {
+            let values = match self.column_by_name(name).distinct_values(&row_ids) {
+                ValueSet::String(values) => values,
+                ValueSet::ByteArray(_) => unreachable!("tag columns are strings"),
+            };
+
+            let found = dst.entry(name.to_string()).or_default();
+            for value in values.into_iter().flatten() {
+                if !found.contains(value) {
+                    found.insert(value.clone());
+                }
+            }
+        }
+
+        dst
+    }
 }
 
 /// Initialise a `RowGroup` from an Arrow RecordBatch.
@@ -966,6 +1163,9 @@ impl From<RecordBatch> for RowGroup {
                         arrow::datatypes::DataType::Boolean => {
                             Column::from(arrow::array::BooleanArray::from(arrow_column.data()))
                         }
+                        arrow::datatypes::DataType::Utf8 => {
+                            Column::from(arrow::array::StringArray::from(arrow_column.data()))
+                        }
                         dt => unimplemented!(
                             "data type {:?} currently not supported for field columns",
                             dt
@@ -982,6 +1182,14 @@ impl From<RecordBatch> for RowGroup {
 
                     columns.insert(col_name.to_owned(), ColumnType::Time(column_data));
                 }
+                // Binary data is not part of the InfluxDB data model so byte
+                // array columns carry no Influx column type; they are fields.
+                None if arrow_column.data_type() == &arrow::datatypes::DataType::Binary => {
+                    let column_data =
+                        Column::from(arrow::array::BinaryArray::from(arrow_column.data()));
+
+                    columns.insert(col_name.to_owned(), ColumnType::Field(column_data));
+                }
                 _ => panic!("unknown column type"),
             }
         }
@@ -990,6 +1198,18 @@ impl From<RecordBatch> for RowGroup {
     }
 }
 
+// Returns the exclusive upper bound of the window of width `every`, aligned to
+// the epoch shifted by `offset`, that contains `timestamp`. The bound of a
+// window that ends past `i64::MAX` saturates to `i64::MAX`.
+fn window_stop(timestamp: i64, every: i64, offset: i64) -> i64 {
+    // near either end of the i64 range the window bounds overflow, so they
+    // are computed as i128s.
+    let (timestamp, every, offset) = (timestamp as i128, every as i128, offset as i128);
+    let t = timestamp - offset;
+    let stop = t - t.rem_euclid(every) + every + offset;
+    i64::try_from(stop).unwrap_or(i64::MAX)
+}
+
 // Packs an encoded values into a `u128` at `pos`, which must be `[0,4)`.
 #[inline(always)]
 fn pack_u32_in_u128(packed_value: u128, encoded_id: u32, pos: usize) -> u128 {
@@ -1082,6 +1302,11 @@ pub enum Literal {
     Unsigned(u64),
     Float(f64),
     Boolean(bool),
+    ByteArray(Vec<u8>),
+
+    // Only valid with the `=` and `!=` operators, which are interpreted as
+    // `IS NULL` and `IS NOT NULL` respectively.
+    Null,
 }
 
 impl<'a> TryFrom<&DFScalarValue> for Literal {
@@ -1151,8 +1376,27 @@ impl BinaryExpr {
             Literal::Unsigned(v) => Value::Scalar(Scalar::U64(*v)),
             Literal::Float(v) => Value::Scalar(Scalar::F64(*v)),
             Literal::Boolean(v) => Value::Boolean(*v),
+            Literal::ByteArray(v) => Value::ByteArray(v),
+            Literal::Null => Value::Null,
         }
     }
+
+    /// Constructs an expression equivalent to `column IS NULL`.
+    pub fn is_null(column_name: impl Into<String>) -> Self {
+        Self::new(column_name, Operator::Equal, Literal::Null)
+    }
+
+    /// Constructs an expression equivalent to `column IS NOT NULL`.
+    pub fn is_not_null(column_name: impl Into<String>) -> Self {
+        Self::new(column_name, Operator::NotEqual, Literal::Null)
+    }
+
+    /// Returns true if the expression is satisfied by a NULL value, i.e., it
+    /// is equivalent to `column IS NULL`. All rows satisfy such an expression
+    /// on a column that doesn't exist.
+    pub fn matches_null(&self) -> bool {
+        matches!((&self.op, &self.value), (Operator::Equal, Literal::Null))
+    }
 }
 
 impl From<(&str, &str, &str)> for BinaryExpr {
@@ -1199,6 +1443,17 @@ impl TryFrom<&DfExpr> for BinaryExpr {
 
     fn try_from(df_expr: &DfExpr) -> Result<Self, Self::Error> {
         let (column_name, op, value) = match df_expr {
+            DfExpr::IsNull(expr) | DfExpr::IsNotNull(expr) => (
+                match &**expr {
+                    DfExpr::Column(name) => name,
+                    _ => return Err(format!("unsupported expression {:?}", *expr)),
+                },
+                match df_expr {
+                    DfExpr::IsNull(_) => Operator::Equal,
+                    _ => Operator::NotEqual,
+                },
+                Literal::Null,
+            ),
             DfExpr::BinaryExpr { left, op, right } => (
                 match &**left {
                     DfExpr::Column(name) => name,
@@ -1372,10 +1627,18 @@ impl MetaData {
     pub fn column_could_satisfy_binary_expr(&self, expr: &BinaryExpr) -> bool {
         let (column_min, column_max) = match self.columns.get(expr.column()) {
             Some(schema) => &schema.range,
-            None => return false, // column doesn't exist.
+            // column doesn't exist so all its values are NULL.
+            None => return expr.matches_null(),
         };
 
         let (op, value) = (expr.op(), &expr.literal_as_value());
+        if value.is_null() {
+            // The column range doesn't track NULL values so any row group
+            // could contain NULL values. However, every column in a row group
+            // has at least one non-null value.
+            return matches!(op, Operator::Equal | Operator::NotEqual);
+        }
+
         match op {
             // If the column range covers the value then it could contain that
             // value.
@@ -1647,10 +1910,10 @@ impl<'row_group> ReadAggregateResult<'row_group> {
                 // drained other, add the rest of self
                 result
                     .group_keys
-                    .extend(self.group_keys.iter().skip(j).cloned());
+                    .extend(self.group_keys.iter().skip(i).cloned());
                 result
                     .aggregates
-                    .extend(self.aggregates.iter().skip(j).cloned());
+                    .extend(self.aggregates.iter().skip(i).cloned());
                 return result;
             }
 
@@ -1722,30 +1985,35 @@ impl TryFrom<ReadAggregateResult<'_>> for RecordBatch {
         let arrow_schema: arrow_deps::arrow::datatypes::SchemaRef = schema.into();
 
         // Build the columns for the group keys. This involves pivoting the
-        // row-wise group keys into column-wise data.
-        let mut group_column_builders = (0..result.schema.group_columns.len())
-            .map(|_| {
-                arrow::array::StringBuilder::with_capacity(
-                    result.cardinality(),
-                    result.cardinality() * 8, // arbitrarily picked for now
-                )
-            })
-            .collect::<Vec<_>>();
-
-        // build each column for a group key value row by row.
-        for gk in result.group_keys.iter() {
-            for (i, v) in gk.0.iter().enumerate() {
-                group_column_builders[i]
-                    .append_value(v.string())
-                    .map_err(|source| Error::ArrowError { source })?;
-            }
-        }
-
-        // Add the group columns to the set of column data for the record batch.
+        // row-wise group keys into column-wise data. Group columns are tag
+        // columns, except for the time column of windowed aggregates.
         let mut columns: Vec<Arc<dyn arrow::array::Array>> =
             Vec::with_capacity(result.schema.len());
-        for col in group_column_builders.iter_mut() {
-            columns.push(Arc::new(col.finish()));
+        for (i, (col_type, _)) in result.schema.group_columns.iter().enumerate() {
+            match col_type {
+                schema::ColumnType::Timestamp(_) => {
+                    let mut builder = array::Int64Builder::new(result.cardinality());
+                    for gk in result.group_keys.iter() {
+                        match gk.0[i] {
+                            Value::Scalar(Scalar::I64(ts)) => {
+                                builder.append_value(ts).context(ArrowError)?
+                            }
+                            v => panic!("cannot convert {:?} to timestamp", v),
+                        }
+                    }
+                    columns.push(Arc::new(builder.finish()));
+                }
+                _ => {
+                    let mut builder = arrow::array::StringBuilder::with_capacity(
+                        result.cardinality(),
+                        result.cardinality() * 8, // arbitrarily picked for now
+                    );
+                    for gk in result.group_keys.iter() {
+                        builder.append_value(gk.0[i].string()).context(ArrowError)?;
+                    }
+                    columns.push(Arc::new(builder.finish()));
+                }
+            }
         }
 
         // For the aggregate columns, build one column at a time, repeatedly
@@ -1954,6 +2222,40 @@ mod test {
         assert!(matches!(row_ids, RowIDsOption::All(_)));
     }
 
+    #[test]
+    fn row_ids_from_predicates_null_and_ranges() {
+        let mut columns = BTreeMap::new();
+        let tc = ColumnType::Time(Column::from(&[100_i64, 200, 500, 600][..]));
+        columns.insert("time".to_string(), tc);
+        let hc = ColumnType::Tag(Column::from(
+            &[Some("host-a"), Some("host-m"), None, Some("host-c")][..],
+        ));
+        columns.insert("host".to_string(), hc);
+        let row_group = RowGroup::new(4, columns);
+
+        // Lexical range on a string column
+        let row_ids = row_group.row_ids_from_predicate(&Predicate::new(vec![
+            BinaryExpr::from(("host", ">=", "host-a")),
+            BinaryExpr::from(("host", "<", "host-m")),
+        ]));
+        assert_eq!(row_ids.unwrap().to_vec(), vec![0, 3]);
+
+        let row_ids = row_group.row_ids_from_predicate(&col_pred(BinaryExpr::is_null("host")));
+        assert_eq!(row_ids.unwrap().to_vec(), vec![2]);
+
+        let row_ids = row_group.row_ids_from_predicate(&col_pred(BinaryExpr::is_not_null("host")));
+        assert_eq!(row_ids.unwrap().to_vec(), vec![0, 1, 3]);
+
+        // All values in a column that doesn't exist are NULL
+        let predicate = col_pred(BinaryExpr::is_null("region"));
+        assert!(row_group.could_satisfy_conjunctive_binary_expressions(predicate.iter()));
+        let row_ids = row_group.row_ids_from_predicate(&predicate);
+        assert!(matches!(row_ids, RowIDsOption::All(_)));
+
+        let predicate = col_pred(BinaryExpr::is_not_null("region"));
+        assert!(!row_group.could_satisfy_conjunctive_binary_expressions(predicate.iter()));
+    }
+
     #[test]
     fn read_filter() {
         let mut columns = BTreeMap::new();
@@ -2101,6 +2403,90 @@ west,4
         read_group_single_groupby_column(&row_group);
     }
 
+    #[test]
+    fn read_window_aggregate() {
+        let mut columns = BTreeMap::new();
+        let tc = ColumnType::Time(Column::from(&[1_i64, 2, 3, 4, 5, 6][..]));
+        columns.insert("time".to_string(), tc);
+
+        let rc = ColumnType::Tag(Column::from(
+            &["west", "west", "east", "west", "south", "north"][..],
+        ));
+        columns.insert("region".to_string(), rc);
+
+        let mc = ColumnType::Tag(Column::from(
+            &["GET", "POST", "POST", "POST", "PUT", "GET"][..],
+        ));
+        columns.insert("method".to_string(), mc);
+
+        let fc = ColumnType::Field(Column::from(&[100_u64, 101, 200, 203, 203, 10][..]));
+        columns.insert("counter".to_string(), fc);
+
+        let row_group = RowGroup::new(6, columns);
+
+        let cases = vec![
+            (
+                Predicate::default(),
+                vec!["region"],
+                vec![
+                    ("counter", AggregateType::Sum),
+                    ("counter", AggregateType::Last),
+                ],
+                4,
+                0,
+                "region,time,counter_sum,counter_last
+east,4,200,200
+north,8,10,10
+south,8,203,203
+west,4,201,101
+west,8,203,203
+",
+            ),
+            (
+                col_pred(BinaryExpr::from(("method", "=", "POST"))),
+                vec![],
+                vec![
+                    ("counter", AggregateType::Count),
+                    ("counter", AggregateType::First),
+                ],
+                4,
+                1,
+                "time,counter_count,counter_first
+5,3,101
+",
+            ),
+            (
+                Predicate::with_time_range(&[], 3, 5),
+                vec!["method"],
+                vec![("counter", AggregateType::Min)],
+                1,
+                0,
+                "method,time,counter_min
+POST,4,200
+POST,5,203
+",
+            ),
+        ];
+
+        for (predicate, group_cols, aggs, every, offset, expected) in cases {
+            let mut results =
+                row_group.read_window_aggregate(&predicate, &group_cols, &aggs, every, offset);
+            results.sort();
+            assert_eq!(format!("{:?}", &results), expected);
+        }
+
+        // windows are aligned to the epoch, including for negative timestamps.
+        assert_eq!(window_stop(-1, 10, 0), 0);
+        assert_eq!(window_stop(-10, 10, 0), 0);
+        assert_eq!(window_stop(0, 10, 0), 10);
+        assert_eq!(window_stop(14, 10, 5), 15);
+        assert_eq!(window_stop(15, 10, 5), 25);
+        assert_eq!(window_stop(i64::MAX, 10, 0), i64::MAX);
+        assert_eq!(window_stop(i64::MAX - 1, 10, 5), i64::MAX);
+        assert_eq!(window_stop(i64::MIN, 10, 0), i64::MIN + 8);
+        assert_eq!(window_stop(i64::MIN, 10, -5), i64::MIN + 3);
+    }
+
     // the read_group path where grouping is on fewer than five columns.
     fn read_group_hash_u128_key(row_group: &RowGroup) {
         let cases = vec![
@@ -2655,6 +3041,49 @@ west,host-d,11,9
             }
         );
 
+        // merging a result that runs out of group keys first keeps the rest
+        // of the result's group keys, from where the merge got to in the
+        // result rather than in the other result.
+        let mut other_result = ReadAggregateResult {
+            schema: schema.clone(),
+            ..Default::default()
+        };
+        other_result.add_row(
+            vec![Value::String("east"), Value::String("host-b")],
+            vec![
+                AggregateResult::Sum(Scalar::I64(5)),
+                AggregateResult::Count(1),
+            ],
+        );
+        result = result.merge(other_result);
+
+        assert_eq!(
+            result,
+            ReadAggregateResult {
+                schema: schema.clone(),
+                group_keys: vec![
+                    GroupKey(vec![Value::String("east"), Value::String("host-a")]),
+                    GroupKey(vec![Value::String("east"), Value::String("host-b")]),
+                    GroupKey(vec![Value::String("north"), Value::String("host-a")]),
+                ],
+                aggregates: vec![
+                    AggregateResults(vec![
+                        AggregateResult::Sum(Scalar::I64(20)),
+                        AggregateResult::Count(6),
+                    ]),
+                    AggregateResults(vec![
+                        AggregateResult::Sum(Scalar::I64(45)),
+                        AggregateResult::Count(9),
+                    ]),
+                    AggregateResults(vec![
+                        AggregateResult::Sum(Scalar::I64(-5)),
+                        AggregateResult::Count(2),
+                    ]),
+                ],
+                ..Default::default()
+            }
+        );
+
         // merging nothing in doesn't change the result.
         let other_result = ReadAggregateResult {
             schema: schema.clone(),
@@ -2722,4 +3151,88 @@ west,host-d,11,9
         assert_ne!(col1, col3);
         assert_ne!(col2, col3);
     }
+
+    #[test]
+    fn column_values() {
+        let mut columns = BTreeMap::new();
+        let tc = ColumnType::Time(Column::from(&[1_i64, 2, 3, 4, 5, 6][..]));
+        columns.insert("time".to_string(), tc);
+
+        let rc = ColumnType::Tag(Column::from(
+            &[
+                Some("west"),
+                Some("west"),
+                None,
+                Some("west"),
+                Some("south"),
+                Some("north"),
+            ][..],
+        ));
+        columns.insert("region".to_string(), rc);
+
+        let mc = ColumnType::Tag(Column::from(
+            &["GET", "POST", "POST", "POST", "PUT", "GET"][..],
+        ));
+        columns.insert("method".to_string(), mc);
+
+        let fc = ColumnType::Field(Column::from(&[100_u64, 101, 200, 203, 203, 10][..]));
+        columns.insert("counter".to_string(), fc);
+
+        let row_group = RowGroup::new(6, columns);
+
+        let to_map = |values: Vec<(&str, Vec<&str>)>| {
+            values
+                .into_iter()
+                .map(|(k, v)| {
+                    (
+                        k.to_string(),
+                        v.into_iter()
+                            .map(ToString::to_string)
+                            .collect::<BTreeSet<_>>(),
+                    )
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        // NULL values are not returned, and non-tag or unknown columns are
+        // ignored.
+        let values = row_group.column_values(
+            &Predicate::default(),
+            &["region", "method", "counter", "unknown"],
+            BTreeMap::new(),
+        );
+        assert_eq!(
+            values,
+            to_map(vec![
+                ("method", vec!["GET", "POST", "PUT"]),
+                ("region", vec!["north", "south", "west"]),
+            ])
+        );
+
+        let values = row_group.column_values(
+            &col_pred(BinaryExpr::from(("method", "=", "POST"))),
+            &["region"],
+            BTreeMap::new(),
+        );
+        assert_eq!(values, to_map(vec![("region", vec!["west"])]));
+
+        // values are added to the provided set
+        let values = row_group.column_values(
+            &Predicate::with_time_range(&[], 5, 7),
+            &["region"],
+            to_map(vec![("region", vec!["east"])]),
+        );
+        assert_eq!(
+            values,
+            to_map(vec![("region", vec!["east", "north", "south"])])
+        );
+
+        // no rows match the predicate
+        let values = row_group.column_values(
+            &col_pred(BinaryExpr::from(("method", "=", "DELETE"))),
+            &["region"],
+            BTreeMap::new(),
+        );
+        assert!(values.is_empty());
+    }
 }
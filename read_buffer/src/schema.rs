@@ -104,6 +104,11 @@ impl TryFrom<&ResultSchema> for data_types::schema::Schema {
         for (col_type, data_type) in &rs.select_columns {
             match col_type {
                 ColumnType::Tag(name) => builder = builder.tag(name.as_str()),
+                // Binary data has no Influx field type so it is represented
+                // as a plain Arrow field.
+                ColumnType::Field(name) if *data_type == LogicalDataType::Binary => {
+                    builder = builder.field(name.as_str(), data_type.into())
+                }
                 ColumnType::Field(name) => {
                     builder = builder.influx_field(name.as_str(), data_type.into())
                 }
@@ -115,6 +120,9 @@ impl TryFrom<&ResultSchema> for data_types::schema::Schema {
         for (col_type, data_type) in &rs.group_columns {
             match col_type {
                 ColumnType::Tag(name) => builder = builder.tag(name.as_str()),
+                ColumnType::Field(name) if *data_type == LogicalDataType::Binary => {
+                    builder = builder.field(name.as_str(), data_type.into())
+                }
                 ColumnType::Field(name) => {
                     builder = builder.influx_field(name.as_str(), data_type.into())
                 }
@@ -127,6 +135,9 @@ impl TryFrom<&ResultSchema> for data_types::schema::Schema {
             let col_name = rs.aggregate_result_column_name(i);
 
             match col_type {
+                ColumnType::Field(_) if *data_type == LogicalDataType::Binary => {
+                    builder = builder.field(col_name.as_str(), data_type.into())
+                }
                 ColumnType::Field(_) => {
                     builder = builder.influx_field(col_name.as_str(), data_type.into())
                 }
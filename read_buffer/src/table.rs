@@ -11,17 +11,35 @@ use data_types::selection::Selection;
 use snafu::{ensure, Snafu};
 
 use crate::column::{AggregateResult, Scalar, Value};
-use crate::row_group::{self, ColumnName, GroupKey, Predicate, RowGroup};
+use crate::row_group::{self, ColumnName, Predicate, RowGroup};
 use crate::schema::{AggregateType, ColumnType, LogicalDataType, ResultSchema};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("cannot drop last row group in table; drop table"))]
     EmptyTableError {},
+
+    #[snafu(display(
+        "cannot group by column '{}': only tag columns are supported",
+        column_name
+    ))]
+    UnsupportedGroupColumn { column_name: String },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// The memory used by a column in a table.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ColumnSize {
+    /// The total size in bytes of the column across all row groups.
+    pub size: u64,
+
+    /// The size in bytes of the column stored with each encoding. The
+    /// encoding is chosen independently in each row group, so a column may
+    /// be stored using several encodings.
+    pub encodings: BTreeMap<&'static str, u64>,
+}
+
 /// A Table represents data for a single measurement.
 ///
 /// Tables contain potentially many collections of rows in the form of row
@@ -129,6 +147,22 @@ impl Table {
         self.table_data.read().unwrap().meta.size
     }
 
+    /// The size of each column in the table, broken down by the encodings
+    /// chosen for the column in each of the table's row groups.
+    pub fn column_sizes(&self) -> BTreeMap<String, ColumnSize> {
+        let row_groups = self.table_data.read().unwrap();
+
+        let mut sizes: BTreeMap<String, ColumnSize> = BTreeMap::new();
+        for rg in &row_groups.data {
+            for (name, encoding, size) in rg.column_sizes() {
+                let column_size = sizes.entry(name.to_owned()).or_default();
+                column_size.size += size;
+                *column_size.encodings.entry(encoding).or_default() += size;
+            }
+        }
+        sizes
+    }
+
     // Returns the total number of row groups in this table.
     pub fn row_groups(&self) -> usize {
         self.table_data.read().unwrap().data.len()
@@ -174,6 +208,23 @@ impl Table {
         (Arc::clone(&table_data.meta), row_groups)
     }
 
+    /// Returns the schema of the specified columns of the table, without
+    /// reading any of its row groups. Columns that do not exist within the
+    /// table are ignored.
+    pub fn schema(&self, columns: &Selection<'_>) -> ResultSchema {
+        let table_data = self.table_data.read().unwrap();
+
+        ResultSchema {
+            select_columns: match columns {
+                Selection::All => table_data.meta.schema_for_all_columns(),
+                Selection::Some(column_names) => {
+                    table_data.meta.schema_for_column_names(column_names)
+                }
+            },
+            ..ResultSchema::default()
+        }
+    }
+
     /// Select data for the specified column selections with the provided
     /// predicates applied.
     ///
@@ -247,33 +298,67 @@ impl Table {
     /// Returns aggregates segmented by grouping keys and windowed by time.
     ///
     /// The set of data to be aggregated may be filtered by (currently only)
-    /// equality predicates, but can be ranged by time, which should be
+    /// conjunctive predicates, but can be ranged by time, which should be
     /// represented as nanoseconds since the epoch. Results are included if they
     /// satisfy the predicate and fall with the [min, max) time range domain.
     ///
     /// Group keys are determined according to the provided group column names
-    /// (`group_columns`). Currently only grouping by string (tag key) columns
-    /// is supported.
+    /// (`group_columns`), where `Selection::All` means all tag columns in the
+    /// table. Currently only grouping by string (tag key) columns is
+    /// supported.
     ///
     /// Required aggregates are specified via a tuple comprising a column name
     /// and the type of aggregation required. Multiple aggregations can be
     /// applied to the same column.
     ///
-    /// Results are grouped and windowed according to the `window` parameter,
-    /// which represents an interval in nanoseconds. For example, to window
-    /// results by one minute, window should be set to 600_000_000_000.
-    pub fn aggregate_window<'a>(
+    /// Results are grouped and windowed according to the `every` parameter,
+    /// which represents an interval in nanoseconds, with windows aligned to the
+    /// epoch shifted by `offset` nanoseconds. For example, to window results by
+    /// one minute, `every` should be set to 60_000_000_000. The time column in
+    /// the results holds the exclusive upper bound of each window.
+    ///
+    /// Returns an error if any of `group_columns` is not a tag column.
+    pub fn read_window_aggregate<'input>(
         &self,
-        time_range: (i64, i64),
-        predicates: &[(&str, &str)],
-        group_columns: Vec<ColumnName<'a>>,
-        aggregates: Vec<(ColumnName<'a>, AggregateType)>,
-        window: i64,
-    ) -> BTreeMap<GroupKey<'_>, Vec<(ColumnName<'a>, AggregateResult<'_>)>> {
-        // identify segments where time range and predicates match could match
-        // using segment meta data, and then execute against those segments and
-        // merge results.
-        todo!()
+        predicate: Predicate,
+        group_columns: &'input Selection<'_>,
+        aggregates: &'input [(ColumnName<'input>, AggregateType)],
+        every: i64,
+        offset: i64,
+    ) -> Result<ReadWindowAggregateResults> {
+        let (meta, row_groups) = self.filter_row_groups(&predicate);
+
+        // Filter out any column names that we do not have data for.
+        let mut group_columns = match group_columns {
+            Selection::All => meta.schema_for_tag_columns(),
+            Selection::Some(column_names) => meta.schema_for_column_names(column_names),
+        };
+        // the row groups can only group by the encoded ids of tag columns
+        for (column_type, _) in &group_columns {
+            ensure!(
+                matches!(column_type, ColumnType::Tag(_)),
+                UnsupportedGroupColumn {
+                    column_name: column_type.as_str(),
+                }
+            );
+        }
+        group_columns.extend(meta.schema_for_column_names(&[row_group::TIME_COLUMN_NAME]));
+
+        let schema = ResultSchema {
+            group_columns,
+            aggregate_columns: meta.schema_for_aggregate_column_names(aggregates),
+            ..ResultSchema::default()
+        };
+
+        // return the iterator to build the results.
+        Ok(ReadWindowAggregateResults {
+            schema,
+            predicate,
+            row_groups,
+            every,
+            offset,
+            ..Default::default()
+        })
     }
 
     // Perform aggregates without any grouping. Filtering on optional predicates
@@ -443,26 +528,39 @@ impl Table {
     }
 
     /// Returns the distinct set of tag values (column values) for each provided
-    /// tag key, where each returned value lives in a row matching the provided
-    /// optional predicates and time range.
+    /// tag column, where each returned value lives in a row matching the
+    /// provided predicate. Values are added to `dst`, which may already
+    /// contain values found in other tables.
     ///
-    /// As a special case, if `tag_keys` is empty then all distinct values for
-    /// all columns (tag keys) are returned for the chunk.
-    pub fn tag_values<'a>(
+    /// As a special case, if `columns` is `Selection::All` then all distinct
+    /// values for all tag columns are returned for the table.
+    pub fn column_values(
         &self,
-        time_range: (i64, i64),
-        predicates: &[(&str, &str)],
-        tag_keys: &[String],
-        found_tag_values: &BTreeMap<String, BTreeSet<&String>>,
-    ) -> BTreeMap<ColumnName<'a>, BTreeSet<&String>> {
-        // identify segments where time range, predicates and tag keys match
-        // could match using segment meta data, and then execute against those
-        // segments and merge results.
-        //
-        // For each segment push the tag values that have already been found for
-        // the tag key down in an attempt to reduce execution against columns
-        // that only have values that have already been found.
-        todo!();
+        predicate: &Predicate,
+        columns: &Selection<'_>,
+        mut dst: BTreeMap<String, BTreeSet<String>>,
+    ) -> BTreeMap<String, BTreeSet<String>> {
+        // Identify row groups where the predicate could match using row group
+        // meta data, and then execute against those row groups, pushing the
+        // values found so far down so that columns only containing those
+        // values can be skipped.
+        let (meta, row_groups) = self.filter_row_groups(predicate);
+
+        let columns = match columns {
+            Selection::All => meta
+                .columns
+                .iter()
+                .filter(|(_, schema)| matches!(schema.typ, ColumnType::Tag(_)))
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            Selection::Some(names) => names.to_vec(),
+        };
+
+        for row_group in row_groups {
+            dst = row_group.column_values(predicate, &columns, dst);
+        }
+
+        dst
     }
 
     /// Determines if this table could satisfy the provided predicate.
@@ -478,10 +576,11 @@ impl Table {
         };
 
         // if the table doesn't have a column for one of the predicate's
-        // expressions then the table cannot satisfy the predicate.
+        // expressions then the table cannot satisfy the predicate, unless the
+        // expression is matching NULL values.
         if !predicate
             .iter()
-            .all(|expr| meta.columns.contains_key(expr.column()))
+            .all(|expr| meta.columns.contains_key(expr.column()) || expr.matches_null())
         {
             return false;
         }
@@ -505,10 +604,11 @@ impl Table {
         };
 
         // if the table doesn't have a column for one of the predicate's
-        // expressions then the table cannot satisfy the predicate.
+        // expressions then the table cannot satisfy the predicate, unless the
+        // expression is matching NULL values.
         if !predicate
             .iter()
-            .all(|expr| meta.columns.contains_key(expr.column()))
+            .all(|expr| meta.columns.contains_key(expr.column()) || expr.matches_null())
         {
             return false;
         }
@@ -627,6 +727,15 @@ impl MetaData {
             .collect::<Vec<_>>()
     }
 
+    // As `schema_for_column_names` but for all tag columns in the table.
+    fn schema_for_tag_columns(&self) -> Vec<(ColumnType, LogicalDataType)> {
+        self.columns
+            .values()
+            .filter(|schema| matches!(schema.typ, ColumnType::Tag(_)))
+            .map(|schema| (schema.typ.clone(), schema.logical_data_type))
+            .collect::<Vec<_>>()
+    }
+
     // As `schema_for_column_names` but also embeds the provided aggregate type.
     fn schema_for_aggregate_column_names(
         &self,
@@ -863,6 +972,98 @@ impl Iterator for ReadAggregateResults {
     }
 }
 
+#[derive(Default)]
+pub struct ReadWindowAggregateResults {
+    // schema information for the results. The last group column is always the
+    // time column holding window bounds.
+    schema: ResultSchema,
+
+    // the predicate to apply to each row group.
+    predicate: Predicate,
+
+    // row groups that will be executed against. The columns to group on and the
+    // aggregates to produce are determined by the `schema`.
+    row_groups: Vec<Arc<RowGroup>>,
+
+    // the window duration and offset, in nanoseconds.
+    every: i64,
+    offset: i64,
+
+    drained: bool, // currently this iterator only yields once.
+}
+
+impl ReadWindowAggregateResults {
+    /// Returns the schema associated with table result and therefore all of
+    /// results from row groups.
+    pub fn schema(&self) -> &ResultSchema {
+        &self.schema
+    }
+
+    // As `ReadAggregateResults::next_merged_result`, merging windowed results
+    // across all row groups for the table.
+    fn next_merged_result(&mut self) -> Option<row_group::ReadAggregateResult<'_>> {
+        if self.row_groups.is_empty() || self.drained {
+            return None;
+        }
+
+        // The time column is added to the group key by the row group itself.
+        let group_columns = self
+            .schema
+            .group_column_names_iter()
+            .take(self.schema.group_columns.len() - 1)
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>();
+        let aggregates = self
+            .schema
+            .aggregate_columns
+            .iter()
+            .map(|(name, agg_type, _)| (name.as_str(), *agg_type))
+            .collect::<Vec<_>>();
+
+        let mut merged_results = row_group::ReadAggregateResult {
+            schema: self.schema.clone(),
+            ..Default::default()
+        };
+        for row_group in &self.row_groups {
+            let result = row_group.read_window_aggregate(
+                &self.predicate,
+                &group_columns,
+                &aggregates,
+                self.every,
+                self.offset,
+            );
+
+            if result.is_empty() {
+                continue;
+            }
+            assert_eq!(result.schema(), self.schema()); // validate schema
+
+            // merge result into on-going results.
+            merged_results = merged_results.merge(result);
+        }
+
+        // results are emitted ordered by group key and window.
+        if !merged_results.group_keys_sorted() {
+            merged_results.sort();
+        }
+
+        self.drained = true;
+        Some(merged_results)
+    }
+}
+
+/// Implements an iterator on the Table's results for `read_window_aggregate`.
+/// As with `ReadAggregateResults`, results from all row groups are merged
+/// before a single record batch is yielded.
+impl Iterator for ReadWindowAggregateResults {
+    type Item = RecordBatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_merged_result()
+            .map(|merged_result| merged_result.try_into().unwrap())
+    }
+}
+
 // Helper type that can pretty print a set of results for `read_aggregate`.
 struct DisplayReadAggregateResults<'a>(Vec<row_group::ReadAggregateResult<'a>>);
 
@@ -1206,6 +1407,86 @@ mod test {
         assert!(matches!(results.next_merged_result(), None));
     }
 
+    #[test]
+    fn read_window_aggregate() {
+        // Build first row group.
+        let mut columns = BTreeMap::new();
+        columns.insert(
+            "time".to_string(),
+            ColumnType::create_time(&[100, 200, 300]),
+        );
+        columns.insert(
+            "region".to_string(),
+            ColumnType::create_tag(&["west", "west", "east"]),
+        );
+        let rg = RowGroup::new(3, columns);
+        let mut table = Table::new("cpu", rg);
+
+        // Build another row group, overlapping in time with the first.
+        let mut columns = BTreeMap::new();
+        columns.insert("time".to_string(), ColumnType::create_time(&[150, 310]));
+        columns.insert(
+            "region".to_string(),
+            ColumnType::create_tag(&["west", "north"]),
+        );
+        let rg = RowGroup::new(2, columns);
+        table.add_row_group(rg);
+
+        // group on all tag columns, windows of 200ns
+        let mut results = table
+            .read_window_aggregate(
+                Predicate::default(),
+                &Selection::All,
+                &[("time", AggregateType::Count), ("time", AggregateType::Max)],
+                200,
+                0,
+            )
+            .unwrap();
+
+        assert_eq!(
+            DisplayReadAggregateResults(vec![results.next_merged_result().unwrap()]).to_string(),
+            "region,time,time_count,time_max
+east,400,1,300
+north,400,1,310
+west,200,2,150
+west,400,1,200
+",
+        );
+        assert!(matches!(results.next_merged_result(), None));
+
+        // apply a predicate
+        let mut results = table
+            .read_window_aggregate(
+                Predicate::new(vec![BinaryExpr::from(("region", "=", "west"))]),
+                &Selection::Some(&[]),
+                &[("time", AggregateType::Count)],
+                1000,
+                0,
+            )
+            .unwrap();
+
+        assert_eq!(
+            DisplayReadAggregateResults(vec![results.next_merged_result().unwrap()]).to_string(),
+            "time,time_count\n1000,3\n",
+        );
+
+        // only tag columns can be grouped on
+        let err = table
+            .read_window_aggregate(
+                Predicate::default(),
+                &Selection::Some(&["time"]),
+                &[("time", AggregateType::Count)],
+                1000,
+                0,
+            )
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::UnsupportedGroupColumn { column_name } if column_name == "time"),
+            "{}",
+            err
+        );
+    }
+
     #[test]
     fn read_aggregate_result_display() {
         let mut result_a = ReadAggregateResult {
@@ -1269,4 +1550,65 @@ west,host-b,100
 "
         );
     }
+
+    #[test]
+    fn column_values() {
+        // Build first row group.
+        let mut columns = BTreeMap::new();
+        columns.insert(
+            "time".to_string(),
+            ColumnType::create_time(&[100, 200, 300]),
+        );
+        columns.insert(
+            "region".to_string(),
+            ColumnType::create_tag(&["west", "west", "east"]),
+        );
+        columns.insert("host".to_string(), ColumnType::create_tag(&["a", "b", "c"]));
+        let rg = RowGroup::new(3, columns);
+        let mut table = Table::new("cpu", rg);
+
+        // Build another row group, which can be pruned by time.
+        let mut columns = BTreeMap::new();
+        columns.insert("time".to_string(), ColumnType::create_time(&[1000, 1100]));
+        columns.insert(
+            "region".to_string(),
+            ColumnType::create_tag(&["south", "north"]),
+        );
+        columns.insert("host".to_string(), ColumnType::create_tag(&["d", "e"]));
+        let rg = RowGroup::new(2, columns);
+        table.add_row_group(rg);
+
+        let to_set = |values: &[&str]| {
+            values
+                .iter()
+                .map(ToString::to_string)
+                .collect::<BTreeSet<_>>()
+        };
+
+        // all tag columns
+        let values = table.column_values(&Predicate::default(), &Selection::All, BTreeMap::new());
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["host"], to_set(&["a", "b", "c", "d", "e"]));
+        assert_eq!(
+            values["region"],
+            to_set(&["east", "north", "south", "west"])
+        );
+
+        // second row group pruned by the time range
+        let values = table.column_values(
+            &Predicate::with_time_range(&[], 0, 250),
+            &Selection::Some(&["region"]),
+            BTreeMap::new(),
+        );
+        assert_eq!(values.len(), 1);
+        assert_eq!(values["region"], to_set(&["west"]));
+
+        // predicate on another tag column
+        let values = table.column_values(
+            &Predicate::new(vec![BinaryExpr::from(("region", "=", "north"))]),
+            &Selection::Some(&["host"]),
+            BTreeMap::new(),
+        );
+        assert_eq!(values["host"], to_set(&["e"]));
+    }
 }
@@ -7,11 +7,14 @@ use data_types::{
 use generated_types::wal;
 use object_store::path::ObjectStorePath;
 
+use crate::sequence_set::SequenceSet;
+
 use std::{
     collections::BTreeMap,
     convert::{TryFrom, TryInto},
     mem,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 //use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
@@ -20,6 +23,7 @@ use chrono::{DateTime, Utc};
 use crc32fast::Hasher;
 use data_types::database_rules::WalBufferConfig;
 use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tokio::sync::broadcast;
 use tracing::warn;
 
 #[derive(Debug, Snafu)]
@@ -68,10 +72,21 @@ pub enum Error {
 
     #[snafu(display("the flatbuffers Segment is invalid"))]
     InvalidFlatbuffersSegment,
+
+    #[snafu(display(
+        "writes from writer {} up to sequence {} are no longer in the buffer",
+        writer,
+        sequence
+    ))]
+    WritesNotBuffered { writer: WriterId, sequence: u64 },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// The number of writes a subscriber to the live writes of a buffer can fall
+/// behind before it has to catch up from the buffer's segments
+const LIVE_WRITES_CAPACITY: usize = 1024;
+
 /// An in-memory buffer of a write ahead log. It is split up into segments,
 /// which can be persisted to object storage.
 #[derive(Debug)]
@@ -80,9 +95,19 @@ pub struct Buffer {
     current_size: u64,
     segment_size: u64,
     pub persist: bool,
+    /// If set, the open segment is closed once this long has passed since
+    /// its first write, even if it hasn't reached `segment_size`
+    pub close_segment_after: Option<Duration>,
     open_segment: Segment,
+    /// When the first write was appended to the open segment
+    open_segment_started: Option<Instant>,
     closed_segments: Vec<Arc<Segment>>,
     rollover_behavior: WalBufferRollover,
+    /// The highest sequence of each writer whose writes are not all in the
+    /// buffer, because they were dropped or written before a restart
+    missing: BTreeMap<WriterId, u64>,
+    /// Sends every write appended to the buffer to its subscribers
+    live_writes: broadcast::Sender<Arc<ReplicatedWrite>>,
 }
 
 impl Buffer {
@@ -96,10 +121,14 @@ impl Buffer {
             max_size,
             segment_size,
             persist,
+            close_segment_after: None,
             rollover_behavior,
             open_segment: Segment::new(1),
+            open_segment_started: None,
             current_size: 0,
             closed_segments: vec![],
+            missing: BTreeMap::new(),
+            live_writes: broadcast::channel(LIVE_WRITES_CAPACITY).0,
         }
     }
 
@@ -128,6 +157,8 @@ impl Buffer {
                         "WAL is full, dropping incoming write for current segment (segment id: {:?})",
                         self.open_segment.id,
                     );
+                    let (writer, sequence) = write.writer_and_sequence();
+                    self.mark_missing(writer, sequence);
                     return Ok(None);
                 }
                 WalBufferRollover::DropOldSegment => {
@@ -150,19 +181,107 @@ impl Buffer {
         let mut closed_segment = None;
 
         self.current_size += write_size;
-        self.open_segment.append(write)?;
+        self.open_segment.append(Arc::clone(&write))?;
+        self.open_segment_started.get_or_insert_with(Instant::now);
+        // there may be no subscribers to send to, which is fine
+        let _ = self.live_writes.send(write);
         if self.open_segment.size > self.segment_size {
-            let next_id = self.open_segment.id + 1;
-            let segment = mem::replace(&mut self.open_segment, Segment::new(next_id));
-            let segment = Arc::new(segment);
-
-            self.closed_segments.push(segment.clone());
-            closed_segment = Some(segment);
+            closed_segment = Some(self.close_open_segment());
         }
 
         Ok(closed_segment)
     }
 
+    /// Returns when the open segment should be closed because of
+    /// `close_segment_after`, if it has any writes.
+    pub fn open_segment_deadline(&self) -> Option<Instant> {
+        let close_segment_after = self.close_segment_after?;
+        self.open_segment_started
+            .map(|started| started + close_segment_after)
+    }
+
+    /// Closes the open segment if it has been open for longer than
+    /// `close_segment_after` at `now`, returning it.
+    pub fn close_expired_segment(&mut self, now: Instant) -> Option<Arc<Segment>> {
+        match self.open_segment_deadline() {
+            Some(deadline) if deadline <= now => Some(self.close_open_segment()),
+            _ => None,
+        }
+    }
+
+    // Replaces the open segment with an empty one, returning the closed segment
+    fn close_open_segment(&mut self) -> Arc<Segment> {
+        let next_id = self.open_segment.id + 1;
+        let segment = mem::replace(&mut self.open_segment, Segment::new(next_id));
+        let segment = Arc::new(segment);
+        self.open_segment_started = None;
+
+        self.closed_segments.push(segment.clone());
+        segment
+    }
+
+    /// Continues the numbering of segments after `segment_id`, the id of the
+    /// last segment persisted before a restart, so that persisting new
+    /// segments does not overwrite it. Has no effect once writes have been
+    /// appended to the buffer.
+    pub fn resume_after_segment(&mut self, segment_id: u64) {
+        if self.closed_segments.is_empty()
+            && self.open_segment.writes.is_empty()
+            && self.open_segment.id <= segment_id
+        {
+            self.open_segment = Segment::new(segment_id + 1);
+        }
+    }
+
+    /// Records that the writes from `writer` up to `sequence` may not be in
+    /// the buffer, such as the writes restored from object storage after a
+    /// restart.
+    pub fn mark_missing(&mut self, writer: WriterId, sequence: u64) {
+        let missing = self.missing.entry(writer).or_default();
+        *missing = (*missing).max(sequence);
+    }
+
+    /// Returns the writes that aren't in `seen`, which has the sequences
+    /// seen from each writer; every write from the writers not in it is
+    /// returned. Also returns a receiver of the writes appended from now on,
+    /// some of which may be in the returned writes too.
+    ///
+    /// An error is returned if some of the writes that haven't been seen are
+    /// no longer in the buffer.
+    pub(crate) fn subscribe(
+        &self,
+        seen: &BTreeMap<WriterId, SequenceSet>,
+    ) -> Result<(
+        Vec<Arc<ReplicatedWrite>>,
+        broadcast::Receiver<Arc<ReplicatedWrite>>,
+    )> {
+        for (&writer, &sequence) in &self.missing {
+            ensure!(
+                seen.get(&writer)
+                    .map_or(false, |seen| seen.contains_up_to(sequence)),
+                WritesNotBuffered { writer, sequence }
+            );
+        }
+
+        // writes from a writer can be appended out of order, so every write
+        // that hasn't been seen is returned, not just those after the
+        // highest sequence seen
+        let writes = self
+            .closed_segments
+            .iter()
+            .flat_map(|segment| segment.writes.iter())
+            .chain(self.open_segment.writes.iter())
+            .filter(|write| {
+                let (writer, sequence) = write.writer_and_sequence();
+                seen.get(&writer)
+                    .map_or(true, |seen| !seen.contains(sequence))
+            })
+            .cloned()
+            .collect();
+
+        Ok((writes, self.live_writes.subscribe()))
+    }
+
     /// Returns the current size of the buffer.
     pub fn size(&self) -> u64 {
         self.current_size
@@ -241,18 +360,23 @@ impl Buffer {
     fn remove_oldest_segment(&mut self) -> u64 {
         let removed_segment = self.closed_segments.remove(0);
         self.current_size -= removed_segment.size;
+        for (&writer, summary) in &removed_segment.writers {
+            self.mark_missing(writer, summary.end_sequence);
+        }
         removed_segment.id
     }
 }
 
 impl From<&WalBufferConfig> for Buffer {
     fn from(config: &WalBufferConfig) -> Self {
-        Self::new(
+        let mut buffer = Self::new(
             config.buffer_size,
             config.segment_size,
             config.buffer_rollover,
             config.store_segments,
-        )
+        );
+        buffer.close_segment_after = config.close_segment_after;
+        buffer
     }
 }
 
@@ -538,6 +662,57 @@ mod tests {
         assert_eq!(segment.id, 2);
     }
 
+    #[test]
+    fn closes_segment_after_duration() {
+        let max = 1 << 16;
+        let segment = 1 << 16;
+        let mut buf = Buffer::new(max, segment, WalBufferRollover::ReturnError, false);
+        buf.close_segment_after = Some(Duration::from_secs(60));
+        let start = Instant::now();
+
+        // an empty segment is never closed
+        assert!(buf.open_segment_deadline().is_none());
+        assert!(buf
+            .close_expired_segment(start + Duration::from_secs(120))
+            .is_none());
+
+        let write = lp_to_replicated_write(1, 1, "cpu val=1 10");
+        assert!(buf.append(write).unwrap().is_none());
+        let deadline = buf.open_segment_deadline().unwrap();
+        assert!(deadline >= start + Duration::from_secs(60));
+
+        assert!(buf
+            .close_expired_segment(deadline - Duration::from_secs(1))
+            .is_none());
+        let closed = buf.close_expired_segment(deadline).unwrap();
+        assert_eq!(closed.id, 1);
+        assert_eq!(closed.writes.len(), 1);
+
+        // the next segment's deadline starts with its first write
+        assert!(buf.open_segment_deadline().is_none());
+        let write = lp_to_replicated_write(1, 2, "cpu val=1 10");
+        buf.append(write).unwrap();
+        assert!(buf.open_segment_deadline().unwrap() >= deadline);
+    }
+
+    #[test]
+    fn resume_after_segment() {
+        let max = 1 << 16;
+        let segment = 1;
+        let mut buf = Buffer::new(max, segment, WalBufferRollover::ReturnError, false);
+        buf.resume_after_segment(41);
+
+        let write = lp_to_replicated_write(1, 1, "cpu val=1 10");
+        let segment = buf.append(write).unwrap().unwrap();
+        assert_eq!(segment.id, 42);
+
+        // resuming after writes have been appended has no effect
+        buf.resume_after_segment(100);
+        let write = lp_to_replicated_write(1, 2, "cpu val=1 10");
+        let segment = buf.append(write).unwrap().unwrap();
+        assert_eq!(segment.id, 43);
+    }
+
     #[test]
     fn drops_persisted_segment_when_over_size() {
         let max = 600;
@@ -805,6 +980,83 @@ mod tests {
         );
     }
 
+    fn watermark(since: &[(WriterId, u64)]) -> BTreeMap<WriterId, SequenceSet> {
+        since
+            .iter()
+            .map(|&(writer, sequence)| (writer, SequenceSet::up_to(sequence)))
+            .collect()
+    }
+
+    fn sequences(writes: Vec<Arc<ReplicatedWrite>>) -> Vec<(WriterId, u64)> {
+        writes.iter().map(|w| w.writer_and_sequence()).collect()
+    }
+
+    #[test]
+    fn subscribe_from_watermark() {
+        let max = 1 << 16;
+        let segment = 1;
+        let mut buf = Buffer::new(max, segment, WalBufferRollover::ReturnError, false);
+        buf.append(lp_to_replicated_write(1, 1, "cpu val=1 10"))
+            .unwrap();
+        buf.append(lp_to_replicated_write(2, 1, "cpu val=2 10"))
+            .unwrap();
+        buf.append(lp_to_replicated_write(1, 2, "cpu val=3 10"))
+            .unwrap();
+
+        // every write of a writer missing from the watermark is returned
+        let (writes, mut live) = buf.subscribe(&watermark(&[(1, 1)])).unwrap();
+        assert_eq!(sequences(writes), vec![(2, 1), (1, 2)]);
+
+        buf.append(lp_to_replicated_write(2, 2, "cpu val=4 10"))
+            .unwrap();
+        let write = live.try_recv().unwrap();
+        assert_eq!(write.writer_and_sequence(), (2, 2));
+
+        let (writes, _) = buf.subscribe(&watermark(&[(1, 2), (2, 2)])).unwrap();
+        assert!(writes.is_empty());
+    }
+
+    #[test]
+    fn subscribe_to_out_of_order_writes() {
+        let max = 1 << 16;
+        let segment = 1;
+        let mut buf = Buffer::new(max, segment, WalBufferRollover::ReturnError, false);
+        buf.append(lp_to_replicated_write(1, 3, "cpu val=3 10"))
+            .unwrap();
+        buf.append(lp_to_replicated_write(1, 2, "cpu val=2 10"))
+            .unwrap();
+
+        // a write older than one already seen is still returned
+        let mut seen = watermark(&[(1, 1)]);
+        seen.get_mut(&1).unwrap().insert(3);
+        let (writes, _) = buf.subscribe(&seen).unwrap();
+        assert_eq!(sequences(writes), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn subscribe_fails_when_writes_are_no_longer_buffered() {
+        let max = 1 << 16;
+        let segment = 1 << 16;
+        let mut buf = Buffer::new(max, segment, WalBufferRollover::ReturnError, false);
+        buf.append(lp_to_replicated_write(1, 2, "cpu val=1 10"))
+            .unwrap();
+
+        // writes up to sequence 1 were restored from object storage
+        buf.mark_missing(1, 1);
+
+        let err = buf.subscribe(&BTreeMap::new()).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::WritesNotBuffered {
+                writer: 1,
+                sequence: 1
+            }
+        ));
+
+        let (writes, _) = buf.subscribe(&watermark(&[(1, 1)])).unwrap();
+        assert_eq!(writes.len(), 1);
+    }
+
     #[test]
     fn object_store_path_for_segment() {
         let path = ObjectStorePath::from_cloud_unchecked("1/mydb");
@@ -865,11 +1117,9 @@ mod tests {
     ) -> Arc<ReplicatedWrite> {
         let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
         let rules = DatabaseRules::default();
-        Arc::new(lines_to_replicated_write(
-            writer_id,
-            sequence_number,
-            &lines,
-            &rules,
-        ))
+        Arc::new(
+            lines_to_replicated_write(writer_id, sequence_number, &lines, &rules)
+                .expect("computing partition keys"),
+        )
     }
 }
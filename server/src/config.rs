@@ -1,5 +1,5 @@
 /// This module contains code for managing the configuration of the server.
-use crate::{db::Db, Error, Result};
+use crate::{db::Db, hash_ring::HashRing, Error, Result};
 use data_types::{
     database_rules::{DatabaseRules, HostGroup, HostGroupId},
     DatabaseName,
@@ -60,14 +60,25 @@ impl Config {
         state.databases.get(name).cloned()
     }
 
+    /// Returns all committed databases, ordered by name
+    pub(crate) fn databases(&self) -> Vec<(DatabaseName<'static>, Arc<Db>)> {
+        let state = self.state.read().expect("mutex poisoned");
+        state
+            .databases
+            .iter()
+            .map(|(name, db)| (name.clone(), Arc::clone(db)))
+            .collect()
+    }
+
     pub(crate) fn create_host_group(&self, host_group: HostGroup) {
+        let ring = Arc::new(HashRing::new(&host_group.hosts));
         let mut state = self.state.write().expect("mutex poisoned");
-        state
-            .host_groups
-            .insert(host_group.id.clone(), Arc::new(host_group));
+        state.host_groups.insert(host_group.id, ring);
     }
 
-    pub(crate) fn host_group(&self, host_group_id: &str) -> Option<Arc<HostGroup>> {
+    /// Returns the hash ring of the hosts in a host group, which is built
+    /// when the host group is created
+    pub(crate) fn host_group(&self, host_group_id: &str) -> Option<Arc<HashRing>> {
         let state = self.state.read().expect("mutex poinsoned");
         state.host_groups.get(host_group_id).cloned()
     }
@@ -101,7 +112,7 @@ pub fn object_store_path_for_database_config(
 struct ConfigState {
     reservations: BTreeSet<DatabaseName<'static>>,
     databases: BTreeMap<DatabaseName<'static>, Arc<Db>>,
-    host_groups: BTreeMap<HostGroupId, Arc<HostGroup>>,
+    host_groups: BTreeMap<HostGroupId, Arc<HashRing>>,
 }
 
 /// CreateDatabaseHandle is retunred when a call is made to `create_db` on
@@ -2,26 +2,64 @@
 //! instances of the mutable buffer, read buffer, and object store
 
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet, HashSet},
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicU64, AtomicUsize, Ordering},
         Arc, Mutex, RwLock,
     },
 };
 
+use arrow_deps::{
+    arrow::{array::StringArray, record_batch::RecordBatch},
+    datafusion::{
+        error::DataFusionError,
+        logical_plan::{col, lit, Expr, LogicalPlan, LogicalPlanBuilder},
+        optimizer::utils::expr_to_column_names,
+    },
+    util::str_iter_to_batch,
+};
 use async_trait::async_trait;
-use data_types::{data::ReplicatedWrite, database_rules::DatabaseRules, selection::Selection};
+use data_types::{
+    data::ReplicatedWrite,
+    database_rules::{CompiledMatcher, DatabaseRules, Subscription},
+    partition_metadata::Table as TableStats,
+    schema::{InfluxColumnType, InfluxFieldType, Schema, TIME_COLUMN_NAME},
+    selection::Selection,
+    MEASUREMENT_COLUMN_NAME,
+};
 use mutable_buffer::MutableBufferDb;
-use query::{Database, PartitionChunk};
-use read_buffer::Database as ReadBufferDb;
+use object_store::{path::ObjectStorePath, ObjectStore};
+use query::{
+    exec::{
+        make_schema_pivot, stringset::StringSet, FieldListPlan, SeriesSetPlan, SeriesSetPlans,
+        StringSetPlan,
+    },
+    group_by::{Aggregate, GroupByAndAggregate, WindowDuration},
+    predicate::Predicate,
+    provider::ProviderBuilder,
+    util::AndExprBuilder,
+    Database, PartitionChunk,
+};
+use read_buffer::{AggregateType, Database as ReadBufferDb};
 use serde::{Deserialize, Serialize};
 use snafu::{OptionExt, ResultExt, Snafu};
+use tracing::error;
+
+use tokio::sync::watch;
 
-use crate::buffer::Buffer;
+use crate::{
+    buffer::Buffer, replication_queue::ReplicationQueue, sequence_set::PartitionSequenceSet,
+};
 
 mod chunk;
 use chunk::DBChunk;
+mod lifecycle;
+use lifecycle::LifecycleState;
+mod local_wal;
+use local_wal::LocalWal;
 pub mod pred;
+mod recovery;
+mod sort;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -61,11 +99,94 @@ pub enum Error {
 
     #[snafu(display("Error dropping data from read buffer: {}", source))]
     ReadBufferDrop { source: read_buffer::Error },
+
+    #[snafu(display("Error querying read buffer: {}", source))]
+    ReadBufferRead { source: read_buffer::Error },
+
+    #[snafu(display("Error planning read buffer query: {}", source))]
+    ReadBufferPlan { source: DataFusionError },
+
+    #[snafu(display("Error sorting table '{}' for the read buffer: {}", table_name, source))]
+    ReadBufferSort {
+        table_name: String,
+        source: sort::Error,
+    },
+
+    #[snafu(display("Error reading chunk: {}", source))]
+    ChunkRead { source: chunk::Error },
+
+    #[snafu(display("Error creating table provider for chunk: {}", source))]
+    ChunkProvider { source: query::provider::Error },
+
+    #[snafu(display("Error planning chunk query: {}", source))]
+    ChunkPlan { source: DataFusionError },
+
+    #[snafu(display("Error snapshotting chunk to object store: {}", source))]
+    Snapshotting { source: crate::snapshot::Error },
+
+    #[snafu(display("Error listing object store: {}", source))]
+    ListingObjectStore { source: object_store::Error },
+
+    #[snafu(display("Error reading from object store: {}", source))]
+    ReadingObjectStore { source: object_store::Error },
+
+    #[snafu(display("Error reading WAL segment from object store: {}", source))]
+    ReadingWalSegment { source: crate::buffer::Error },
+
+    #[snafu(display(
+        "Invalid read only partition id {}, expected <writer id>/<database>/<partition key>",
+        partition_id
+    ))]
+    InvalidPartitionId { partition_id: String },
+
+    #[snafu(display("Error creating local WAL directory {:?}: {}", path, source))]
+    CreatingLocalWalDirectory {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Error opening local WAL in {:?}: {}", path, source))]
+    OpeningLocalWal {
+        path: std::path::PathBuf,
+        source: wal::writer::Error,
+    },
+
+    #[snafu(display("Local WAL in {:?} has unsupported format {:?}", path, format))]
+    UnsupportedLocalWalFormat {
+        path: std::path::PathBuf,
+        format: wal::writer::WalFormat,
+    },
+
+    #[snafu(display(
+        "Error recovering local WAL in {:?}: {}. It can be repaired with `influxdb_iox wal repair`",
+        path,
+        source
+    ))]
+    RecoveringLocalWal {
+        path: std::path::PathBuf,
+        source: wal::Error,
+    },
+
+    #[snafu(display("Error reading local WAL in {:?}: {}", path, source))]
+    ReadingLocalWal {
+        path: std::path::PathBuf,
+        source: wal::Error,
+    },
+
+    #[snafu(display("Error writing to local WAL: {}", source))]
+    WritingLocalWal { source: wal::writer::Error },
+
+    #[snafu(display("Error deleting snapshotted local WAL files: {}", source))]
+    TruncatingLocalWal { source: wal::writer::Error },
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 const STARTING_SEQUENCE: u64 = 1;
 
+/// The name of the column produced by schema pivot plans, which find the
+/// names of tag columns
+const TAG_NAMES_COLUMN_NAME: &str = "non_null_column";
+
 #[derive(Debug, Serialize, Deserialize)]
 /// This is the main IOx Database object. It is the root object of any
 /// specific InfluxDB IOx instance
@@ -92,9 +213,119 @@ pub struct Db {
     /// and to persist segments in object storage for recovery.
     pub wal_buffer: Option<Mutex<Buffer>>,
 
+    #[serde(skip)]
+    /// Writes waiting to be replicated to host groups in the background,
+    /// after the write was acknowledged by `replication_count` host groups.
+    pub replication_queue: Mutex<ReplicationQueue>,
+
     #[serde(skip)]
     sequence: AtomicU64,
+
+    #[serde(skip)]
+    /// The subscriptions of the rules with their matchers compiled, so
+    /// that they aren't parsed again for every write
+    subscriptions: Vec<(Subscription, CompiledMatcher)>,
+
+    #[serde(skip)]
+    /// The chunks that the lifecycle policy is persisting, or has
+    /// persisted, to object storage
+    lifecycle_state: Mutex<LifecycleState>,
+
+    #[serde(skip)]
+    /// The estimated number of bytes used by the mutable buffer and read
+    /// buffer. See `memory_usage`.
+    memory_usage: AtomicUsize,
+
+    #[serde(skip)]
+    /// Chunks that have been snapshotted to object storage, by partition key,
+    /// source (see `DBChunk::source`) and chunk id. These are read from
+    /// object storage when the chunk is in neither the mutable buffer nor
+    /// the read buffer.
+    parquet_chunks: RwLock<BTreeMap<(String, Option<String>, u32), Arc<DBChunk>>>,
+
+    #[serde(skip)]
+    /// The WAL on local disk that writes are appended to before they are
+    /// acknowledged, if the rules have a `local_wal_config`. It is opened
+    /// by `open_local_wal`.
+    local_wal: RwLock<Option<Arc<LocalWal>>>,
+
+    #[serde(skip)]
+    /// The writes replicated to this database that have been applied, or
+    /// are being applied. See `start_applying`.
+    replicated_writes: Mutex<ReplicatedWrites>,
+}
+
+/// The partitions of the replicated writes that have been applied, or are
+/// being applied, by writer id. A write can be split by partition key
+/// between the hosts of a host group, and a host can be sent more than one
+/// part of the same write, so each partition of a write is tracked
+/// separately.
+#[derive(Debug, Default)]
+struct ReplicatedWrites {
+    applied: BTreeMap<u32, PartitionSequenceSet>,
+    /// The partitions being applied, with a receiver that sees its sender
+    /// dropped once the attempt applying them is done
+    applying: BTreeMap<(u32, u64, String), watch::Receiver<()>>,
+}
+
+impl ReplicatedWrites {
+    fn is_applied(&self, writer_id: u32, sequence: u64, partition_key: &str) -> bool {
+        self.applied
+            .get(&writer_id)
+            .map_or(false, |applied| applied.contains(sequence, partition_key))
+    }
+
+    fn mark_applied(&mut self, writer_id: u32, sequence: u64, partition_key: &str) {
+        self.applied
+            .entry(writer_id)
+            .or_insert_with(|| PartitionSequenceSet::up_to(STARTING_SEQUENCE - 1))
+            .insert(sequence, partition_key);
+    }
+}
+
+/// The partitions of a replicated write that are being applied, returned by
+/// `Db::start_applying`. Unless it is finished with `finish`, the partitions
+/// aren't recorded as applied and can be sent again.
+#[derive(Debug)]
+pub struct ApplyingWrite<'a> {
+    writes: &'a Mutex<ReplicatedWrites>,
+    writer_id: u32,
+    sequence: u64,
+    partition_keys: Vec<String>,
+    applied: bool,
+    /// Dropped along with this, waking any attempt waiting for these
+    /// partitions
+    _done: watch::Sender<()>,
+}
+
+impl<'a> ApplyingWrite<'a> {
+    /// The partition keys of the write that haven't been applied yet, and
+    /// so have to be
+    pub fn partition_keys(&self) -> &[String] {
+        &self.partition_keys
+    }
+
+    /// Records that applying the partitions is done. If they weren't
+    /// `applied` they can be sent again.
+    pub fn finish(mut self, applied: bool) {
+        self.applied = applied;
+    }
+}
+
+impl<'a> Drop for ApplyingWrite<'a> {
+    fn drop(&mut self) {
+        let mut writes = self.writes.lock().expect("mutex poisoned");
+        for partition_key in self.partition_keys.drain(..) {
+            if self.applied {
+                writes.mark_applied(self.writer_id, self.sequence, &partition_key);
+            }
+            writes
+                .applying
+                .remove(&(self.writer_id, self.sequence, partition_key));
+        }
+    }
 }
+
 impl Db {
     pub fn new(
         rules: DatabaseRules,
@@ -104,12 +335,41 @@ impl Db {
     ) -> Self {
         let wal_buffer = wal_buffer.map(Mutex::new);
         let read_buffer = Arc::new(RwLock::new(read_buffer));
+        let replication_queue = Mutex::new(ReplicationQueue::new(
+            rules.replication_queue_max_size,
+            rules.replication_queue_overflow,
+        ));
+        // the rules are validated when the database is created, so this only
+        // skips subscriptions of rules that were never validated
+        let subscriptions = rules
+            .subscriptions
+            .iter()
+            .filter_map(
+                |subscription| match subscription.matcher.compile(&rules.regex_cache) {
+                    Ok(matcher) => Some((subscription.clone(), matcher)),
+                    Err(e) => {
+                        error!(
+                            "skipping subscription {} with invalid matcher: {}",
+                            subscription.name, e
+                        );
+                        None
+                    }
+                },
+            )
+            .collect();
         Self {
             rules,
             mutable_buffer,
             read_buffer,
             wal_buffer,
+            replication_queue,
             sequence: AtomicU64::new(STARTING_SEQUENCE),
+            subscriptions,
+            lifecycle_state: Mutex::new(LifecycleState::default()),
+            memory_usage: AtomicUsize::new(0),
+            parquet_chunks: RwLock::new(BTreeMap::new()),
+            local_wal: RwLock::new(None),
+            replicated_writes: Mutex::new(ReplicatedWrites::default()),
         }
     }
 
@@ -153,6 +413,46 @@ impl Db {
             .collect()
     }
 
+    /// List chunks that can be read from object storage
+    pub fn parquet_chunks(&self, partition_key: &str) -> Vec<Arc<DBChunk>> {
+        self.parquet_chunks
+            .read()
+            .expect("mutex poisoned")
+            .iter()
+            .filter(|((key, _, _), _)| key == partition_key)
+            .map(|(_, chunk)| Arc::clone(chunk))
+            .collect()
+    }
+
+    /// Makes the chunk `chunk_id` of the partition `partition_key`, which a
+    /// snapshot wrote to `data_path` in `store`, available to queries.
+    /// `source` is the id of the read only partition the chunk belongs to,
+    /// or `None` if this database wrote it. `tables` are the table
+    /// statistics saved with the snapshot.
+    pub fn add_parquet_chunk(
+        &self,
+        store: Arc<ObjectStore>,
+        data_path: ObjectStorePath,
+        partition_key: &str,
+        chunk_id: u32,
+        source: Option<&str>,
+        tables: Vec<TableStats>,
+    ) {
+        let source = source.map(ToString::to_string);
+        let chunk = DBChunk::new_parquet(
+            store,
+            data_path,
+            partition_key,
+            chunk_id,
+            source.clone(),
+            tables,
+        );
+        self.parquet_chunks
+            .write()
+            .expect("mutex poisoned")
+            .insert((partition_key.to_string(), source, chunk_id), chunk);
+    }
+
     /// Drops the specified chunk from the mutable buffer, returning
     /// the dropped chunk.
     pub async fn drop_mutable_buffer_chunk(
@@ -160,13 +460,16 @@ impl Db {
         partition_key: &str,
         chunk_id: u32,
     ) -> Result<Arc<DBChunk>> {
-        self.mutable_buffer
+        let chunk = self
+            .mutable_buffer
             .as_ref()
             .context(DatatbaseNotWriteable)?
             .drop_chunk(partition_key, chunk_id)
             .await
-            .map(DBChunk::new_mb)
-            .context(MutableBufferDrop)
+            .context(MutableBufferDrop)?;
+        self.sub_memory_usage(chunk.size());
+
+        Ok(DBChunk::new_mb(chunk))
     }
 
     /// Drops the specified chunk from the read buffer, returning
@@ -176,11 +479,14 @@ impl Db {
         partition_key: &str,
         chunk_id: u32,
     ) -> Result<Arc<DBChunk>> {
-        self.read_buffer
-            .write()
-            .expect("mutex poisoned")
+        let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
+        let size_before = read_buffer.size();
+        read_buffer
             .drop_chunk(partition_key, chunk_id)
             .context(ReadBufferDrop)?;
+        let dropped = size_before.saturating_sub(read_buffer.size());
+        drop(read_buffer);
+        self.sub_memory_usage(dropped as usize);
 
         Ok(DBChunk::new_rb(
             self.read_buffer.clone(),
@@ -221,11 +527,7 @@ impl Db {
                 .table_to_arrow(&mut batches, &stats.name, Selection::All)
                 .unwrap();
             for batch in batches.drain(..) {
-                // As implemented now, taking this write lock will wait
-                // until all reads to the read buffer to complete and
-                // then will block all reads while the insert is occuring
-                let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
-                read_buffer.upsert_partition(partition_key, mb_chunk.id(), &stats.name, batch)
+                self.load_table_to_read_buffer(partition_key, mb_chunk.id(), &stats.name, batch)?;
             }
         }
 
@@ -236,131 +538,1107 @@ impl Db {
         ))
     }
 
+    /// Sorts the rows of `batch`, the data for table `table_name`, and adds
+    /// them to the chunk `chunk_id` in the read buffer.
+    fn load_table_to_read_buffer(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+        table_name: &str,
+        batch: RecordBatch,
+    ) -> Result<()> {
+        // Sort the rows before building the row groups, because the read
+        // buffer's compression and pruning depend on row order.
+        let config = &self.rules.read_buffer_config;
+        let sort_key = match config.sort_key(table_name) {
+            Some(sort_key) => sort_key.to_vec(),
+            None => sort::default_sort_key(&batch).context(ReadBufferSort { table_name })?,
+        };
+        let row_groups = sort::sort_and_split(batch, &sort_key, config.max_row_group_rows)
+            .context(ReadBufferSort { table_name })?;
+
+        // As implemented now, taking this write lock will wait
+        // until all reads to the read buffer to complete and
+        // then will block all reads while the insert is occuring
+        let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
+        let size_before = read_buffer.size();
+        for row_group in row_groups {
+            read_buffer.upsert_partition(partition_key, chunk_id, table_name, row_group)
+        }
+        let added = read_buffer.size().saturating_sub(size_before);
+        drop(read_buffer);
+        self.add_memory_usage(added as usize);
+
+        Ok(())
+    }
+
+    /// Returns the subscriptions of the rules that writes are pushed to,
+    /// each with its compiled matcher
+    pub fn subscriptions(&self) -> &[(Subscription, CompiledMatcher)] {
+        &self.subscriptions
+    }
+
     /// Returns the next write sequence number
     pub fn next_sequence(&self) -> u64 {
         self.sequence.fetch_add(1, Ordering::SeqCst)
     }
-}
 
-impl PartialEq for Db {
-    fn eq(&self, other: &Self) -> bool {
-        self.rules == other.rules
-    }
-}
-impl Eq for Db {}
+    /// Records that the partitions `partition_keys` of the write with
+    /// `sequence` from `writer_id`, replicated from another server, are
+    /// being applied. The returned `ApplyingWrite` has the partitions that
+    /// haven't been applied yet; the others must be skipped.
+    ///
+    /// If another attempt is applying any of the partitions, such as when a
+    /// write is sent again after a timeout, this waits for it to be done
+    /// first, so that the write isn't acknowledged before it is applied.
+    ///
+    /// Only the writes applied since the server started, or restored from
+    /// the WAL by `recover`, are known.
+    pub async fn start_applying(
+        &self,
+        writer_id: u32,
+        sequence: u64,
+        partition_keys: &[&str],
+    ) -> ApplyingWrite<'_> {
+        loop {
+            let mut in_progress = {
+                let mut writes = self.replicated_writes.lock().expect("mutex poisoned");
+                let in_progress = partition_keys.iter().find_map(|partition_key| {
+                    let key = (writer_id, sequence, partition_key.to_string());
+                    writes.applying.get(&key).cloned()
+                });
+
+                match in_progress {
+                    Some(in_progress) => in_progress,
+                    None => {
+                        let (done, in_progress) = watch::channel(());
+                        let partition_keys: Vec<_> = partition_keys
+                            .iter()
+                            .filter(|key| !writes.is_applied(writer_id, sequence, key))
+                            .map(ToString::to_string)
+                            .collect();
+                        for partition_key in &partition_keys {
+                            writes.applying.insert(
+                                (writer_id, sequence, partition_key.clone()),
+                                in_progress.clone(),
+                            );
+                        }
 
-#[async_trait]
-impl Database for Db {
-    type Error = Error;
-    type Chunk = DBChunk;
+                        return ApplyingWrite {
+                            writes: &self.replicated_writes,
+                            writer_id,
+                            sequence,
+                            partition_keys,
+                            applied: false,
+                            _done: done,
+                        };
+                    }
+                }
+            };
 
-    /// Return a covering set of chunks for a particular partition
-    async fn chunks(&self, partition_key: &str) -> Vec<Arc<Self::Chunk>> {
-        // return a coverting set of chunks. TODO include read buffer
-        // chunks and take them preferentially from the read buffer.
-        // returns a coverting set of chunks -- aka take chunks from read buffer
-        // preferentially
-        let mutable_chunk_iter = self.mutable_buffer_chunks(partition_key).await.into_iter();
+            // the sender is dropped once the other attempt is done, and what
+            // it applied is checked again
+            let _ = in_progress.changed().await;
+        }
+    }
 
-        let read_buffer_chunk_iter = self.read_buffer_chunks(partition_key).await.into_iter();
+    /// Records that every partition of `write` has been applied, such as
+    /// when it is restored by `recover`
+    fn mark_applied(&self, write: &ReplicatedWrite) {
+        let (writer_id, sequence) = write.writer_and_sequence();
+        let mut writes = self.replicated_writes.lock().expect("mutex poisoned");
+        for partition_key in write.partition_keys() {
+            writes.mark_applied(writer_id, sequence, partition_key);
+        }
+    }
 
-        let chunks: BTreeMap<_, _> = mutable_chunk_iter
-            .chain(read_buffer_chunk_iter)
-            .map(|chunk| (chunk.id(), chunk))
-            .collect();
+    /// Returns the estimated number of bytes used by the data in the mutable
+    /// buffer and read buffer. Computing the size of every chunk is too
+    /// expensive to do on each write, so this is the usage as of the last
+    /// call to `update_memory_usage`, adjusted by the size of every chunk
+    /// moved into or dropped from either buffer since.
+    ///
+    /// Writes are recorded with `add_memory_usage` using the size of the
+    /// encoded write, not the (usually smaller) growth of the mutable
+    /// buffer, so between calls to `update_memory_usage` this tends to
+    /// overestimate the usage. Limits checked against it are therefore
+    /// reached early rather than late.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Acquire)
+    }
 
-        // inserting into the map will have removed any dupes
-        chunks.into_iter().map(|(_id, chunk)| chunk).collect()
+    /// Adds `bytes`, the size of data written to the mutable buffer, to the
+    /// estimated memory usage
+    pub fn add_memory_usage(&self, bytes: usize) {
+        self.memory_usage.fetch_add(bytes, Ordering::AcqRel);
     }
 
-    // Note that most of the functions below will eventually be removed from
-    // this trait. For now, pass them directly on to the local store
+    /// Subtracts `bytes`, the size of data dropped from the mutable buffer or
+    /// read buffer, from the estimated memory usage
+    fn sub_memory_usage(&self, bytes: usize) {
+        // the estimate can be below the size of the data, so don't underflow
+        let _ = self
+            .memory_usage
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |usage| {
+                Some(usage.saturating_sub(bytes))
+            });
+    }
 
-    async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
-        self.mutable_buffer
-            .as_ref()
-            .context(DatatbaseNotWriteable)?
-            .store_replicated_write(write)
-            .await
-            .context(MutableBufferWrite)
+    /// Recomputes the estimated memory usage from the sizes of the chunks in
+    /// the mutable buffer and read buffer, returning the new estimate
+    pub async fn update_memory_usage(&self) -> usize {
+        let mutable_buffer = match self.mutable_buffer.as_ref() {
+            Some(mutable_buffer) => mutable_buffer.size().await,
+            None => 0,
+        };
+        self.store_memory_usage(mutable_buffer)
     }
 
-    async fn tag_column_names(
-        &self,
-        predicate: query::predicate::Predicate,
-    ) -> Result<query::exec::StringSetPlan, Self::Error> {
-        self.mutable_buffer
-            .as_ref()
-            .context(DatabaseNotReadable)?
-            .tag_column_names(predicate)
-            .await
-            .context(MutableBufferRead)
+    /// Sets the estimated memory usage to `mutable_buffer_size`, the size of
+    /// the chunks in the mutable buffer, plus the size of the read buffer,
+    /// returning the new estimate
+    fn store_memory_usage(&self, mutable_buffer_size: usize) -> usize {
+        let read_buffer = self.read_buffer.read().expect("mutex poisoned").size() as usize;
+
+        let usage = mutable_buffer_size + read_buffer;
+        self.memory_usage.store(usage, Ordering::Release);
+        usage
     }
 
-    async fn field_column_names(
+    /// Creates plans for a windowed aggregate over every chunk in the read
+    /// buffer. The aggregates are computed by the read buffer directly over
+    /// its encoded data, so the plans only scan the (small) results.
+    ///
+    /// Returns the plans along with the (partition key, chunk id) of each
+    /// chunk planned.
+    fn read_buffer_window_plans(
         &self,
-        predicate: query::predicate::Predicate,
-    ) -> Result<query::exec::FieldListPlan, Self::Error> {
-        self.mutable_buffer
-            .as_ref()
-            .context(DatabaseNotReadable)?
-            .field_column_names(predicate)
-            .await
-            .context(MutableBufferRead)
+        predicate: &Predicate,
+        rb_predicate: &read_buffer::Predicate,
+        agg: AggregateType,
+        every: u64,
+        offset: i64,
+    ) -> Result<(Vec<SeriesSetPlan>, BTreeSet<(String, u32)>)> {
+        let read_buffer = self.read_buffer.read().expect("mutex poisoned");
+        let mut plans = vec![];
+        let mut planned_chunks = BTreeSet::new();
+
+        for partition_key in read_buffer.partition_keys() {
+            if matches!(&predicate.partition_key, Some(key) if key != &partition_key) {
+                continue;
+            }
+
+            for chunk_id in read_buffer.chunk_ids(&partition_key) {
+                let table_names = read_buffer_table_names(
+                    &read_buffer,
+                    &partition_key,
+                    chunk_id,
+                    predicate,
+                    rb_predicate,
+                )?;
+
+                for table_name in &table_names {
+                    if let Some(plan) = read_buffer_window_plan(
+                        &read_buffer,
+                        &partition_key,
+                        chunk_id,
+                        table_name,
+                        predicate,
+                        rb_predicate,
+                        agg,
+                        every,
+                        offset,
+                    )? {
+                        plans.push(plan);
+                    }
+                }
+
+                planned_chunks.insert((partition_key.clone(), chunk_id));
+            }
+        }
+
+        Ok((plans, planned_chunks))
     }
 
-    async fn column_values(
+    /// Returns the distinct non-null values of the tag column `column_name`
+    /// in all read buffer chunks matching `predicate`, or the names of the
+    /// tables with matching rows if `column_name` is the measurement. The
+    /// values are found by the read buffer directly from its encoded columns.
+    ///
+    /// Returns the values along with the (partition key, chunk id) of each
+    /// chunk searched.
+    fn read_buffer_column_values(
         &self,
         column_name: &str,
-        predicate: query::predicate::Predicate,
-    ) -> Result<query::exec::StringSetPlan, Self::Error> {
-        self.mutable_buffer
-            .as_ref()
-            .context(DatabaseNotReadable)?
-            .column_values(column_name, predicate)
-            .await
-            .context(MutableBufferRead)
+        predicate: &Predicate,
+        rb_predicate: &read_buffer::Predicate,
+    ) -> Result<(StringSet, BTreeSet<(String, u32)>)> {
+        let read_buffer = self.read_buffer.read().expect("mutex poisoned");
+        let mut values = StringSet::new();
+        let mut searched_chunks = BTreeSet::new();
+
+        for partition_key in read_buffer.partition_keys() {
+            if matches!(&predicate.partition_key, Some(key) if key != &partition_key) {
+                continue;
+            }
+
+            for chunk_id in read_buffer.chunk_ids(&partition_key) {
+                let table_names = read_buffer_table_names(
+                    &read_buffer,
+                    &partition_key,
+                    chunk_id,
+                    predicate,
+                    rb_predicate,
+                )?;
+
+                if column_name == MEASUREMENT_COLUMN_NAME {
+                    values.extend(table_names);
+                    searched_chunks.insert((partition_key.clone(), chunk_id));
+                    continue;
+                }
+
+                for table_name in &table_names {
+                    let mut chunk_values = read_buffer
+                        .tag_values(
+                            &partition_key,
+                            table_name,
+                            &[chunk_id],
+                            rb_predicate.clone(),
+                            Selection::Some(&[column_name]),
+                        )
+                        .context(ReadBufferRead)?;
+
+                    if let Some(chunk_values) = chunk_values.remove(column_name) {
+                        values.extend(chunk_values);
+                    }
+                }
+
+                searched_chunks.insert((partition_key.clone(), chunk_id));
+            }
+        }
+
+        Ok((values, searched_chunks))
     }
 
-    async fn query_series(
+    /// As `read_buffer_column_values`, but for predicates the read buffer
+    /// does not support. The column and those `predicate` refers to are read
+    /// from each table in the read buffer, filtered only by time, and
+    /// `predicate` is then applied by DataFusion.
+    ///
+    /// Returns plans producing the values along with the (partition key,
+    /// chunk id) of each chunk planned.
+    fn read_buffer_column_values_plans(
         &self,
-        predicate: query::predicate::Predicate,
-    ) -> Result<query::exec::SeriesSetPlans, Self::Error> {
-        self.mutable_buffer
-            .as_ref()
-            .context(DatabaseNotReadable)?
-            .query_series(predicate)
-            .await
-            .context(MutableBufferRead)
+        column_name: &str,
+        predicate: &Predicate,
+    ) -> Result<(Vec<LogicalPlan>, BTreeSet<(String, u32)>)> {
+        let rb_predicate = match predicate.range {
+            Some(range) => read_buffer::Predicate::with_time_range(&[], range.start, range.end),
+            None => read_buffer::Predicate::default(),
+        };
+
+        // Tables without every column the predicate refers to have no
+        // matching rows
+        let mut required_columns = HashSet::new();
+        for expr in &predicate.exprs {
+            expr_to_column_names(expr, &mut required_columns).context(ReadBufferPlan)?;
+        }
+        if column_name != MEASUREMENT_COLUMN_NAME {
+            required_columns.insert(column_name.to_string());
+        }
+
+        // Only the required columns are read. The time column is read if there
+        // are none, so that the rows of the table can still be found
+        let mut select_columns = required_columns
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>();
+        if select_columns.is_empty() {
+            select_columns.push(TIME_COLUMN_NAME);
+        }
+        select_columns.sort_unstable();
+
+        let filter_expr = predicate
+            .exprs
+            .iter()
+            .fold(AndExprBuilder::default(), |builder, expr| {
+                builder.append_opt_ref(Some(expr))
+            })
+            .build();
+
+        let read_buffer = self.read_buffer.read().expect("mutex poisoned");
+        let mut plans = vec![];
+        let mut planned_chunks = BTreeSet::new();
+
+        for partition_key in read_buffer.partition_keys() {
+            if matches!(&predicate.partition_key, Some(key) if key != &partition_key) {
+                continue;
+            }
+
+            for chunk_id in read_buffer.chunk_ids(&partition_key) {
+                let table_names = read_buffer_table_names(
+                    &read_buffer,
+                    &partition_key,
+                    chunk_id,
+                    predicate,
+                    &rb_predicate,
+                )?;
+
+                for table_name in &table_names {
+                    let schema = read_buffer
+                        .table_schema(
+                            &partition_key,
+                            table_name,
+                            &[chunk_id],
+                            Selection::Some(&select_columns),
+                        )
+                        .context(ReadBufferRead)?;
+                    if required_columns
+                        .iter()
+                        .any(|name| schema.find_index_of(name).is_none())
+                    {
+                        continue;
+                    }
+
+                    let batches = read_buffer
+                        .read_filter(
+                            &partition_key,
+                            table_name,
+                            &[chunk_id],
+                            rb_predicate.clone(),
+                            Selection::Some(&select_columns),
+                        )
+                        .context(ReadBufferRead)?
+                        .collect::<Vec<_>>();
+                    if batches.is_empty() {
+                        continue;
+                    }
+
+                    let arrow_schema = batches[0].schema();
+                    let mut plan_builder =
+                        LogicalPlanBuilder::scan_memory(vec![batches], arrow_schema, None)
+                            .context(ReadBufferPlan)?;
+                    if let Some(filter_expr) = &filter_expr {
+                        plan_builder = plan_builder
+                            .filter(filter_expr.clone())
+                            .context(ReadBufferPlan)?;
+                    }
+
+                    // A single matching row is enough to find the table name
+                    let select_exprs = if column_name == MEASUREMENT_COLUMN_NAME {
+                        plan_builder = plan_builder.limit(1).context(ReadBufferPlan)?;
+                        vec![lit(table_name.as_str()).alias(MEASUREMENT_COLUMN_NAME)]
+                    } else {
+                        vec![col(column_name)]
+                    };
+
+                    let plan = plan_builder
+                        .project(select_exprs)
+                        .context(ReadBufferPlan)?
+                        .build()
+                        .context(ReadBufferPlan)?;
+                    plans.push(plan);
+                }
+
+                planned_chunks.insert((partition_key.clone(), chunk_id));
+            }
+        }
+
+        Ok((plans, planned_chunks))
     }
 
-    async fn query_groups(
+    /// Returns the tables in the chunks of the database that are not in the
+    /// mutable buffer, such as those in the read buffer and object store,
+    /// that might have rows matching `predicate`. Tables without every
+    /// column `predicate` refers to, or without any of the fields it
+    /// selects, are left out as they have no matching rows.
+    ///
+    /// Returns the tables along with the (partition key, chunk id) of each
+    /// of this database's chunks searched.
+    async fn chunk_tables(
         &self,
-        predicate: query::predicate::Predicate,
-        gby_agg: query::group_by::GroupByAndAggregate,
-    ) -> Result<query::exec::SeriesSetPlans, Self::Error> {
-        self.mutable_buffer
-            .as_ref()
-            .context(DatabaseNotReadable)?
-            .query_groups(predicate, gby_agg)
-            .await
-            .context(MutableBufferRead)
-    }
+        predicate: &Predicate,
+    ) -> Result<(Vec<ChunkTable>, BTreeSet<(String, u32)>)> {
+        let mut required_columns = HashSet::new();
+        for expr in &predicate.exprs {
+            expr_to_column_names(expr, &mut required_columns).context(ChunkPlan)?;
+        }
 
-    async fn partition_keys(&self) -> Result<Vec<String>, Self::Error> {
-        self.mutable_buffer
-            .as_ref()
-            .context(DatabaseNotReadable)?
-            .partition_keys()
-            .await
-            .context(MutableBufferRead)
+        let mut tables = vec![];
+        let mut searched_chunks = BTreeSet::new();
+
+        for partition_key in self.partition_keys().await? {
+            if matches!(&predicate.partition_key, Some(key) if key != &partition_key) {
+                continue;
+            }
+
+            for chunk in self.chunks(&partition_key).await {
+                if matches!(chunk.as_ref(), DBChunk::MutableBuffer { .. }) {
+                    continue;
+                }
+
+                // Chunks of read only partitions were written by other
+                // databases, so they have no copy in the mutable buffer
+                if chunk.source().is_none() {
+                    searched_chunks.insert((partition_key.clone(), chunk.id()));
+                }
+
+                for table_name in chunk.table_names_in_range(predicate).context(ChunkRead)? {
+                    let schema = chunk
+                        .table_schema(&table_name, Selection::All)
+                        .await
+                        .context(ChunkRead)?;
+
+                    if required_columns
+                        .iter()
+                        .any(|name| schema.find_index_of(name).is_none())
+                    {
+                        continue;
+                    }
+                    if predicate.field_columns.is_some()
+                        && chunk_table_field_columns(&schema, predicate).is_empty()
+                    {
+                        continue;
+                    }
+
+                    tables.push(ChunkTable {
+                        chunk: Arc::clone(&chunk),
+                        table_name,
+                        schema,
+                    });
+                }
+            }
+        }
+
+        Ok((tables, searched_chunks))
     }
 }
 
-#[cfg(test)]
-mod test_util {
-    use super::*;
-    /// Create a Database with a local store
+/// A table in a chunk that is planned with DataFusion over the chunk's data
+struct ChunkTable {
+    chunk: Arc<DBChunk>,
+    table_name: String,
+    schema: Schema,
+}
+
+/// Returns the names of the tables in a read buffer chunk that contain data
+/// and are selected by `predicate`.
+fn read_buffer_table_names(
+    read_buffer: &ReadBufferDb,
+    partition_key: &str,
+    chunk_id: u32,
+    predicate: &Predicate,
+    rb_predicate: &read_buffer::Predicate,
+) -> Result<Vec<String>> {
+    let table_names = read_buffer
+        .table_names(partition_key, &[chunk_id], rb_predicate.clone())
+        .context(ReadBufferRead)?;
+    let table_names = table_names
+        .column(0)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("table names are strings");
+
+    Ok(table_names
+        .iter()
+        .flatten()
+        .filter(|table_name| {
+            !matches!(&predicate.table_names, Some(names) if !names.contains(*table_name))
+        })
+        .map(ToString::to_string)
+        .collect())
+}
+
+/// Adds `values` to the set of strings produced by `plan`.
+fn union_string_set_plan(
+    column_name: &str,
+    plan: StringSetPlan,
+    values: StringSet,
+) -> Result<StringSetPlan> {
+    if values.is_empty() {
+        return Ok(plan);
+    }
+
+    Ok(match plan {
+        StringSetPlan::Known(Ok(known)) => {
+            let mut known = Arc::try_unwrap(known).unwrap_or_else(|known| known.as_ref().clone());
+            known.extend(values);
+            known.into()
+        }
+        StringSetPlan::Known(Err(e)) => StringSetPlan::Known(Err(e)),
+        StringSetPlan::Plan(mut plans) => {
+            plans.push(string_set_to_plan(column_name, &values)?);
+            StringSetPlan::Plan(plans)
+        }
+    })
+}
+
+/// Adds the strings produced by `plans` to those produced by `plan`.
+fn union_string_set_plans(
+    column_name: &str,
+    plan: StringSetPlan,
+    plans: Vec<LogicalPlan>,
+) -> Result<StringSetPlan> {
+    if plans.is_empty() {
+        return Ok(plan);
+    }
+
+    Ok(match plan {
+        StringSetPlan::Known(Ok(known)) => {
+            let mut all_plans = plans;
+            if !known.is_empty() {
+                all_plans.push(string_set_to_plan(column_name, &known)?);
+            }
+            StringSetPlan::Plan(all_plans)
+        }
+        StringSetPlan::Known(Err(e)) => StringSetPlan::Known(Err(e)),
+        StringSetPlan::Plan(mut all_plans) => {
+            all_plans.extend(plans);
+            StringSetPlan::Plan(all_plans)
+        }
+    })
+}
+
+/// Creates a plan producing `values` as a single column named `column_name`.
+fn string_set_to_plan(column_name: &str, values: &StringSet) -> Result<LogicalPlan> {
+    let batch = str_iter_to_batch(column_name, values.iter().map(Some))
+        .map_err(DataFusionError::ArrowError)
+        .context(ReadBufferPlan)?;
+    let schema = batch.schema();
+
+    LogicalPlanBuilder::scan_memory(vec![vec![batch]], schema, None)
+        .context(ReadBufferPlan)?
+        .build()
+        .context(ReadBufferPlan)
+}
+
+/// Returns the read buffer aggregate and window (every, offset) equivalent to
+/// a windowed `GroupByAndAggregate`, or `None` if the read buffer can not
+/// compute it directly.
+fn read_buffer_window(
+    agg: Aggregate,
+    every: &WindowDuration,
+    offset: &WindowDuration,
+) -> Option<(AggregateType, u64, i64)> {
+    let agg = match agg {
+        Aggregate::Sum => AggregateType::Sum,
+        Aggregate::Count => AggregateType::Count,
+        Aggregate::Min => AggregateType::Min,
+        Aggregate::Max => AggregateType::Max,
+        Aggregate::First => AggregateType::First,
+        Aggregate::Last => AggregateType::Last,
+        Aggregate::Mean | Aggregate::None => return None,
+    };
+
+    // The read buffer only supports windows of a fixed duration.
+    match (every, offset) {
+        (
+            WindowDuration::Fixed { nanoseconds: every },
+            WindowDuration::Fixed {
+                nanoseconds: offset,
+            },
+        ) if *every > 0 => Some((agg, *every as u64, *offset)),
+        _ => None,
+    }
+}
+
+/// Returns true if the read buffer can compute `agg` over a field of
+/// `field_type`: sums are only of numbers, and minimums and maximums of
+/// numbers and strings, as in DataFusion.
+fn read_buffer_supports_aggregate(agg: AggregateType, field_type: InfluxFieldType) -> bool {
+    match agg {
+        AggregateType::Sum => matches!(
+            field_type,
+            InfluxFieldType::Float | InfluxFieldType::Integer | InfluxFieldType::UInteger
+        ),
+        AggregateType::Min | AggregateType::Max => field_type != InfluxFieldType::Boolean,
+        AggregateType::Count | AggregateType::First | AggregateType::Last => true,
+    }
+}
+
+/// Creates a plan producing the windowed aggregate of each field in a table of
+/// a read buffer chunk, grouped by all of the table's tag columns. Fields the
+/// read buffer can't compute the aggregate of are left out. The output
+/// has the same shape as the equivalent mutable buffer plan: (tag columns,
+/// field columns, time), where time is the upper bound of each window.
+///
+/// Returns `None` if there is no data to aggregate.
+fn read_buffer_window_plan(
+    read_buffer: &ReadBufferDb,
+    partition_key: &str,
+    chunk_id: u32,
+    table_name: &str,
+    predicate: &Predicate,
+    rb_predicate: &read_buffer::Predicate,
+    agg: AggregateType,
+    every: u64,
+    offset: i64,
+) -> Result<Option<SeriesSetPlan>> {
+    let schema = read_buffer
+        .table_schema(partition_key, table_name, &[chunk_id], Selection::All)
+        .context(ReadBufferRead)?;
+
+    let mut tag_columns = vec![];
+    let mut field_columns = vec![];
+    for (influx_column_type, field) in schema.iter() {
+        match influx_column_type {
+            Some(InfluxColumnType::Tag) => tag_columns.push(field.name().to_string()),
+            Some(InfluxColumnType::Field(field_type)) => {
+                if matches!(&predicate.field_columns, Some(names) if !names.contains(field.name()))
+                {
+                    continue;
+                }
+                if !read_buffer_supports_aggregate(agg, field_type) {
+                    continue;
+                }
+                field_columns.push(field.name().to_string())
+            }
+            _ => {}
+        }
+    }
+    tag_columns.sort();
+    field_columns.sort();
+
+    if field_columns.is_empty() {
+        return Ok(None);
+    }
+
+    let group_columns = tag_columns.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    let aggregates = field_columns
+        .iter()
+        .map(|name| (name.as_str(), agg))
+        .collect::<Vec<_>>();
+
+    // The read buffer emits the rows of each chunk ordered by tag values and
+    // then by window, as required for series sets.
+    let batches = read_buffer
+        .read_window_aggregate(
+            partition_key,
+            table_name,
+            &[chunk_id],
+            rb_predicate.clone(),
+            Selection::Some(&group_columns),
+            aggregates,
+            every,
+            offset,
+        )
+        .context(ReadBufferRead)?
+        .collect::<Vec<_>>();
+
+    if batches.is_empty() {
+        return Ok(None);
+    }
+
+    // The read buffer names aggregate columns `<field>_<aggregate>`, so
+    // rename them back to the field names.
+    let select_exprs = tag_columns
+        .iter()
+        .map(|name| col(name))
+        .chain(
+            field_columns
+                .iter()
+                .map(|name| col(&format!("{}_{}", name, agg)).alias(name)),
+        )
+        .chain(std::iter::once(col(TIME_COLUMN_NAME)))
+        .collect::<Vec<_>>();
+
+    let arrow_schema = batches[0].schema();
+    let plan = LogicalPlanBuilder::scan_memory(vec![batches], arrow_schema, None)
+        .context(ReadBufferPlan)?
+        .project(select_exprs)
+        .context(ReadBufferPlan)?
+        .build()
+        .context(ReadBufferPlan)?;
+
+    Ok(Some(SeriesSetPlan::new_from_shared_timestamp(
+        Arc::new(table_name.to_string()),
+        plan,
+        tag_columns.into_iter().map(Arc::new).collect(),
+        field_columns.into_iter().map(Arc::new).collect(),
+    )))
+}
+
+/// Returns the sorted names of the tag columns of a table
+fn chunk_table_tag_columns(schema: &Schema) -> Vec<String> {
+    let mut tag_columns = schema
+        .iter()
+        .filter(|(influx_column_type, _)| matches!(influx_column_type, Some(InfluxColumnType::Tag)))
+        .map(|(_, field)| field.name().to_string())
+        .collect::<Vec<_>>();
+    tag_columns.sort();
+    tag_columns
+}
+
+/// Returns the sorted names of the field columns of a table that are
+/// selected by `predicate`
+fn chunk_table_field_columns(schema: &Schema, predicate: &Predicate) -> Vec<String> {
+    let mut field_columns = schema
+        .iter()
+        .filter(|(influx_column_type, _)| {
+            matches!(influx_column_type, Some(InfluxColumnType::Field(_)))
+        })
+        .map(|(_, field)| field.name().to_string())
+        .filter(|name| !matches!(&predicate.field_columns, Some(names) if !names.contains(name)))
+        .collect::<Vec<_>>();
+    field_columns.sort();
+    field_columns
+}
+
+/// Creates a plan builder for the rows of a table in a chunk that match
+/// `predicate`. Only the columns the plan refers to are read from the
+/// chunk.
+fn scan_chunk_table(table: &ChunkTable, predicate: &Predicate) -> Result<LogicalPlanBuilder> {
+    let mut builder = ProviderBuilder::new(table.table_name.as_str())
+        .add_chunk(Arc::clone(&table.chunk), (&table.schema).into())
+        .context(ChunkProvider)?;
+    if let Some(range) = predicate.range {
+        builder = builder.timestamp_range(range);
+    }
+
+    let mut plan_builder =
+        LogicalPlanBuilder::scan(&table.table_name, Arc::new(builder.build()), None)
+            .context(ChunkPlan)?;
+
+    let filter_expr = predicate
+        .exprs
+        .iter()
+        .fold(AndExprBuilder::default(), |builder, expr| {
+            builder.append_opt_ref(Some(expr))
+        })
+        .build();
+    if let Some(filter_expr) = filter_expr {
+        plan_builder = plan_builder.filter(filter_expr).context(ChunkPlan)?;
+    }
+
+    Ok(plan_builder)
+}
+
+/// Creates a plan producing the names of the tag columns of a table in a
+/// chunk that have values in rows matching `predicate`, with the same shape
+/// as the equivalent mutable buffer plan.
+///
+/// Returns `None` if the table has no tag columns.
+fn chunk_table_tag_names_plan(
+    table: &ChunkTable,
+    predicate: &Predicate,
+) -> Result<Option<LogicalPlan>> {
+    let tag_columns = chunk_table_tag_columns(&table.schema);
+    if tag_columns.is_empty() {
+        return Ok(None);
+    }
+
+    let plan = scan_chunk_table(table, predicate)?
+        .project(tag_columns.iter().map(|name| col(name)).collect())
+        .context(ChunkPlan)?
+        .build()
+        .context(ChunkPlan)?;
+
+    Ok(Some(make_schema_pivot(plan)))
+}
+
+/// Creates a plan producing the field columns selected by `predicate`,
+/// and the time, of the rows of a table in a chunk that match `predicate`,
+/// with the same shape as the equivalent mutable buffer plan.
+///
+/// Returns `None` if the table has no selected field columns.
+fn chunk_table_field_names_plan(
+    table: &ChunkTable,
+    predicate: &Predicate,
+) -> Result<Option<LogicalPlan>> {
+    let field_columns = chunk_table_field_columns(&table.schema, predicate);
+    if field_columns.is_empty() {
+        return Ok(None);
+    }
+
+    let select_exprs = field_columns
+        .iter()
+        .map(|name| col(name))
+        .chain(std::iter::once(col(TIME_COLUMN_NAME)))
+        .collect();
+
+    let plan = scan_chunk_table(table, predicate)?
+        .project(select_exprs)
+        .context(ChunkPlan)?
+        .build()
+        .context(ChunkPlan)?;
+
+    Ok(Some(plan))
+}
+
+/// Creates a plan producing the series of a table in a chunk, from the
+/// rows that match `predicate`, with the same shape as the equivalent
+/// mutable buffer plan: (tag columns, field columns, time), sorted by the
+/// tag columns and then time.
+fn chunk_table_series_set_plan(table: &ChunkTable, predicate: &Predicate) -> Result<SeriesSetPlan> {
+    let tag_columns = chunk_table_tag_columns(&table.schema);
+    let field_columns = chunk_table_field_columns(&table.schema, predicate);
+
+    let sort_exprs = tag_columns
+        .iter()
+        .map(|name| name.as_str())
+        .chain(std::iter::once(TIME_COLUMN_NAME))
+        .map(|name| Expr::Sort {
+            expr: Box::new(col(name)),
+            asc: true,
+            nulls_first: true,
+        })
+        .collect();
+
+    let select_exprs = tag_columns
+        .iter()
+        .chain(field_columns.iter())
+        .map(|name| col(name))
+        .chain(std::iter::once(col(TIME_COLUMN_NAME)))
+        .collect();
+
+    let plan = scan_chunk_table(table, predicate)?
+        .sort(sort_exprs)
+        .context(ChunkPlan)?
+        .project(select_exprs)
+        .context(ChunkPlan)?
+        .build()
+        .context(ChunkPlan)?;
+
+    Ok(SeriesSetPlan::new_from_shared_timestamp(
+        Arc::new(table.table_name.clone()),
+        plan,
+        tag_columns.into_iter().map(Arc::new).collect(),
+        field_columns.into_iter().map(Arc::new).collect(),
+    ))
+}
+
+impl PartialEq for Db {
+    fn eq(&self, other: &Self) -> bool {
+        self.rules == other.rules
+    }
+}
+impl Eq for Db {}
+
+#[async_trait]
+impl Database for Db {
+    type Error = Error;
+    type Chunk = DBChunk;
+
+    /// Return a covering set of chunks for a particular partition
+    async fn chunks(&self, partition_key: &str) -> Vec<Arc<Self::Chunk>> {
+        // return a coverting set of chunks. TODO include read buffer
+        // chunks and take them preferentially from the read buffer.
+        // returns a coverting set of chunks -- aka take chunks from read buffer
+        // preferentially, and only from object storage when the chunk is not
+        // in memory
+        let parquet_chunk_iter = self.parquet_chunks(partition_key).into_iter();
+
+        let mutable_chunk_iter = self.mutable_buffer_chunks(partition_key).await.into_iter();
+
+        let read_buffer_chunk_iter = self.read_buffer_chunks(partition_key).await.into_iter();
+
+        // Chunks of this database with the same id are copies of the same
+        // data, but chunks of read only partitions were written by other
+        // databases, so their ids can collide with this database's
+        let chunks: BTreeMap<_, _> = parquet_chunk_iter
+            .chain(mutable_chunk_iter)
+            .chain(read_buffer_chunk_iter)
+            .map(|chunk| ((chunk.source().map(ToString::to_string), chunk.id()), chunk))
+            .collect();
+
+        // inserting into the map will have removed any dupes
+        chunks.into_iter().map(|(_key, chunk)| chunk).collect()
+    }
+
+    // Note that most of the functions below will eventually be removed from
+    // this trait. For now, pass them directly on to the local store
+
+    async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
+        self.mutable_buffer
+            .as_ref()
+            .context(DatatbaseNotWriteable)?
+            .store_replicated_write(write)
+            .await
+            .context(MutableBufferWrite)
+    }
+
+    async fn tag_column_names(
+        &self,
+        predicate: query::predicate::Predicate,
+    ) -> Result<query::exec::StringSetPlan, Self::Error> {
+        let mutable_buffer = self.mutable_buffer.as_ref().context(DatabaseNotReadable)?;
+
+        // Tables in chunks that are not in the mutable buffer, such as read
+        // buffer and object store chunks, are planned with DataFusion. Only
+        // the remaining chunks are planned by the mutable buffer.
+        let (tables, searched_chunks) = self.chunk_tables(&predicate).await?;
+        let mut plans = vec![];
+        for table in &tables {
+            plans.extend(chunk_table_tag_names_plan(table, &predicate)?);
+        }
+
+        let plan = mutable_buffer
+            .tag_column_names_skipping_chunks(predicate, &searched_chunks)
+            .await
+            .context(MutableBufferRead)?;
+
+        union_string_set_plans(TAG_NAMES_COLUMN_NAME, plan, plans)
+    }
+
+    async fn field_column_names(
+        &self,
+        predicate: query::predicate::Predicate,
+    ) -> Result<query::exec::FieldListPlan, Self::Error> {
+        let mutable_buffer = self.mutable_buffer.as_ref().context(DatabaseNotReadable)?;
+
+        let (tables, searched_chunks) = self.chunk_tables(&predicate).await?;
+        let mut plans = vec![];
+        for table in &tables {
+            plans.extend(chunk_table_field_names_plan(table, &predicate)?);
+        }
+
+        let plan = mutable_buffer
+            .field_column_names_skipping_chunks(predicate, &searched_chunks)
+            .await
+            .context(MutableBufferRead)?;
+
+        Ok(match plan {
+            FieldListPlan::Plans(mut all_plans) => {
+                all_plans.extend(plans);
+                FieldListPlan::Plans(all_plans)
+            }
+            // The mutable buffer plans the fields of every table, so it only
+            // knows the field list up front when there are no tables
+            FieldListPlan::Known(Ok(_)) => FieldListPlan::Plans(plans),
+            FieldListPlan::Known(Err(e)) => FieldListPlan::Known(Err(e)),
+        })
+    }
+
+    async fn column_values(
+        &self,
+        column_name: &str,
+        predicate: query::predicate::Predicate,
+    ) -> Result<query::exec::StringSetPlan, Self::Error> {
+        let mutable_buffer = self.mutable_buffer.as_ref().context(DatabaseNotReadable)?;
+
+        // Tag values (and the table names, for the measurement) in read
+        // buffer chunks are found by the read buffer directly from its
+        // encoded columns, when it supports the predicate. Only the remaining
+        // chunks are planned by the mutable buffer.
+        if let Ok(rb_predicate) = pred::to_read_buffer_predicate(&predicate) {
+            let (values, read_buffer_chunks) =
+                self.read_buffer_column_values(column_name, &predicate, &rb_predicate)?;
+
+            let plan = mutable_buffer
+                .column_values_skipping_chunks(column_name, predicate, &read_buffer_chunks)
+                .await
+                .context(MutableBufferRead)?;
+
+            return union_string_set_plan(column_name, plan, values);
+        }
+
+        // Otherwise the read buffer chunks are planned with DataFusion
+        let (plans, read_buffer_chunks) =
+            self.read_buffer_column_values_plans(column_name, &predicate)?;
+
+        let plan = mutable_buffer
+            .column_values_skipping_chunks(column_name, predicate, &read_buffer_chunks)
+            .await
+            .context(MutableBufferRead)?;
+
+        union_string_set_plans(column_name, plan, plans)
+    }
+
+    async fn query_series(
+        &self,
+        predicate: query::predicate::Predicate,
+    ) -> Result<query::exec::SeriesSetPlans, Self::Error> {
+        let mutable_buffer = self.mutable_buffer.as_ref().context(DatabaseNotReadable)?;
+
+        let (tables, searched_chunks) = self.chunk_tables(&predicate).await?;
+        let mut plans = tables
+            .iter()
+            .map(|table| chunk_table_series_set_plan(table, &predicate))
+            .collect::<Result<Vec<_>>>()?;
+
+        let SeriesSetPlans {
+            plans: mutable_buffer_plans,
+        } = mutable_buffer
+            .query_series_skipping_chunks(predicate, &searched_chunks)
+            .await
+            .context(MutableBufferRead)?;
+        plans.extend(mutable_buffer_plans);
+
+        Ok(plans.into())
+    }
+
+    async fn query_groups(
+        &self,
+        predicate: query::predicate::Predicate,
+        gby_agg: query::group_by::GroupByAndAggregate,
+    ) -> Result<query::exec::SeriesSetPlans, Self::Error> {
+        let mutable_buffer = self.mutable_buffer.as_ref().context(DatabaseNotReadable)?;
+
+        // Windowed aggregates over chunks in the read buffer are computed by
+        // the read buffer itself, when it supports the window, aggregate and
+        // predicate. Only the remaining chunks are planned by the mutable
+        // buffer.
+        if let GroupByAndAggregate::Window { agg, every, offset } = &gby_agg {
+            let rb_window = read_buffer_window(*agg, every, offset);
+            let rb_predicate = pred::to_read_buffer_predicate(&predicate).ok();
+
+            if let (Some((agg, every, offset)), Some(rb_predicate)) = (rb_window, rb_predicate) {
+                let (mut plans, read_buffer_chunks) =
+                    self.read_buffer_window_plans(&predicate, &rb_predicate, agg, every, offset)?;
+
+                let SeriesSetPlans {
+                    plans: mutable_buffer_plans,
+                } = mutable_buffer
+                    .query_groups_skipping_chunks(predicate, gby_agg, &read_buffer_chunks)
+                    .await
+                    .context(MutableBufferRead)?;
+                plans.extend(mutable_buffer_plans);
+
+                return Ok(plans.into());
+            }
+        }
+
+        mutable_buffer
+            .query_groups(predicate, gby_agg)
+            .await
+            .context(MutableBufferRead)
+    }
+
+    async fn partition_keys(&self) -> Result<Vec<String>, Self::Error> {
+        // A database without a mutable buffer can still have partitions in
+        // the read buffer and object storage, such as read only partitions
+        let mut partition_keys = BTreeSet::new();
+        if let Some(mutable_buffer) = self.mutable_buffer.as_ref() {
+            partition_keys.extend(
+                mutable_buffer
+                    .partition_keys()
+                    .await
+                    .context(MutableBufferRead)?,
+            );
+        }
+
+        partition_keys.extend(
+            self.read_buffer
+                .read()
+                .expect("mutex poisoned")
+                .partition_keys(),
+        );
+        partition_keys.extend(
+            self.parquet_chunks
+                .read()
+                .expect("mutex poisoned")
+                .keys()
+                .map(|(partition_key, _, _)| partition_key.clone()),
+        );
+
+        Ok(partition_keys.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod test_util {
+    use super::*;
+    /// Create a Database with a local store
     pub fn make_db() -> Db {
         let name = "test_db";
         Db::new(
@@ -378,10 +1656,22 @@ mod tests {
     use super::*;
 
     use arrow_deps::{
-        arrow::record_batch::RecordBatch, assert_table_eq, datafusion::physical_plan::collect,
+        arrow::record_batch::RecordBatch,
+        assert_table_eq,
+        datafusion::{
+            datasource::TableProvider,
+            logical_plan::{col, lit},
+            physical_plan::collect,
+        },
     };
+    use data_types::database_rules::ReadBufferConfig;
     use query::{
-        exec::Executor, frontend::sql::SQLQueryPlanner, test::TestLPWriter, PartitionChunk,
+        exec::Executor,
+        frontend::sql::SQLQueryPlanner,
+        predicate::{PredicateBuilder, TimestampRange},
+        provider::ProviderBuilder,
+        test::TestLPWriter,
+        PartitionChunk,
     };
     use test_helpers::assert_contains;
 
@@ -403,6 +1693,40 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn concurrent_attempts_to_apply_a_write_wait() {
+        use futures::FutureExt;
+
+        let db = make_db();
+
+        let first = db.start_applying(2, 1, &["a", "b"]).await;
+        assert_eq!(first.partition_keys(), ["a", "b"]);
+
+        // another partition of the same write isn't held up
+        let other = db.start_applying(2, 1, &["c"]).await;
+        assert_eq!(other.partition_keys(), ["c"]);
+        other.finish(true);
+
+        // the write sent again waits for the first attempt
+        let mut second = Box::pin(db.start_applying(2, 1, &["a", "c"]));
+        assert!((&mut second).now_or_never().is_none());
+
+        // which failed, so the partition it was applying is applied again
+        first.finish(false);
+        let second = second.await;
+        assert_eq!(second.partition_keys(), ["a"]);
+        second.finish(true);
+
+        let third = db.start_applying(2, 1, &["a", "b", "c"]).await;
+        assert_eq!(third.partition_keys(), ["b"]);
+
+        // dropping an attempt, such as when the request is cancelled, means
+        // its partitions weren't applied
+        drop(third);
+        let fourth = db.start_applying(2, 1, &["b"]).await;
+        assert_eq!(fourth.partition_keys(), ["b"]);
+    }
+
     #[tokio::test]
     async fn read_write() {
         let db = make_db();
@@ -421,6 +1745,157 @@ mod tests {
         assert_table_eq!(expected, &batches);
     }
 
+    #[tokio::test]
+    async fn query_chunks_with_different_columns() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+        db.rollover_partition("1970-01-01T00").await.unwrap();
+        writer
+            .write_lp_string(&db, "cpu,host=a baz=2 20")
+            .await
+            .unwrap();
+
+        let batches = run_query(&db, "select * from cpu").await;
+        let expected = vec![
+            "+-----+-----+------+------+",
+            "| bar | baz | host | time |",
+            "+-----+-----+------+------+",
+            "| 1   |     |      | 10   |",
+            "|     | 2   | a    | 20   |",
+            "+-----+-----+------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        let batches = run_query(&db, "select host, bar from cpu where time > 15").await;
+        let expected = vec![
+            "+------+-----+",
+            "| host | bar |",
+            "+------+-----+",
+            "| a    |     |",
+            "+------+-----+",
+        ];
+        assert_table_eq!(expected, &batches);
+    }
+
+    #[tokio::test]
+    async fn sql_statements() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(&db, "cpu,host=a bar=1 10\nmem,host=a used=5 10")
+            .await
+            .unwrap();
+
+        let batches = run_query(&db, "show tables").await;
+        let expected = vec![
+            "+------------+",
+            "| table_name |",
+            "+------------+",
+            "| cpu        |",
+            "| mem        |",
+            "+------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        let batches = run_query(&db, "show columns from cpu").await;
+        let expected = vec![
+            "+-------------+-----------+",
+            "| column_name | data_type |",
+            "+-------------+-----------+",
+            "| bar         | Float64   |",
+            "| host        | Utf8      |",
+            "| time        | Int64     |",
+            "+-------------+-----------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // tables referenced only in subqueries are found
+        let batches = run_query(&db, "select used from (select * from mem) as m").await;
+        let expected = vec!["+------+", "| used |", "+------+", "| 5    |", "+------+"];
+        assert_table_eq!(expected, &batches);
+
+        let planner = SQLQueryPlanner::default();
+        let executor = Executor::new();
+        let plans = planner
+            .query_statements(
+                &db,
+                "select * from cpu; explain select * from mem",
+                &executor,
+            )
+            .await
+            .unwrap();
+        assert_eq!(plans.len(), 2);
+
+        let err = planner
+            .query(&db, "select * from cpu; select * from mem", &executor)
+            .await
+            .unwrap_err();
+        assert_contains!(err.to_string(), "Expected a single SQL statement");
+    }
+
+    #[tokio::test]
+    async fn table_provider_prunes_chunks() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+        db.rollover_partition("1970-01-01T00").await.unwrap();
+        writer.write_lp_string(&db, "cpu bar=2 20").await.unwrap();
+
+        let mut builder = ProviderBuilder::new("cpu");
+        for chunk in db.chunks("1970-01-01T00").await {
+            let schema = chunk.table_schema("cpu", Selection::All).await.unwrap();
+            builder = builder.add_chunk(chunk, schema.into()).unwrap();
+        }
+        let provider = builder.build();
+
+        // the chunk with only earlier times is not read, even though the
+        // filter is not applied to the rows of the chunks that are
+        let plan = provider
+            .scan(&None, 1024, &[col("time").gt(lit(15_i64))])
+            .unwrap();
+        let batches = collect(plan).await.unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 2   | 20   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // only the projected columns are read
+        let plan = provider.scan(&Some(vec![1]), 1024, &[]).unwrap();
+        let batches = collect(plan).await.unwrap();
+        let expected = vec![
+            "+------+", "| time |", "+------+", "| 10   |", "| 20   |", "+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+    }
+
+    #[tokio::test]
+    async fn sql_query_timestamp_range() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(&db, "cpu bar=1 10\ncpu bar=2 20\ncpu bar=3 30")
+            .await
+            .unwrap();
+
+        let planner = SQLQueryPlanner::default().with_timestamp_range(TimestampRange::new(15, 30));
+        let executor = Executor::new();
+
+        // rows outside the range are filtered out, even when the time
+        // column is not selected
+        let plan = planner
+            .query(&db, "select bar from cpu", &executor)
+            .await
+            .unwrap();
+        let batches = collect(plan).await.unwrap();
+        let expected = vec!["+-----+", "| bar |", "+-----+", "| 2   |", "+-----+"];
+        assert_table_eq!(expected, &batches);
+    }
+
     #[tokio::test]
     async fn write_with_rollover() {
         let db = make_db();
@@ -462,6 +1937,385 @@ mod tests {
         assert_table_eq!(expected, &batches);
     }
 
+    #[tokio::test]
+    async fn window_groups_from_read_buffer() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,region=west user=1 10\ncpu,region=west user=3 15\ncpu,region=east user=5 25",
+            )
+            .await
+            .unwrap();
+
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        // this write is only in the (open) mutable buffer chunk
+        writer
+            .write_lp_string(&db, "cpu,region=west user=7 30")
+            .await
+            .unwrap();
+
+        let gby_agg = GroupByAndAggregate::Window {
+            agg: Aggregate::Sum,
+            every: WindowDuration::from_nanoseconds(20),
+            offset: WindowDuration::from_nanoseconds(0),
+        };
+        let plans = db
+            .query_groups(Predicate::default(), gby_agg)
+            .await
+            .unwrap()
+            .plans;
+
+        // one plan for the read buffer chunk, and one for the open mutable
+        // buffer chunk. The loaded chunk is not also planned by the mutable
+        // buffer.
+        assert_eq!(plans.len(), 2);
+
+        let executor = Executor::new();
+        let mut plans = plans.into_iter();
+
+        let rb_plan = plans.next().unwrap();
+        assert_eq!(rb_plan.tag_columns, vec![Arc::new("region".to_string())]);
+        let batches = executor.run_logical_plan(rb_plan.plan).await.unwrap();
+        let expected = vec![
+            "+--------+------+------+",
+            "| region | user | time |",
+            "+--------+------+------+",
+            "| east   | 5    | 40   |",
+            "| west   | 4    | 20   |",
+            "+--------+------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        let mb_plan = plans.next().unwrap();
+        let batches = executor.run_logical_plan(mb_plan.plan).await.unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+    }
+
+    #[tokio::test]
+    async fn window_groups_from_read_buffer_skip_unsupported_fields() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,region=west user=1,state=\"on\",active=true 10\n\
+                 cpu,region=west user=3,state=\"off\",active=false 15",
+            )
+            .await
+            .unwrap();
+
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        // the read buffer can't sum strings or booleans, so only the numeric
+        // field is aggregated
+        let gby_agg = GroupByAndAggregate::Window {
+            agg: Aggregate::Sum,
+            every: WindowDuration::from_nanoseconds(20),
+            offset: WindowDuration::from_nanoseconds(0),
+        };
+        let plans = db
+            .query_groups(Predicate::default(), gby_agg)
+            .await
+            .unwrap()
+            .plans;
+
+        let rb_plan = plans.into_iter().next().unwrap();
+        assert_eq!(
+            rb_plan.field_columns,
+            vec![Arc::new("user".to_string())].into()
+        );
+
+        let executor = Executor::new();
+        let batches = executor.run_logical_plan(rb_plan.plan).await.unwrap();
+        let expected = vec![
+            "+--------+------+------+",
+            "| region | user | time |",
+            "+--------+------+------+",
+            "| west   | 4    | 20   |",
+            "+--------+------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+    }
+
+    #[tokio::test]
+    async fn metadata_and_series_from_dropped_mutable_buffer_chunks() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,region=west user=23.2,system=5 100\n\
+                 mem,host=a free=10i 200",
+            )
+            .await
+            .unwrap();
+
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        // while the chunk is in both buffers, its series are only planned once
+        let plans = db.query_series(Predicate::default()).await.unwrap().plans;
+        assert_eq!(plans.len(), 2);
+
+        db.drop_mutable_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        let executor = Executor::new();
+
+        let plan = db.tag_column_names(Predicate::default()).await.unwrap();
+        let names = executor.to_string_set(plan).await.unwrap();
+        assert_eq!(
+            names.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            vec!["host", "region"]
+        );
+
+        let predicate = PredicateBuilder::default()
+            .add_expr(col("region").eq(lit("west")))
+            .build();
+        let plan = db.tag_column_names(predicate).await.unwrap();
+        let names = executor.to_string_set(plan).await.unwrap();
+        assert_eq!(
+            names.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            vec!["region"]
+        );
+
+        let predicate = PredicateBuilder::default().table("cpu").build();
+        let plan = db.field_column_names(predicate).await.unwrap();
+        let fields = executor.to_field_list(plan).await.unwrap();
+        let field_names = fields
+            .fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(field_names, vec!["system", "user"]);
+
+        let predicate = PredicateBuilder::default().table("cpu").build();
+        let plans = db.query_series(predicate).await.unwrap().plans;
+        assert_eq!(plans.len(), 1);
+        let plan = plans.into_iter().next().unwrap();
+        assert_eq!(plan.tag_columns, vec![Arc::new("region".to_string())]);
+
+        let batches = executor.run_logical_plan(plan.plan).await.unwrap();
+        let expected = vec![
+            "+--------+--------+------+------+",
+            "| region | system | user | time |",
+            "+--------+--------+------+------+",
+            "| west   | 5      | 23.2 | 100  |",
+            "+--------+--------+------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+    }
+
+    #[tokio::test]
+    async fn column_values_from_read_buffer() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,region=west user=1 10\ncpu,region=east user=2 20\ncpu,host=a user=3 25",
+            )
+            .await
+            .unwrap();
+
+        // move the data to the read buffer only
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        writer
+            .write_lp_string(&db, "cpu,region=north user=4 30")
+            .await
+            .unwrap();
+
+        let executor = Executor::new();
+
+        // values found in the read buffer, merged with the mutable buffer
+        let plan = db
+            .column_values("region", Predicate::default())
+            .await
+            .unwrap();
+        let values = executor.to_string_set(plan).await.unwrap();
+        assert_eq!(
+            values.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            vec!["east", "north", "west"]
+        );
+
+        // predicates are applied by the read buffer, and the mutable buffer
+        // plans are extended with the read buffer values.
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(0, 100)
+            .add_expr(col("user").lt(lit(3.0)))
+            .build();
+        let plan = db.column_values("region", predicate).await.unwrap();
+        assert!(matches!(plan, StringSetPlan::Plan(_)));
+        let values = executor.to_string_set(plan).await.unwrap();
+        assert_eq!(
+            values.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            vec!["east", "west"]
+        );
+    }
+
+    #[tokio::test]
+    async fn column_values_from_read_buffer_with_unsupported_predicate() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,region=west user=1 10\ncpu,region=east user=2 20\nmem,region=south free=2 20",
+            )
+            .await
+            .unwrap();
+
+        // move the data to the read buffer only
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        writer
+            .write_lp_string(&db, "disk,region=north used=3 30")
+            .await
+            .unwrap();
+
+        let executor = Executor::new();
+
+        // the read buffer can't evaluate `OR`, so the chunks are planned
+        let predicate = PredicateBuilder::default()
+            .add_expr(
+                col("region")
+                    .eq(lit("west"))
+                    .or(col("region").eq(lit("north"))),
+            )
+            .build();
+        assert!(pred::to_read_buffer_predicate(&predicate).is_err());
+
+        let plan = db.column_values("region", predicate.clone()).await.unwrap();
+        let values = executor.to_string_set(plan).await.unwrap();
+        assert_eq!(
+            values.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            vec!["north", "west"]
+        );
+
+        let plan = db
+            .column_values(MEASUREMENT_COLUMN_NAME, predicate)
+            .await
+            .unwrap();
+        let values = executor.to_string_set(plan).await.unwrap();
+        assert_eq!(
+            values.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            vec!["cpu", "disk"]
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_usage_follows_chunk_moves() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        assert!(db.update_memory_usage().await > 0);
+
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        let usage = db.memory_usage();
+        assert_eq!(db.update_memory_usage().await, usage);
+
+        db.drop_mutable_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        let usage = db.memory_usage();
+        assert_eq!(db.update_memory_usage().await, usage);
+
+        db.drop_read_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        assert_eq!(db.memory_usage(), 0);
+    }
+
+    #[tokio::test]
+    async fn measurement_values_from_read_buffer() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(&db, "cpu,region=west user=1 10\nmem,region=east free=2 20")
+            .await
+            .unwrap();
+
+        // move the data to the read buffer only
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        writer
+            .write_lp_string(&db, "disk,region=north used=3 30")
+            .await
+            .unwrap();
+
+        let executor = Executor::new();
+
+        // the tables in the read buffer, merged with the mutable buffer
+        let plan = db
+            .column_values(MEASUREMENT_COLUMN_NAME, Predicate::default())
+            .await
+            .unwrap();
+        let values = executor.to_string_set(plan).await.unwrap();
+        assert_eq!(
+            values.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            vec!["cpu", "disk", "mem"]
+        );
+
+        // only the tables with rows matching the predicate
+        let predicate = PredicateBuilder::default()
+            .add_expr(col("region").eq(lit("west")))
+            .build();
+        let plan = db
+            .column_values(MEASUREMENT_COLUMN_NAME, predicate)
+            .await
+            .unwrap();
+        let values = executor.to_string_set(plan).await.unwrap();
+        assert_eq!(
+            values.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            vec!["cpu"]
+        );
+    }
+
     #[tokio::test]
     async fn read_from_read_buffer() {
         // Test that data can be loaded into the ReadBuffer
@@ -524,6 +2378,94 @@ mod tests {
         // cpu").await; assert_table_eq!(expected, &batches);
     }
 
+    #[tokio::test]
+    async fn load_string_fields_to_read_buffer() {
+        // String fields have no encoding in the mutable buffer other than
+        // as strings, so check they survive being loaded into the ReadBuffer
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(&db, "cpu,region=west msg=\"hello\",bar=1 10")
+            .await
+            .unwrap();
+        writer
+            .write_lp_string(&db, "cpu,region=east bar=2 20")
+            .await
+            .unwrap();
+
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        let expected = vec![
+            "+-----+-------+--------+------+",
+            "| bar | msg   | region | time |",
+            "+-----+-------+--------+------+",
+            "| 2   |       | east   | 20   |",
+            "| 1   | hello | west   | 10   |",
+            "+-----+-------+--------+------+",
+        ];
+        let batches = run_query(&db, "select bar, msg, region, time from cpu").await;
+        assert_table_eq!(expected, &batches);
+    }
+
+    #[tokio::test]
+    async fn load_sorted_row_groups_to_read_buffer() {
+        let rules = DatabaseRules {
+            read_buffer_config: ReadBufferConfig {
+                max_row_group_rows: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let db = Db::new(
+            rules,
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            None, // wal buffer
+        );
+
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,region=west,host=a user=1 10\ncpu,region=east,host=b user=2 20\ncpu,region=west,host=c user=3 30",
+            )
+            .await
+            .unwrap();
+
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        // the three rows are split across two row groups
+        assert_eq!(db.read_buffer.read().unwrap().row_groups(), 2);
+
+        // rows are sorted by region (the lowest cardinality tag), then host
+        // and then time.
+        let expected = vec![
+            "+--------+------+------+------+",
+            "| region | host | user | time |",
+            "+--------+------+------+------+",
+            "| east   | b    | 2    | 20   |",
+            "| west   | a    | 1    | 10   |",
+            "| west   | c    | 3    | 30   |",
+            "+--------+------+------+------+",
+        ];
+        let batches = run_query(&db, "select region, host, user, time from cpu").await;
+        assert_table_eq!(expected, &batches);
+    }
+
     #[tokio::test]
     async fn chunk_id_listing() {
         // Test that chunk id listing is hooked up
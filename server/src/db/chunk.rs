@@ -1,19 +1,38 @@
 use arrow_deps::{
-    arrow::record_batch::RecordBatch, datafusion::logical_plan::LogicalPlan,
+    arrow::{
+        array::StringArray,
+        datatypes::{Schema as ArrowSchema, SchemaRef as ArrowSchemaRef},
+        record_batch::RecordBatch,
+    },
+    datafusion::{
+        logical_plan::LogicalPlan,
+        physical_plan::{common::SizedRecordBatchStream, SendableRecordBatchStream},
+    },
     util::str_iter_to_batch,
 };
-use data_types::{schema::Schema, selection::Selection};
+use data_types::{
+    partition_metadata::{Column as ColumnStats, SequenceRange, Table as TableStats},
+    schema::Schema,
+    selection::Selection,
+    TIME_COLUMN_NAME,
+};
+use object_store::{path::ObjectStorePath, ObjectStore};
 use query::{
     predicate::{Predicate, PredicateBuilder},
     util::make_scan_plan,
     PartitionChunk,
 };
 use read_buffer::Database as ReadBufferDb;
-use snafu::{ResultExt, Snafu};
+use snafu::{OptionExt, ResultExt, Snafu};
 
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    sync::{Arc, Mutex, RwLock},
+};
 
 use super::pred::to_read_buffer_predicate;
+use crate::snapshot;
 
 use async_trait::async_trait;
 
@@ -42,6 +61,27 @@ pub enum Error {
     ArrowConversion {
         source: arrow_deps::arrow::error::ArrowError,
     },
+
+    #[snafu(display("Error reading Parquet file chunk {}: {}", chunk_id, source))]
+    ParquetFileRead {
+        source: snapshot::Error,
+        chunk_id: u32,
+    },
+
+    #[snafu(display("Invalid schema in Parquet file chunk {}: {}", chunk_id, source))]
+    ParquetFileSchema {
+        source: data_types::schema::Error,
+        chunk_id: u32,
+    },
+
+    #[snafu(display("Column {} not found in Parquet file chunk {}", column_name, chunk_id))]
+    ParquetFileColumnNotFound { column_name: String, chunk_id: u32 },
+
+    #[snafu(display(
+        "Parquet file chunk {} is in object storage and must be read with read_table",
+        chunk_id
+    ))]
+    ParquetFileNotInMemory { chunk_id: u32 },
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -57,7 +97,22 @@ pub enum DBChunk {
         partition_key: String,
         chunk_id: u32,
     },
-    ParquetFile, // TODO add appropriate type here
+    ParquetFile {
+        partition_key: String,
+        chunk_id: u32,
+        /// The id of the read only partition this chunk was loaded from, or
+        /// `None` if this database wrote it
+        source: Option<String>,
+        /// The statistics of each table in the chunk, saved beside the
+        /// data by the snapshot that wrote it
+        tables: Vec<TableStats>,
+        store: Arc<ObjectStore>,
+        /// The path the snapshot wrote the data for each table to
+        data_path: ObjectStorePath,
+        /// The schema of each table read so far. Reading a schema downloads
+        /// the whole file, so it is only done once per table.
+        schemas: Mutex<BTreeMap<String, ArrowSchemaRef>>,
+    },
 }
 
 impl DBChunk {
@@ -79,6 +134,140 @@ impl DBChunk {
             partition_key,
         })
     }
+
+    /// Create a new chunk for the data that a snapshot wrote to `data_path`
+    /// in `store`, with the tables described by `tables`. `source` is the id
+    /// of the read only partition the chunk was loaded from, if any.
+    pub fn new_parquet(
+        store: Arc<ObjectStore>,
+        data_path: ObjectStorePath,
+        partition_key: impl Into<String>,
+        chunk_id: u32,
+        source: Option<String>,
+        tables: Vec<TableStats>,
+    ) -> Arc<Self> {
+        Arc::new(Self::ParquetFile {
+            partition_key: partition_key.into(),
+            chunk_id,
+            source,
+            tables,
+            store,
+            data_path,
+            schemas: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Returns the id of the read only partition this chunk was loaded
+    /// from, or `None` if this database wrote it. Chunk ids are only unique
+    /// among the chunks written by the same database.
+    pub fn source(&self) -> Option<&str> {
+        match self {
+            Self::MutableBuffer { .. } | Self::ReadBuffer { .. } => None,
+            Self::ParquetFile { source, .. } => source.as_deref(),
+        }
+    }
+
+    /// Returns the ranges of sequence numbers, by writer id, of the writes
+    /// in this chunk. Only mutable buffer chunks track these.
+    pub fn writer_sequences(&self) -> BTreeMap<u32, SequenceRange> {
+        match self {
+            Self::MutableBuffer { chunk } => chunk.writer_sequences.clone(),
+            Self::ReadBuffer { .. } | Self::ParquetFile { .. } => BTreeMap::new(),
+        }
+    }
+
+    /// Returns the names of the tables in this chunk that might have rows
+    /// passing the table name restriction and time range of `predicate`.
+    /// Its other parts are not evaluated.
+    pub fn table_names_in_range(&self, predicate: &Predicate) -> Result<Vec<String>> {
+        let range_predicate = Predicate {
+            table_names: predicate.table_names.clone(),
+            range: predicate.range,
+            ..Default::default()
+        };
+
+        match self {
+            Self::MutableBuffer { chunk } => Ok(chunk
+                .table_stats()
+                .context(MutableBufferChunk)?
+                .into_iter()
+                .filter(|table| table_might_pass(table, &range_predicate))
+                .map(|table| table.name)
+                .collect()),
+            Self::ReadBuffer {
+                db,
+                partition_key,
+                chunk_id,
+            } => {
+                let chunk_id = *chunk_id;
+                let rb_predicate = to_read_buffer_predicate(&range_predicate)
+                    .context(InternalPredicateConversion)?;
+
+                let db = db.read().unwrap();
+                let batch = db
+                    .table_names(partition_key, &[chunk_id], rb_predicate)
+                    .context(ReadBufferChunk { chunk_id })?;
+                let table_names = batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .expect("table names are strings");
+
+                Ok(table_names
+                    .iter()
+                    .flatten()
+                    .filter(|table_name| match &range_predicate.table_names {
+                        Some(names) => names.contains(*table_name),
+                        None => true,
+                    })
+                    .map(ToString::to_string)
+                    .collect())
+            }
+            Self::ParquetFile { tables, .. } => Ok(tables
+                .iter()
+                .filter(|table| table_might_pass(table, &range_predicate))
+                .map(|table| table.name.clone())
+                .collect()),
+        }
+    }
+}
+
+/// Returns true if rows of the partition with key `partition_key` might pass
+/// `predicate`
+fn partition_might_pass(partition_key: &str, predicate: &Predicate) -> bool {
+    predicate
+        .partition_key
+        .as_ref()
+        .map_or(true, |key| key == partition_key)
+}
+
+/// Returns true if the statistics of `table` show that it might have rows
+/// that pass `predicate`
+fn table_might_pass(table: &TableStats, predicate: &Predicate) -> bool {
+    if let Some(table_names) = &predicate.table_names {
+        if !table_names.contains(&table.name) {
+            return false;
+        }
+    }
+
+    if let Some(field_columns) = &predicate.field_columns {
+        if !field_columns
+            .iter()
+            .any(|name| table.column(name).is_some())
+        {
+            return false;
+        }
+    }
+
+    match (
+        &predicate.range,
+        table.column(TIME_COLUMN_NAME).map(|c| &c.stats),
+    ) {
+        (Some(range), Some(ColumnStats::I64(stats))) => {
+            stats.max >= range.start && stats.min < range.end
+        }
+        _ => true,
+    }
 }
 
 #[async_trait]
@@ -89,7 +278,7 @@ impl PartitionChunk for DBChunk {
         match self {
             Self::MutableBuffer { chunk } => chunk.id(),
             Self::ReadBuffer { chunk_id, .. } => *chunk_id,
-            Self::ParquetFile => unimplemented!("parquet file not implemented"),
+            Self::ParquetFile { chunk_id, .. } => *chunk_id,
         }
     }
 
@@ -97,7 +286,29 @@ impl PartitionChunk for DBChunk {
         match self {
             Self::MutableBuffer { chunk } => chunk.table_stats().context(MutableBufferChunk),
             Self::ReadBuffer { .. } => unimplemented!("read buffer not implemented"),
-            Self::ParquetFile => unimplemented!("parquet file not implemented"),
+            Self::ParquetFile { tables, .. } => Ok(tables.clone()),
+        }
+    }
+
+    fn might_pass_predicate(&self, predicate: &Predicate) -> bool {
+        match self {
+            Self::MutableBuffer { chunk } => match chunk.table_stats() {
+                Ok(tables) => tables
+                    .iter()
+                    .any(|table| table_might_pass(table, predicate)),
+                Err(_) => true,
+            },
+            Self::ReadBuffer { .. } => true,
+            Self::ParquetFile {
+                partition_key,
+                tables,
+                ..
+            } => {
+                partition_might_pass(partition_key, predicate)
+                    && tables
+                        .iter()
+                        .any(|table| table_might_pass(table, predicate))
+            }
         }
     }
 
@@ -139,11 +350,48 @@ impl PartitionChunk for DBChunk {
                 // copy the RecordBatches into dst
                 dst.extend(read_result);
             }
-            Self::ParquetFile => unimplemented!("parquet file not implemented"),
+            Self::ParquetFile { chunk_id, .. } => {
+                return ParquetFileNotInMemory {
+                    chunk_id: *chunk_id,
+                }
+                .fail()
+            }
         }
         Ok(())
     }
 
+    async fn read_table(
+        &self,
+        table_name: &str,
+        selection: Selection<'_>,
+    ) -> Result<SendableRecordBatchStream, Self::Error> {
+        // Parquet files are decoded as the stream is read
+        if let Self::ParquetFile {
+            chunk_id,
+            store,
+            data_path,
+            ..
+        } = self
+        {
+            return snapshot::read_table(store, data_path, table_name, selection)
+                .await
+                .context(ParquetFileRead {
+                    chunk_id: *chunk_id,
+                });
+        }
+
+        let schema = self.table_schema(table_name, selection.clone()).await?;
+
+        let mut batches = Vec::new();
+        self.table_to_arrow(&mut batches, table_name, selection)?;
+
+        let batches = batches.into_iter().map(Arc::new).collect();
+        Ok(Box::pin(SizedRecordBatchStream::new(
+            schema.into(),
+            batches,
+        )))
+    }
+
     async fn table_names(&self, predicate: &Predicate) -> Result<LogicalPlan, Self::Error> {
         match self {
             Self::MutableBuffer { chunk } => {
@@ -182,8 +430,26 @@ impl PartitionChunk for DBChunk {
                     .context(ReadBufferChunk { chunk_id })?;
                 make_scan_plan(batch).context(InternalPlanCreation)
             }
-            Self::ParquetFile => {
-                unimplemented!("parquet file not implemented")
+            Self::ParquetFile {
+                partition_key,
+                tables,
+                ..
+            } => {
+                // Without reading the data, only the statistics can be used
+                // to tell whether a table has rows that match, so the names
+                // of tables that might match are returned
+                let names: Vec<_> = tables
+                    .iter()
+                    .filter(|table| {
+                        partition_might_pass(partition_key, predicate)
+                            && table_might_pass(table, predicate)
+                    })
+                    .map(|table| Some(table.name.as_str()))
+                    .collect();
+
+                let batch = str_iter_to_batch("tables", names).context(ArrowConversion)?;
+
+                make_scan_plan(batch).context(InternalPlanCreation)
             }
         }
     }
@@ -212,14 +478,8 @@ impl PartitionChunk for DBChunk {
                 // back
                 let needs_sort = matches!(selection, Selection::All);
 
-                // For now, since read_filter is evaluated lazily,
-                // "run" a query with no predicates simply to get back the
-                // schema
-                let predicate = read_buffer::Predicate::default();
                 let mut schema = db
-                    .read_filter(partition_key, table_name, &[chunk_id], predicate, selection)
-                    .context(ReadBufferChunk { chunk_id })?
-                    .schema()
+                    .table_schema(partition_key, table_name, &[chunk_id], selection)
                     .context(ReadBufferChunk { chunk_id })?;
 
                 // Ensure the order of the output columns is as
@@ -230,8 +490,61 @@ impl PartitionChunk for DBChunk {
 
                 Ok(schema)
             }
-            DBChunk::ParquetFile => {
-                unimplemented!("parquet file not implemented for table schema")
+            DBChunk::ParquetFile {
+                chunk_id,
+                store,
+                data_path,
+                schemas,
+                ..
+            } => {
+                let chunk_id = *chunk_id;
+                let cached = schemas
+                    .lock()
+                    .expect("mutex poisoned")
+                    .get(table_name)
+                    .cloned();
+                let schema = match cached {
+                    Some(schema) => schema,
+                    None => {
+                        let schema = snapshot::read_table_schema(store, data_path, table_name)
+                            .await
+                            .context(ParquetFileRead { chunk_id })?;
+                        schemas
+                            .lock()
+                            .expect("mutex poisoned")
+                            .insert(table_name.to_string(), Arc::clone(&schema));
+                        schema
+                    }
+                };
+
+                let sort_fields = matches!(selection, Selection::All);
+                let schema = match selection {
+                    Selection::All => schema,
+                    Selection::Some(columns) => {
+                        let fields = columns
+                            .iter()
+                            .map(|&column_name| {
+                                schema.field_with_name(column_name).ok().cloned().context(
+                                    ParquetFileColumnNotFound {
+                                        column_name,
+                                        chunk_id,
+                                    },
+                                )
+                            })
+                            .collect::<Result<Vec<_>>>()?;
+                        Arc::new(ArrowSchema::new_with_metadata(
+                            fields,
+                            schema.metadata().clone(),
+                        ))
+                    }
+                };
+
+                let schema = Schema::try_from(schema).context(ParquetFileSchema { chunk_id })?;
+                if sort_fields {
+                    Ok(schema.sort_fields_by_name())
+                } else {
+                    Ok(schema)
+                }
             }
         }
     }
@@ -248,8 +561,8 @@ impl PartitionChunk for DBChunk {
                 let db = db.read().unwrap();
                 db.has_table(partition_key, table_name, &[chunk_id])
             }
-            DBChunk::ParquetFile => {
-                unimplemented!("parquet file not implemented for has_table")
+            DBChunk::ParquetFile { tables, .. } => {
+                tables.iter().any(|table| table.name == table_name)
             }
         }
     }
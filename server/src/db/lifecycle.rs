@@ -0,0 +1,541 @@
+//! This module contains the logic that moves chunks through the database
+//! according to its `LifecycleRules`: open mutable buffer chunks are closed,
+//! closed chunks are loaded into the read buffer and snapshotted to object
+//! storage, and copies that are no longer needed are dropped from memory.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    num::NonZeroUsize,
+    sync::Arc,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use data_types::database_rules::LifecycleRules;
+use mutable_buffer::chunk::{Chunk as MBChunk, ChunkSummary};
+use object_store::{path::ObjectStorePath, ObjectStore};
+use snafu::ResultExt;
+use tracing::{error, info};
+
+use super::{Db, Result, RollingPartition, Snapshotting};
+use crate::snapshot::{chunk_snapshot_paths, snapshot_chunk, Snapshot};
+
+/// Identifies a chunk by its partition key and chunk id
+type ChunkKey = (String, u32);
+
+/// The lifecycle policy's record of the chunks it is persisting, or has
+/// persisted, to object storage
+#[derive(Debug, Default)]
+pub struct LifecycleState {
+    /// Snapshots that have been started but have not yet completed
+    persisting: BTreeMap<ChunkKey, Arc<Snapshot<MBChunk>>>,
+
+    /// Chunks whose snapshots have completed
+    persisted: BTreeSet<ChunkKey>,
+}
+
+impl LifecycleState {
+    /// Moves completed snapshots to `persisted`, returning them. Failed
+    /// snapshots are forgotten, so that they are retried on the next check.
+    fn update(&mut self) -> Vec<Arc<Snapshot<MBChunk>>> {
+        let mut done = vec![];
+        for (key, snapshot) in &self.persisting {
+            if snapshot.completed() {
+                done.push((key.clone(), true));
+            } else if snapshot.failed() {
+                error!(
+                    "snapshot of chunk {} in partition {} failed, retrying",
+                    key.1, key.0
+                );
+                done.push((key.clone(), false));
+            }
+        }
+
+        let mut completed_snapshots = vec![];
+        for (key, completed) in done {
+            let snapshot = self.persisting.remove(&key);
+            if completed {
+                self.persisted.insert(key);
+                completed_snapshots.extend(snapshot);
+            }
+        }
+        completed_snapshots
+    }
+
+    /// Records that the chunk has been persisted, for example by a previous
+    /// run of the server
+    pub(super) fn mark_persisted(&mut self, key: ChunkKey) {
+        self.persisted.insert(key);
+    }
+
+    /// Returns true if the chunk has been, or is being, persisted
+    fn is_persisting_or_persisted(&self, key: &ChunkKey) -> bool {
+        self.persisting.contains_key(key) || self.persisted.contains(key)
+    }
+}
+
+impl Db {
+    /// Applies this database's lifecycle rules as of `now`. Snapshots are
+    /// written to `store` under the database's base path `db_path`.
+    ///
+    /// Each call makes as much progress as it can without waiting for
+    /// snapshots to complete, so this is intended to be called periodically
+    /// by a background task.
+    pub async fn check_lifecycle(
+        &self,
+        store: &Arc<ObjectStore>,
+        db_path: &ObjectStorePath,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let rules = &self.rules.lifecycle_rules;
+        let mutable_buffer = match self.mutable_buffer.as_ref() {
+            Some(mutable_buffer) => mutable_buffer,
+            None => return Ok(()),
+        };
+
+        // Summarising the chunks walks every partition, so it is done once
+        // and the summaries of the chunks closed below are updated in place
+        let mut summaries = mutable_buffer.chunk_summaries().await;
+        let mut mutable_buffer_size: usize = summaries.iter().map(|c| c.size).sum();
+
+        // Once over the soft memory limit, every chunk with data is closed and
+        // moved to the read buffer, where it is stored more compactly
+        let over_soft_limit = over_limit(
+            rules.buffer_size_soft,
+            self.store_memory_usage(mutable_buffer_size),
+        );
+
+        // Close any open chunks that have reached their age or size limit
+        for summary in &mut summaries {
+            let close = should_close(rules, summary, now) || (over_soft_limit && summary.size > 0);
+            if summary.time_closed.is_none() && close {
+                info!(
+                    "closing chunk {} in partition {}",
+                    summary.id, summary.partition_key
+                );
+                let chunk = mutable_buffer
+                    .rollover_partition(&summary.partition_key)
+                    .await
+                    .context(RollingPartition)?;
+                *summary = chunk.summary(&summary.partition_key);
+            }
+        }
+
+        let mut closed: Vec<_> = summaries
+            .into_iter()
+            .filter(|c| c.time_closed.is_some())
+            .collect();
+        closed.sort_by_key(|c| c.time_closed);
+
+        // Start snapshots of any closed chunks that aren't persisted yet
+        if rules.persist {
+            let completed = self
+                .lifecycle_state
+                .lock()
+                .expect("mutex poisoned")
+                .update();
+
+            // Persisted chunks can be read from object storage once they are
+            // dropped from memory, and their writes are no longer needed in
+            // the local WAL
+            for snapshot in completed {
+                let meta = &snapshot.partition_meta;
+                if let Some(chunk_id) = meta.chunk_id {
+                    self.add_parquet_chunk(
+                        Arc::clone(store),
+                        snapshot.data_path.clone(),
+                        &meta.key,
+                        chunk_id,
+                        None,
+                        meta.tables.clone(),
+                    );
+                }
+                self.local_wal_snapshotted(&meta.key, &meta.writer_sequences)
+                    .await?;
+            }
+
+            for summary in &closed {
+                let key = (summary.partition_key.clone(), summary.id);
+                let started = self
+                    .lifecycle_state
+                    .lock()
+                    .expect("mutex poisoned")
+                    .is_persisting_or_persisted(&key);
+                if started {
+                    continue;
+                }
+
+                let chunk = match mutable_buffer.get_chunk(&key.0, key.1).await {
+                    Some(chunk) => chunk,
+                    None => continue,
+                };
+                let (metadata_path, data_path) = chunk_snapshot_paths(db_path, &key.0, key.1);
+                let writer_sequences = chunk.writer_sequences.clone();
+                let snapshot = snapshot_chunk(
+                    metadata_path,
+                    data_path,
+                    Arc::clone(store),
+                    &key.0,
+                    chunk,
+                    writer_sequences,
+                    None,
+                )
+                .context(Snapshotting)?;
+
+                self.lifecycle_state
+                    .lock()
+                    .expect("mutex poisoned")
+                    .persisting
+                    .insert(key, snapshot);
+            }
+        }
+
+        // Move closed chunks into the read buffer once they have been closed
+        // for long enough, or, oldest first, while the mutable buffer is over
+        // its size limit or the database is over its soft memory limit
+        let mut in_read_buffer = self.read_buffer_chunk_keys();
+        for summary in &closed {
+            let key = (summary.partition_key.clone(), summary.id);
+            if in_read_buffer.contains(&key) {
+                continue;
+            }
+
+            let delay_elapsed = match (rules.read_buffer_delay_seconds, summary.time_closed) {
+                (Some(delay), Some(time_closed)) => {
+                    now.signed_duration_since(time_closed) >= Duration::seconds(delay.into())
+                }
+                _ => false,
+            };
+            let over_size = over_limit(rules.mutable_buffer_max_size, mutable_buffer_size);
+
+            if delay_elapsed || over_size || over_soft_limit {
+                info!(
+                    "moving chunk {} in partition {} to the read buffer",
+                    summary.id, summary.partition_key
+                );
+                self.load_chunk_to_read_buffer(&key.0, key.1).await?;
+                mutable_buffer_size = mutable_buffer_size.saturating_sub(summary.size);
+                in_read_buffer.insert(key);
+            }
+        }
+
+        // Drop mutable buffer chunks that are in the read buffer, once they
+        // are persisted if persistence is enabled
+        let persisted = self
+            .lifecycle_state
+            .lock()
+            .expect("mutex poisoned")
+            .persisted
+            .clone();
+        let mut in_mutable_buffer = BTreeSet::new();
+        for summary in &closed {
+            let key = (summary.partition_key.clone(), summary.id);
+            if in_read_buffer.contains(&key) && (!rules.persist || persisted.contains(&key)) {
+                self.drop_mutable_buffer_chunk(&key.0, key.1).await?;
+            } else {
+                in_mutable_buffer.insert(key);
+            }
+        }
+
+        // Drop persisted chunks from the read buffer once they are no longer
+        // in the mutable buffer, either because the rules say so or, oldest
+        // first, until the database is back under its soft memory limit
+        if rules.persist {
+            for key in &persisted {
+                if !in_read_buffer.contains(key) || in_mutable_buffer.contains(key) {
+                    continue;
+                }
+
+                if rules.drop_persisted_from_read_buffer
+                    || over_limit(rules.buffer_size_soft, self.memory_usage())
+                {
+                    info!(
+                        "dropping persisted chunk {} in partition {} from the read buffer",
+                        key.1, key.0
+                    );
+                    self.drop_read_buffer_chunk(&key.0, key.1).await?;
+                    self.lifecycle_state
+                        .lock()
+                        .expect("mutex poisoned")
+                        .persisted
+                        .remove(key);
+                }
+            }
+        }
+
+        self.update_memory_usage().await;
+
+        Ok(())
+    }
+
+    /// Returns the partition key and id of every chunk in the read buffer
+    fn read_buffer_chunk_keys(&self) -> BTreeSet<ChunkKey> {
+        let read_buffer = self.read_buffer.read().expect("mutex poisoned");
+        read_buffer
+            .partition_keys()
+            .into_iter()
+            .flat_map(|partition_key| {
+                read_buffer
+                    .chunk_ids(&partition_key)
+                    .into_iter()
+                    .map(move |chunk_id| (partition_key.clone(), chunk_id))
+            })
+            .collect()
+    }
+}
+
+/// Returns true if `size` is over `limit`, if there is one
+fn over_limit(limit: Option<NonZeroUsize>, size: usize) -> bool {
+    limit.map(|limit| size > limit.get()).unwrap_or(false)
+}
+
+/// Returns true if the open chunk described by `summary` should be closed
+fn should_close(rules: &LifecycleRules, summary: &ChunkSummary, now: DateTime<Utc>) -> bool {
+    let elapsed_since = |time: Option<DateTime<Utc>>, seconds: u32| {
+        time.map(|time| now.signed_duration_since(time) >= Duration::seconds(seconds.into()))
+            .unwrap_or(false)
+    };
+
+    let lingered = rules
+        .mutable_linger_seconds
+        .map(|linger| elapsed_since(summary.time_of_last_write, linger.get()))
+        .unwrap_or(false);
+    let aged = rules
+        .mutable_max_age_seconds
+        .map(|max_age| elapsed_since(summary.time_of_first_write, max_age.get()))
+        .unwrap_or(false);
+    let too_big = rules
+        .mutable_size_threshold
+        .map(|threshold| summary.size >= threshold.get())
+        .unwrap_or(false);
+
+    lingered || aged || too_big
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::num::NonZeroU32;
+
+    use data_types::database_rules::DatabaseRules;
+    use mutable_buffer::MutableBufferDb;
+    use object_store::memory::InMemory;
+    use query::{test::TestLPWriter, PartitionChunk};
+    use read_buffer::Database as ReadBufferDb;
+
+    fn make_db(lifecycle_rules: LifecycleRules) -> Db {
+        let rules = DatabaseRules {
+            lifecycle_rules,
+            ..Default::default()
+        };
+        Db::new(
+            rules,
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            None, // wal buffer
+        )
+    }
+
+    fn summary(size: usize, first_write: i64, last_write: i64) -> ChunkSummary {
+        let time = |seconds| {
+            Some(DateTime::from_utc(
+                chrono::NaiveDateTime::from_timestamp(seconds, 0),
+                Utc,
+            ))
+        };
+        ChunkSummary {
+            partition_key: "1970-01-01T00".to_string(),
+            id: 0,
+            size,
+            time_of_first_write: time(first_write),
+            time_of_last_write: time(last_write),
+            time_closed: None,
+        }
+    }
+
+    async fn chunk_ids(db: &Db) -> (Vec<u32>, Vec<u32>) {
+        let partition_key = "1970-01-01T00";
+        let mutable_buffer = db
+            .mutable_buffer_chunks(partition_key)
+            .await
+            .iter()
+            .map(|c| c.id())
+            .collect();
+        let read_buffer = db.read_buffer.read().unwrap().chunk_ids(partition_key);
+        (mutable_buffer, read_buffer)
+    }
+
+    #[test]
+    fn should_close_rules() {
+        let now = DateTime::from_utc(chrono::NaiveDateTime::from_timestamp(100, 0), Utc);
+        let never = LifecycleRules::default();
+        assert!(!should_close(&never, &summary(1_000_000, 0, 0), now));
+
+        let linger = LifecycleRules {
+            mutable_linger_seconds: NonZeroU32::new(10),
+            ..Default::default()
+        };
+        assert!(should_close(&linger, &summary(10, 0, 90), now));
+        assert!(!should_close(&linger, &summary(10, 0, 95), now));
+
+        let max_age = LifecycleRules {
+            mutable_max_age_seconds: NonZeroU32::new(60),
+            ..Default::default()
+        };
+        assert!(should_close(&max_age, &summary(10, 40, 99), now));
+        assert!(!should_close(&max_age, &summary(10, 50, 99), now));
+
+        let size = LifecycleRules {
+            mutable_size_threshold: NonZeroUsize::new(100),
+            ..Default::default()
+        };
+        assert!(should_close(&size, &summary(100, 99, 99), now));
+        assert!(!should_close(&size, &summary(99, 99, 99), now));
+    }
+
+    #[tokio::test]
+    async fn lifecycle_without_persistence() {
+        let db = make_db(LifecycleRules {
+            mutable_linger_seconds: NonZeroU32::new(1),
+            read_buffer_delay_seconds: Some(0),
+            ..Default::default()
+        });
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let db_path = ObjectStorePath::default();
+
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+
+        // nothing happens until the chunk has lingered
+        db.check_lifecycle(&store, &db_path, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(chunk_ids(&db).await, (vec![0], vec![]));
+
+        // the chunk is closed, moved to the read buffer and dropped from the
+        // mutable buffer
+        let later = Utc::now() + Duration::seconds(10);
+        db.check_lifecycle(&store, &db_path, later).await.unwrap();
+        assert_eq!(chunk_ids(&db).await, (vec![1], vec![0]));
+    }
+
+    #[tokio::test]
+    async fn lifecycle_size_pressure() {
+        let db = make_db(LifecycleRules {
+            mutable_buffer_max_size: NonZeroUsize::new(1),
+            ..Default::default()
+        });
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let db_path = ObjectStorePath::default();
+
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+        db.rollover_partition("1970-01-01T00").await.unwrap();
+        writer.write_lp_string(&db, "cpu bar=2 20").await.unwrap();
+        db.rollover_partition("1970-01-01T00").await.unwrap();
+
+        // without a delay, closed chunks are moved to the read buffer only
+        // because the mutable buffer is over its size limit
+        db.check_lifecycle(&store, &db_path, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(chunk_ids(&db).await, (vec![2], vec![0, 1]));
+    }
+
+    #[tokio::test]
+    async fn lifecycle_with_persistence() {
+        let db = make_db(LifecycleRules {
+            read_buffer_delay_seconds: Some(0),
+            persist: true,
+            drop_persisted_from_read_buffer: true,
+            ..Default::default()
+        });
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let mut db_path = ObjectStorePath::default();
+        db_path.push_all_dirs(&["1", "test_db"]);
+
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+        db.rollover_partition("1970-01-01T00").await.unwrap();
+
+        // the chunk is loaded into the read buffer, but stays in the mutable
+        // buffer until its snapshot has completed
+        db.check_lifecycle(&store, &db_path, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(chunk_ids(&db).await, (vec![0, 1], vec![0]));
+
+        let snapshot = Arc::clone(
+            db.lifecycle_state
+                .lock()
+                .unwrap()
+                .persisting
+                .values()
+                .next()
+                .unwrap(),
+        );
+        while !snapshot.completed() {
+            assert!(!snapshot.failed());
+            tokio::task::yield_now().await;
+        }
+
+        let (mut metadata_path, _) = chunk_snapshot_paths(&db_path, "1970-01-01T00", 0);
+        metadata_path.set_file_name("1970-01-01T00.json");
+        assert!(store.get(&metadata_path).await.is_ok());
+
+        // once persisted, the chunk is dropped from both the mutable buffer
+        // and the read buffer, but can still be read from object storage
+        db.check_lifecycle(&store, &db_path, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(chunk_ids(&db).await, (vec![1], vec![]));
+
+        let parquet_chunks = db.parquet_chunks("1970-01-01T00");
+        assert_eq!(parquet_chunks.len(), 1);
+        assert_eq!(parquet_chunks[0].id(), 0);
+        assert!(parquet_chunks[0].has_table("cpu").await);
+    }
+
+    #[tokio::test]
+    async fn lifecycle_soft_limit() {
+        let db = make_db(LifecycleRules {
+            persist: true,
+            buffer_size_soft: NonZeroUsize::new(1),
+            ..Default::default()
+        });
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let db_path = ObjectStorePath::default();
+
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+
+        // over the soft limit, the open chunk is closed and moved to the read
+        // buffer straight away
+        db.check_lifecycle(&store, &db_path, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(chunk_ids(&db).await, (vec![0, 1], vec![0]));
+        assert!(db.memory_usage() > 1);
+
+        let snapshot = Arc::clone(
+            db.lifecycle_state
+                .lock()
+                .unwrap()
+                .persisting
+                .values()
+                .next()
+                .unwrap(),
+        );
+        while !snapshot.completed() {
+            assert!(!snapshot.failed());
+            tokio::task::yield_now().await;
+        }
+
+        // once persisted, the chunk is evicted from memory
+        db.check_lifecycle(&store, &db_path, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(chunk_ids(&db).await, (vec![1], vec![]));
+        assert_eq!(db.memory_usage(), 0);
+    }
+}
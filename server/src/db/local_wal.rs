@@ -0,0 +1,490 @@
+//! This module contains the WAL a database keeps on the local disk of the
+//! server when its rules have a `local_wal_config`. Every write is appended
+//! to the WAL, and synced to disk, before it is acknowledged, so the writes
+//! that were only in the mutable buffer can be replayed after a crash.
+//!
+//! A WAL entry is needed until the data it wrote to each of its partitions
+//! has been snapshotted to object storage; the files whose entries are all
+//! snapshotted are then deleted.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use data_types::{
+    data::ReplicatedWrite,
+    database_rules::{LocalWalConfig, LocalWalSyncPolicy},
+    partition_metadata::SequenceRange,
+};
+use snafu::{ensure, ResultExt};
+use tracing::{info, warn};
+use wal::{
+    writer::{start_wal_sync_task, WalDetails, WalFormat},
+    RecoveryOptions, SequenceNumber, SyncPolicy, WalBuilder,
+};
+
+use super::{
+    CreatingLocalWalDirectory, Db, MutableBufferWrite, OpeningLocalWal, ReadingLocalWal,
+    RecoveringLocalWal, Result, TruncatingLocalWal, UnsupportedLocalWalFormat, WritingLocalWal,
+};
+
+/// The local WAL of a database
+#[derive(Debug)]
+pub struct LocalWal {
+    directory: PathBuf,
+    details: WalDetails,
+    /// The entries whose data hasn't been snapshotted yet, by sequence
+    /// number
+    pending: Mutex<BTreeMap<SequenceNumber, PendingEntry>>,
+}
+
+/// A WAL entry with data that hasn't been snapshotted
+#[derive(Debug)]
+struct PendingEntry {
+    writer_id: u32,
+    sequence: u64,
+    /// The partitions that the entry wrote to whose data from it hasn't
+    /// been snapshotted
+    partition_keys: BTreeSet<String>,
+}
+
+impl LocalWal {
+    /// Opens the WAL in `directory`, creating it if it doesn't exist
+    pub async fn open(directory: &Path, config: &LocalWalConfig) -> Result<Self> {
+        fs::create_dir_all(directory).context(CreatingLocalWalDirectory { path: directory })?;
+
+        let sync_policy = match config.sync_policy {
+            LocalWalSyncPolicy::EveryWrite => SyncPolicy::EveryWrite,
+            LocalWalSyncPolicy::Interval(interval) => SyncPolicy::Interval(interval),
+            LocalWalSyncPolicy::Bytes { bytes, max_latency } => {
+                SyncPolicy::Bytes { bytes, max_latency }
+            }
+        };
+        let builder = WalBuilder::new(directory)
+            .file_rollover_size(config.file_rollover_size)
+            .sync_policy(sync_policy);
+
+        // a crash in the middle of an append leaves a torn entry at the end
+        // of the WAL, which was never acknowledged and is dropped. Corrupt
+        // entries before it need an operator to decide to lose them.
+        let report = builder
+            .clone()
+            .recover(RecoveryOptions {
+                skip_corrupt_entries: false,
+                repair: true,
+            })
+            .context(RecoveringLocalWal { path: directory })?;
+        if let Some(torn_tail) = report.torn_tail {
+            warn!(
+                "truncated torn entry at offset {} of {:?} ({} bytes)",
+                torn_tail.offset, torn_tail.path, torn_tail.len
+            );
+        }
+
+        let details = start_wal_sync_task(builder)
+            .await
+            .context(OpeningLocalWal { path: directory })?;
+        ensure!(
+            details.metadata.format == WalFormat::FlatBuffers,
+            UnsupportedLocalWalFormat {
+                path: directory,
+                format: details.metadata.format,
+            }
+        );
+        details
+            .write_metadata()
+            .await
+            .context(OpeningLocalWal { path: directory })?;
+
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            details,
+            pending: Default::default(),
+        })
+    }
+
+    /// Appends `write` to the WAL, returning once it is synced to disk
+    pub async fn append(&self, write: &ReplicatedWrite) -> Result<()> {
+        let entry = self
+            .details
+            .write_and_sync(write.data.clone())
+            .await
+            .context(WritingLocalWal)?;
+
+        let (writer_id, sequence) = write.writer_and_sequence();
+        let partition_keys = write
+            .partition_keys()
+            .into_iter()
+            .map(ToString::to_string)
+            .collect();
+        self.add_pending(entry, writer_id, sequence, partition_keys);
+
+        Ok(())
+    }
+
+    /// Returns every write in the WAL, with the sequence number of its
+    /// entry
+    pub fn writes(&self) -> Result<Vec<(SequenceNumber, ReplicatedWrite)>> {
+        let entries = WalBuilder::new(&self.directory)
+            .entries()
+            .context(ReadingLocalWal {
+                path: &self.directory,
+            })?;
+
+        entries
+            .map(|entry| {
+                let entry = entry.context(ReadingLocalWal {
+                    path: &self.directory,
+                })?;
+                Ok((
+                    entry.sequence_number(),
+                    ReplicatedWrite::from(entry.as_data()),
+                ))
+            })
+            .collect()
+    }
+
+    /// Records that the entry `entry` has data for the partitions
+    /// `partition_keys` that hasn't been snapshotted
+    fn add_pending(
+        &self,
+        entry: SequenceNumber,
+        writer_id: u32,
+        sequence: u64,
+        partition_keys: BTreeSet<String>,
+    ) {
+        if partition_keys.is_empty() {
+            return;
+        }
+
+        self.pending.lock().expect("mutex poisoned").insert(
+            entry,
+            PendingEntry {
+                writer_id,
+                sequence,
+                partition_keys,
+            },
+        );
+    }
+
+    /// Records that the writes with the sequences `writer_sequences` have
+    /// been snapshotted for `partition_key`, and deletes the WAL files that
+    /// are no longer needed
+    pub async fn snapshotted(
+        &self,
+        partition_key: &str,
+        writer_sequences: &BTreeMap<u32, SequenceRange>,
+    ) -> Result<()> {
+        let first_pending = {
+            let mut pending = self.pending.lock().expect("mutex poisoned");
+            pending.retain(|_, entry| {
+                let snapshotted = writer_sequences
+                    .get(&entry.writer_id)
+                    .map_or(false, |range| range.contains(entry.sequence));
+                if snapshotted {
+                    entry.partition_keys.remove(partition_key);
+                }
+                !entry.partition_keys.is_empty()
+            });
+            pending.keys().next().copied()
+        };
+
+        // If nothing is pending every file but the active one can go
+        let up_to = first_pending.unwrap_or(SequenceNumber::MAX);
+        self.details
+            .delete_up_to_entry(up_to)
+            .await
+            .context(TruncatingLocalWal)
+    }
+}
+
+impl Db {
+    /// Opens the local WAL of this database in `directory`. Writes to the
+    /// database are appended to it from then on; call `recover` to replay
+    /// the writes that are already in it.
+    pub async fn open_local_wal(&self, directory: &Path, config: &LocalWalConfig) -> Result<()> {
+        let local_wal = LocalWal::open(directory, config).await?;
+        info!("opened local WAL in {:?}", directory);
+
+        *self.local_wal.write().expect("mutex poisoned") = Some(Arc::new(local_wal));
+        Ok(())
+    }
+
+    /// Returns the local WAL of this database, if it has been opened
+    pub(super) fn local_wal(&self) -> Option<Arc<LocalWal>> {
+        self.local_wal.read().expect("mutex poisoned").clone()
+    }
+
+    /// Appends `write` to the local WAL, if there is one, returning once
+    /// it is synced to disk
+    pub async fn append_to_local_wal(&self, write: &ReplicatedWrite) -> Result<()> {
+        match self.local_wal() {
+            Some(local_wal) => local_wal.append(write).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Replays the writes in the local WAL, if there is one, into the
+    /// mutable buffer. The partitions of each write for which
+    /// `is_snapshotted` returns true, and the writes in `replayed` (which
+    /// are already in the mutable buffer), are skipped. Returns the
+    /// writer id and sequence number of every write in the WAL.
+    pub(super) async fn replay_local_wal(
+        &self,
+        is_snapshotted: impl Fn(&str, u32, u64) -> bool,
+        replayed: &BTreeSet<(u32, u64)>,
+    ) -> Result<BTreeSet<(u32, u64)>> {
+        let local_wal = match self.local_wal() {
+            Some(local_wal) => local_wal,
+            None => return Ok(BTreeSet::new()),
+        };
+
+        let writes = local_wal.writes()?;
+        let mut sequences = BTreeSet::new();
+        for (entry, write) in &writes {
+            let (writer_id, sequence) = write.writer_and_sequence();
+            sequences.insert((writer_id, sequence));
+            self.mark_applied(write);
+
+            let partition_keys: BTreeSet<_> = write
+                .partition_keys()
+                .into_iter()
+                .filter(|partition_key| !is_snapshotted(*partition_key, writer_id, sequence))
+                .map(ToString::to_string)
+                .collect();
+
+            if !replayed.contains(&(writer_id, sequence)) {
+                let write =
+                    write.filter_partitions(|partition_key| partition_keys.contains(partition_key));
+                if let (Some(write), Some(mutable_buffer)) = (write, &self.mutable_buffer) {
+                    mutable_buffer
+                        .store_replicated_write(&write)
+                        .await
+                        .context(MutableBufferWrite)?;
+                }
+            }
+
+            // the entry is needed until the data it wrote to the mutable
+            // buffer is snapshotted
+            local_wal.add_pending(*entry, writer_id, sequence, partition_keys);
+        }
+
+        if !writes.is_empty() {
+            info!("replayed {} writes from the local WAL", writes.len());
+        }
+
+        Ok(sequences)
+    }
+
+    /// Records that the writes with the sequences `writer_sequences` have
+    /// been snapshotted for `partition_key`, deleting the local WAL files
+    /// that are no longer needed
+    pub(super) async fn local_wal_snapshotted(
+        &self,
+        partition_key: &str,
+        writer_sequences: &BTreeMap<u32, SequenceRange>,
+    ) -> Result<()> {
+        match self.local_wal() {
+            Some(local_wal) => local_wal.snapshotted(partition_key, writer_sequences).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use arrow_deps::assert_table_eq;
+    use chrono::Utc;
+    use data_types::{data::lines_to_replicated_write, database_rules::DatabaseRules};
+    use influxdb_line_protocol::parse_lines;
+    use mutable_buffer::MutableBufferDb;
+    use query::frontend::sql::SQLQueryPlanner;
+    use read_buffer::Database as ReadBufferDb;
+
+    fn make_db() -> Db {
+        Db::new(
+            DatabaseRules::default(),
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            None,
+        )
+    }
+
+    fn make_write(db: &Db, sequence: u64, lp: &str) -> ReplicatedWrite {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        lines_to_replicated_write(1, sequence, &lines, &db.rules).unwrap()
+    }
+
+    async fn write(db: &Db, sequence: u64, lp: &str) {
+        let write = make_write(db, sequence, lp);
+        db.append_to_local_wal(&write).await.unwrap();
+        db.mutable_buffer
+            .as_ref()
+            .unwrap()
+            .store_replicated_write(&write)
+            .await
+            .unwrap();
+    }
+
+    async fn query(db: &Db, sql: &str) -> Vec<arrow_deps::arrow::record_batch::RecordBatch> {
+        let executor = query::exec::Executor::new();
+        let plan = SQLQueryPlanner::default()
+            .query(db, sql, &executor)
+            .await
+            .unwrap();
+        arrow_deps::datafusion::physical_plan::collect(plan)
+            .await
+            .unwrap()
+    }
+
+    fn wal_files(directory: &Path) -> usize {
+        fs::read_dir(directory)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .path()
+                    .extension()
+                    .map_or(false, |ext| ext == "db")
+            })
+            .count()
+    }
+
+    #[tokio::test]
+    async fn replay_local_wal() {
+        let dir = test_helpers::tmp_dir().unwrap();
+        let config = LocalWalConfig::default();
+
+        let db = make_db();
+        db.open_local_wal(dir.path(), &config).await.unwrap();
+        write(&db, 1, "cpu bar=1 10").await;
+        write(&db, 2, "cpu bar=2 20").await;
+        drop(db);
+
+        // a new server process replays the writes
+        let db = make_db();
+        db.open_local_wal(dir.path(), &config).await.unwrap();
+        let sequences = db
+            .replay_local_wal(|_, _, _| false, &BTreeSet::new())
+            .await
+            .unwrap();
+        assert_eq!(sequences, vec![(1, 1), (1, 2)].into_iter().collect());
+
+        let batches = query(&db, "select * from cpu").await;
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "| 2   | 20   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+    }
+
+    #[tokio::test]
+    async fn replay_concurrent_writes_synced_on_interval() {
+        let dir = test_helpers::tmp_dir().unwrap();
+        let config = LocalWalConfig {
+            sync_policy: LocalWalSyncPolicy::Interval(std::time::Duration::from_millis(10)),
+            ..Default::default()
+        };
+
+        let db = make_db();
+        db.open_local_wal(dir.path(), &config).await.unwrap();
+        futures::future::join_all((1..=5).map(|sequence| {
+            let lp = format!("cpu bar={} {}", sequence, sequence * 10);
+            let write = make_write(&db, sequence, &lp);
+            let db = &db;
+            async move { db.append_to_local_wal(&write).await.unwrap() }
+        }))
+        .await;
+        drop(db);
+
+        let db = make_db();
+        db.open_local_wal(dir.path(), &config).await.unwrap();
+        let sequences = db
+            .replay_local_wal(|_, _, _| false, &BTreeSet::new())
+            .await
+            .unwrap();
+        assert_eq!(sequences, (1..=5).map(|sequence| (1, sequence)).collect());
+    }
+
+    #[tokio::test]
+    async fn replay_skips_snapshotted_writes() {
+        let dir = test_helpers::tmp_dir().unwrap();
+        let config = LocalWalConfig::default();
+
+        let db = make_db();
+        db.open_local_wal(dir.path(), &config).await.unwrap();
+        write(&db, 1, "cpu bar=1 10").await;
+        write(&db, 2, "cpu bar=2 20").await;
+        drop(db);
+
+        let db = make_db();
+        db.open_local_wal(dir.path(), &config).await.unwrap();
+        let replayed = vec![(1, 2)].into_iter().collect();
+        db.replay_local_wal(|_, _, sequence| sequence == 1, &replayed)
+            .await
+            .unwrap();
+
+        // neither the snapshotted write nor the already replayed one is
+        // written to the mutable buffer
+        let partition_keys = db.mutable_buffer.as_ref().unwrap().partition_keys().await;
+        assert!(partition_keys.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn snapshotted_entries_are_deleted() {
+        let dir = test_helpers::tmp_dir().unwrap();
+        // every entry goes to a new file
+        let config = LocalWalConfig {
+            file_rollover_size: 1,
+            ..Default::default()
+        };
+
+        let db = make_db();
+        db.open_local_wal(dir.path(), &config).await.unwrap();
+        let partition_key = "1970-01-01T00";
+        write(&db, 1, "cpu bar=1 10").await;
+        write(&db, 2, "cpu bar=2 20").await;
+        write(&db, 3, "cpu bar=3 30").await;
+        assert_eq!(wal_files(dir.path()), 3);
+
+        // A file is deleted once the file after it starts before the
+        // first entry that isn't snapshotted
+        let mut writer_sequences = BTreeMap::new();
+        writer_sequences.insert(1, SequenceRange { min: 1, max: 2 });
+        db.local_wal_snapshotted(partition_key, &writer_sequences)
+            .await
+            .unwrap();
+        assert_eq!(wal_files(dir.path()), 2);
+        let writes = db.local_wal().unwrap().writes().unwrap();
+        let sequences: Vec<_> = writes
+            .iter()
+            .map(|(_, write)| write.writer_and_sequence())
+            .collect();
+        assert_eq!(sequences, vec![(1, 2), (1, 3)]);
+
+        // Once everything is snapshotted only the active file is kept
+        writer_sequences.insert(1, SequenceRange { min: 3, max: 3 });
+        db.local_wal_snapshotted(partition_key, &writer_sequences)
+            .await
+            .unwrap();
+        assert_eq!(wal_files(dir.path()), 1);
+
+        // Other partitions' snapshots don't affect the entries
+        write(&db, 4, "cpu bar=4 40").await;
+        writer_sequences.insert(1, SequenceRange { min: 4, max: 4 });
+        db.local_wal_snapshotted("another_partition", &writer_sequences)
+            .await
+            .unwrap();
+        assert_eq!(wal_files(dir.path()), 2);
+    }
+}
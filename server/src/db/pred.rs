@@ -3,6 +3,7 @@
 
 use std::convert::TryFrom;
 
+use arrow_deps::datafusion::logical_plan::{Expr, Operator};
 use query::predicate::Predicate;
 use snafu::Snafu;
 
@@ -15,11 +16,17 @@ pub enum Error {
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 pub fn to_read_buffer_predicate(predicate: &Predicate) -> Result<read_buffer::Predicate> {
+    // Conjunctions such as `host >= 'a' AND host < 'm'` are applied as
+    // separate expressions by the read buffer.
+    let mut exprs = vec![];
+    for expr in &predicate.exprs {
+        split_conjunction(expr, &mut exprs);
+    }
+
     // Try to convert non-time column expressions into binary expressions
     // that are compatible with the read buffer.
-    match predicate
-        .exprs
-        .iter()
+    match exprs
+        .into_iter()
         .map(read_buffer::BinaryExpr::try_from)
         .collect::<Result<Vec<_>, _>>()
     {
@@ -40,10 +47,25 @@ pub fn to_read_buffer_predicate(predicate: &Predicate) -> Result<read_buffer::Pr
     }
 }
 
+// Appends the expressions that are conjunctively combined in `expr` to
+// `exprs`, e.g., `a AND (b AND c)` appends `a`, `b` and `c`.
+fn split_conjunction<'a>(expr: &'a Expr, exprs: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => {
+            split_conjunction(left, exprs);
+            split_conjunction(right, exprs);
+        }
+        other => exprs.push(other),
+    }
+}
+
 #[cfg(test)]
 pub mod test {
     use super::*;
-    use arrow_deps::datafusion::logical_plan::Expr;
     use arrow_deps::datafusion::scalar::ScalarValue;
 
     use query::predicate::PredicateBuilder;
@@ -108,6 +130,36 @@ pub mod test {
                     2000,
                 ),
             ),
+            // a lexical range of strings expressed as a conjunction
+            (
+                PredicateBuilder::default()
+                    .add_expr(
+                        Expr::Column("host".to_owned())
+                            .gt_eq(Expr::Literal(ScalarValue::Utf8(Some("a".to_owned()))))
+                            .and(
+                                Expr::Column("host".to_owned())
+                                    .lt(Expr::Literal(ScalarValue::Utf8(Some("m".to_owned())))),
+                            ),
+                    )
+                    .build(),
+                RBPredicate::new(vec![
+                    RBBinaryExpr::from(("host", ">=", "a")),
+                    RBBinaryExpr::from(("host", "<", "m")),
+                ]),
+            ),
+            // NULL checks
+            (
+                PredicateBuilder::default()
+                    .add_expr(Expr::IsNull(Box::new(Expr::Column("track".to_owned()))))
+                    .add_expr(Expr::IsNotNull(Box::new(Expr::Column(
+                        "counter".to_owned(),
+                    ))))
+                    .build(),
+                RBPredicate::new(vec![
+                    RBBinaryExpr::is_null("track"),
+                    RBBinaryExpr::is_not_null("counter"),
+                ]),
+            ),
         ];
 
         for (predicate, exp) in cases {
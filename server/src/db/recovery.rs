@@ -0,0 +1,551 @@
+//! This module contains the logic that restores a database from object
+//! storage when the server starts: the chunks written by snapshots are made
+//! available to queries, and the writes in persisted WAL segments (and the
+//! local WAL, if the database has one) that are not part of any of those
+//! chunks are replayed into the mutable buffer.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use data_types::partition_metadata::{Partition as PartitionMeta, SequenceRange};
+use futures::TryStreamExt;
+use object_store::{path::ObjectStorePath, ObjectStore};
+use query::Database;
+use snafu::ResultExt;
+use tracing::{info, warn};
+
+use super::{
+    Db, InvalidPartitionId, ListingObjectStore, MutableBufferWrite, ReadingObjectStore,
+    ReadingWalSegment, Result,
+};
+use crate::{buffer::Segment, snapshot::chunk_snapshot_paths};
+
+impl Db {
+    /// Restores the data written under the database's base path `db_path`
+    /// in `store` by a previous run of the server with id `writer_id`.
+    ///
+    /// This is intended to be called once, before the database accepts any
+    /// writes. Afterwards the sequence numbers returned by `next_sequence`,
+    /// and the ids of new mutable buffer chunks and WAL segments, continue
+    /// from where the previous run left off.
+    ///
+    /// If the database's local WAL has been opened (see `open_local_wal`),
+    /// the writes in it that are not part of any snapshot are replayed too.
+    pub async fn recover(
+        &self,
+        store: &Arc<ObjectStore>,
+        db_path: &ObjectStorePath,
+        writer_id: u32,
+    ) -> Result<()> {
+        let snapshots = self.recover_snapshots(store, db_path).await?;
+
+        // the sequence ranges in the snapshots of each partition, by writer
+        let mut snapshotted: BTreeMap<&str, BTreeMap<u32, Vec<SequenceRange>>> = BTreeMap::new();
+        for meta in &snapshots {
+            let partition = snapshotted.entry(&meta.key).or_default();
+            for (&writer, &range) in &meta.writer_sequences {
+                partition.entry(writer).or_default().push(range);
+            }
+        }
+        let is_snapshotted = |partition_key: &str, writer: u32, sequence: u64| {
+            snapshotted
+                .get(partition_key)
+                .and_then(|writers| writers.get(&writer))
+                .map_or(false, |ranges| {
+                    ranges.iter().any(|range| range.contains(sequence))
+                })
+        };
+
+        let replayed = self.replay_wal(store, db_path, &is_snapshotted).await?;
+        let in_local_wal = self.replay_local_wal(&is_snapshotted, &replayed).await?;
+
+        let max_sequence = snapshots
+            .iter()
+            .filter_map(|meta| meta.writer_sequences.get(&writer_id))
+            .map(|range| range.max)
+            .chain(
+                replayed
+                    .iter()
+                    .chain(&in_local_wal)
+                    .filter(|(writer, _)| *writer == writer_id)
+                    .map(|(_, sequence)| *sequence),
+            )
+            .max();
+
+        if let Some(sequence) = max_sequence {
+            self.sequence
+                .fetch_max(sequence + 1, std::sync::atomic::Ordering::SeqCst);
+        }
+
+        // the restored writes aren't in the WAL buffer, so subscribers that
+        // haven't seen them have to resync from object storage
+        if let Some(wal_buffer) = &self.wal_buffer {
+            let mut wal_buffer = wal_buffer.lock().expect("mutex poisoned");
+            let snapshot_sequences = snapshots.iter().flat_map(|meta| {
+                meta.writer_sequences
+                    .iter()
+                    .map(|(&writer, range)| (writer, range.max))
+            });
+            let restored = snapshot_sequences.chain(replayed).chain(in_local_wal);
+            for (writer, sequence) in restored {
+                wal_buffer.mark_missing(writer, sequence);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes the chunks snapshotted for the partitions listed in the
+    /// database's `read_only_partitions` available to queries. Only their
+    /// metadata is read here; the data stays in object storage until a query
+    /// needs it.
+    pub async fn load_read_only_partitions(&self, store: &Arc<ObjectStore>) -> Result<()> {
+        for partition_id in &self.rules.read_only_partitions {
+            let parts: Vec<_> = partition_id
+                .split('/')
+                .filter(|part| !part.is_empty())
+                .collect();
+            let (db_path, partition_key) = match parts.as_slice() {
+                [writer_id, db_name, partition_key] => {
+                    let mut db_path = ObjectStorePath::default();
+                    db_path.push_all_dirs(&[*writer_id, *db_name]);
+                    (db_path, *partition_key)
+                }
+                _ => return InvalidPartitionId { partition_id }.fail(),
+            };
+
+            let mut meta_path = db_path.clone();
+            meta_path.push_all_dirs(&["meta", partition_key]);
+
+            for (meta, chunk_id) in list_snapshots(store, &meta_path).await? {
+                if meta.key != partition_key {
+                    continue;
+                }
+
+                let (_, data_path) = chunk_snapshot_paths(&db_path, &meta.key, chunk_id);
+                self.add_parquet_chunk(
+                    Arc::clone(store),
+                    data_path,
+                    &meta.key,
+                    chunk_id,
+                    Some(partition_id.as_str()),
+                    meta.tables,
+                );
+            }
+            info!("loaded read only partition {}", partition_id);
+        }
+
+        Ok(())
+    }
+
+    /// Makes every chunk snapshotted under `db_path` available to queries,
+    /// returning the metadata of the chunks. As for read only partitions,
+    /// only the metadata is read here, so recovery doesn't need the memory
+    /// to hold every snapshot; the data stays in object storage until a
+    /// query needs it.
+    async fn recover_snapshots(
+        &self,
+        store: &Arc<ObjectStore>,
+        db_path: &ObjectStorePath,
+    ) -> Result<Vec<PartitionMeta>> {
+        let mut meta_path = db_path.clone();
+        meta_path.push_dir("meta");
+
+        let mut snapshots = vec![];
+        for (meta, chunk_id) in list_snapshots(store, &meta_path).await? {
+            let (_, data_path) = chunk_snapshot_paths(db_path, &meta.key, chunk_id);
+            info!(
+                "recovered chunk {} of partition {} from object store",
+                chunk_id, meta.key
+            );
+
+            self.add_parquet_chunk(
+                Arc::clone(store),
+                data_path,
+                &meta.key,
+                chunk_id,
+                None,
+                meta.tables.clone(),
+            );
+            self.lifecycle_state
+                .lock()
+                .expect("mutex poisoned")
+                .mark_persisted((meta.key.clone(), chunk_id));
+            if let Some(mutable_buffer) = &self.mutable_buffer {
+                mutable_buffer
+                    .reserve_chunk_ids(&meta.key, chunk_id + 1)
+                    .await;
+            }
+
+            snapshots.push(meta);
+        }
+
+        Ok(snapshots)
+    }
+
+    /// Replays the writes in the WAL segments persisted under `db_path` into
+    /// the mutable buffer, skipping the partitions of each write for which
+    /// `is_snapshotted` returns true. Returns the writer id and sequence
+    /// number of every write in the segments.
+    ///
+    /// The segments are read one at a time, in order of id, so only one is
+    /// held in memory at once.
+    async fn replay_wal(
+        &self,
+        store: &ObjectStore,
+        db_path: &ObjectStorePath,
+        is_snapshotted: impl Fn(&str, u32, u64) -> bool,
+    ) -> Result<BTreeSet<(u32, u64)>> {
+        let mut wal_path = db_path.clone();
+        wal_path.push_dir("wal");
+
+        // segment paths are made of the zero padded digits of the segment
+        // id, so they sort in the same order as the ids
+        let mut locations: Vec<_> = list_all(store, &wal_path)
+            .await?
+            .into_iter()
+            .map(|location| (store.convert_path(&location), location))
+            .collect();
+        locations.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut sequences = BTreeSet::new();
+        let mut last_segment_id = None;
+        for (_, location) in &locations {
+            let data = get_bytes(store, location).await?;
+            let segment = Segment::from_file_bytes(&data).context(ReadingWalSegment)?;
+            drop(data);
+
+            for write in &segment.writes {
+                let (writer, sequence) = write.writer_and_sequence();
+                sequences.insert((writer, sequence));
+                // writes from other servers that are restored mustn't be
+                // applied again if they are replicated to this server again
+                self.mark_applied(write);
+
+                let write = write.filter_partitions(|partition_key| {
+                    !is_snapshotted(partition_key, writer, sequence)
+                });
+
+                if let (Some(write), Some(mutable_buffer)) = (write, &self.mutable_buffer) {
+                    mutable_buffer
+                        .store_replicated_write(&write)
+                        .await
+                        .context(MutableBufferWrite)?;
+                }
+            }
+
+            last_segment_id = last_segment_id.max(Some(segment.id));
+        }
+
+        if !locations.is_empty() {
+            info!(
+                "replayed {} WAL segments from object store",
+                locations.len()
+            );
+        }
+        if let (Some(segment_id), Some(wal_buffer)) = (last_segment_id, &self.wal_buffer) {
+            wal_buffer
+                .lock()
+                .expect("mutex poisoned")
+                .resume_after_segment(segment_id);
+        }
+
+        Ok(sequences)
+    }
+}
+
+/// Returns the metadata, and chunk id, of every snapshot whose metadata is
+/// stored under `prefix`. Metadata that can't be parsed is skipped.
+async fn list_snapshots(
+    store: &ObjectStore,
+    prefix: &ObjectStorePath,
+) -> Result<Vec<(PartitionMeta, u32)>> {
+    let mut snapshots = vec![];
+    for location in list_all(store, prefix).await? {
+        let data = get_bytes(store, &location).await?;
+        let meta: PartitionMeta = match serde_json::from_slice(&data) {
+            Ok(meta) => meta,
+            Err(e) => {
+                warn!(
+                    "skipping unreadable snapshot metadata {}: {}",
+                    store.convert_path(&location),
+                    e
+                );
+                continue;
+            }
+        };
+        match meta.chunk_id {
+            Some(chunk_id) => snapshots.push((meta, chunk_id)),
+            None => warn!(
+                "skipping snapshot metadata {} without a chunk id",
+                store.convert_path(&location)
+            ),
+        }
+    }
+
+    Ok(snapshots)
+}
+
+/// Returns the locations of every object under `prefix`
+async fn list_all(store: &ObjectStore, prefix: &ObjectStorePath) -> Result<Vec<ObjectStorePath>> {
+    let locations: Vec<Vec<_>> = store
+        .list(Some(prefix))
+        .await
+        .context(ListingObjectStore)?
+        .try_collect()
+        .await
+        .context(ListingObjectStore)?;
+
+    Ok(locations.into_iter().flatten().collect())
+}
+
+async fn get_bytes(store: &ObjectStore, location: &ObjectStorePath) -> Result<bytes::BytesMut> {
+    store
+        .get(location)
+        .await
+        .context(ReadingObjectStore)?
+        .map_ok(|b| bytes::BytesMut::from(&b[..]))
+        .try_concat()
+        .await
+        .context(ReadingObjectStore)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use arrow_deps::assert_table_eq;
+    use chrono::Utc;
+    use data_types::{
+        data::lines_to_replicated_write,
+        database_rules::{DatabaseRules, LifecycleRules, WalBufferRollover},
+        selection::Selection,
+    };
+    use influxdb_line_protocol::parse_lines;
+    use mutable_buffer::MutableBufferDb;
+    use object_store::memory::InMemory;
+    use query::PartitionChunk;
+    use read_buffer::Database as ReadBufferDb;
+
+    use crate::buffer::{object_store_path_for_segment, Buffer};
+
+    fn make_db() -> Db {
+        let rules = DatabaseRules {
+            lifecycle_rules: LifecycleRules {
+                persist: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        Db::new(
+            rules,
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            Some(Buffer::new(
+                1 << 20,
+                1,
+                WalBufferRollover::ReturnError,
+                false,
+            )),
+        )
+    }
+
+    fn write(sequence: u64, lp: &str) -> data_types::data::ReplicatedWrite {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        lines_to_replicated_write(1, sequence, &lines, &DatabaseRules::default()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn recover_snapshots_and_wal() {
+        let partition_key = "1970-01-01T00";
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let mut db_path = ObjectStorePath::default();
+        db_path.push_all_dirs(&["1", "test_db"]);
+
+        // the first write is snapshotted, the second is only in the WAL
+        let db = make_db();
+        let write1 = write(1, "cpu bar=1 10");
+        let write2 = write(2, "cpu bar=2 20");
+        db.store_replicated_write(&write1).await.unwrap();
+        db.rollover_partition(partition_key).await.unwrap();
+        db.check_lifecycle(&store, &db_path, Utc::now())
+            .await
+            .unwrap();
+        db.store_replicated_write(&write2).await.unwrap();
+
+        let (mut metadata_path, _) = chunk_snapshot_paths(&db_path, partition_key, 0);
+        metadata_path.set_file_name(format!("{}.json", partition_key));
+        while store.get(&metadata_path).await.is_err() {
+            tokio::task::yield_now().await;
+        }
+
+        // persist each write in its own WAL segment
+        let mut wal = Buffer::new(1 << 20, 1, WalBufferRollover::ReturnError, false);
+        wal.resume_after_segment(6);
+        for replicated_write in vec![write1, write2, write(3, "mem bar=3 30")] {
+            let segment = wal.append(Arc::new(replicated_write)).unwrap().unwrap();
+            let data = segment.to_file_bytes(1).unwrap();
+            let len = data.len();
+            store
+                .put(
+                    &object_store_path_for_segment(&db_path, segment.id).unwrap(),
+                    futures::stream::once(async move { std::io::Result::Ok(data) }),
+                    len,
+                )
+                .await
+                .unwrap();
+        }
+
+        // recover into a new database
+        let db = make_db();
+        db.recover(&store, &db_path, 1).await.unwrap();
+
+        // the snapshotted chunk is read from object storage, not loaded into
+        // memory
+        assert!(db.read_buffer_chunks(partition_key).await.is_empty());
+        let parquet_chunk = &db.parquet_chunks(partition_key)[0];
+        assert_eq!(parquet_chunk.id(), 0);
+        let stream = parquet_chunk
+            .read_table("cpu", Selection::Some(&["bar", "time"]))
+            .await
+            .unwrap();
+        let batches = arrow_deps::datafusion::physical_plan::common::collect(stream)
+            .await
+            .unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // writes already in the snapshot are not replayed, and new chunks
+        // do not reuse the snapshotted chunk's id
+        let mb_chunks = db.mutable_buffer_chunks(partition_key).await;
+        assert_eq!(mb_chunks.len(), 1);
+        assert_eq!(mb_chunks[0].id(), 1);
+        let mut batches = vec![];
+        mb_chunks[0]
+            .table_to_arrow(&mut batches, "cpu", Selection::All)
+            .unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 2   | 20   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        assert_eq!(db.next_sequence(), 4);
+
+        // new WAL segments are numbered after the recovered one
+        let segment = db
+            .wal_buffer
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .append(Arc::new(write(4, "cpu bar=4 40")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(segment.id, 10);
+    }
+
+    #[tokio::test]
+    async fn load_read_only_partitions() {
+        let partition_key = "1970-01-01T00";
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let mut db_path = ObjectStorePath::default();
+        db_path.push_all_dirs(&["2", "other_db"]);
+
+        // snapshot a chunk of another writer's database
+        let db = make_db();
+        db.store_replicated_write(&write(1, "cpu,host=a bar=1 10\ncpu,host=b bar=2 20"))
+            .await
+            .unwrap();
+        db.rollover_partition(partition_key).await.unwrap();
+        db.check_lifecycle(&store, &db_path, Utc::now())
+            .await
+            .unwrap();
+
+        let (mut metadata_path, _) = chunk_snapshot_paths(&db_path, partition_key, 0);
+        metadata_path.set_file_name(format!("{}.json", partition_key));
+        while store.get(&metadata_path).await.is_err() {
+            tokio::task::yield_now().await;
+        }
+
+        let rules = DatabaseRules {
+            read_only_partitions: vec![format!("/2/other_db/{}/", partition_key)],
+            ..Default::default()
+        };
+        let db = Db::new(rules, None, ReadBufferDb::new(), None);
+        db.load_read_only_partitions(&store).await.unwrap();
+
+        let chunks = db.parquet_chunks(partition_key);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].id(), 0);
+        assert!(chunks[0].has_table("cpu").await);
+
+        let stream = chunks[0]
+            .read_table("cpu", Selection::Some(&["host", "bar"]))
+            .await
+            .unwrap();
+        let batches = arrow_deps::datafusion::physical_plan::common::collect(stream)
+            .await
+            .unwrap();
+        let expected = vec![
+            "+------+-----+",
+            "| host | bar |",
+            "+------+-----+",
+            "| a    | 1   |",
+            "| b    | 2   |",
+            "+------+-----+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // the schema is only downloaded once
+        chunks[0].table_schema("cpu", Selection::All).await.unwrap();
+        match chunks[0].as_ref() {
+            crate::db::DBChunk::ParquetFile { schemas, .. } => {
+                assert_eq!(schemas.lock().unwrap().len(), 1)
+            }
+            _ => panic!("expected a parquet chunk"),
+        }
+
+        // without a mutable buffer, the partition is still listed
+        assert_eq!(db.partition_keys().await.unwrap(), vec![partition_key]);
+
+        // chunks of read only partitions aren't mistaken for this database's
+        // chunks with the same id
+        let rules = DatabaseRules {
+            read_only_partitions: vec![format!("/2/other_db/{}/", partition_key)],
+            ..Default::default()
+        };
+        let db = Db::new(
+            rules,
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            None,
+        );
+        db.load_read_only_partitions(&store).await.unwrap();
+        db.store_replicated_write(&write(1, "cpu,host=c bar=3 30"))
+            .await
+            .unwrap();
+        let chunks = db.chunks(partition_key).await;
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks.iter().all(|chunk| chunk.id() == 0));
+
+        let rules = DatabaseRules {
+            read_only_partitions: vec!["2/other_db".to_string()],
+            ..Default::default()
+        };
+        let db = Db::new(rules, None, ReadBufferDb::new(), None);
+        let err = db.load_read_only_partitions(&store).await.unwrap_err();
+        assert!(matches!(err, crate::db::Error::InvalidPartitionId { .. }));
+    }
+}
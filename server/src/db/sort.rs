@@ -0,0 +1,225 @@
+//! Functions for organising the rows of a table before they are loaded into
+//! the read buffer. Sorting rows so that values repeat in long runs improves
+//! the read buffer's compression, and its ability to prune row groups based
+//! on their column ranges.
+
+use std::{collections::BTreeSet, convert::TryFrom, sync::Arc};
+
+use arrow_deps::arrow::{
+    array::{Array, StringArray, UInt32Array},
+    compute::kernels::{
+        sort::{lexsort_to_indices, SortColumn, SortOptions},
+        take::take,
+    },
+    record_batch::RecordBatch,
+};
+use data_types::schema::{InfluxColumnType, Schema, TIME_COLUMN_NAME};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid table schema: {}", source))]
+    InvalidSchema { source: data_types::schema::Error },
+
+    #[snafu(display("Error sorting table data: {}", source))]
+    Sorting {
+        source: arrow_deps::arrow::error::ArrowError,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns the default sort key for the rows in `batch`: its tag columns in
+/// ascending order of cardinality, followed by the time column.
+pub fn default_sort_key(batch: &RecordBatch) -> Result<Vec<String>> {
+    let schema = Schema::try_from(batch.schema()).context(InvalidSchema)?;
+
+    let mut tags = vec![];
+    for (i, (influx_type, field)) in schema.iter().enumerate() {
+        if influx_type == Some(InfluxColumnType::Tag) {
+            tags.push((cardinality(batch.column(i).as_ref()), field.name()));
+        }
+    }
+
+    // Tags with the same cardinality are ordered by name so the key is
+    // deterministic.
+    tags.sort();
+
+    let mut sort_key = tags
+        .into_iter()
+        .map(|(_, name)| name.to_string())
+        .collect::<Vec<_>>();
+    if schema.find_index_of(TIME_COLUMN_NAME).is_some() {
+        sort_key.push(TIME_COLUMN_NAME.to_string());
+    }
+
+    Ok(sort_key)
+}
+
+// The number of distinct values (including NULL) in a tag column.
+fn cardinality(array: &dyn Array) -> usize {
+    let array = StringArray::from(array.data());
+    (0..array.len())
+        .map(|i| {
+            if array.is_null(i) {
+                None
+            } else {
+                Some(array.value(i))
+            }
+        })
+        .collect::<BTreeSet<_>>()
+        .len()
+}
+
+/// Sorts the rows of `batch` in ascending order of the columns in
+/// `sort_key`, with NULL values first, and splits the sorted rows into
+/// batches of at most `max_rows` rows. Columns in the sort key that are not
+/// in `batch` are ignored.
+pub fn sort_and_split(
+    batch: RecordBatch,
+    sort_key: &[String],
+    max_rows: usize,
+) -> Result<Vec<RecordBatch>> {
+    let schema = batch.schema();
+    let sort_columns = sort_key
+        .iter()
+        .filter_map(|name| schema.index_of(name).ok())
+        .map(|i| SortColumn {
+            values: Arc::clone(batch.column(i)),
+            options: Some(SortOptions {
+                descending: false,
+                nulls_first: true,
+            }),
+        })
+        .collect::<Vec<_>>();
+
+    if sort_columns.is_empty() && batch.num_rows() <= max_rows {
+        return Ok(vec![batch]);
+    }
+
+    let indices = if sort_columns.is_empty() {
+        UInt32Array::from((0..batch.num_rows() as u32).collect::<Vec<_>>())
+    } else {
+        lexsort_to_indices(&sort_columns).context(Sorting)?
+    };
+
+    let max_rows = max_rows.max(1);
+    let mut batches = Vec::with_capacity((indices.len() + max_rows - 1) / max_rows);
+    for offset in (0..indices.len()).step_by(max_rows) {
+        let end = (offset + max_rows).min(indices.len());
+        let row_group_indices =
+            UInt32Array::from((offset..end).map(|i| indices.value(i)).collect::<Vec<_>>());
+
+        let columns = batch
+            .columns()
+            .iter()
+            .map(|column| take(column, &row_group_indices, None))
+            .collect::<Result<Vec<_>, _>>()
+            .context(Sorting)?;
+
+        batches.push(RecordBatch::try_new(Arc::clone(&schema), columns).context(Sorting)?);
+    }
+
+    Ok(batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use arrow_deps::{
+        arrow::{
+            array::{ArrayRef, Float64Array, Int64Array},
+            datatypes::DataType::Float64,
+        },
+        assert_table_eq,
+    };
+    use data_types::schema::builder::SchemaBuilder;
+
+    fn gen_recordbatch() -> RecordBatch {
+        let schema = SchemaBuilder::new()
+            .tag("region")
+            .tag("host")
+            .field("usage", Float64)
+            .timestamp()
+            .build()
+            .unwrap()
+            .into();
+
+        let data: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec![
+                Some("west"),
+                Some("east"),
+                Some("west"),
+                None,
+                Some("east"),
+            ])),
+            Arc::new(StringArray::from(vec!["b", "c", "a", "d", "a"])),
+            Arc::new(Float64Array::from(vec![1.0, 2.0, 3.0, 4.0, 5.0])),
+            Arc::new(Int64Array::from(vec![50, 40, 30, 20, 10])),
+        ];
+
+        RecordBatch::try_new(schema, data).unwrap()
+    }
+
+    #[test]
+    fn default_sort_key() {
+        let batch = gen_recordbatch();
+        assert_eq!(
+            super::default_sort_key(&batch).unwrap(),
+            vec!["region", "host", "time"]
+        );
+    }
+
+    #[test]
+    fn sort_and_split() {
+        let batch = gen_recordbatch();
+        let sort_key = super::default_sort_key(&batch).unwrap();
+
+        let batches = super::sort_and_split(batch.clone(), &sort_key, 100).unwrap();
+        let expected = vec![
+            "+--------+------+-------+------+",
+            "| region | host | usage | time |",
+            "+--------+------+-------+------+",
+            "|        | d    | 4     | 20   |",
+            "| east   | a    | 5     | 10   |",
+            "| east   | c    | 2     | 40   |",
+            "| west   | a    | 3     | 30   |",
+            "| west   | b    | 1     | 50   |",
+            "+--------+------+-------+------+",
+        ];
+        assert_eq!(batches.len(), 1);
+        assert_table_eq!(expected, &batches);
+
+        // rows are split into batches of at most two rows
+        let batches = super::sort_and_split(batch.clone(), &sort_key, 2).unwrap();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        assert_table_eq!(expected, &batches);
+
+        // unknown columns in the sort key are ignored
+        let sort_key = vec!["time".to_string(), "unknown".to_string()];
+        let batches = super::sort_and_split(batch.clone(), &sort_key, 100).unwrap();
+        let expected = vec![
+            "+--------+------+-------+------+",
+            "| region | host | usage | time |",
+            "+--------+------+-------+------+",
+            "| east   | a    | 5     | 10   |",
+            "|        | d    | 4     | 20   |",
+            "| west   | a    | 3     | 30   |",
+            "| east   | c    | 2     | 40   |",
+            "| west   | b    | 1     | 50   |",
+            "+--------+------+-------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // batches are split even without a sort key
+        let batches = super::sort_and_split(batch, &[], 3).unwrap();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! This module contains the consistent hashing used to pick which host in a
+//! host group receives the writes for a partition key.
+use crc32fast::Hasher;
+
+/// The number of points each host is given on the ring. More points spread
+/// the partition keys more evenly over the hosts.
+const POINTS_PER_HOST: u32 = 128;
+
+/// `HashRing` places each host of a host group at many points on a ring of
+/// 32-bit hashes. A partition key belongs to the host owning the first point
+/// at or after the hash of the key, so adding or removing a host only moves
+/// the keys adjacent to its points.
+///
+/// Building the ring hashes every point, so it is built once per host group
+/// and reused for every write.
+#[derive(Debug)]
+pub(crate) struct HashRing {
+    hosts: Vec<String>,
+    /// (hash, index into `hosts`), sorted by hash
+    points: Vec<(u32, usize)>,
+}
+
+impl HashRing {
+    pub(crate) fn new(hosts: &[String]) -> Self {
+        let mut points: Vec<_> = hosts
+            .iter()
+            .enumerate()
+            .flat_map(|(index, host)| {
+                (0..POINTS_PER_HOST).map(move |point| (hash(&format!("{}-{}", host, point)), index))
+            })
+            .collect();
+        points.sort_unstable();
+
+        Self {
+            hosts: hosts.to_vec(),
+            points,
+        }
+    }
+
+    /// Returns true if the ring has no hosts
+    pub(crate) fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Returns every host in the ring, in the order they should be tried for
+    /// `partition_key`. The first host is the one the key hashes to; if it
+    /// is unavailable the rest follow in the order they are found walking
+    /// around the ring.
+    pub(crate) fn hosts_for(&self, partition_key: &str) -> Vec<&str> {
+        let key_hash = hash(partition_key);
+        let start = match self.points.binary_search_by_key(&key_hash, |(h, _)| *h) {
+            Ok(i) | Err(i) => i,
+        };
+
+        let mut hosts: Vec<&str> = Vec::with_capacity(self.hosts.len());
+        for (_, index) in self.points[start..].iter().chain(&self.points[..start]) {
+            let host = self.hosts[*index].as_str();
+            if !hosts.contains(&host) {
+                hosts.push(host);
+                if hosts.len() == self.hosts.len() {
+                    break;
+                }
+            }
+        }
+
+        hosts
+    }
+}
+
+fn hash(value: &str) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(value.as_bytes());
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn hosts(names: &[&str]) -> Vec<String> {
+        names.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn returns_every_host_once() {
+        let hosts = hosts(&["a", "b", "c"]);
+        let ring = HashRing::new(&hosts);
+
+        let mut found = ring.hosts_for("2020-10-10");
+        found.sort_unstable();
+        assert_eq!(found, vec!["a", "b", "c"]);
+
+        assert!(HashRing::new(&[]).hosts_for("2020-10-10").is_empty());
+    }
+
+    #[test]
+    fn is_deterministic() {
+        let hosts = hosts(&["a", "b", "c"]);
+        let first = HashRing::new(&hosts);
+        let second = HashRing::new(&hosts);
+
+        for key in &["cpu", "mem", "2020-10-10", ""] {
+            assert_eq!(first.hosts_for(key), second.hosts_for(key));
+        }
+    }
+
+    #[test]
+    fn spreads_keys_over_hosts() {
+        let hosts = hosts(&["a", "b", "c"]);
+        let ring = HashRing::new(&hosts);
+
+        let mut counts = BTreeMap::new();
+        for day in 0..3000 {
+            let key = format!("partition-{}", day);
+            *counts.entry(ring.hosts_for(&key)[0]).or_insert(0) += 1;
+        }
+
+        assert_eq!(counts.len(), 3);
+        for count in counts.values() {
+            assert!(*count > 600, "uneven distribution: {:?}", counts);
+        }
+    }
+
+    #[test]
+    fn removing_host_only_moves_its_keys() {
+        let all = hosts(&["a", "b", "c"]);
+        let without_c = hosts(&["a", "b"]);
+        let all_ring = HashRing::new(&all);
+        let without_c_ring = HashRing::new(&without_c);
+
+        for day in 0..1000 {
+            let key = format!("partition-{}", day);
+            let preference = all_ring.hosts_for(&key);
+            let expected: Vec<_> = preference.into_iter().filter(|h| *h != "c").collect();
+            assert_eq!(without_c_ring.hosts_for(&key), expected);
+        }
+    }
+}
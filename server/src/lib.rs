@@ -69,31 +69,49 @@
 pub mod buffer;
 mod config;
 pub mod db;
+mod hash_ring;
+pub mod replication_queue;
+mod sequence_set;
 pub mod snapshot;
-
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc,
+pub mod subscription;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, RwLock,
+    },
 };
 
 use crate::{
+    buffer::{Buffer, Segment},
     config::{object_store_path_for_database_config, Config, DB_RULES_FILE_NAME},
     db::Db,
+    replication_queue::ReplicationQueueStatus,
+    subscription::WriteSubscription,
 };
 use data_types::{
-    data::{lines_to_replicated_write, ReplicatedWrite},
-    database_rules::{DatabaseRules, HostGroup, HostGroupId, MatchTables},
+    data::{entry_bytes_to_replicated_write, lines_to_entry_bytes, ReplicatedWrite},
+    database_rules::{DatabaseRules, HostGroup, HostGroupId, Matcher, WriterId},
     {DatabaseName, DatabaseNameError},
 };
+use generated_types::replication::v1::{replication_client::ReplicationClient, ReplicateRequest};
 use influxdb_line_protocol::ParsedLine;
 use object_store::{path::ObjectStorePath, ObjectStore};
 use query::{exec::Executor, Database, DatabaseStore};
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use chrono::Utc;
 use futures::stream::TryStreamExt;
-use snafu::{OptionExt, ResultExt, Snafu};
-use tracing::{error, info};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tonic::{
+    transport::{Channel, Endpoint, Uri},
+    Code, Status,
+};
+use tracing::{error, info, warn};
 
 type DatabaseError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -116,6 +134,20 @@ pub enum Error {
     HostGroupNotFound { id: HostGroupId },
     #[snafu(display("no hosts in group: {}", id))]
     NoHostInGroup { id: HostGroupId },
+    #[snafu(display(
+        "no host in group {} took partitions {}",
+        id,
+        failed
+            .iter()
+            .map(|(key, error)| format!("{:?} ({})", key, error))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))]
+    PartitionsNotReplicated {
+        id: HostGroupId,
+        /// The partition keys no host took, each with the last error
+        failed: Vec<(String, String)>,
+    },
     #[snafu(display("unable to get connection to remote server: {}", server))]
     UnableToGetConnection {
         server: String,
@@ -123,6 +155,18 @@ pub enum Error {
     },
     #[snafu(display("error replicating to remote: {}", source))]
     ErrorReplicating { source: DatabaseError },
+    #[snafu(display("invalid address for remote server {}: {}", connect, source))]
+    InvalidRemoteServerAddress {
+        connect: String,
+        source: tonic::codegen::http::uri::InvalidUri,
+    },
+    #[snafu(display("unable to connect to remote server {}: {}", connect, source))]
+    RemoteServerConnectError {
+        connect: String,
+        source: tonic::transport::Error,
+    },
+    #[snafu(display("remote server {} rejected write: {}", connect, source))]
+    RemoteServerReplicateError { connect: String, source: Status },
     #[snafu(display("unable to use server until id is set"))]
     IdNotSet,
     #[snafu(display("error serializing configuration {}", source))]
@@ -135,6 +179,62 @@ pub enum Error {
     DatabaseAlreadyExists { db_name: String },
     #[snafu(display("error appending to wal buffer: {}", source))]
     WalError { source: buffer::Error },
+    #[snafu(display("error computing partition key: {}", source))]
+    PartitionKeyError {
+        source: data_types::database_rules::Error,
+    },
+    #[snafu(display("invalid database rules: {}", source))]
+    InvalidDatabaseRules {
+        source: data_types::database_rules::Error,
+    },
+    #[snafu(display("invalid replicated write: {}", source))]
+    InvalidReplicatedWrite {
+        source: data_types::data::verify::Error,
+    },
+    /// The write was applied locally, and is queued for the host groups it
+    /// wasn't replicated to, so retrying it would apply it twice
+    #[snafu(display(
+        "write applied, but only replicated to {} of the required {} host groups. \
+         It is queued for replication to the rest, so shouldn't be retried",
+        acknowledged,
+        required
+    ))]
+    ReplicationPending {
+        required: usize,
+        acknowledged: usize,
+    },
+    #[snafu(display("error queueing write for replication: {}", source))]
+    ReplicationQueueError { source: replication_queue::Error },
+    #[snafu(display(
+        "database {} is using {} bytes of memory, over its hard limit of {} bytes. Retry the write later",
+        db_name,
+        usage,
+        limit
+    ))]
+    HardLimitReached {
+        db_name: String,
+        usage: usize,
+        limit: usize,
+    },
+    #[snafu(display(
+        "server is using {} bytes of memory, over its limit of {} bytes. Retry the write later",
+        usage,
+        limit
+    ))]
+    ServerMemoryLimitReached { usage: usize, limit: usize },
+    #[snafu(display("error loading read only partitions: {}", source))]
+    LoadingReadOnlyPartitions { source: db::Error },
+    #[snafu(display(
+        "database {} has a local WAL, but the server has no WAL directory",
+        db_name
+    ))]
+    NoWalDirectory { db_name: String },
+    #[snafu(display("error opening local WAL of database {}: {}", db_name, source))]
+    OpeningLocalWal { db_name: String, source: db::Error },
+    #[snafu(display("error writing to local WAL: {}", source))]
+    WritingLocalWal { source: db::Error },
+    #[snafu(display("error subscribing to writes: {}", source))]
+    Subscribing { source: subscription::Error },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -149,6 +249,8 @@ pub struct Server<M: ConnectionManager> {
     connection_manager: Arc<M>,
     pub store: Arc<ObjectStore>,
     executor: Arc<Executor>,
+    wal_directory: Option<PathBuf>,
+    memory_limit: Option<NonZeroUsize>,
 }
 
 impl<M: ConnectionManager> Server<M> {
@@ -159,9 +261,26 @@ impl<M: ConnectionManager> Server<M> {
             store,
             connection_manager: Arc::new(connection_manager),
             executor: Arc::new(Executor::new()),
+            wal_directory: None,
+            memory_limit: None,
         }
     }
 
+    /// Sets the local directory that databases with a `local_wal_config`
+    /// keep their WALs in, each in a subdirectory named after the database.
+    pub fn with_wal_directory(mut self, wal_directory: impl Into<PathBuf>) -> Self {
+        self.wal_directory = Some(wal_directory.into());
+        self
+    }
+
+    /// Sets the number of bytes of memory that the data in all databases
+    /// may use before writes are rejected. Each database can also have its
+    /// own limit in its lifecycle rules.
+    pub fn with_memory_limit(mut self, memory_limit: NonZeroUsize) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
+
     /// sets the id of the server, which is used for replication and the base
     /// path in object storage.
     ///
@@ -191,8 +310,20 @@ impl<M: ConnectionManager> Server<M> {
         let name = db_name.into();
         let db_name = DatabaseName::new(name.clone()).context(InvalidDatabaseName)?;
         rules.name = name;
+        rules.validate().context(InvalidDatabaseRules)?;
 
         let db_reservation = self.config.create_db(db_name, rules)?;
+        open_local_wal(
+            self.wal_directory.as_deref(),
+            &db_reservation.name,
+            &db_reservation.db,
+        )
+        .await?;
+        db_reservation
+            .db
+            .load_read_only_partitions(&self.store)
+            .await
+            .context(LoadingReadOnlyPartitions)?;
 
         let data =
             Bytes::from(serde_json::to_vec(&db_reservation.db.rules).context(ErrorSerializing)?);
@@ -212,7 +343,10 @@ impl<M: ConnectionManager> Server<M> {
             .await
             .context(StoreError)?;
 
+        let name = db_reservation.name.clone();
+        let db = Arc::clone(&db_reservation.db);
         db_reservation.commit();
+        start_segment_timer(id, name, &db, Arc::clone(&self.store));
 
         Ok(())
     }
@@ -239,6 +373,7 @@ impl<M: ConnectionManager> Server<M> {
             .map(|mut path| {
                 let store = self.store.clone();
                 let config = self.config.clone();
+                let wal_directory = self.wal_directory.clone();
 
                 path.set_file_name(DB_RULES_FILE_NAME);
 
@@ -257,19 +392,68 @@ impl<M: ConnectionManager> Server<M> {
                     }
 
                     let res = res.unwrap();
+                    let rules = match serde_json::from_slice::<DatabaseRules>(&res) {
+                        Ok(rules) => rules,
+                        Err(e) => {
+                            error!("error parsing database config {:?} from store: {}", path, e);
+                            return;
+                        }
+                    };
+
+                    // the rules were validated when the database was created, but may have
+                    // been stored by an older version or edited since
+                    if let Err(e) = rules.validate() {
+                        error!("invalid database config {:?} in store: {}", path, e);
+                        return;
+                    }
+
+                    let name = match DatabaseName::new(rules.name.clone()) {
+                        Ok(name) => name,
+                        Err(e) => {
+                            error!("error parsing name {} from rules: {}", rules.name, e);
+                            return;
+                        }
+                    };
 
-                    match serde_json::from_slice::<DatabaseRules>(&res) {
+                    let handle = match config.create_db(name, rules) {
+                        Ok(handle) => handle,
                         Err(e) => {
-                            error!("error parsing database config {:?} from store: {}", path, e)
+                            error!("error adding database to config: {}", e);
+                            return;
                         }
-                        Ok(rules) => match DatabaseName::new(rules.name.clone()) {
-                            Err(e) => error!("error parsing name {} from rules: {}", rules.name, e),
-                            Ok(name) => match config.create_db(name, rules) {
-                                Err(e) => error!("error adding database to config: {}", e),
-                                Ok(handle) => handle.commit(),
-                            },
-                        },
+                    };
+
+                    if let Err(e) =
+                        open_local_wal(wal_directory.as_deref(), &handle.name, &handle.db).await
+                    {
+                        // without its WAL the database can't make writes durable, so it
+                        // isn't loaded
+                        error!("{}", e);
+                        return;
                     }
+
+                    // restore the data persisted by the previous run before the database
+                    // accepts writes
+                    let db_path = database_object_store_path(id, &handle.name);
+                    if let Err(e) = handle.db.recover(&store, &db_path, id).await {
+                        // writes accepted now could reuse the sequence numbers of data that
+                        // wasn't restored, so the database isn't loaded
+                        error!(
+                            "error recovering database {} from object store: {}",
+                            handle.name, e
+                        );
+                        return;
+                    }
+                    if let Err(e) = handle.db.load_read_only_partitions(&store).await {
+                        error!(
+                            "error loading read only partitions of database {}: {}",
+                            handle.name, e
+                        );
+                    }
+                    let name = handle.name.clone();
+                    let db = Arc::clone(&handle.db);
+                    handle.commit();
+                    start_segment_timer(id, name, &db, store);
                 })
             })
             .collect();
@@ -304,25 +488,157 @@ impl<M: ConnectionManager> Server<M> {
             .db(&db_name)
             .context(DatabaseNotFound { db_name: &*db_name })?;
 
+        // the partition keys are computed and the write admitted before a
+        // sequence number is taken, so that rejected writes don't leave a gap
+        // in them
+        let entry_bytes = lines_to_entry_bytes(lines, &db.rules).context(PartitionKeyError)?;
+        self.check_accepting_writes(&db_name, &db)?;
         let sequence = db.next_sequence();
-        let write = lines_to_replicated_write(id, sequence, lines, &db.rules);
+        let write = entry_bytes_to_replicated_write(id, sequence, &entry_bytes);
 
         self.handle_replicated_write(&db_name, &db, write).await?;
 
         Ok(())
     }
 
-    pub async fn handle_replicated_write(
+    /// `write_replicated` takes a `ReplicatedWrite` sent by another server
+    /// and handles it as if it had been written to this server. This is the
+    /// receiving end of step #2 from the crate level documentation.
+    ///
+    /// The write is verified before it is read, and is skipped if it was
+    /// written by this server. Only the partitions of the write that haven't
+    /// already been applied are applied.
+    pub async fn write_replicated(&self, db_name: &str, write: ReplicatedWrite) -> Result<()> {
+        let id = self.require_id()?;
+
+        let db_name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
+        let db = self
+            .config
+            .db(&db_name)
+            .context(DatabaseNotFound { db_name: &*db_name })?;
+
+        write.verify().context(InvalidReplicatedWrite)?;
+
+        // a write from this server can come back through a replication
+        // loop, and a write can be sent again after a failed or timed out
+        // replication, so neither is applied twice
+        let (writer_id, sequence) = write.writer_and_sequence();
+        if writer_id == id {
+            warn!(
+                "skipping write {} to {} replicated back to its writer",
+                sequence, db_name
+            );
+            return Ok(());
+        }
+
+        // a host can be sent several partitions of the same write, so only
+        // the partitions that haven't been applied are
+        let partition_keys = write.partition_keys();
+        let applying = db
+            .start_applying(writer_id, sequence, &partition_keys)
+            .await;
+        if applying.partition_keys().is_empty() {
+            info!(
+                "skipping write {} from writer {} to {} that was already applied",
+                sequence, writer_id, db_name
+            );
+            return Ok(());
+        }
+
+        self.check_accepting_writes(&db_name, &db)?;
+
+        let write = if applying.partition_keys().len() == partition_keys.len() {
+            write
+        } else {
+            write
+                .filter_partitions(|key| applying.partition_keys().iter().any(|k| k == key))
+                .expect("the write has the partitions being applied")
+        };
+
+        let result = self.handle_replicated_write(&db_name, &db, write).await;
+        applying.finish(matches!(
+            result,
+            Ok(()) | Err(Error::ReplicationPending { .. })
+        ));
+        result
+    }
+
+    /// Subscribes to the writes to the database `db_name` that match
+    /// `matcher`, starting after the watermark `since`, which has the
+    /// sequence of the last write the subscriber has seen from each writer.
+    /// See `WriteSubscription`.
+    pub fn subscribe(
+        &self,
+        db_name: &str,
+        since: BTreeMap<WriterId, u64>,
+        matcher: Matcher,
+    ) -> Result<WriteSubscription> {
+        let db_name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
+        let db = self
+            .config
+            .db(&db_name)
+            .context(DatabaseNotFound { db_name: &*db_name })?;
+
+        WriteSubscription::new(db, since, matcher).context(Subscribing)
+    }
+
+    // returns an error if the database can't take another write, because its
+    // replication queue is full or it or the server is over its memory limit
+    fn check_accepting_writes(&self, db_name: &DatabaseName<'_>, db: &Db) -> Result<()> {
+        if !db.rules.replication.is_empty() || !db.rules.subscriptions.is_empty() {
+            db.replication_queue
+                .lock()
+                .expect("mutex poisoned")
+                .check_accepting_writes()
+                .context(ReplicationQueueError)?;
+        }
+
+        if let Some(limit) = db.rules.lifecycle_rules.buffer_size_hard {
+            let usage = db.memory_usage();
+            ensure!(
+                usage <= limit.get(),
+                HardLimitReached {
+                    db_name: db_name.to_string(),
+                    usage,
+                    limit: limit.get(),
+                }
+            );
+        }
+
+        if let Some(limit) = self.memory_limit {
+            let usage = self.memory_usage();
+            ensure!(
+                usage <= limit.get(),
+                ServerMemoryLimitReached {
+                    usage,
+                    limit: limit.get(),
+                }
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Applies `write` to `db` and replicates it, once it has been admitted by
+    /// `check_accepting_writes`
+    async fn handle_replicated_write(
         &self,
         db_name: &DatabaseName<'_>,
         db: &Db,
         write: ReplicatedWrite,
     ) -> Result<()> {
+        // the write is only durable once it is in the local WAL, so it is
+        // appended before it is applied or acknowledged
+        db.append_to_local_wal(&write)
+            .await
+            .context(WritingLocalWal)?;
+
         if let Some(buf) = &db.mutable_buffer {
             buf.store_replicated_write(&write)
                 .await
                 .map_err(|e| Box::new(e) as DatabaseError)
                 .context(UnknownDatabaseError {})?;
+            db.add_memory_usage(write.data.len());
         }
 
         let write = Arc::new(write);
@@ -345,56 +661,277 @@ impl<M: ConnectionManager> Server<M> {
             if let Some(segment) = segment {
                 if persist {
                     let writer_id = self.require_id()?;
-                    let data = segment.to_file_bytes(writer_id).context(WalError)?;
-                    let store = self.store.clone();
-                    let location = database_object_store_path(writer_id, db_name);
-                    let location = buffer::object_store_path_for_segment(&location, segment.id)
-                        .context(WalError)?;
-                    persist_bytes_in_background(data, store, location);
+                    persist_segment_in_background(
+                        &segment,
+                        writer_id,
+                        self.store.clone(),
+                        db_name,
+                    )?;
                 }
             }
         }
 
-        for host_group_id in &db.rules.replication {
-            self.replicate_to_host_group(host_group_id, db_name, &write)
-                .await?;
+        self.replicate_to_host_groups(db_name, db, &write).await?;
+
+        // the write has been applied and replicated by now, so failing to push
+        // it to a subscriber mustn't fail it, or the client would retry it.
+        // Instead the matched part of the write is queued, like the writes
+        // host groups missed, and pushed by `drain_replication_queues`
+        for (subscription, matcher) in db.subscriptions() {
+            if let Some(matched) = matcher.filter_write(&write) {
+                if let Err(e) = self
+                    .replicate_to_host_group(&subscription.host_group_id, db_name, &matched)
+                    .await
+                {
+                    error!(
+                        "error pushing write to subscription {}: {}",
+                        subscription.name, e
+                    );
+                    db.replication_queue
+                        .lock()
+                        .expect("mutex poisoned")
+                        .push(&[subscription.host_group_id.clone()], &Arc::new(matched));
+                }
+            }
         }
 
-        for subscription in &db.rules.subscriptions {
-            match subscription.matcher.tables {
-                MatchTables::All => {
-                    self.replicate_to_host_group(&subscription.host_group_id, db_name, &write)
-                        .await?
+        Ok(())
+    }
+
+    // replicates the write synchronously until `replication_count` host groups
+    // have acknowledged it, then queues it for the remaining host groups, which
+    // are replicated to in the background. If the replication queue has a max
+    // size of zero, every host group is tried synchronously and any failures
+    // are dropped. The write has already been applied locally, so the host
+    // groups that missed it are queued even if too few acknowledged it, and
+    // `ReplicationPending` is returned rather than a failure.
+    async fn replicate_to_host_groups(
+        &self,
+        db_name: &DatabaseName<'_>,
+        db: &Db,
+        write: &Arc<ReplicatedWrite>,
+    ) -> Result<()> {
+        let required = usize::from(db.rules.replication_count);
+        let synchronous_only = db.rules.replication_queue_max_size == 0;
+
+        let mut acknowledged = 0;
+        let mut missed = vec![];
+
+        for host_group_id in &db.rules.replication {
+            if acknowledged >= required && !synchronous_only {
+                missed.push(host_group_id.clone());
+                continue;
+            }
+
+            match self
+                .replicate_to_host_group(host_group_id, db_name, write)
+                .await
+            {
+                Ok(()) => acknowledged += 1,
+                Err(e) => {
+                    error!("error replicating to host group {}: {}", host_group_id, e);
+                    missed.push(host_group_id.clone());
                 }
-                MatchTables::Table(_) => unimplemented!(),
-                MatchTables::Regex(_) => unimplemented!(),
             }
         }
 
+        if !missed.is_empty() {
+            // the write has been applied by now, so it is only rejected by the
+            // check before it was, and anything that no longer fits is dropped
+            db.replication_queue
+                .lock()
+                .expect("mutex poisoned")
+                .push(&missed, write);
+        }
+
+        ensure!(
+            acknowledged >= required,
+            ReplicationPending {
+                required,
+                acknowledged
+            }
+        );
+
         Ok(())
     }
 
-    // replicates to a single host in the group based on hashing rules. If that host
-    // is unavailable an error will be returned. The request may still succeed
-    // if enough of the other host groups have returned a success.
+    /// Tries to replicate the writes waiting in each database's replication
+    /// queue, oldest first. Writes that are replicated are removed from the
+    /// queue. Once replicating to a host group fails, the rest of its writes
+    /// are left in the queue, in order, for the next attempt.
+    pub async fn drain_replication_queues(&self) {
+        for (db_name, db) in self.config.databases() {
+            let queued = db
+                .replication_queue
+                .lock()
+                .expect("mutex poisoned")
+                .entries();
+
+            let mut failed_host_groups = BTreeSet::new();
+            for queued_write in queued {
+                if failed_host_groups.contains(&queued_write.host_group_id) {
+                    continue;
+                }
+
+                let result = self
+                    .replicate_to_host_group(
+                        &queued_write.host_group_id,
+                        &db_name,
+                        &queued_write.write,
+                    )
+                    .await;
+
+                match result {
+                    Ok(()) => {
+                        db.replication_queue
+                            .lock()
+                            .expect("mutex poisoned")
+                            .remove(queued_write.id);
+                    }
+                    Err(e) => {
+                        error!(
+                            "error replicating queued write to host group {}: {}",
+                            queued_write.host_group_id, e
+                        );
+                        failed_host_groups.insert(queued_write.host_group_id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the estimated number of bytes of memory used by the data in
+    /// every database on this server
+    pub fn memory_usage(&self) -> usize {
+        self.config
+            .databases()
+            .iter()
+            .map(|(_, db)| db.memory_usage())
+            .sum()
+    }
+
+    /// Applies each database's lifecycle rules, moving chunks between the
+    /// mutable buffer, the read buffer and object storage. Does nothing until
+    /// the server's id has been set, as the id is part of the object storage
+    /// path of every database.
+    pub async fn check_lifecycles(&self) {
+        let id = match self.require_id() {
+            Ok(id) => id,
+            Err(_) => return,
+        };
+
+        let now = Utc::now();
+        for (db_name, db) in self.config.databases() {
+            let db_path = database_object_store_path(id, &db_name);
+            if let Err(e) = db.check_lifecycle(&self.store, &db_path, now).await {
+                error!(
+                    "error applying lifecycle rules to database {}: {}",
+                    db_name, e
+                );
+            }
+        }
+    }
+
+    /// Runs the server's background tasks, such as draining the replication
+    /// queues and applying the databases' lifecycle rules, until the returned
+    /// future is dropped.
+    pub async fn background_worker(&self) {
+        loop {
+            self.drain_replication_queues().await;
+            self.check_lifecycles().await;
+
+            tokio::time::sleep(tokio::time::Duration::from_secs(
+                BACKGROUND_WORKER_INTERVAL_SECONDS,
+            ))
+            .await;
+        }
+    }
+
+    // replicates each partition in the write to a single host in the group,
+    // chosen by consistent hashing of the partition key. If that host is
+    // unavailable, the next host in the ring is tried, which may already have
+    // been sent other partitions of the write. Each host is sent only its
+    // partitions, with the writer and sequence of the whole write, which
+    // receivers dedupe per partition so none of them is lost. Every
+    // partition is tried even if no host takes some of the others, and an
+    // error listing those partitions is returned. The request may still
+    // succeed if enough of the other host groups have returned a success.
     async fn replicate_to_host_group(
         &self,
         host_group_id: &str,
         db_name: &DatabaseName<'_>,
         write: &ReplicatedWrite,
     ) -> Result<()> {
-        let group = self
+        let ring = self
             .config
             .host_group(host_group_id)
             .context(HostGroupNotFound { id: host_group_id })?;
 
-        // TODO: handle hashing rules to determine which host in the group should get
-        // the write.       for now, just write to the first one.
-        let host = group
-            .hosts
-            .get(0)
-            .context(NoHostInGroup { id: host_group_id })?;
+        ensure!(!ring.is_empty(), NoHostInGroup { id: host_group_id });
+
+        // the partition keys that no host took, with the last error for each
+        let mut failed = vec![];
+
+        // the hosts left to try for each partition key, in ring order
+        let partition_keys = write.partition_keys();
+        let mut pending: Vec<_> = partition_keys
+            .iter()
+            .map(|key| (*key, ring.hosts_for(key).into_iter()))
+            .collect();
+
+        while !pending.is_empty() {
+            // group the partition keys by the next host to try
+            let mut by_host: BTreeMap<&str, Vec<_>> = BTreeMap::new();
+            for (key, mut hosts) in pending.drain(..) {
+                let host = hosts
+                    .next()
+                    .expect("partition keys are dropped once all hosts are tried");
+                by_host.entry(host).or_default().push((key, hosts));
+            }
+
+            for (host, keys) in by_host {
+                let host_write = if keys.len() == partition_keys.len() {
+                    Some(write.clone())
+                } else {
+                    write.filter_partitions(|k| keys.iter().any(|(key, _)| *key == k))
+                };
+
+                let result = match host_write {
+                    Some(host_write) => self.replicate_to_host(host, db_name, &host_write).await,
+                    None => Ok(()),
+                };
+
+                if let Err(e) = result {
+                    error!("error replicating to host {}: {}", host, e);
+
+                    for (key, hosts) in keys {
+                        if hosts.as_slice().is_empty() {
+                            failed.push((key.to_string(), e.to_string()));
+                        } else {
+                            pending.push((key, hosts));
+                        }
+                    }
+                }
+            }
+        }
+
+        ensure!(
+            failed.is_empty(),
+            PartitionsNotReplicated {
+                id: host_group_id,
+                failed
+            }
+        );
+
+        Ok(())
+    }
 
+    async fn replicate_to_host(
+        &self,
+        host: &str,
+        db_name: &DatabaseName<'_>,
+        write: &ReplicatedWrite,
+    ) -> Result<()> {
         let connection = self
             .connection_manager
             .remote_server(host)
@@ -418,6 +955,16 @@ impl<M: ConnectionManager> Server<M> {
     pub async fn db_rules(&self, name: &DatabaseName<'_>) -> Option<DatabaseRules> {
         self.config.db(name).map(|d| d.rules.clone())
     }
+
+    /// Returns the state of the background replication queue of a database
+    pub async fn replication_queue_status(
+        &self,
+        name: &DatabaseName<'_>,
+    ) -> Option<ReplicationQueueStatus> {
+        self.config
+            .db(name)
+            .map(|d| d.replication_queue.lock().expect("mutex poisoned").status())
+    }
 }
 
 #[async_trait]
@@ -489,25 +1036,85 @@ pub trait RemoteServer {
     ) -> Result<(), Self::Error>;
 }
 
-/// The connection manager maps a host identifier to a remote server.
-#[derive(Debug)]
-pub struct ConnectionManagerImpl {}
+/// The number of times `RemoteServerImpl` tries to send a write before giving
+/// up, if the remote server is unavailable.
+const REPLICATE_ATTEMPTS: u32 = 3;
+
+/// How long `RemoteServerImpl` waits before retrying a write, multiplied by
+/// the number of attempts made so far.
+const REPLICATE_RETRY_BACKOFF_MILLISECONDS: u64 = 100;
+
+/// The connection manager maps a host identifier to a remote server. Host
+/// identifiers are the gRPC addresses of other IOx servers, such as
+/// `http://127.0.0.1:8082`; if no scheme is given `http` is assumed.
+/// Connections are cached, so all writes to a host share a single connection,
+/// which is reestablished if it is lost.
+#[derive(Debug, Default)]
+pub struct ConnectionManagerImpl {
+    remote_servers: RwLock<BTreeMap<String, Arc<RemoteServerImpl>>>,
+}
+
+impl ConnectionManagerImpl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
 
 #[async_trait]
 impl ConnectionManager for ConnectionManagerImpl {
     type Error = Error;
     type RemoteServer = RemoteServerImpl;
 
-    async fn remote_server(&self, _connect: &str) -> Result<Arc<Self::RemoteServer>, Self::Error> {
-        unimplemented!()
+    async fn remote_server(&self, connect: &str) -> Result<Arc<Self::RemoteServer>, Self::Error> {
+        if let Some(remote) = self
+            .remote_servers
+            .read()
+            .expect("mutex poisoned")
+            .get(connect)
+        {
+            return Ok(Arc::clone(remote));
+        }
+
+        let remote = Arc::new(RemoteServerImpl::new(connect)?);
+
+        let mut remote_servers = self.remote_servers.write().expect("mutex poisoned");
+        let remote = remote_servers.entry(connect.to_string()).or_insert(remote);
+
+        Ok(Arc::clone(remote))
     }
 }
 
-/// An implementation for communicating with other IOx servers. This should
-/// be moved into and implemented in an influxdb_iox_client create at a later
-/// date.
+/// An implementation for communicating with other IOx servers over gRPC.
+/// This should be moved into and implemented in an influxdb_iox_client
+/// create at a later date.
 #[derive(Debug)]
-pub struct RemoteServerImpl {}
+pub struct RemoteServerImpl {
+    connect: String,
+    client: ReplicationClient<Channel>,
+}
+
+impl RemoteServerImpl {
+    /// Creates a client for the server at `connect`. The connection is made
+    /// when the first write is sent.
+    fn new(connect: &str) -> Result<Self> {
+        let uri = if connect.contains("://") {
+            connect.to_string()
+        } else {
+            format!("http://{}", connect)
+        };
+        let uri: Uri = uri
+            .parse()
+            .context(InvalidRemoteServerAddress { connect })?;
+        let channel = Endpoint::from(uri)
+            .connect_lazy()
+            .context(RemoteServerConnectError { connect })?;
+
+        Ok(Self {
+            connect: connect.to_string(),
+            client: ReplicationClient::new(channel),
+        })
+    }
+}
 
 #[async_trait]
 impl RemoteServer for RemoteServerImpl {
@@ -515,13 +1122,48 @@ impl RemoteServer for RemoteServerImpl {
 
     async fn replicate(
         &self,
-        _db: &str,
-        _replicated_write: &ReplicatedWrite,
+        db: &str,
+        replicated_write: &ReplicatedWrite,
     ) -> Result<(), Self::Error> {
-        unimplemented!()
+        let request = ReplicateRequest {
+            db_name: db.to_string(),
+            replicated_write: replicated_write.data.clone(),
+        };
+
+        let mut attempt = 1;
+        loop {
+            let mut client = self.client.clone();
+            match client.replicate(request.clone()).await {
+                Ok(_) => return Ok(()),
+                Err(status) if attempt < REPLICATE_ATTEMPTS && is_retryable(&status) => {
+                    warn!(
+                        "error replicating to {} (attempt {}), retrying: {}",
+                        self.connect, attempt, status
+                    );
+                    tokio::time::sleep(tokio::time::Duration::from_millis(
+                        REPLICATE_RETRY_BACKOFF_MILLISECONDS * u64::from(attempt),
+                    ))
+                    .await;
+                    attempt += 1;
+                }
+                Err(status) => {
+                    return Err(status).context(RemoteServerReplicateError {
+                        connect: &self.connect,
+                    })
+                }
+            }
+        }
     }
 }
 
+// returns true if a failed request may succeed if it is sent again
+fn is_retryable(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::DeadlineExceeded | Code::Aborted
+    )
+}
+
 // base location in object store for a given database name
 fn database_object_store_path(writer_id: u32, database_name: &DatabaseName<'_>) -> ObjectStorePath {
     let mut path = ObjectStorePath::default();
@@ -536,6 +1178,9 @@ fn server_object_store_path(writer_id: u32) -> ObjectStorePath {
 
 const STORE_ERROR_PAUSE_SECONDS: u64 = 100;
 
+/// How long the background worker waits between runs of its tasks
+const BACKGROUND_WORKER_INTERVAL_SECONDS: u64 = 1;
+
 /// Spawns a tokio task that will continuously try to persist the bytes to the
 /// given object store location.
 fn persist_bytes_in_background(data: Bytes, store: Arc<ObjectStore>, location: ObjectStorePath) {
@@ -560,6 +1205,114 @@ fn persist_bytes_in_background(data: Bytes, store: Arc<ObjectStore>, location: O
     });
 }
 
+/// Serializes a closed WAL buffer segment of the database `db_name` and
+/// persists it to object storage in the background.
+fn persist_segment_in_background(
+    segment: &Segment,
+    writer_id: u32,
+    store: Arc<ObjectStore>,
+    db_name: &DatabaseName<'_>,
+) -> Result<()> {
+    let data = segment.to_file_bytes(writer_id).context(WalError)?;
+    let location = database_object_store_path(writer_id, db_name);
+    let location =
+        buffer::object_store_path_for_segment(&location, segment.id).context(WalError)?;
+    persist_bytes_in_background(data, store, location);
+    Ok(())
+}
+
+/// Spawns a task that closes the open WAL buffer segment of `db` once it has
+/// been open for the buffer's `close_segment_after`, persisting it if the
+/// buffer stores segments, so a segment doesn't wait for more writes to be
+/// closed. The task stops once the database is dropped.
+fn start_segment_timer(
+    writer_id: u32,
+    db_name: DatabaseName<'static>,
+    db: &Arc<Db>,
+    store: Arc<ObjectStore>,
+) {
+    fn wal_buffer(db: &Db) -> std::sync::MutexGuard<'_, Buffer> {
+        db.wal_buffer
+            .as_ref()
+            .expect("the timer is only started for databases with a WAL buffer")
+            .lock()
+            .expect("mutex poisoned")
+    }
+
+    let close_segment_after = match db
+        .wal_buffer
+        .as_ref()
+        .and_then(|buffer| buffer.lock().expect("mutex poisoned").close_segment_after)
+    {
+        Some(close_segment_after) => close_segment_after,
+        None => return,
+    };
+    let db = Arc::downgrade(db);
+
+    tokio::task::spawn(async move {
+        loop {
+            let deadline = match db.upgrade() {
+                Some(db) => wal_buffer(&db).open_segment_deadline(),
+                None => return,
+            };
+            // an empty segment has no deadline yet, so check again once a
+            // write appended now would be due
+            let deadline =
+                deadline.unwrap_or_else(|| std::time::Instant::now() + close_segment_after);
+            tokio::time::sleep_until(deadline.into()).await;
+
+            let db = match db.upgrade() {
+                Some(db) => db,
+                None => return,
+            };
+            let (segment, persist) = {
+                let mut wal_buffer = wal_buffer(&db);
+                let segment = wal_buffer.close_expired_segment(std::time::Instant::now());
+                (segment, wal_buffer.persist)
+            };
+
+            if let Some(segment) = segment {
+                info!(
+                    "closed segment {} of database {} after {:?}",
+                    segment.id, db_name, close_segment_after
+                );
+                if persist {
+                    if let Err(e) =
+                        persist_segment_in_background(&segment, writer_id, store.clone(), &db_name)
+                    {
+                        error!(
+                            "error persisting segment {} of database {}: {}",
+                            segment.id, db_name, e
+                        );
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Opens the local WAL of `db`, if its rules have a `local_wal_config`, in
+/// the subdirectory of `wal_directory` named after the database
+async fn open_local_wal(
+    wal_directory: Option<&Path>,
+    db_name: &DatabaseName<'_>,
+    db: &Db,
+) -> Result<()> {
+    let config = match &db.rules.local_wal_config {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    let wal_directory = wal_directory.context(NoWalDirectory {
+        db_name: db_name.to_string(),
+    })?;
+
+    db.open_local_wal(&wal_directory.join(db_name.as_str()), config)
+        .await
+        .context(OpeningLocalWal {
+            db_name: db_name.to_string(),
+        })
+}
+
 // get bytes from the location in object store
 async fn get_store_bytes(
     location: &ObjectStorePath,
@@ -583,17 +1336,20 @@ mod tests {
     use crate::buffer::Segment;
     use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
     use async_trait::async_trait;
-    use data_types::database_rules::{
-        MatchTables, Matcher, PartitionTemplate, Subscription, TemplatePart, WalBufferConfig,
-        WalBufferRollover,
+    use data_types::{
+        data::lines_to_replicated_write,
+        database_rules::{
+            LifecycleRules, MatchTables, Matcher, PartitionTemplate, RegexCapture, StrftimeColumn,
+            Subscription, TemplatePart, WalBufferConfig, WalBufferRollover,
+        },
     };
     use futures::TryStreamExt;
     use influxdb_line_protocol::parse_lines;
     use object_store::memory::InMemory;
-    use query::frontend::sql::SQLQueryPlanner;
+    use query::{frontend::sql::SQLQueryPlanner, PartitionChunk};
     use snafu::Snafu;
     use std::collections::BTreeMap;
-    use std::sync::Mutex;
+    use std::sync::{atomic::AtomicBool, Mutex};
 
     type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
     type Result<T = (), E = TestError> = std::result::Result<T, E>;
@@ -682,158 +1438,1126 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn duplicate_database_name_rejected() -> Result {
-        // Covers #643
-
+    async fn database_not_loaded_if_recovery_fails() {
         let manager = TestConnectionManager::new();
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
-        let server = Server::new(manager, store);
+        let server = Server::new(manager, Arc::clone(&store));
         server.set_id(1);
 
         let name = "bananas";
-
-        // Create a database
         server
             .create_database(name, DatabaseRules::default())
             .await
             .expect("failed to create database");
 
-        // Then try and create another with the same name
-        let got = server
-            .create_database(name, DatabaseRules::default())
+        // a WAL segment that can't be read
+        let data = Bytes::from("not a segment");
+        let len = data.len();
+        store
+            .put(
+                &ObjectStorePath::from_cloud_unchecked("1/bananas/wal/000/000/001.segment"),
+                futures::stream::once(async move { std::io::Result::Ok(data) }),
+                len,
+            )
             .await
-            .unwrap_err();
+            .unwrap();
 
-        if !matches!(got, Error::DatabaseAlreadyExists {..}) {
-            panic!("expected already exists error");
-        }
+        let manager = TestConnectionManager::new();
+        let server2 = Server::new(manager, store);
+        server2.set_id(1);
+        server2.load_database_configs().await.unwrap();
 
-        Ok(())
+        assert!(server2
+            .db(&DatabaseName::new(name).unwrap())
+            .await
+            .is_none());
     }
 
     #[tokio::test]
-    async fn database_name_validation() -> Result {
+    async fn database_not_loaded_with_invalid_rules() {
         let manager = TestConnectionManager::new();
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
-        let server = Server::new(manager, store);
+        let server = Server::new(manager, Arc::clone(&store));
         server.set_id(1);
 
-        let reject = vec![
-            "bananas\t",
-            "bananas\"are\u{0099}\"great",
-            "bananas\nfoster",
-        ];
-
-        for name in reject {
-            let rules = DatabaseRules {
-                store_locally: true,
-                ..Default::default()
-            };
-            let got = server.create_database(name, rules).await.unwrap_err();
-            if !matches!(got, Error::InvalidDatabaseName { .. }) {
-                panic!("expected invalid name error");
-            }
-        }
-
-        Ok(())
-    }
+        let name = "bananas";
+        server
+            .create_database(name, DatabaseRules::default())
+            .await
+            .expect("failed to create database");
 
-    #[tokio::test]
-    async fn writes_local() -> Result {
-        let manager = TestConnectionManager::new();
-        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
-        let server = Server::new(manager, store);
-        server.set_id(1);
+        // rules stored with a regex that doesn't compile
         let rules = DatabaseRules {
-            store_locally: true,
+            name: name.to_string(),
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::RegexCapture(RegexCapture {
+                    column: "host".to_string(),
+                    regex: "([a-z]+".to_string(),
+                })],
+            },
+            ..Default::default()
+        };
+        let data = Bytes::from(serde_json::to_vec(&rules).unwrap());
+        let len = data.len();
+        store
+            .put(
+                &ObjectStorePath::from_cloud_unchecked("1/bananas/rules.json"),
+                futures::stream::once(async move { std::io::Result::Ok(data) }),
+                len,
+            )
+            .await
+            .unwrap();
+
+        let manager = TestConnectionManager::new();
+        let server2 = Server::new(manager, store);
+        server2.set_id(1);
+        server2.load_database_configs().await.unwrap();
+
+        assert!(server2
+            .db(&DatabaseName::new(name).unwrap())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn duplicate_database_name_rejected() -> Result {
+        // Covers #643
+
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        let name = "bananas";
+
+        // Create a database
+        server
+            .create_database(name, DatabaseRules::default())
+            .await
+            .expect("failed to create database");
+
+        // Then try and create another with the same name
+        let got = server
+            .create_database(name, DatabaseRules::default())
+            .await
+            .unwrap_err();
+
+        if !matches!(got, Error::DatabaseAlreadyExists {..}) {
+            panic!("expected already exists error");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn database_name_validation() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        let reject = vec![
+            "bananas\t",
+            "bananas\"are\u{0099}\"great",
+            "bananas\nfoster",
+        ];
+
+        for name in reject {
+            let rules = DatabaseRules {
+                store_locally: true,
+                ..Default::default()
+            };
+            let got = server.create_database(name, rules).await.unwrap_err();
+            if !matches!(got, Error::InvalidDatabaseName { .. }) {
+                panic!("expected invalid name error");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_local() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        let line = "cpu bar=1 10";
+        let lines: Vec<_> = parse_lines(line).map(|l| l.unwrap()).collect();
+        server.write_lines("foo", &lines).await.unwrap();
+
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = server.db(&db_name).await.unwrap();
+
+        let buff = db.mutable_buffer.as_ref().unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let executor = server.executor();
+        let physical_plan = planner
+            .query(buff, "select * from cpu", executor.as_ref())
+            .await
+            .unwrap();
+
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replicated_writes_are_applied_once() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("foo", rules.clone()).await?;
+
+        let lines = parsed_lines("cpu bar=1 10");
+        let write =
+            |writer, sequence| lines_to_replicated_write(writer, sequence, &lines, &rules).unwrap();
+
+        // a write of this server that is replicated back to it is skipped
+        server.write_replicated("foo", write(1, 1)).await?;
+
+        // the writes of another server are applied once, in any order
+        for &sequence in &[2, 1, 2, 1] {
+            server.write_replicated("foo", write(2, sequence)).await?;
+        }
+
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = server.db(&db_name).await.unwrap();
+        let buff = db.mutable_buffer.as_ref().unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let executor = server.executor();
+        let physical_plan = planner
+            .query(buff, "select * from cpu", executor.as_ref())
+            .await
+            .unwrap();
+
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "| 1   | 10   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn partitions_of_a_replicated_write_are_applied_separately() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Column("region".to_string())],
+            },
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("foo", rules.clone()).await?;
+
+        let lines = parsed_lines("cpu,region=west bar=1 10\ncpu,region=east bar=2 20");
+        let write = lines_to_replicated_write(2, 1, &lines, &rules).unwrap();
+        let partition = |key: &str| write.filter_partitions(|k| k == key).unwrap();
+
+        // the partitions of the write are sent to this server one at a time,
+        // such as when the host another was sent to fails over to this one
+        server
+            .write_replicated("foo", partition("region_west"))
+            .await?;
+        server
+            .write_replicated("foo", partition("region_east"))
+            .await?;
+
+        // and then sent again, with neither applied twice
+        server.write_replicated("foo", write.clone()).await?;
+        server
+            .write_replicated("foo", partition("region_east"))
+            .await?;
+
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = server.db(&db_name).await.unwrap();
+        let buff = db.mutable_buffer.as_ref().unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let executor = server.executor();
+        let physical_plan = planner
+            .query(buff, "select * from cpu order by time", executor.as_ref())
+            .await
+            .unwrap();
+
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+-----+--------+------+",
+            "| bar | region | time |",
+            "+-----+--------+------+",
+            "| 1   | west   | 10   |",
+            "| 2   | east   | 20   |",
+            "+-----+--------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_recovered_from_local_wal() -> Result {
+        let wal_dir = test_helpers::tmp_dir()?;
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), Arc::clone(&store))
+            .with_wal_directory(wal_dir.path());
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            local_wal_config: Some(Default::default()),
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        let lines = parsed_lines("cpu bar=1 10");
+        server.write_lines("foo", &lines).await.unwrap();
+        drop(server);
+
+        let server =
+            Server::new(TestConnectionManager::new(), store).with_wal_directory(wal_dir.path());
+        server.set_id(1);
+        server.load_database_configs().await.unwrap();
+
+        let db = server.db(&DatabaseName::new("foo").unwrap()).await.unwrap();
+        let buff = db.mutable_buffer.as_ref().unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let executor = server.executor();
+        let physical_plan = planner
+            .query(buff, "select * from cpu", executor.as_ref())
+            .await
+            .unwrap();
+
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_database_requires_wal_directory_for_local_wal() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            local_wal_config: Some(Default::default()),
+            ..Default::default()
+        };
+
+        let err = server.create_database("foo", rules).await.unwrap_err();
+        assert!(matches!(err, Error::NoWalDirectory { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn applies_lifecycle_rules() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            lifecycle_rules: LifecycleRules {
+                mutable_size_threshold: std::num::NonZeroUsize::new(1),
+                read_buffer_delay_seconds: Some(0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        server.write_lines("foo", &lines).await.unwrap();
+
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = server.db(&db_name).await.unwrap();
+        let partition_key = "1970-01-01T00";
+
+        // the first check closes the chunk, the second moves it to the read
+        // buffer
+        server.check_lifecycles().await;
+        server.check_lifecycles().await;
+
+        let mutable_buffer_chunks: Vec<_> = db
+            .mutable_buffer_chunks(partition_key)
+            .await
+            .iter()
+            .map(|c| c.id())
+            .collect();
+        assert_eq!(mutable_buffer_chunks, vec![1]);
+        assert_eq!(
+            db.read_buffer.read().unwrap().chunk_ids(partition_key),
+            vec![0]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_writes_over_hard_limit() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            lifecycle_rules: LifecycleRules {
+                buffer_size_hard: std::num::NonZeroUsize::new(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        server.write_lines("foo", &lines).await.unwrap();
+        assert!(server.memory_usage() > 1);
+
+        let err = server.write_lines("foo", &lines).await.unwrap_err();
+        assert!(matches!(err, Error::HardLimitReached { limit: 1, .. }));
+
+        // the rejected write doesn't use up a sequence number
+        let db = server.db(&DatabaseName::new("foo").unwrap()).await.unwrap();
+        assert_eq!(db.next_sequence(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_writes_over_server_memory_limit() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store).with_memory_limit(NonZeroUsize::new(1).unwrap());
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("foo", rules.clone()).await?;
+        server.create_database("bar", rules).await?;
+
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        server.write_lines("foo", &lines).await.unwrap();
+        assert!(server.memory_usage() > 1);
+
+        // the usage of every database counts towards the limit
+        let err = server.write_lines("bar", &lines).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ServerMemoryLimitReached { limit: 1, .. }
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replicate_to_single_group() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        let remote_id = "serverA";
+        manager
+            .remotes
+            .insert(remote_id.to_string(), remote.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let host_group_id = "az1".to_string();
+        let rules = DatabaseRules {
+            replication: vec![host_group_id.clone()],
+            replication_count: 1,
+            ..Default::default()
+        };
+        server
+            .create_host_group(host_group_id.clone(), vec![remote_id.to_string()])
+            .await
+            .unwrap();
+        let db_name = "foo";
+        server.create_database(db_name, rules).await.unwrap();
+
+        let lines = parsed_lines("cpu bar=1 10");
+        server.write_lines("foo", &lines).await.unwrap();
+
+        let writes = remote.writes.lock().unwrap().get(db_name).unwrap().clone();
+
+        let write_text = r#"
+writer:1, sequence:1, checksum:226387645
+partition_key:
+  table:cpu
+    bar:1 time:10
+"#;
+
+        assert_eq!(write_text, writes[0].to_string());
+
+        // ensure sequence number goes up
+        let lines = parsed_lines("mem,server=A,region=west user=232 12");
+        server.write_lines("foo", &lines).await.unwrap();
+
+        let writes = remote.writes.lock().unwrap().get(db_name).unwrap().clone();
+        assert_eq!(2, writes.len());
+
+        let write_text = r#"
+writer:1, sequence:2, checksum:3759030699
+partition_key:
+  table:mem
+    server:A region:west user:232 time:12
+"#;
+
+        assert_eq!(write_text, writes[1].to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_partition_template() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::RegexCapture(RegexCapture {
+                    column: "host".to_string(),
+                    regex: "([a-z]+".to_string(),
+                })],
+            },
+            ..Default::default()
+        };
+        let err = server.create_database("foo", rules).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidDatabaseRules { .. }));
+
+        // lines the template fails on don't use up a sequence number
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::StrftimeColumn(StrftimeColumn {
+                    column: "event_time".to_string(),
+                    format: "%Y-%m-%d".to_string(),
+                })],
+            },
+            ..Default::default()
+        };
+        server.create_database("bar", rules).await?;
+
+        let lines = parsed_lines("cpu event_time=1.5 10");
+        let err = server.write_lines("bar", &lines).await.unwrap_err();
+        assert!(matches!(err, Error::PartitionKeyError { .. }));
+
+        let lines = parsed_lines("cpu event_time=1602338097000000000i 10");
+        server.write_lines("bar", &lines).await?;
+
+        let db = server.db(&DatabaseName::new("bar").unwrap()).await.unwrap();
+        assert_eq!(db.next_sequence(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replicate_partitions_to_hosts_by_hash() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote_a = Arc::new(TestRemoteServer::default());
+        let remote_b = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), remote_a.clone());
+        manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let host_group_id = "az1".to_string();
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Table],
+            },
+            replication: vec![host_group_id.clone()],
+            replication_count: 1,
+            ..Default::default()
+        };
+        server
+            .create_host_group(
+                host_group_id.clone(),
+                vec!["serverA".to_string(), "serverB".to_string()],
+            )
+            .await
+            .unwrap();
+        let db_name = "foo";
+        server.create_database(db_name, rules).await.unwrap();
+
+        // the "cpu" partition hashes to serverB and "net" to serverA
+        let lines = parsed_lines("cpu bar=1 10\nnet bytes=4 10");
+        server.write_lines("foo", &lines).await.unwrap();
+
+        let partitions = |remote: &Arc<TestRemoteServer>| -> Vec<Vec<String>> {
+            remote
+                .writes
+                .lock()
+                .unwrap()
+                .get(db_name)
+                .unwrap()
+                .iter()
+                .map(|w| {
+                    assert_eq!((1, 1), w.writer_and_sequence());
+                    w.partition_keys().iter().map(ToString::to_string).collect()
+                })
+                .collect()
+        };
+
+        assert_eq!(partitions(&remote_a), vec![vec!["net".to_string()]]);
+        assert_eq!(partitions(&remote_b), vec![vec!["cpu".to_string()]]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replicate_to_next_host_when_unavailable() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote_b = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let host_group_id = "az1".to_string();
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Table],
+            },
+            replication: vec![host_group_id.clone()],
+            replication_count: 1,
+            ..Default::default()
+        };
+        // serverA has no connection, so is unavailable
+        server
+            .create_host_group(
+                host_group_id.clone(),
+                vec!["serverA".to_string(), "serverB".to_string()],
+            )
+            .await
+            .unwrap();
+        let db_name = "foo";
+        server.create_database(db_name, rules).await.unwrap();
+
+        // the "net" partition hashes to serverA, so falls over to serverB
+        let lines = parsed_lines("net bytes=4 10");
+        server.write_lines("foo", &lines).await.unwrap();
+
+        let writes = remote_b
+            .writes
+            .lock()
+            .unwrap()
+            .get(db_name)
+            .unwrap()
+            .clone();
+        assert_eq!(1, writes.len());
+        assert_eq!(vec!["net"], writes[0].partition_keys());
+
+        // with no hosts available the write fails
+        let host_group_id = "az2".to_string();
+        server
+            .create_host_group(host_group_id.clone(), vec!["serverC".to_string()])
+            .await
+            .unwrap();
+        let rules = DatabaseRules {
+            replication: vec![host_group_id],
+            replication_count: 1,
+            ..Default::default()
+        };
+        server.create_database("bar", rules).await.unwrap();
+
+        let err = server.write_lines("bar", &lines).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ReplicationPending {
+                required: 1,
+                acknowledged: 0
+            }
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replicate_to_host_that_took_another_partition() -> Result {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Table],
+            },
+            store_locally: true,
+            ..Default::default()
+        };
+
+        // serverB applies the writes replicated to it to a real server
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let receiver = Arc::new(Server::new(TestConnectionManager::new(), store));
+        receiver.set_id(2);
+        receiver.create_database("foo", rules.clone()).await?;
+
+        let mut manager = TestConnectionManager::new();
+        let remote_a = Arc::new(TestRemoteServer::default());
+        let remote_b = Arc::new(TestRemoteServer {
+            forward_to: Some(Arc::clone(&receiver)),
+            ..Default::default()
+        });
+        manager
+            .remotes
+            .insert("serverA".to_string(), remote_a.clone());
+        manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        server
+            .create_host_group(
+                "az1".to_string(),
+                vec!["serverA".to_string(), "serverB".to_string()],
+            )
+            .await
+            .unwrap();
+
+        // the "cpu" partition hashes to serverB and "net" to serverA, which is
+        // unavailable, so serverB is sent both partitions of the write one at
+        // a time, with the same writer and sequence
+        let lines = parsed_lines("cpu bar=1 10\nnet bytes=4 10");
+        let write = lines_to_replicated_write(1, 1, &lines, &rules)?;
+        let db_name = DatabaseName::new("foo").unwrap();
+
+        remote_a.set_unavailable(true);
+        server
+            .replicate_to_host_group("az1", &db_name, &write)
+            .await?;
+        assert_eq!(remote_a.write_count("foo"), 0);
+        assert_eq!(remote_b.write_count("foo"), 2);
+
+        // and the write sent again, such as from the replication queue, isn't
+        // applied twice
+        server
+            .replicate_to_host_group("az1", &db_name, &write)
+            .await?;
+
+        let db = receiver.db(&db_name).await.unwrap();
+        let buff = db.mutable_buffer.as_ref().unwrap();
+        let planner = SQLQueryPlanner::default();
+        let executor = receiver.executor();
+        for (query, expected) in &[
+            (
+                "select * from cpu",
+                vec![
+                    "+-----+------+",
+                    "| bar | time |",
+                    "+-----+------+",
+                    "| 1   | 10   |",
+                    "+-----+------+",
+                ],
+            ),
+            (
+                "select * from net",
+                vec![
+                    "+-------+------+",
+                    "| bytes | time |",
+                    "+-------+------+",
+                    "| 4     | 10   |",
+                    "+-------+------+",
+                ],
+            ),
+        ] {
+            let physical_plan = planner.query(buff, query, executor.as_ref()).await.unwrap();
+            let batches = collect(physical_plan).await.unwrap();
+            assert_table_eq!(expected, &batches);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_every_partition_no_host_took() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote_a = Arc::new(TestRemoteServer::default());
+        let remote_b = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), remote_a.clone());
+        manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        server
+            .create_host_group(
+                "az1".to_string(),
+                vec!["serverA".to_string(), "serverB".to_string()],
+            )
+            .await
+            .unwrap();
+
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Table],
+            },
+            ..Default::default()
+        };
+        let lines = parsed_lines("cpu bar=1 10\nnet bytes=4 10");
+        let write = lines_to_replicated_write(1, 1, &lines, &rules)?;
+        let db_name = DatabaseName::new("foo").unwrap();
+
+        remote_a.set_unavailable(true);
+        remote_b.set_unavailable(true);
+        let err = server
+            .replicate_to_host_group("az1", &db_name, &write)
+            .await
+            .unwrap_err();
+        match err {
+            Error::PartitionsNotReplicated { id, failed } => {
+                assert_eq!(id, "az1");
+                let mut keys: Vec<_> = failed.iter().map(|(key, _)| key.as_str()).collect();
+                keys.sort_unstable();
+                assert_eq!(keys, vec!["cpu", "net"]);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+
+        // once a host is back it takes every partition
+        remote_b.set_unavailable(false);
+        server
+            .replicate_to_host_group("az1", &db_name, &write)
+            .await?;
+        assert_eq!(remote_b.write_count("foo"), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn queues_writes_for_host_groups_beyond_replication_count() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote_a = Arc::new(TestRemoteServer::default());
+        let remote_b = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), remote_a.clone());
+        manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        server
+            .create_host_group("az1".to_string(), vec!["serverA".to_string()])
+            .await
+            .unwrap();
+        server
+            .create_host_group("az2".to_string(), vec!["serverB".to_string()])
+            .await
+            .unwrap();
+        let rules = DatabaseRules {
+            replication: vec!["az1".to_string(), "az2".to_string()],
+            replication_count: 1,
+            replication_queue_max_size: 2,
             ..Default::default()
         };
-        server.create_database("foo", rules).await?;
+        let db_name = DatabaseName::new("foo").unwrap();
+        server.create_database("foo", rules).await.unwrap();
 
-        let line = "cpu bar=1 10";
-        let lines: Vec<_> = parse_lines(line).map(|l| l.unwrap()).collect();
+        // only the first host group is written to synchronously
+        let lines = parsed_lines("cpu bar=1 10");
+        server.write_lines("foo", &lines).await.unwrap();
+        assert_eq!(remote_a.write_count("foo"), 1);
+        assert_eq!(remote_b.write_count("foo"), 0);
+
+        // the queue holds the write until the host group is reachable
+        remote_b.set_unavailable(true);
+        server.drain_replication_queues().await;
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.len, 1);
+        assert_eq!(status.host_groups.get("az2"), Some(&1));
+
+        remote_b.set_unavailable(false);
+        server.drain_replication_queues().await;
+        assert_eq!(remote_b.write_count("foo"), 1);
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.len, 0);
+
+        // the required host group failing is queued once another acknowledges
+        remote_a.set_unavailable(true);
+        server.write_lines("foo", &lines).await.unwrap();
+        assert_eq!(remote_b.write_count("foo"), 2);
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.host_groups.get("az1"), Some(&1));
+
+        // once the queue is full new writes are rejected
         server.write_lines("foo", &lines).await.unwrap();
+        let err = server.write_lines("foo", &lines).await.unwrap_err();
+        assert!(matches!(err, Error::ReplicationQueueError { .. }));
+
+        remote_a.set_unavailable(false);
+        server.drain_replication_queues().await;
+        assert_eq!(remote_a.write_count("foo"), 3);
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.len, 0);
+        assert_eq!(status.dropped, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_replicated_to_too_few_host_groups_are_queued() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote_a = Arc::new(TestRemoteServer::default());
+        let remote_b = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), remote_a.clone());
+        manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
 
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        server
+            .create_host_group("az1".to_string(), vec!["serverA".to_string()])
+            .await
+            .unwrap();
+        server
+            .create_host_group("az2".to_string(), vec!["serverB".to_string()])
+            .await
+            .unwrap();
+        let rules = DatabaseRules {
+            replication: vec!["az1".to_string(), "az2".to_string()],
+            replication_count: 2,
+            replication_queue_max_size: 10,
+            store_locally: true,
+            ..Default::default()
+        };
         let db_name = DatabaseName::new("foo").unwrap();
-        let db = server.db(&db_name).await.unwrap();
+        server.create_database("foo", rules.clone()).await.unwrap();
 
-        let buff = db.mutable_buffer.as_ref().unwrap();
+        // the write is applied and queued for the host group that missed it,
+        // even though too few host groups acknowledged it
+        remote_b.set_unavailable(true);
+        let lines = parsed_lines("cpu bar=1 10");
+        let err = server.write_lines("foo", &lines).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ReplicationPending {
+                required: 2,
+                acknowledged: 1
+            }
+        ));
+        assert_eq!(remote_a.write_count("foo"), 1);
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.host_groups.get("az2"), Some(&1));
+
+        // a replicated write that is pending replication has been applied, so
+        // isn't applied or queued again when it is retried
+        let write = lines_to_replicated_write(2, 1, &lines, &rules)?;
+        let err = server
+            .write_replicated("foo", write.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ReplicationPending { .. }));
+        server.write_replicated("foo", write).await?;
+        assert_eq!(remote_a.write_count("foo"), 2);
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.host_groups.get("az2"), Some(&2));
 
+        let db = server.db(&db_name).await.unwrap();
+        let buff = db.mutable_buffer.as_ref().unwrap();
         let planner = SQLQueryPlanner::default();
         let executor = server.executor();
         let physical_plan = planner
             .query(buff, "select * from cpu", executor.as_ref())
             .await
             .unwrap();
-
         let batches = collect(physical_plan).await.unwrap();
         let expected = vec![
             "+-----+------+",
             "| bar | time |",
             "+-----+------+",
             "| 1   | 10   |",
+            "| 1   | 10   |",
             "+-----+------+",
         ];
         assert_table_eq!(expected, &batches);
 
+        remote_b.set_unavailable(false);
+        server.drain_replication_queues().await;
+        assert_eq!(remote_b.write_count("foo"), 2);
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.len, 0);
+
         Ok(())
     }
 
     #[tokio::test]
-    async fn replicate_to_single_group() -> Result {
+    async fn applied_writes_are_not_failed_by_a_full_replication_queue() -> Result {
         let mut manager = TestConnectionManager::new();
-        let remote = Arc::new(TestRemoteServer::default());
-        let remote_id = "serverA";
-        manager
-            .remotes
-            .insert(remote_id.to_string(), remote.clone());
+        let remotes: Vec<_> = ["serverA", "serverB", "serverC"]
+            .iter()
+            .map(|id| {
+                let remote = Arc::new(TestRemoteServer::default());
+                manager.remotes.insert(id.to_string(), remote.clone());
+                remote
+            })
+            .collect();
 
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
 
         let mut server = Server::new(manager, store);
         server.set_id(1);
-        let host_group_id = "az1".to_string();
+        for (group, host) in &[("az1", "serverA"), ("az2", "serverB"), ("az3", "serverC")] {
+            server
+                .create_host_group(group.to_string(), vec![host.to_string()])
+                .await
+                .unwrap();
+        }
         let rules = DatabaseRules {
-            replication: vec![host_group_id.clone()],
+            replication: vec!["az1".to_string(), "az2".to_string(), "az3".to_string()],
             replication_count: 1,
+            replication_queue_max_size: 1,
             ..Default::default()
         };
+        let db_name = DatabaseName::new("foo").unwrap();
+        server.create_database("foo", rules).await.unwrap();
+
+        // the write passes the check with room for one of the two host groups
+        // it has to be queued for, and is applied, so the other is dropped
+        remotes[1].set_unavailable(true);
+        remotes[2].set_unavailable(true);
+        let lines = parsed_lines("cpu bar=1 10");
+        server.write_lines("foo", &lines).await?;
+        assert_eq!(remotes[0].write_count("foo"), 1);
+
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.len, 1);
+        assert_eq!(status.dropped, 1);
+
+        // the full queue rejects the next write before it is applied
+        let err = server.write_lines("foo", &lines).await.unwrap_err();
+        assert!(matches!(err, Error::ReplicationQueueError { .. }));
+        assert_eq!(remotes[0].write_count("foo"), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn zero_replication_queue_size_drops_failed_writes() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote_a = Arc::new(TestRemoteServer::default());
+        let remote_b = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), remote_a.clone());
+        manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
         server
-            .create_host_group(host_group_id.clone(), vec![remote_id.to_string()])
+            .create_host_group("az1".to_string(), vec!["serverA".to_string()])
             .await
             .unwrap();
-        let db_name = "foo";
-        server.create_database(db_name, rules).await.unwrap();
+        server
+            .create_host_group("az2".to_string(), vec!["serverB".to_string()])
+            .await
+            .unwrap();
+        let rules = DatabaseRules {
+            replication: vec!["az1".to_string(), "az2".to_string()],
+            replication_count: 1,
+            replication_queue_max_size: 0,
+            ..Default::default()
+        };
+        let db_name = DatabaseName::new("foo").unwrap();
+        server.create_database("foo", rules).await.unwrap();
 
+        // every host group is written to synchronously
         let lines = parsed_lines("cpu bar=1 10");
         server.write_lines("foo", &lines).await.unwrap();
+        assert_eq!(remote_a.write_count("foo"), 1);
+        assert_eq!(remote_b.write_count("foo"), 1);
 
-        let writes = remote.writes.lock().unwrap().get(db_name).unwrap().clone();
+        remote_b.set_unavailable(true);
+        server.write_lines("foo", &lines).await.unwrap();
+        remote_b.set_unavailable(false);
+        server.drain_replication_queues().await;
 
-        let write_text = r#"
-writer:1, sequence:1, checksum:226387645
-partition_key:
-  table:cpu
-    bar:1 time:10
-"#;
+        assert_eq!(remote_a.write_count("foo"), 2);
+        assert_eq!(remote_b.write_count("foo"), 1);
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.len, 0);
+        assert_eq!(status.dropped, 1);
 
-        assert_eq!(write_text, writes[0].to_string());
+        Ok(())
+    }
 
-        // ensure sequence number goes up
-        let lines = parsed_lines("mem,server=A,region=west user=232 12");
-        server.write_lines("foo", &lines).await.unwrap();
+    #[tokio::test]
+    async fn remote_server_connections_are_cached() -> Result {
+        let manager = ConnectionManagerImpl::new();
 
-        let writes = remote.writes.lock().unwrap().get(db_name).unwrap().clone();
-        assert_eq!(2, writes.len());
+        let first = manager.remote_server("127.0.0.1:8082").await?;
+        let second = manager.remote_server("127.0.0.1:8082").await?;
+        assert!(Arc::ptr_eq(&first, &second));
 
-        let write_text = r#"
-writer:1, sequence:2, checksum:3759030699
-partition_key:
-  table:mem
-    server:A region:west user:232 time:12
-"#;
+        let other = manager.remote_server("http://127.0.0.1:8083").await?;
+        assert!(!Arc::ptr_eq(&first, &other));
 
-        assert_eq!(write_text, writes[1].to_string());
+        let err = manager.remote_server("not an address").await.unwrap_err();
+        assert!(matches!(err, Error::InvalidRemoteServerAddress { .. }));
 
         Ok(())
     }
@@ -903,6 +2627,168 @@ partition_key:
         Ok(())
     }
 
+    #[tokio::test]
+    async fn sends_matching_tables_and_rows_to_subscribers() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let table_remote = Arc::new(TestRemoteServer::default());
+        let regex_remote = Arc::new(TestRemoteServer::default());
+        let predicate_remote = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), table_remote.clone());
+        manager
+            .remotes
+            .insert("serverB".to_string(), regex_remote.clone());
+        manager
+            .remotes
+            .insert("serverC".to_string(), predicate_remote.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+
+        let subscription =
+            |name: &str, tables: MatchTables, predicate: Option<&str>| Subscription {
+                name: name.to_string(),
+                host_group_id: name.to_string(),
+                matcher: Matcher {
+                    tables,
+                    predicate: predicate.map(ToString::to_string),
+                },
+            };
+
+        let rules = DatabaseRules {
+            subscriptions: vec![
+                subscription("table", MatchTables::Table("cpu".to_string()), None),
+                subscription("regex", MatchTables::Regex("^m".to_string()), None),
+                subscription("predicate", MatchTables::All, Some("region = 'west'")),
+            ],
+            ..Default::default()
+        };
+        for (group, host) in &[
+            ("table", "serverA"),
+            ("regex", "serverB"),
+            ("predicate", "serverC"),
+        ] {
+            server
+                .create_host_group(group.to_string(), vec![host.to_string()])
+                .await
+                .unwrap();
+        }
+        let db_name = "foo";
+        server.create_database(db_name, rules).await.unwrap();
+
+        let lines = parsed_lines(
+            "cpu,region=west bar=1 10\nmem,region=east user=232 12\nmem,region=west user=10 13",
+        );
+        server.write_lines("foo", &lines).await.unwrap();
+
+        // the checksum changes with the contents, so only compare the entries
+        let entries = |remote: &Arc<TestRemoteServer>| {
+            let writes = remote.writes.lock().unwrap().get(db_name).unwrap().clone();
+            assert_eq!(1, writes.len());
+            assert_eq!((1, 1), writes[0].writer_and_sequence());
+            writes[0]
+                .to_string()
+                .lines()
+                .skip(2)
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        let expected = r#"partition_key:
+  table:cpu
+    region:west bar:1 time:10"#;
+        assert_eq!(expected, entries(&table_remote));
+
+        let expected = r#"partition_key:
+  table:mem
+    region:east user:232 time:12
+    region:west user:10 time:13"#;
+        assert_eq!(expected, entries(&regex_remote));
+
+        let expected = r#"partition_key:
+  table:cpu
+    region:west bar:1 time:10
+  table:mem
+    region:west user:10 time:13"#;
+        assert_eq!(expected, entries(&predicate_remote));
+
+        // writes that match nothing are not sent
+        let lines = parsed_lines("disk,region=east used=5 14");
+        server.write_lines("foo", &lines).await.unwrap();
+
+        for remote in &[table_remote, regex_remote, predicate_remote] {
+            let writes = remote.writes.lock().unwrap().get(db_name).unwrap().clone();
+            assert_eq!(1, writes.len());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscriber_failures_do_not_fail_writes() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), remote.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        server
+            .create_host_group("az1".to_string(), vec!["serverA".to_string()])
+            .await
+            .unwrap();
+
+        let subscription = |predicate: &str| Subscription {
+            name: "query_server_1".to_string(),
+            host_group_id: "az1".to_string(),
+            matcher: Matcher {
+                tables: MatchTables::All,
+                predicate: Some(predicate.to_string()),
+            },
+        };
+
+        // invalid matchers are rejected up front
+        let rules = DatabaseRules {
+            subscriptions: vec![subscription("region = ")],
+            ..Default::default()
+        };
+        let err = server.create_database("foo", rules).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidDatabaseRules { .. }));
+
+        let rules = DatabaseRules {
+            subscriptions: vec![subscription("region = 'west'")],
+            replication_queue_max_size: 1,
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+        let db_name = DatabaseName::new("foo").unwrap();
+
+        // the write that couldn't be pushed is queued for the subscription
+        remote.set_unavailable(true);
+        let lines = parsed_lines("cpu,region=west bar=1 10");
+        server.write_lines("foo", &lines).await?;
+        assert_eq!(remote.write_count("foo"), 0);
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.host_groups.get("az1"), Some(&1));
+
+        remote.set_unavailable(false);
+        server.drain_replication_queues().await;
+        assert_eq!(remote.write_count("foo"), 1);
+        let status = server.replication_queue_status(&db_name).await.unwrap();
+        assert_eq!(status.len, 0);
+
+        server.write_lines("foo", &lines).await?;
+        assert_eq!(remote.write_count("foo"), 2);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn segment_persisted_on_rollover() {
         let manager = TestConnectionManager::new();
@@ -950,6 +2836,49 @@ partition_key:
         assert_eq!(segment.writes[0].to_string(), write);
     }
 
+    #[tokio::test]
+    async fn segment_persisted_after_close_segment_after() {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let server = Server::new(manager, store.clone());
+        server.set_id(1);
+        let db_name = "my_db";
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 500,
+                segment_size: 500,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: true,
+                close_segment_after: Some(std::time::Duration::from_millis(10)),
+            }),
+            ..Default::default()
+        };
+        server.create_database(db_name, rules).await.unwrap();
+
+        let lines = parsed_lines("disk,host=a used=10.1 12");
+        server.write_lines(db_name, &lines).await.unwrap();
+
+        // the segment is far from full, so only the timer can close it
+        let path = ObjectStorePath::from_cloud_unchecked("1/my_db/wal/000/000/001.segment");
+        let mut attempts = 0;
+        let data = loop {
+            if let Ok(stream) = store.get(&path).await {
+                break stream
+                    .map_ok(|b| bytes::BytesMut::from(&b[..]))
+                    .try_concat()
+                    .await
+                    .unwrap();
+            }
+            attempts += 1;
+            assert!(attempts < 100, "segment was not persisted");
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        };
+
+        let segment = Segment::from_file_bytes(&data).unwrap();
+        assert_eq!(segment.writes.len(), 1);
+    }
+
     #[derive(Snafu, Debug, Clone)]
     enum TestClusterError {
         #[snafu(display("Test cluster error:  {}", message))]
@@ -975,13 +2904,28 @@ partition_key:
         type RemoteServer = TestRemoteServer;
 
         async fn remote_server(&self, id: &str) -> Result<Arc<TestRemoteServer>, Self::Error> {
-            Ok(self.remotes.get(id).unwrap().clone())
+            self.remotes.get(id).cloned().context(General {
+                message: format!("no remote {}", id),
+            })
         }
     }
 
     #[derive(Debug, Default)]
     struct TestRemoteServer {
         writes: Mutex<BTreeMap<String, Vec<ReplicatedWrite>>>,
+        unavailable: AtomicBool,
+        // a server the writes are also applied to, as a remote would
+        forward_to: Option<Arc<Server<TestConnectionManager>>>,
+    }
+
+    impl TestRemoteServer {
+        fn set_unavailable(&self, unavailable: bool) {
+            self.unavailable.store(unavailable, Ordering::SeqCst);
+        }
+
+        fn write_count(&self, db: &str) -> usize {
+            self.writes.lock().unwrap().get(db).map_or(0, Vec::len)
+        }
     }
 
     #[async_trait]
@@ -993,6 +2937,22 @@ partition_key:
             db: &str,
             replicated_write: &ReplicatedWrite,
         ) -> Result<(), Self::Error> {
+            if self.unavailable.load(Ordering::SeqCst) {
+                return General {
+                    message: "unavailable",
+                }
+                .fail();
+            }
+
+            if let Some(server) = &self.forward_to {
+                server
+                    .write_replicated(db, replicated_write.clone())
+                    .await
+                    .map_err(|e| TestClusterError::General {
+                        message: e.to_string(),
+                    })?;
+            }
+
             let mut writes = self.writes.lock().unwrap();
             let entries = writes.entry(db.to_string()).or_insert_with(Vec::new);
             entries.push(replicated_write.clone());
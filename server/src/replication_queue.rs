@@ -0,0 +1,251 @@
+//! This module contains the per-database queue of writes waiting to be
+//! replicated to host groups, or pushed to subscriptions, in the background.
+
+use data_types::{
+    data::ReplicatedWrite,
+    database_rules::{HostGroupId, ReplicationQueueOverflow},
+};
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("replication queue is full ({} writes), rejecting write", max_size))]
+    QueueFull { max_size: usize },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// `ReplicationQueue` holds writes that have been acknowledged to the client
+/// but still need to be replicated to some host groups. It is bounded by
+/// `max_size` queued writes; what happens when it is full is determined by
+/// its `ReplicationQueueOverflow` policy. A queue with a max size of zero
+/// holds nothing and drops every write pushed to it.
+#[derive(Debug, Default)]
+pub struct ReplicationQueue {
+    max_size: usize,
+    overflow: ReplicationQueueOverflow,
+    entries: VecDeque<QueuedWrite>,
+    next_id: u64,
+    dropped: u64,
+}
+
+/// A write waiting to be replicated to a single host group
+#[derive(Debug, Clone)]
+pub struct QueuedWrite {
+    pub id: u64,
+    pub host_group_id: HostGroupId,
+    pub write: Arc<ReplicatedWrite>,
+}
+
+/// A summary of the state of a `ReplicationQueue`
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicationQueueStatus {
+    /// The number of writes waiting to be replicated
+    pub len: usize,
+    /// The maximum number of writes the queue will hold
+    pub max_size: usize,
+    /// The number of queued writes for each host group
+    pub host_groups: BTreeMap<HostGroupId, usize>,
+    /// The number of writes dropped because the queue was full, or because
+    /// its max size is zero
+    pub dropped: u64,
+}
+
+impl ReplicationQueue {
+    pub fn new(max_size: usize, overflow: ReplicationQueueOverflow) -> Self {
+        Self {
+            max_size,
+            overflow,
+            ..Default::default()
+        }
+    }
+
+    /// Returns an error if the queue is full and new writes should be
+    /// rejected. This is the only place writes are rejected, as it is
+    /// checked before a write is applied.
+    pub fn check_accepting_writes(&self) -> Result<()> {
+        if self.max_size > 0 && self.overflow == ReplicationQueueOverflow::RejectWrite {
+            ensure!(
+                self.entries.len() < self.max_size,
+                QueueFull {
+                    max_size: self.max_size
+                }
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Queues `write` to be replicated to each of `host_group_ids`. The write
+    /// has already been applied and acknowledged by the time it is queued,
+    /// so this never fails. If the queue would overflow, writes are dropped
+    /// and counted instead: the oldest queued writes if the policy is to
+    /// drop them, otherwise the host groups of this write that don't fit.
+    /// The latter only happens when concurrent writes pass
+    /// `check_accepting_writes` together.
+    pub fn push(&mut self, host_group_ids: &[HostGroupId], write: &Arc<ReplicatedWrite>) {
+        if self.max_size == 0 {
+            self.dropped += host_group_ids.len() as u64;
+            return;
+        }
+
+        for host_group_id in host_group_ids {
+            if self.overflow == ReplicationQueueOverflow::RejectWrite
+                && self.entries.len() >= self.max_size
+            {
+                self.dropped += 1;
+                continue;
+            }
+
+            self.entries.push_back(QueuedWrite {
+                id: self.next_id,
+                host_group_id: host_group_id.clone(),
+                write: Arc::clone(write),
+            });
+            self.next_id += 1;
+        }
+
+        while self.entries.len() > self.max_size {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    /// Returns the queued writes, oldest first
+    pub fn entries(&self) -> Vec<QueuedWrite> {
+        self.entries.iter().cloned().collect()
+    }
+
+    /// Removes the queued write with `id`, if it is still in the queue
+    pub fn remove(&mut self, id: u64) {
+        self.entries.retain(|e| e.id != id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn status(&self) -> ReplicationQueueStatus {
+        let mut host_groups = BTreeMap::new();
+        for entry in &self.entries {
+            *host_groups.entry(entry.host_group_id.clone()).or_default() += 1;
+        }
+
+        ReplicationQueueStatus {
+            len: self.entries.len(),
+            max_size: self.max_size,
+            host_groups,
+            dropped: self.dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(ids: &[&str]) -> Vec<HostGroupId> {
+        ids.iter().map(ToString::to_string).collect()
+    }
+
+    fn write() -> Arc<ReplicatedWrite> {
+        Arc::new(ReplicatedWrite::default())
+    }
+
+    #[test]
+    fn push_and_remove() {
+        let mut queue = ReplicationQueue::new(10, ReplicationQueueOverflow::RejectWrite);
+        queue.push(&groups(&["az1", "az2"]), &write());
+        queue.push(&groups(&["az1"]), &write());
+
+        let entries = queue.entries();
+        let ids: Vec<_> = entries.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![0, 1, 2]);
+
+        let mut expected = BTreeMap::new();
+        expected.insert("az1".to_string(), 2);
+        expected.insert("az2".to_string(), 1);
+        assert_eq!(
+            queue.status(),
+            ReplicationQueueStatus {
+                len: 3,
+                max_size: 10,
+                host_groups: expected,
+                dropped: 0,
+            }
+        );
+
+        queue.remove(1);
+        queue.remove(1);
+        let ids: Vec<_> = queue.entries().iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![0, 2]);
+    }
+
+    #[test]
+    fn reject_when_full() {
+        let mut queue = ReplicationQueue::new(2, ReplicationQueueOverflow::RejectWrite);
+        queue.push(&groups(&["az1"]), &write());
+        queue.check_accepting_writes().unwrap();
+
+        queue.push(&groups(&["az2"]), &write());
+        let err = queue.check_accepting_writes().unwrap_err();
+        assert!(matches!(err, Error::QueueFull { max_size: 2 }));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.status().dropped, 0);
+    }
+
+    #[test]
+    fn push_past_check_drops_what_does_not_fit() {
+        // a write that passed `check_accepting_writes` has been applied, so
+        // queueing it can't fail even if the queue fills up in the meantime
+        let mut queue = ReplicationQueue::new(2, ReplicationQueueOverflow::RejectWrite);
+        queue.push(&groups(&["az1"]), &write());
+        queue.check_accepting_writes().unwrap();
+
+        queue.push(&groups(&["az2", "az3"]), &write());
+        let queued: Vec<_> = queue
+            .entries()
+            .into_iter()
+            .map(|e| e.host_group_id)
+            .collect();
+        assert_eq!(queued, groups(&["az1", "az2"]));
+        assert_eq!(queue.status().dropped, 1);
+    }
+
+    #[test]
+    fn drop_oldest_when_full() {
+        let mut queue = ReplicationQueue::new(2, ReplicationQueueOverflow::DropOldest);
+        queue.push(&groups(&["az1"]), &write());
+        queue.push(&groups(&["az2", "az3"]), &write());
+        queue.check_accepting_writes().unwrap();
+
+        let queued: Vec<_> = queue
+            .entries()
+            .into_iter()
+            .map(|e| e.host_group_id)
+            .collect();
+        assert_eq!(queued, groups(&["az2", "az3"]));
+        assert_eq!(queue.status().dropped, 1);
+    }
+
+    #[test]
+    fn zero_size_drops_everything() {
+        let mut queue = ReplicationQueue::new(0, ReplicationQueueOverflow::RejectWrite);
+        queue.push(&groups(&["az1", "az2"]), &write());
+        queue.check_accepting_writes().unwrap();
+
+        assert!(queue.is_empty());
+        assert_eq!(queue.status().dropped, 2);
+    }
+}
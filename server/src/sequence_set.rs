@@ -0,0 +1,185 @@
+//! This module contains `SequenceSet`, which records the sequence numbers of
+//! the writes from one writer that have been seen, when they can be seen in
+//! any order, and `PartitionSequenceSet`, which records the partitions of
+//! those writes that have been seen.
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The most sequences that are kept above the lowest one not yet seen. Past
+/// this the oldest gap is given up on.
+const MAX_OUT_OF_ORDER: usize = 10_000;
+
+/// The sequence numbers seen from a writer. Writes are numbered in order, but
+/// can arrive out of order, so this keeps every sequence below `below` as a
+/// single number and only the sequences seen after a gap individually.
+///
+/// A write that never arrives would make the set grow forever, so once more
+/// than `MAX_OUT_OF_ORDER` sequences are above a gap the gap is treated as
+/// seen.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct SequenceSet {
+    /// Every sequence lower than this has been seen
+    below: u64,
+    /// The sequences at or above `below` that have been seen
+    above: BTreeSet<u64>,
+}
+
+impl SequenceSet {
+    /// Returns a set containing every sequence up to and including `sequence`
+    pub(crate) fn up_to(sequence: u64) -> Self {
+        Self {
+            below: sequence.saturating_add(1),
+            above: BTreeSet::new(),
+        }
+    }
+
+    /// Returns true if `sequence` has been seen
+    pub(crate) fn contains(&self, sequence: u64) -> bool {
+        sequence < self.below || self.above.contains(&sequence)
+    }
+
+    /// Returns true if every sequence up to and including `sequence` has
+    /// been seen
+    pub(crate) fn contains_up_to(&self, sequence: u64) -> bool {
+        sequence < self.below
+    }
+
+    /// Records that `sequence` has been seen, returning false if it already
+    /// had been
+    pub(crate) fn insert(&mut self, sequence: u64) -> bool {
+        if sequence < self.below || !self.above.insert(sequence) {
+            return false;
+        }
+
+        if self.above.len() > MAX_OUT_OF_ORDER {
+            self.below = *self.above.iter().next().expect("set isn't empty");
+        }
+        while self.above.remove(&self.below) {
+            self.below += 1;
+        }
+
+        true
+    }
+}
+
+/// The partitions of the writes from a writer that have been seen. A write
+/// can be split by partition key, and its parts seen separately and in any
+/// order, so the partition keys seen are kept for each sequence.
+///
+/// The parts of a write never seen would make the set grow forever, so only
+/// the latest `MAX_OUT_OF_ORDER` sequences are kept, and every partition of
+/// an older sequence is treated as seen.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct PartitionSequenceSet {
+    /// Every partition of every sequence lower than this has been seen
+    below: u64,
+    /// The partition keys seen of the sequences at or above `below`
+    above: BTreeMap<u64, BTreeSet<String>>,
+}
+
+impl PartitionSequenceSet {
+    /// Returns a set containing every partition of every sequence up to and
+    /// including `sequence`
+    pub(crate) fn up_to(sequence: u64) -> Self {
+        Self {
+            below: sequence.saturating_add(1),
+            above: BTreeMap::new(),
+        }
+    }
+
+    /// Returns true if `partition_key` of `sequence` has been seen
+    pub(crate) fn contains(&self, sequence: u64, partition_key: &str) -> bool {
+        sequence < self.below
+            || self
+                .above
+                .get(&sequence)
+                .map_or(false, |keys| keys.contains(partition_key))
+    }
+
+    /// Records that `partition_key` of `sequence` has been seen, returning
+    /// false if it already had been
+    pub(crate) fn insert(&mut self, sequence: u64, partition_key: &str) -> bool {
+        if self.contains(sequence, partition_key) {
+            return false;
+        }
+
+        self.above
+            .entry(sequence)
+            .or_default()
+            .insert(partition_key.to_string());
+
+        while self.above.len() > MAX_OUT_OF_ORDER {
+            let oldest = *self.above.keys().next().expect("set isn't empty");
+            self.above.remove(&oldest);
+            self.below = oldest + 1;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_order_sequences() {
+        let mut set = SequenceSet::up_to(2);
+        assert!(set.contains(1));
+        assert!(set.contains(2));
+        assert!(!set.contains(3));
+        assert!(set.contains_up_to(2));
+        assert!(!set.contains_up_to(3));
+
+        assert!(set.insert(5));
+        assert!(set.insert(3));
+        assert!(!set.insert(5));
+        assert!(!set.insert(2));
+        assert!(set.contains(3));
+        assert!(!set.contains(4));
+        assert!(set.contains(5));
+        assert!(!set.contains_up_to(5));
+
+        assert!(set.insert(4));
+        assert_eq!(set, SequenceSet::up_to(5));
+    }
+
+    #[test]
+    fn gives_up_on_the_oldest_gap() {
+        let mut set = SequenceSet::default();
+        for sequence in 2..(MAX_OUT_OF_ORDER as u64 + 3) {
+            assert!(set.insert(sequence));
+        }
+
+        assert!(set.contains(0));
+        assert!(set.above.is_empty());
+        assert_eq!(set, SequenceSet::up_to(MAX_OUT_OF_ORDER as u64 + 2));
+    }
+
+    #[test]
+    fn partitions_of_a_sequence() {
+        let mut set = PartitionSequenceSet::up_to(2);
+        assert!(set.contains(2, "a"));
+        assert!(!set.contains(3, "a"));
+
+        assert!(set.insert(3, "a"));
+        assert!(!set.insert(3, "a"));
+        assert!(!set.insert(1, "b"));
+        assert!(set.contains(3, "a"));
+        assert!(!set.contains(3, "b"));
+
+        assert!(set.insert(3, "b"));
+        assert!(set.contains(3, "b"));
+    }
+
+    #[test]
+    fn forgets_the_oldest_sequences() {
+        let mut set = PartitionSequenceSet::default();
+        for sequence in 1..(MAX_OUT_OF_ORDER as u64 + 2) {
+            assert!(set.insert(sequence, "a"));
+        }
+
+        assert_eq!(set.above.len(), MAX_OUT_OF_ORDER);
+        assert!(set.contains(1, "b"));
+        assert!(!set.contains(2, "b"));
+    }
+}
@@ -1,22 +1,40 @@
 //! This module contains code for snapshotting a database chunk to Parquet
 //! files in object storage.
 use arrow_deps::{
-    arrow::record_batch::RecordBatch,
-    parquet::{self, arrow::ArrowWriter, file::writer::TryClone},
+    arrow::{
+        datatypes::{Schema as ArrowSchema, SchemaRef as ArrowSchemaRef},
+        error::{ArrowError, Result as ArrowResult},
+        record_batch::RecordBatch,
+    },
+    datafusion::physical_plan::{RecordBatchStream, SendableRecordBatchStream},
+    parquet::{
+        self,
+        arrow::{ArrowReader, ArrowWriter, ParquetFileArrowReader},
+        file::{
+            metadata::KeyValue,
+            properties::WriterProperties,
+            serialized_reader::{SerializedFileReader, SliceableCursor},
+            writer::TryClone,
+        },
+    },
 };
 use data_types::{
-    partition_metadata::{Partition as PartitionMeta, Table},
+    partition_metadata::{Partition as PartitionMeta, SequenceRange, Table},
     selection::Selection,
 };
 use object_store::{path::ObjectStorePath, ObjectStore};
 use query::PartitionChunk;
 
+use std::collections::BTreeMap;
 use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 
 use bytes::Bytes;
-use snafu::{ResultExt, Snafu};
-use tokio::sync::oneshot;
+use futures::{Stream, TryStreamExt};
+use snafu::{OptionExt, ResultExt, Snafu};
+use tokio::sync::{mpsc, oneshot};
 use tracing::{error, info};
 use uuid::Uuid;
 
@@ -51,6 +69,22 @@ pub enum Error {
     #[snafu(display("Error writing to object store: {}", source))]
     WritingToObjectStore { source: object_store::Error },
 
+    #[snafu(display("Error reading from object store: {}", source))]
+    ReadingFromObjectStore { source: object_store::Error },
+
+    #[snafu(display("Error opening Parquet Reader: {}", source))]
+    OpeningParquetReader {
+        source: parquet::errors::ParquetError,
+    },
+
+    #[snafu(display("Error reading Parquet: {}", source))]
+    ReadingParquet {
+        source: arrow_deps::arrow::error::ArrowError,
+    },
+
+    #[snafu(display("Column {} not found in snapshot", column_name))]
+    ColumnNotFound { column_name: String },
+
     #[snafu(display("Stopped early"))]
     StoppedEarly,
 }
@@ -82,6 +116,7 @@ where
         store: Arc<ObjectStore>,
         partition: Arc<T>,
         tables: Vec<Table>,
+        writer_sequences: BTreeMap<u32, SequenceRange>,
     ) -> Self {
         let table_states = vec![TableState::NotStarted; tables.len()];
 
@@ -95,6 +130,8 @@ where
             partition_meta: PartitionMeta {
                 key: partition_key.into(),
                 tables,
+                chunk_id: Some(partition.id()),
+                writer_sequences,
             },
             metadata_path,
             data_path,
@@ -144,6 +181,19 @@ where
             .all(|state| matches!(state, TableState::Finished))
     }
 
+    /// Returns true once the data for every table and the partition
+    /// metadata have been written to object storage
+    pub fn completed(&self) -> bool {
+        let status = self.status.lock().expect("mutex poisoned");
+        status.meta_written
+    }
+
+    /// Returns true if the snapshot stopped because of an error
+    pub fn failed(&self) -> bool {
+        let status = self.status.lock().expect("mutex poisoned");
+        status.error.is_some()
+    }
+
     fn should_stop(&self) -> bool {
         let status = self.status.lock().expect("mutex poisoned");
         status.stop_on_next_update
@@ -200,9 +250,22 @@ where
         batches: Vec<RecordBatch>,
         file_name: &ObjectStorePath,
     ) -> Result<()> {
+        let schema = batches[0].schema();
+
+        // The IOx schema is stored in the schema metadata, which is kept as
+        // key value metadata so it can be restored when the file is read.
+        let key_value_metadata = schema
+            .metadata()
+            .iter()
+            .map(|(k, v)| KeyValue::new(k.clone(), v.clone()))
+            .collect();
+        let props = WriterProperties::builder()
+            .set_key_value_metadata(Some(key_value_metadata))
+            .build();
+
         let mem_writer = MemWriter::default();
         {
-            let mut writer = ArrowWriter::try_new(mem_writer.clone(), batches[0].schema(), None)
+            let mut writer = ArrowWriter::try_new(mem_writer.clone(), schema, Some(props))
                 .context(OpeningParquetWriter)?;
             for batch in batches.into_iter() {
                 writer.write(&batch).context(WritingParquetToMemory)?;
@@ -249,12 +312,206 @@ pub struct Status {
     error: Option<Error>,
 }
 
+/// Returns the metadata and data paths for a snapshot of the chunk with id
+/// `chunk_id` in the partition `partition_key`, under the database's base
+/// path `db_path`. Each chunk gets its own paths, so that snapshots of
+/// different chunks of a partition do not overwrite each other.
+pub fn chunk_snapshot_paths(
+    db_path: &ObjectStorePath,
+    partition_key: &str,
+    chunk_id: u32,
+) -> (ObjectStorePath, ObjectStorePath) {
+    let chunk_id = chunk_id.to_string();
+
+    let mut metadata_path = db_path.clone();
+    metadata_path.push_all_dirs(&["meta", partition_key, &chunk_id]);
+
+    let mut data_path = db_path.clone();
+    data_path.push_all_dirs(&["data", partition_key, &chunk_id]);
+
+    (metadata_path, data_path)
+}
+
+/// The number of rows read into each record batch by `read_table`
+const READ_BATCH_SIZE: usize = 1024;
+
+/// The number of record batches `read_table` decodes ahead of the consumer
+/// of its stream
+const READ_AHEAD_BATCHES: usize = 2;
+
+/// Reads the data for the table `table_name` written by a snapshot to
+/// `data_path`, restoring the schema metadata stored with it. Only the
+/// columns in `selection` are decoded.
+///
+/// The file is downloaded before this returns, but its row groups are only
+/// decoded, on a blocking thread, as the returned stream is read.
+pub async fn read_table(
+    store: &ObjectStore,
+    data_path: &ObjectStorePath,
+    table_name: &str,
+    selection: Selection<'_>,
+) -> Result<SendableRecordBatchStream> {
+    let data = get_table_data(store, data_path, table_name).await?;
+    let file_schema = open_reader(Arc::clone(&data))?
+        .get_schema()
+        .context(OpeningParquetReader)?;
+
+    let column_names = match selection {
+        Selection::All => {
+            let mut names: Vec<_> = file_schema
+                .fields()
+                .iter()
+                .map(|f| f.name().as_str())
+                .collect();
+            names.sort_unstable();
+            names
+        }
+        Selection::Some(columns) => columns.to_vec(),
+    };
+    let indices = column_names
+        .iter()
+        .map(|&column_name| {
+            file_schema
+                .index_of(column_name)
+                .ok()
+                .context(ColumnNotFound { column_name })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let fields = indices
+        .iter()
+        .map(|&i| file_schema.field(i).clone())
+        .collect();
+    let schema = Arc::new(ArrowSchema::new_with_metadata(
+        fields,
+        file_schema.metadata().clone(),
+    ));
+
+    let (tx, rx) = mpsc::channel(READ_AHEAD_BATCHES);
+    let reader_tx = tx.clone();
+    let reader_schema = Arc::clone(&schema);
+    let reader = tokio::task::spawn_blocking(move || {
+        // the record reader returns the projected columns in the order they
+        // are stored in the file
+        let mut file_indices = indices.clone();
+        file_indices.sort_unstable();
+        file_indices.dedup();
+
+        let record_reader = match open_reader(data).and_then(|mut arrow_reader| {
+            arrow_reader
+                .get_record_reader_by_columns(file_indices.clone(), READ_BATCH_SIZE)
+                .context(OpeningParquetReader)
+        }) {
+            Ok(record_reader) => record_reader,
+            Err(e) => {
+                let _ = reader_tx.blocking_send(Err(ArrowError::ExternalError(Box::new(e))));
+                return;
+            }
+        };
+
+        for batch in record_reader {
+            let batch = batch.and_then(|batch| {
+                let columns = indices
+                    .iter()
+                    .map(|i| {
+                        let position = file_indices.binary_search(i).expect("projected column");
+                        Arc::clone(batch.column(position))
+                    })
+                    .collect();
+                RecordBatch::try_new(Arc::clone(&reader_schema), columns)
+            });
+
+            // stop reading once the stream is dropped
+            if reader_tx.blocking_send(batch).is_err() {
+                return;
+            }
+        }
+    });
+
+    // if decoding panics the stream ends with an error, rather than early
+    tokio::task::spawn(async move {
+        if let Err(e) = reader.await {
+            let _ = tx.send(Err(ArrowError::ExternalError(Box::new(e)))).await;
+        }
+    });
+
+    Ok(Box::pin(TableBatchStream { schema, input: rx }))
+}
+
+/// Reads the schema, including its metadata, of the table `table_name`
+/// written by a snapshot to `data_path`.
+pub async fn read_table_schema(
+    store: &ObjectStore,
+    data_path: &ObjectStorePath,
+    table_name: &str,
+) -> Result<ArrowSchemaRef> {
+    let data = get_table_data(store, data_path, table_name).await?;
+    let schema = open_reader(data)?
+        .get_schema()
+        .context(OpeningParquetReader)?;
+    Ok(Arc::new(schema))
+}
+
+/// Downloads the Parquet file for the table `table_name` written by a
+/// snapshot to `data_path`
+async fn get_table_data(
+    store: &ObjectStore,
+    data_path: &ObjectStorePath,
+    table_name: &str,
+) -> Result<Arc<Vec<u8>>> {
+    let mut location = data_path.clone();
+    location.set_file_name(&format!("{}.parquet", table_name));
+
+    let data = store
+        .get(&location)
+        .await
+        .context(ReadingFromObjectStore)?
+        .try_fold(Vec::new(), |mut data, bytes| async move {
+            data.extend_from_slice(&bytes);
+            Ok(data)
+        })
+        .await
+        .context(ReadingFromObjectStore)?;
+
+    Ok(Arc::new(data))
+}
+
+fn open_reader(data: Arc<Vec<u8>>) -> Result<ParquetFileArrowReader> {
+    let file_reader =
+        SerializedFileReader::new(SliceableCursor::new(data)).context(OpeningParquetReader)?;
+    Ok(ParquetFileArrowReader::new(Arc::new(file_reader)))
+}
+
+/// The stream of the batches decoded from a table by `read_table`
+#[derive(Debug)]
+struct TableBatchStream {
+    schema: ArrowSchemaRef,
+    input: mpsc::Receiver<ArrowResult<RecordBatch>>,
+}
+
+impl Stream for TableBatchStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.input.poll_recv(cx)
+    }
+}
+
+impl RecordBatchStream for TableBatchStream {
+    fn schema(&self) -> ArrowSchemaRef {
+        Arc::clone(&self.schema)
+    }
+}
+
+/// Starts a snapshot of `chunk` to object storage, recording the ranges of
+/// sequence numbers of the writes it contains in its metadata.
 pub fn snapshot_chunk<T>(
     metadata_path: ObjectStorePath,
     data_path: ObjectStorePath,
     store: Arc<ObjectStore>,
     partition_key: &str,
     chunk: Arc<T>,
+    writer_sequences: BTreeMap<u32, SequenceRange>,
     notify: Option<oneshot::Sender<()>>,
 ) -> Result<Arc<Snapshot<T>>>
 where
@@ -272,6 +529,7 @@ where
         store,
         chunk,
         table_stats,
+        writer_sequences,
     );
     let snapshot = Arc::new(snapshot);
 
@@ -338,12 +596,16 @@ impl TryClone for MemWriter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use arrow_deps::{
+        arrow::util::pretty::pretty_format_batches, datafusion::physical_plan::common::collect,
+    };
     use data_types::data::lines_to_replicated_write;
     use data_types::database_rules::DatabaseRules;
-    use futures::TryStreamExt;
+    use data_types::schema::Schema;
     use influxdb_line_protocol::parse_lines;
     use mutable_buffer::chunk::Chunk as ChunkWB;
     use object_store::memory::InMemory;
+    use std::convert::TryFrom;
 
     #[tokio::test]
     async fn snapshot() {
@@ -355,12 +617,13 @@ mem,host=A,region=west used=45 1
         "#;
 
         let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
-        let write = lines_to_replicated_write(1, 1, &lines, &DatabaseRules::default());
+        let write = lines_to_replicated_write(1, 1, &lines, &DatabaseRules::default()).unwrap();
         let mut chunk = ChunkWB::new(11);
 
         for e in write.write_buffer_batch().unwrap().entries().unwrap() {
             chunk.write_entry(&e).unwrap();
         }
+        chunk.record_sequence(1, 1);
 
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
         let chunk = Arc::new(chunk);
@@ -373,10 +636,11 @@ mem,host=A,region=west used=45 1
 
         let snapshot = snapshot_chunk(
             metadata_path.clone(),
-            data_path,
+            data_path.clone(),
             store.clone(),
             "testaroo",
             chunk.clone(),
+            chunk.writer_sequences.clone(),
             Some(tx),
         )
         .unwrap();
@@ -397,6 +661,50 @@ mem,host=A,region=west used=45 1
 
         let meta: PartitionMeta = serde_json::from_slice(&*summary).unwrap();
         assert_eq!(meta, snapshot.partition_meta);
+        assert_eq!(meta.chunk_id, Some(11));
+        assert_eq!(
+            meta.writer_sequences.get(&1),
+            Some(&SequenceRange { min: 1, max: 1 })
+        );
+        assert!(snapshot.completed());
+        assert!(!snapshot.failed());
+
+        // the table data, including its IOx schema, can be read back
+        let stream = read_table(&store, &data_path, "cpu", Selection::All)
+            .await
+            .unwrap();
+        let batches = collect(stream).await.unwrap();
+        Schema::try_from(batches[0].schema()).unwrap();
+
+        let mut expected = vec![];
+        chunk
+            .table_to_arrow(&mut expected, "cpu", Selection::All)
+            .unwrap();
+        assert_eq!(
+            pretty_format_batches(&batches).unwrap(),
+            pretty_format_batches(&expected).unwrap()
+        );
+
+        // only the selected columns are read, in the order they were selected
+        let selection = Selection::Some(&["user", "host"]);
+        let stream = read_table(&store, &data_path, "cpu", selection.clone())
+            .await
+            .unwrap();
+        let batches = collect(stream).await.unwrap();
+        let mut expected = vec![];
+        chunk
+            .table_to_arrow(&mut expected, "cpu", selection)
+            .unwrap();
+        assert_eq!(
+            pretty_format_batches(&batches).unwrap(),
+            pretty_format_batches(&expected).unwrap()
+        );
+
+        let schema = read_table_schema(&store, &data_path, "cpu").await.unwrap();
+        assert_eq!(schema.fields().len(), 5);
+
+        let result = read_table(&store, &data_path, "cpu", Selection::Some(&["foo"])).await;
+        assert!(matches!(result, Err(Error::ColumnNotFound { .. })));
     }
 
     #[test]
@@ -424,7 +732,15 @@ mem,host=A,region=west used=45 1
         let mut data_path = ObjectStorePath::default();
         data_path.push_dir("data");
 
-        let snapshot = Snapshot::new("testaroo", metadata_path, data_path, store, chunk, tables);
+        let snapshot = Snapshot::new(
+            "testaroo",
+            metadata_path,
+            data_path,
+            store,
+            chunk,
+            tables,
+            BTreeMap::new(),
+        );
 
         let (pos, name) = snapshot.next_table().unwrap();
         assert_eq!(0, pos);
@@ -447,5 +763,27 @@ mem,host=A,region=west used=45 1
         snapshot.mark_table_finished(0);
         snapshot.mark_table_finished(2);
         assert!(snapshot.finished());
+        assert!(!snapshot.completed());
+
+        snapshot.mark_meta_written();
+        assert!(snapshot.completed());
+        assert!(!snapshot.failed());
+
+        snapshot.set_error(Error::StoppedEarly);
+        assert!(snapshot.failed());
+    }
+
+    #[test]
+    fn chunk_snapshot_paths() {
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let mut db_path = ObjectStorePath::default();
+        db_path.push_all_dirs(&["1", "mydb"]);
+
+        let (metadata_path, data_path) = super::chunk_snapshot_paths(&db_path, "2020-01-01", 3);
+        assert_eq!(
+            store.convert_path(&metadata_path),
+            "1/mydb/meta/2020-01-01/3/"
+        );
+        assert_eq!(store.convert_path(&data_path), "1/mydb/data/2020-01-01/3/");
     }
 }
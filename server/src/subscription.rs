@@ -0,0 +1,266 @@
+//! This module contains the pull based subscriptions to the writes of a
+//! database. A subscriber gets the writes held in the database's WAL buffer
+//! after the point it has already seen, followed by the writes applied to
+//! the database from then on.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+
+use data_types::{
+    data::ReplicatedWrite,
+    database_rules::{CompiledMatcher, Matcher, WriterId},
+};
+use snafu::{OptionExt, ResultExt, Snafu};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{buffer, db::Db, sequence_set::SequenceSet};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("database {} has no WAL buffer to subscribe to", db_name))]
+    NoWalBuffer { db_name: String },
+
+    #[snafu(display("subscriber is too far behind, resync from object store: {}", source))]
+    TooFarBehind { source: buffer::Error },
+
+    #[snafu(display("invalid subscription matcher: {}", source))]
+    InvalidMatcher {
+        source: data_types::database_rules::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A subscription to the writes of a database that match a `Matcher`. The
+/// writes are returned in the order they were applied to the database,
+/// with the same writer ID and sequence number.
+#[derive(Debug)]
+pub struct WriteSubscription {
+    db: Arc<Db>,
+    matcher: CompiledMatcher,
+    /// The sequences seen from each writer. Writes from a writer can be
+    /// applied out of order, so these are sets rather than the highest
+    /// sequence seen.
+    seen: BTreeMap<WriterId, SequenceSet>,
+    /// Writes read from the WAL buffer that haven't been returned yet
+    buffered: VecDeque<Arc<ReplicatedWrite>>,
+    live: broadcast::Receiver<Arc<ReplicatedWrite>>,
+}
+
+impl WriteSubscription {
+    /// Subscribes to the writes to `db` after the watermark `since`, which
+    /// has the sequence of the last write the subscriber has seen from each
+    /// writer. Every buffered write from the writers not in it is returned.
+    ///
+    /// Returns `Error::InvalidMatcher` if the matcher's table regex or
+    /// predicate is invalid, and `Error::TooFarBehind` if the WAL buffer no
+    /// longer holds all the writes after the watermark, in which case the
+    /// subscriber has to resync from object storage.
+    pub fn new(db: Arc<Db>, since: BTreeMap<WriterId, u64>, matcher: Matcher) -> Result<Self> {
+        let matcher = matcher
+            .compile(&db.rules.regex_cache)
+            .context(InvalidMatcher)?;
+        let seen = since
+            .into_iter()
+            .map(|(writer, sequence)| (writer, SequenceSet::up_to(sequence)))
+            .collect();
+        let (buffered, live) = Self::read_buffer(&db, &seen)?;
+
+        Ok(Self {
+            db,
+            matcher,
+            seen,
+            buffered: buffered.into(),
+            live,
+        })
+    }
+
+    /// Waits for the next write that matches the subscription's `Matcher`,
+    /// returning only the part of it that matches.
+    ///
+    /// If the subscriber falls so far behind the live writes that the WAL
+    /// buffer no longer holds the ones it missed, `Error::TooFarBehind` is
+    /// returned.
+    pub async fn next(&mut self) -> Result<ReplicatedWrite> {
+        loop {
+            let write = match self.buffered.pop_front() {
+                Some(write) => write,
+                None => match self.live.recv().await {
+                    Ok(write) => write,
+                    Err(RecvError::Lagged(_)) => {
+                        // catch up on the missed writes from the buffer
+                        let (buffered, live) = Self::read_buffer(&self.db, &self.seen)?;
+                        self.buffered = buffered.into();
+                        self.live = live;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => {
+                        unreachable!("the subscription keeps the database alive")
+                    }
+                },
+            };
+
+            // writes read from the buffer can arrive again as live writes
+            let (writer, sequence) = write.writer_and_sequence();
+            if !self.seen.entry(writer).or_default().insert(sequence) {
+                continue;
+            }
+
+            if let Some(matched) = self.matcher.filter_write(&write) {
+                return Ok(matched);
+            }
+        }
+    }
+
+    fn read_buffer(
+        db: &Db,
+        seen: &BTreeMap<WriterId, SequenceSet>,
+    ) -> Result<(
+        Vec<Arc<ReplicatedWrite>>,
+        broadcast::Receiver<Arc<ReplicatedWrite>>,
+    )> {
+        db.wal_buffer
+            .as_ref()
+            .context(NoWalBuffer {
+                db_name: &db.rules.name,
+            })?
+            .lock()
+            .expect("mutex poisoned")
+            .subscribe(seen)
+            .context(TooFarBehind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use data_types::{
+        data::lines_to_replicated_write,
+        database_rules::{DatabaseRules, MatchTables, WalBufferRollover},
+    };
+    use influxdb_line_protocol::parse_lines;
+    use mutable_buffer::MutableBufferDb;
+    use read_buffer::Database as ReadBufferDb;
+
+    fn make_db() -> Arc<Db> {
+        make_db_with_segment_size(1 << 20)
+    }
+
+    fn make_db_with_segment_size(segment_size: u64) -> Arc<Db> {
+        Arc::new(Db::new(
+            DatabaseRules::default(),
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            Some(Buffer::new(
+                1 << 20,
+                segment_size,
+                WalBufferRollover::ReturnError,
+                false,
+            )),
+        ))
+    }
+
+    fn append(db: &Db, writer: u32, sequence: u64, lp: &str) {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(writer, sequence, &lines, &db.rules).unwrap();
+        db.wal_buffer
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .append(Arc::new(write))
+            .unwrap();
+    }
+
+    fn all_tables() -> Matcher {
+        Matcher {
+            tables: MatchTables::All,
+            predicate: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn buffered_then_live_writes() {
+        let db = make_db();
+        append(&db, 1, 1, "cpu bar=1 10");
+        append(&db, 1, 2, "cpu bar=2 20");
+
+        let since = vec![(1, 1)].into_iter().collect();
+        let mut subscription =
+            WriteSubscription::new(Arc::clone(&db), since, all_tables()).unwrap();
+        append(&db, 1, 3, "cpu bar=3 30");
+
+        let write = subscription.next().await.unwrap();
+        assert_eq!(write.writer_and_sequence(), (1, 2));
+        let write = subscription.next().await.unwrap();
+        assert_eq!(write.writer_and_sequence(), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn matcher_filters_writes() {
+        let db = make_db();
+        append(&db, 1, 1, "mem used=1 10");
+        append(&db, 1, 2, "cpu bar=1 10\nmem used=2 10");
+
+        let matcher = Matcher {
+            tables: MatchTables::Table("cpu".to_string()),
+            predicate: None,
+        };
+        let mut subscription =
+            WriteSubscription::new(Arc::clone(&db), BTreeMap::new(), matcher).unwrap();
+
+        let write = subscription.next().await.unwrap();
+        assert_eq!(write.writer_and_sequence(), (1, 2));
+        let write = write.to_string();
+        assert!(write.contains("table:cpu"), "{}", write);
+        assert!(!write.contains("table:mem"), "{}", write);
+    }
+
+    #[tokio::test]
+    async fn out_of_order_writes() {
+        // a segment per write, as a segment rejects out of order writes
+        let db = make_db_with_segment_size(1);
+        append(&db, 1, 3, "cpu bar=3 30");
+
+        let since = vec![(1, 1)].into_iter().collect();
+        let mut subscription =
+            WriteSubscription::new(Arc::clone(&db), since, all_tables()).unwrap();
+        append(&db, 1, 2, "cpu bar=2 20");
+        append(&db, 1, 4, "cpu bar=4 40");
+
+        let write = subscription.next().await.unwrap();
+        assert_eq!(write.writer_and_sequence(), (1, 3));
+        let write = subscription.next().await.unwrap();
+        assert_eq!(write.writer_and_sequence(), (1, 2));
+        let write = subscription.next().await.unwrap();
+        assert_eq!(write.writer_and_sequence(), (1, 4));
+    }
+
+    #[tokio::test]
+    async fn invalid_matcher() {
+        let matcher = Matcher {
+            tables: MatchTables::Regex("(".to_string()),
+            predicate: None,
+        };
+        let err = WriteSubscription::new(make_db(), BTreeMap::new(), matcher).unwrap_err();
+        assert!(matches!(err, Error::InvalidMatcher { .. }));
+    }
+
+    #[tokio::test]
+    async fn too_far_behind() {
+        let db = make_db();
+        db.wal_buffer
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .mark_missing(1, 5);
+
+        let since = vec![(1, 4)].into_iter().collect();
+        let err = WriteSubscription::new(db, since, all_tables()).unwrap_err();
+        assert!(matches!(err, Error::TooFarBehind { .. }));
+    }
+}
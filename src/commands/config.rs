@@ -1,7 +1,7 @@
 //! Implementation of command line option for manipulating and showing server
 //! config
 
-use std::{net::SocketAddr, path::PathBuf};
+use std::{net::SocketAddr, num::NonZeroUsize, path::PathBuf};
 
 use lazy_static::lazy_static;
 use structopt::StructOpt;
@@ -100,6 +100,11 @@ pub struct Config {
     #[structopt(long = "--data-dir", env = "INFLUXDB_IOX_DB_DIR")]
     pub database_directory: Option<PathBuf>,
 
+    /// The number of bytes of memory that the data in all databases may use
+    /// before writes are rejected. Unlimited if not set.
+    #[structopt(long = "--memory-limit", env = "INFLUXDB_IOX_MEMORY_LIMIT")]
+    pub memory_limit: Option<NonZeroUsize>,
+
     /// If using Google Cloud Storage for the object store, this item, as well
     /// as SERVICE_ACCOUNT must be set.
     #[structopt(long = "--gcp-bucket", env = "INFLUXDB_IOX_GCP_BUCKET")]
@@ -0,0 +1,99 @@
+//! Commands to check and repair the local WAL of a database
+use snafu::{ensure, ResultExt, Snafu};
+use std::path::Path;
+use tracing::info;
+use wal::{RecoveryOptions, RecoveryReport, WalBuilder};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("WAL directory {:?} does not exist", path))]
+    NoWalDirectory { path: std::path::PathBuf },
+
+    #[snafu(display("Unable to recover WAL in {:?}: {}", path, source))]
+    RecoveringWal {
+        path: std::path::PathBuf,
+        source: wal::Error,
+    },
+
+    #[snafu(display("WAL in {:?} is damaged", path))]
+    DamagedWal { path: std::path::PathBuf },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Checks every entry of the WAL in `path` without changing it, failing if
+/// any entry is damaged
+pub fn verify(path: &Path) -> Result<()> {
+    info!("verifying WAL in {:?}", path);
+
+    let report = recover(
+        path,
+        RecoveryOptions {
+            skip_corrupt_entries: true,
+            repair: false,
+        },
+    )?;
+    print_report(&report);
+
+    ensure!(report.is_clean(), DamagedWal { path });
+    Ok(())
+}
+
+/// Truncates a torn final entry off the WAL in `path`. If
+/// `skip_corrupt_entries` is set, corrupt entries before it are removed
+/// too; otherwise they are an error.
+pub fn repair(path: &Path, skip_corrupt_entries: bool) -> Result<()> {
+    info!("repairing WAL in {:?}", path);
+
+    let report = recover(
+        path,
+        RecoveryOptions {
+            skip_corrupt_entries,
+            repair: true,
+        },
+    )?;
+    print_report(&report);
+
+    Ok(())
+}
+
+fn recover(path: &Path, options: RecoveryOptions) -> Result<RecoveryReport> {
+    ensure!(path.is_dir(), NoWalDirectory { path });
+
+    WalBuilder::new(path)
+        .recover(options)
+        .context(RecoveringWal { path })
+}
+
+fn print_report(report: &RecoveryReport) {
+    println!("WAL Recovery Report:");
+    println!("  Files: {}", report.files);
+    println!("  Valid Entries: {}", report.entries);
+
+    match &report.torn_tail {
+        Some(torn_tail) => println!(
+            "  Torn Entry: {:?} offset {} ({} bytes){}",
+            torn_tail.path,
+            torn_tail.offset,
+            torn_tail.len,
+            if torn_tail.truncated {
+                ", truncated"
+            } else {
+                ""
+            }
+        ),
+        None => println!("  Torn Entry: none"),
+    }
+
+    println!("  Corrupt Entries: {}", report.corrupt_entries.len());
+    for corrupt in &report.corrupt_entries {
+        println!(
+            "    {:?} offset {} ({} bytes){}: {}",
+            corrupt.path,
+            corrupt.offset,
+            corrupt.len,
+            if corrupt.removed { ", removed" } else { "" },
+            corrupt.reason
+        );
+    }
+}
@@ -110,8 +110,16 @@ pub async fn main(logging_level: LoggingLevel, config: Option<Config>) -> Result
     };
     let object_storage = Arc::new(object_store);
 
-    let connection_manager = ConnectionManager {};
-    let app_server = Arc::new(AppServer::new(connection_manager, object_storage));
+    let connection_manager = ConnectionManager::new();
+    let mut app_server = AppServer::new(connection_manager, object_storage);
+    if let Some(db_dir) = db_dir {
+        // Databases with a local WAL configured keep it next to the object store
+        app_server = app_server.with_wal_directory(db_dir.join("wal"));
+    }
+    if let Some(memory_limit) = config.memory_limit {
+        app_server = app_server.with_memory_limit(memory_limit);
+    }
+    let app_server = Arc::new(app_server);
 
     // if this ID isn't set the server won't be usable until this is set via an API
     // call
@@ -127,6 +135,10 @@ pub async fn main(logging_level: LoggingLevel, config: Option<Config>) -> Result
         warn!("server ID not set. ID must be set via the INFLUXDB_IOX_ID config or API before writing or querying data.");
     }
 
+    // Start the server's background tasks, such as replicating queued writes
+    let background_server = app_server.clone();
+    tokio::spawn(async move { background_server.background_worker().await });
+
     // Construct and start up gRPC server
 
     let grpc_bind_addr = config.grpc_bind_address;
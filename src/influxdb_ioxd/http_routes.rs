@@ -9,8 +9,12 @@
 //! Long term, we expect to create IOx specific api in terms of
 //! database names and may remove this quasi /v2 API.
 
+mod format;
+
 // Influx crates
-use arrow_deps::{arrow, datafusion::physical_plan::collect};
+use arrow_deps::{
+    arrow::record_batch::RecordBatch, datafusion::physical_plan::SendableRecordBatchStream,
+};
 use data_types::{
     database_rules::DatabaseRules,
     names::{org_and_bucket_to_database, OrgBucketMappingError},
@@ -18,19 +22,21 @@ use data_types::{
 };
 use influxdb_line_protocol::parse_lines;
 use object_store::path::ObjectStorePath;
-use query::{frontend::sql::SQLQueryPlanner, Database, DatabaseStore};
+use query::{frontend::sql::SQLQueryPlanner, predicate::TimestampRange, Database, DatabaseStore};
 use server::{ConnectionManager, Server as AppServer};
 
 // External crates
 use bytes::{Bytes, BytesMut};
 use futures::{self, StreamExt};
-use http::header::CONTENT_ENCODING;
+use http::header::{ACCEPT, CONTENT_ENCODING, CONTENT_TYPE};
 use hyper::{Body, Method, Request, Response, StatusCode};
 use routerify::{prelude::*, Middleware, RequestInfo, Router, RouterService};
 use serde::{Deserialize, Serialize};
 use snafu::{OptionExt, ResultExt, Snafu};
 use tracing::{debug, error, info};
 
+use format::{BatchEncoder, QueryOutputFormat};
+
 use std::{fmt::Debug, str, sync::Arc};
 
 #[derive(Debug, Snafu)]
@@ -62,6 +68,9 @@ pub enum ApplicationError {
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
+    #[snafu(display("{}", source))]
+    MemoryLimitExceeded { source: server::Error },
+
     #[snafu(display("Error planning query {}: {}", query, source))]
     PlanningSQLQuery {
         query: String,
@@ -143,6 +152,12 @@ pub enum ApplicationError {
 
     #[snafu(display("Database {} not found", name))]
     DatabaseNotFound { name: String },
+
+    #[snafu(display("Cannot return query results in a requested format: {}", source))]
+    NotAcceptable { source: format::Error },
+
+    #[snafu(display("Internal error encoding query results: {}", source))]
+    EncodingResults { source: format::Error },
 }
 
 impl ApplicationError {
@@ -151,6 +166,7 @@ impl ApplicationError {
             Self::BucketByName { .. } => self.internal_error(),
             Self::BucketMappingError { .. } => self.internal_error(),
             Self::WritingPoints { .. } => self.internal_error(),
+            Self::MemoryLimitExceeded { .. } => self.service_unavailable(),
             Self::PlanningSQLQuery { .. } => self.bad_request(),
             Self::Query { .. } => self.internal_error(),
             Self::QueryError { .. } => self.bad_request(),
@@ -171,6 +187,8 @@ impl ApplicationError {
             Self::ErrorCreatingDatabase { .. } => self.bad_request(),
             Self::DatabaseNameError { .. } => self.bad_request(),
             Self::DatabaseNotFound { .. } => self.not_found(),
+            Self::NotAcceptable { .. } => self.not_acceptable(),
+            Self::EncodingResults { .. } => self.internal_error(),
         })
     }
 
@@ -188,6 +206,21 @@ impl ApplicationError {
             .unwrap()
     }
 
+    fn not_acceptable(&self) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::NOT_ACCEPTABLE)
+            .body(self.body())
+            .unwrap()
+    }
+
+    fn service_unavailable(&self) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(hyper::header::RETRY_AFTER, "1")
+            .body(self.body())
+            .unwrap()
+    }
+
     fn not_found(&self) -> Response<Body> {
         Response::builder()
             .status(StatusCode::NOT_FOUND)
@@ -252,6 +285,10 @@ where
         .get("/api/v2/read", read_handler::<M>)
         .put("/iox/api/v1/databases/:name", create_database_handler::<M>)
         .get("/iox/api/v1/databases/:name", get_database_handler::<M>)
+        .get(
+            "/iox/api/v1/databases/:name/replication_queue",
+            get_replication_queue_handler::<M>,
+        )
         .put("/iox/api/v1/id", set_writer_handler::<M>)
         .get("/api/v1/partitions", list_partitions_handler::<M>)
         .post("/api/v1/snapshot", snapshot_partition_handler::<M>)
@@ -385,14 +422,27 @@ where
         write_info.bucket
     );
 
-    server
-        .write_lines(&db_name, &lines)
-        .await
-        .map_err(|e| Box::new(e) as _)
-        .context(WritingPoints {
-            org: write_info.org.clone(),
-            bucket_name: write_info.bucket.clone(),
-        })?;
+    match server.write_lines(&db_name, &lines).await {
+        // The write can be retried once the database has freed some memory
+        Err(e @ server::Error::HardLimitReached { .. })
+        | Err(e @ server::Error::ServerMemoryLimitReached { .. }) => {
+            return Err(e).context(MemoryLimitExceeded);
+        }
+        // The write was applied, and is queued for the host groups that missed
+        // it, so it is accepted rather than failed, which would be retried
+        Err(e @ server::Error::ReplicationPending { .. }) => {
+            return Ok(Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .body(Body::from(e.to_string()))
+                .unwrap());
+        }
+        result => result
+            .map_err(|e| Box::new(e) as _)
+            .context(WritingPoints {
+                org: write_info.org.clone(),
+                bucket_name: write_info.bucket.clone(),
+            })?,
+    }
 
     Ok(Response::builder()
         .status(StatusCode::NO_CONTENT)
@@ -408,6 +458,14 @@ struct ReadInfo {
     // TODO This is currently a "SQL" request -- should be updated to conform
     // to the V2 API for reading (using timestamps, etc).
     sql_query: String,
+    /// Only rows with times (in nanoseconds since the epoch) at or
+    /// after `start` are read
+    start: Option<i64>,
+    /// Only rows with times (in nanoseconds since the epoch) before
+    /// `stop` are read
+    stop: Option<i64>,
+    /// The maximum number of rows to return
+    limit: Option<usize>,
 }
 
 #[tracing::instrument(level = "debug")]
@@ -425,8 +483,9 @@ where
     }
 }
 
-// TODO: figure out how to stream read results out rather than rendering the
-// whole thing in mem
+/// Runs a SQL query and streams the results back as the batches are
+/// produced, in the format requested by the `Accept` header (see
+/// `QueryOutputFormat`).
 #[tracing::instrument(level = "debug")]
 async fn read<M: ConnectionManager + Send + Sync + Debug + 'static>(
     req: Request<Body>,
@@ -441,7 +500,22 @@ async fn read<M: ConnectionManager + Send + Sync + Debug + 'static>(
         query_string: query,
     })?;
 
-    let planner = SQLQueryPlanner::default();
+    let header_name = ACCEPT;
+    let accept = match req.headers().get(&header_name) {
+        Some(accept) => Some(accept.to_str().context(ReadingHeaderAsUtf8 {
+            header_name: header_name.as_str(),
+        })?),
+        None => None,
+    };
+    let format = QueryOutputFormat::from_accept(accept).context(NotAcceptable)?;
+
+    let mut planner = SQLQueryPlanner::default();
+    if read_info.start.is_some() || read_info.stop.is_some() {
+        planner = planner.with_timestamp_range(TimestampRange::new(
+            read_info.start.unwrap_or(i64::MIN),
+            read_info.stop.unwrap_or(i64::MAX),
+        ));
+    }
     let executor = server.executor();
 
     let db_name = org_and_bucket_to_database(&read_info.org, &read_info.bucket)
@@ -457,14 +531,103 @@ async fn read<M: ConnectionManager + Send + Sync + Debug + 'static>(
         .await
         .context(PlanningSQLQuery { query })?;
 
-    let batches = collect(physical_plan)
+    let encoder = format
+        .encoder(physical_plan.schema())
+        .context(EncodingResults)?;
+    let results = executor
+        .new_context()
+        .execute(physical_plan)
         .await
         .map_err(|e| Box::new(e) as _)
         .context(Query { db_name })?;
 
-    let results = arrow::util::pretty::pretty_format_batches(&batches).unwrap();
+    let (sender, body) = Body::channel();
+    tokio::task::spawn(stream_results(
+        results,
+        encoder,
+        format,
+        read_info.limit,
+        sender,
+    ));
 
-    Ok(Response::new(Body::from(results.into_bytes())))
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, format.content_type())
+        .body(body)
+        .unwrap())
+}
+
+/// Encodes the batches of `results` as they are produced, sending them
+/// to `sender`, until `limit` rows have been sent.
+///
+/// The status of the response has already been sent, so errors are
+/// reported by ending the body with an error line in the text formats,
+/// and by aborting the body in the others.
+async fn stream_results(
+    mut results: SendableRecordBatchStream,
+    mut encoder: Box<dyn BatchEncoder>,
+    format: QueryOutputFormat,
+    limit: Option<usize>,
+    mut sender: hyper::body::Sender,
+) {
+    let mut remaining = limit.unwrap_or(usize::MAX);
+    while remaining > 0 {
+        let batch = match results.next().await {
+            Some(Ok(batch)) => truncate_batch(batch, remaining),
+            Some(Err(e)) => return send_error(sender, format, e.to_string()).await,
+            None => break,
+        };
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(e) => return send_error(sender, format, e.to_string()).await,
+        };
+        remaining -= batch.num_rows();
+
+        let bytes = match encoder.encode(&batch) {
+            Ok(bytes) => bytes,
+            Err(e) => return send_error(sender, format, e.to_string()).await,
+        };
+        if !bytes.is_empty() && sender.send_data(bytes.into()).await.is_err() {
+            // the client went away, so stop running the query
+            return;
+        }
+    }
+
+    match encoder.finish() {
+        Ok(bytes) => {
+            sender.send_data(bytes.into()).await.ok();
+        }
+        Err(e) => send_error(sender, format, e.to_string()).await,
+    }
+}
+
+/// Returns the first `num_rows` rows of `batch`
+fn truncate_batch(
+    batch: RecordBatch,
+    num_rows: usize,
+) -> Result<RecordBatch, arrow_deps::arrow::error::ArrowError> {
+    if batch.num_rows() <= num_rows {
+        return Ok(batch);
+    }
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| column.slice(0, num_rows))
+        .collect();
+    RecordBatch::try_new(batch.schema(), columns)
+}
+
+/// Reports an error that happened after the response was started
+async fn send_error(mut sender: hyper::body::Sender, format: QueryOutputFormat, message: String) {
+    error!(error_message = %message, "Error while streaming query results");
+
+    if format.is_text() {
+        sender
+            .send_data(format.error_line(&message).into())
+            .await
+            .ok();
+    } else {
+        sender.abort();
+    }
 }
 
 #[tracing::instrument(level = "debug")]
@@ -553,6 +716,53 @@ async fn get_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
     Ok(response)
 }
 
+#[tracing::instrument(level = "debug")]
+async fn get_replication_queue_handler<M>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match get_replication_queue::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_replication_queue<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name_str = req
+        .param("name")
+        .expect("db name must have been set")
+        .clone();
+    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
+    let status = server
+        .replication_queue_status(&db_name)
+        .await
+        .context(DatabaseNotFound { name: &db_name_str })?;
+
+    let data = serde_json::to_string(&status).context(JsonGenerationError)?;
+    let response = Response::builder()
+        .header("Content-Type", "application/json")
+        .status(StatusCode::OK)
+        .body(Body::from(data))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
 #[tracing::instrument(level = "debug")]
 async fn set_writer_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
 where
@@ -723,12 +933,14 @@ async fn snapshot_partition<M: ConnectionManager + Send + Sync + Debug + 'static
 
     let partition_key = &snapshot.partition;
     let chunk = db.rollover_partition(partition_key).await.unwrap();
+    let writer_sequences = chunk.writer_sequences();
     let snapshot = server::snapshot::snapshot_chunk(
         metadata_path,
         data_path,
         server.store.clone(),
         partition_key,
         chunk,
+        writer_sequences,
         None,
     )
     .unwrap();
@@ -749,14 +961,14 @@ mod tests {
     use super::*;
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
-    use arrow_deps::{arrow::record_batch::RecordBatch, assert_table_eq};
+    use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
     use http::header;
     use query::exec::Executor;
     use reqwest::{Client, Response};
 
     use hyper::Server;
 
-    use data_types::database_rules::DatabaseRules;
+    use data_types::database_rules::{DatabaseRules, LifecycleRules};
     use data_types::DatabaseName;
     use object_store::{memory::InMemory, ObjectStore};
     use server::{db::Db, ConnectionManagerImpl};
@@ -767,7 +979,7 @@ mod tests {
     #[tokio::test]
     async fn test_ping() -> Result<()> {
         let test_storage = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::new(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         let server_url = test_server(test_storage.clone());
@@ -783,7 +995,7 @@ mod tests {
     #[tokio::test]
     async fn test_write() -> Result<()> {
         let test_storage = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::new(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         test_storage.set_id(1);
@@ -834,6 +1046,175 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_read_formats() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        test_storage
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body("cpu,host=a value=1 10\ncpu,host=b value=2 20\ncpu,host=c value=3 30")
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let read_url = format!("{}/api/v2/read", server_url);
+        let read = |accept: &str, params: &[(&str, &str)]| {
+            let mut query = vec![
+                ("org", "MyOrg"),
+                ("bucket", "MyBucket"),
+                ("sql_query", "select host, value from cpu"),
+            ];
+            query.extend_from_slice(params);
+            client
+                .get(&read_url)
+                .header(header::ACCEPT, accept)
+                .query(&query)
+                .send()
+        };
+
+        check_response(
+            "csv",
+            read("text/csv", &[]).await,
+            StatusCode::OK,
+            "host,value\na,1.0\nb,2.0\nc,3.0\n",
+        )
+        .await;
+
+        check_response(
+            "csv with time range",
+            read("text/csv", &[("start", "15"), ("stop", "30")]).await,
+            StatusCode::OK,
+            "host,value\nb,2.0\n",
+        )
+        .await;
+
+        check_response(
+            "json with limit",
+            read("application/x-ndjson", &[("limit", "2")]).await,
+            StatusCode::OK,
+            "{\"host\":\"a\",\"value\":1.0}\n{\"host\":\"b\",\"value\":2.0}\n",
+        )
+        .await;
+
+        let response = read("image/png", &[]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_over_memory_limit() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            lifecycle_rules: LifecycleRules {
+                buffer_size_hard: std::num::NonZeroUsize::new(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        test_storage
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let write_url = format!(
+            "{}/api/v2/write?bucket={}&org={}",
+            server_url, "MyBucket", "MyOrg"
+        );
+
+        let response = client.post(&write_url).body("cpu bar=1 10").send().await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // the first write took the database over its hard limit
+        let response = client
+            .post(&write_url)
+            .body("cpu bar=2 20")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        let body = response.text().await.unwrap();
+        assert!(body.contains("over its hard limit"), "{}", body);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_pending_replication() -> Result<()> {
+        let mut test_storage = AppServer::new(
+            ConnectionManagerImpl::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        );
+        test_storage.set_id(1);
+        test_storage
+            .create_host_group("az1".to_string(), vec!["not an address".to_string()])
+            .await
+            .unwrap();
+        let rules = DatabaseRules {
+            store_locally: true,
+            replication: vec!["az1".to_string()],
+            replication_count: 1,
+            replication_queue_max_size: 10,
+            ..Default::default()
+        };
+        test_storage
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let test_storage = Arc::new(test_storage);
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket={}&org={}",
+                server_url, "MyBucket", "MyOrg"
+            ))
+            .body("cpu bar=1 10")
+            .send()
+            .await
+            .unwrap();
+
+        // the write was applied and queued for the unreachable host group
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = response.text().await.unwrap();
+        assert!(body.contains("shouldn't be retried"), "{}", body);
+
+        let db_name = DatabaseName::new("MyOrg_MyBucket").unwrap();
+        let status = test_storage
+            .replication_queue_status(&db_name)
+            .await
+            .unwrap();
+        assert_eq!(status.host_groups.get("az1"), Some(&1));
+
+        Ok(())
+    }
+
     fn gzip_str(s: &str) -> Vec<u8> {
         use flate2::{write::GzEncoder, Compression};
         use std::io::Write;
@@ -845,7 +1226,7 @@ mod tests {
     #[tokio::test]
     async fn test_gzip_write() -> Result<()> {
         let test_storage = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::new(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         test_storage.set_id(1);
@@ -900,7 +1281,7 @@ mod tests {
     #[tokio::test]
     async fn set_writer_id() {
         let server = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::new(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         server.set_id(1);
@@ -923,7 +1304,7 @@ mod tests {
     #[tokio::test]
     async fn create_database() {
         let server = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::new(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         server.set_id(1);
@@ -953,7 +1334,7 @@ mod tests {
     #[tokio::test]
     async fn get_database() {
         let server = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::new(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         server.set_id(1);
@@ -981,6 +1362,41 @@ mod tests {
         check_response("create_database", response, StatusCode::OK, &data).await;
     }
 
+    #[tokio::test]
+    async fn get_replication_queue() {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        let server_url = test_server(server.clone());
+
+        let database_name = "foo_bar";
+        let rules = DatabaseRules {
+            name: database_name.to_owned(),
+            replication_queue_max_size: 10,
+            ..Default::default()
+        };
+        server.create_database(database_name, rules).await.unwrap();
+
+        let client = Client::new();
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/{}/replication_queue",
+                server_url, database_name
+            ))
+            .send()
+            .await;
+
+        check_response(
+            "get_replication_queue",
+            response,
+            StatusCode::OK,
+            r#"{"len":0,"max_size":10,"host_groups":{},"dropped":0}"#,
+        )
+        .await;
+    }
+
     /// checks a http response against expected results
     async fn check_response(
         description: &str,
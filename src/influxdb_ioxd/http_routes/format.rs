@@ -0,0 +1,504 @@
+//! This module contains the encoders for the output formats of the
+//! /read endpoint, which are chosen by the `Accept` header of the
+//! request
+
+use std::{
+    io::{self, Seek, SeekFrom, Write},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use arrow_deps::{
+    arrow::{
+        self, csv::WriterBuilder, datatypes::SchemaRef, ipc::writer::StreamWriter,
+        json::writer::record_batches_to_json_rows, record_batch::RecordBatch,
+    },
+    parquet::{arrow::ArrowWriter, errors::ParquetError, file::writer::TryClone},
+};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("None of the requested formats '{}' is supported", accept))]
+    UnsupportedFormat { accept: String },
+
+    #[snafu(display("Error encoding results as {}: {}", format, source))]
+    EncodingArrow {
+        format: &'static str,
+        source: arrow::error::ArrowError,
+    },
+
+    #[snafu(display("Error encoding results as JSON: {}", source))]
+    EncodingJson { source: serde_json::Error },
+
+    #[snafu(display("Error encoding results as Parquet: {}", source))]
+    EncodingParquet { source: ParquetError },
+
+    #[snafu(display("Error encoding results as Parquet: the writer stopped unexpectedly"))]
+    ParquetWriterStopped,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The formats that query results can be returned in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryOutputFormat {
+    /// A table of the results, as printed by the CLI. The widths of the
+    /// columns depend on every value, so nothing is sent until all the
+    /// results are received: use a `limit` with large results.
+    Pretty,
+    /// Comma separated values, with a header line (the default)
+    Csv,
+    /// A JSON object per row, one per line
+    Json,
+    /// An Arrow IPC stream
+    ArrowIpc,
+    /// A Parquet file
+    Parquet,
+}
+
+impl QueryOutputFormat {
+    /// Returns the first format of the (comma separated) media ranges
+    /// of an `Accept` header that is supported. Media type parameters
+    /// (including quality values) are ignored, and no header (or
+    /// `*/*`) means `Csv`, as it can be sent as the results are produced.
+    pub fn from_accept(accept: Option<&str>) -> Result<Self> {
+        let accept = match accept {
+            Some(accept) => accept,
+            None => return Ok(Self::Csv),
+        };
+
+        accept
+            .split(',')
+            .filter_map(|media_range| {
+                let media_type = media_range.split(';').next().unwrap_or_default().trim();
+                match media_type {
+                    "text/plain" => Some(Self::Pretty),
+                    "*/*" | "text/*" | "text/csv" => Some(Self::Csv),
+                    "application/x-ndjson" => Some(Self::Json),
+                    "application/vnd.apache.arrow.stream" => Some(Self::ArrowIpc),
+                    "application/vnd.apache.parquet" | "application/x-parquet" => {
+                        Some(Self::Parquet)
+                    }
+                    _ => None,
+                }
+            })
+            .next()
+            .ok_or_else(|| Error::UnsupportedFormat {
+                accept: accept.to_string(),
+            })
+    }
+
+    /// The value of the `Content-Type` header of results in this format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Pretty => "text/plain",
+            Self::Csv => "text/csv",
+            Self::Json => "application/x-ndjson",
+            Self::ArrowIpc => "application/vnd.apache.arrow.stream",
+            Self::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    /// Returns true if errors can be reported within results in this
+    /// format, as a line of text
+    pub fn is_text(&self) -> bool {
+        matches!(self, Self::Pretty | Self::Csv | Self::Json)
+    }
+
+    /// Formats `message` as an error line for results in this (text)
+    /// format
+    pub fn error_line(&self, message: &str) -> Vec<u8> {
+        let line = match self {
+            Self::Json => serde_json::json!({ "error": message }).to_string(),
+            _ => format!("Error: {}", message),
+        };
+        format!("{}\n", line).into_bytes()
+    }
+
+    /// Creates an encoder for results with schema `schema` in this format
+    pub fn encoder(&self, schema: SchemaRef) -> Result<Box<dyn BatchEncoder>> {
+        Ok(match self {
+            Self::Pretty => Box::new(PrettyEncoder::default()),
+            Self::Csv => Box::new(CsvEncoder::default()),
+            Self::Json => Box::new(JsonEncoder {}),
+            Self::ArrowIpc => {
+                let buffer = SharedBuffer::default();
+                let writer =
+                    StreamWriter::try_new(buffer.clone(), &schema).context(EncodingArrow {
+                        format: "Arrow IPC",
+                    })?;
+                Box::new(ArrowIpcEncoder { buffer, writer })
+            }
+            Self::Parquet => Box::new(ParquetEncoder::new(schema)),
+        })
+    }
+}
+
+/// Encodes a stream of record batches, returning the bytes to send to
+/// the client as each batch is encoded
+pub trait BatchEncoder: Send {
+    /// Encodes `batch`, returning the bytes that are ready to be sent
+    fn encode(&mut self, batch: &RecordBatch) -> Result<Vec<u8>>;
+
+    /// Returns the remaining bytes, once all the batches are encoded
+    fn finish(self: Box<Self>) -> Result<Vec<u8>>;
+}
+
+/// The pretty table needs the widths of all the values of a column,
+/// so it can only be produced once all the batches are received
+#[derive(Debug, Default)]
+struct PrettyEncoder {
+    batches: Vec<RecordBatch>,
+}
+
+impl BatchEncoder for PrettyEncoder {
+    fn encode(&mut self, batch: &RecordBatch) -> Result<Vec<u8>> {
+        self.batches.push(batch.clone());
+        Ok(vec![])
+    }
+
+    fn finish(self: Box<Self>) -> Result<Vec<u8>> {
+        let table = arrow::util::pretty::pretty_format_batches(&self.batches)
+            .context(EncodingArrow { format: "a table" })?;
+        Ok(table.into_bytes())
+    }
+}
+
+#[derive(Debug, Default)]
+struct CsvEncoder {
+    wrote_header: bool,
+}
+
+impl BatchEncoder for CsvEncoder {
+    fn encode(&mut self, batch: &RecordBatch) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        {
+            // the writer flushes to `bytes` when it is dropped
+            let mut writer = WriterBuilder::new()
+                .has_headers(!self.wrote_header)
+                .build(&mut bytes);
+            writer
+                .write(batch)
+                .context(EncodingArrow { format: "CSV" })?;
+        }
+        self.wrote_header = true;
+        Ok(bytes)
+    }
+
+    fn finish(self: Box<Self>) -> Result<Vec<u8>> {
+        Ok(vec![])
+    }
+}
+
+#[derive(Debug)]
+struct JsonEncoder {}
+
+impl BatchEncoder for JsonEncoder {
+    fn encode(&mut self, batch: &RecordBatch) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        for row in record_batches_to_json_rows(&[batch.clone()]) {
+            serde_json::to_writer(&mut bytes, &row).context(EncodingJson)?;
+            bytes.push(b'\n');
+        }
+        Ok(bytes)
+    }
+
+    fn finish(self: Box<Self>) -> Result<Vec<u8>> {
+        Ok(vec![])
+    }
+}
+
+struct ArrowIpcEncoder {
+    buffer: SharedBuffer,
+    writer: StreamWriter<SharedBuffer>,
+}
+
+impl BatchEncoder for ArrowIpcEncoder {
+    fn encode(&mut self, batch: &RecordBatch) -> Result<Vec<u8>> {
+        self.writer.write(batch).context(EncodingArrow {
+            format: "Arrow IPC",
+        })?;
+        Ok(self.buffer.take())
+    }
+
+    fn finish(self: Box<Self>) -> Result<Vec<u8>> {
+        let Self { buffer, mut writer } = *self;
+        writer.finish().context(EncodingArrow {
+            format: "Arrow IPC",
+        })?;
+        // dropping the writer flushes anything it has buffered
+        drop(writer);
+        Ok(buffer.take())
+    }
+}
+
+/// Writes a row group per batch as the batches are received, and the
+/// footer of the file once they all are.
+///
+/// The Parquet writer can't be sent between threads, so it runs on a
+/// thread of its own, and `encode` waits for each batch to be written.
+#[derive(Debug)]
+struct ParquetEncoder {
+    buffer: SharedBuffer,
+    batches: Sender<RecordBatch>,
+    written: Receiver<Result<(), ParquetError>>,
+}
+
+impl ParquetEncoder {
+    fn new(schema: SchemaRef) -> Self {
+        let buffer = SharedBuffer::default();
+        let (batches, batches_rx) = mpsc::channel::<RecordBatch>();
+        let (written_tx, written) = mpsc::channel();
+
+        let writer_buffer = buffer.clone();
+        std::thread::spawn(move || {
+            let mut writer = match ArrowWriter::try_new(writer_buffer, schema, None) {
+                Ok(writer) => writer,
+                Err(e) => {
+                    written_tx.send(Err(e)).ok();
+                    return;
+                }
+            };
+
+            // the batches stop once the encoder is finished or dropped
+            for batch in batches_rx {
+                let result = writer.write(&batch);
+                let failed = result.is_err();
+                if written_tx.send(result).is_err() || failed {
+                    return;
+                }
+            }
+            written_tx.send(writer.close()).ok();
+        });
+
+        Self {
+            buffer,
+            batches,
+            written,
+        }
+    }
+}
+
+impl BatchEncoder for ParquetEncoder {
+    fn encode(&mut self, batch: &RecordBatch) -> Result<Vec<u8>> {
+        // if the writer has already stopped, its error is returned below
+        self.batches.send(batch.clone()).ok();
+        wait_for_writer(&self.written)?;
+        Ok(self.buffer.take())
+    }
+
+    fn finish(self: Box<Self>) -> Result<Vec<u8>> {
+        let Self {
+            buffer,
+            batches,
+            written,
+        } = *self;
+
+        // the writer writes the footer once there are no more batches
+        drop(batches);
+        wait_for_writer(&written)?;
+        Ok(buffer.take())
+    }
+}
+
+/// Waits for the Parquet writer to finish writing a batch (or the file)
+fn wait_for_writer(written: &Receiver<Result<(), ParquetError>>) -> Result<()> {
+    match written.recv() {
+        Ok(result) => result.context(EncodingParquet),
+        // the writer only stops early after reporting an error, so this is
+        // only reached if it panicked
+        Err(_) => ParquetWriterStopped.fail(),
+    }
+}
+
+/// An in memory writer whose contents can be taken as they are
+/// written, so they can be sent while the writer is still in use.
+///
+/// The Parquet writer needs to `Seek`, but only to find the current
+/// position, which is the total number of bytes written (including
+/// the bytes that have been taken).
+#[derive(Debug, Default, Clone)]
+struct SharedBuffer {
+    inner: Arc<Mutex<SharedBufferInner>>,
+}
+
+#[derive(Debug, Default)]
+struct SharedBufferInner {
+    bytes: Vec<u8>,
+    position: u64,
+}
+
+impl SharedBuffer {
+    /// Removes and returns the bytes written since the last call
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.inner.lock().expect("mutex poisoned").bytes)
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().expect("mutex poisoned");
+        inner.bytes.extend_from_slice(buf);
+        inner.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SharedBuffer {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = self.inner.lock().expect("mutex poisoned").position;
+        match pos {
+            SeekFrom::Current(0) => Ok(position),
+            SeekFrom::Start(offset) if offset == position => Ok(position),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "SharedBuffer can only seek to the current position",
+            )),
+        }
+    }
+}
+
+impl TryClone for SharedBuffer {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{
+        array::{Int64Array, StringArray},
+        datatypes::{DataType, Field, Schema},
+        ipc::reader::StreamReader,
+    };
+    use arrow_deps::parquet::file::{
+        reader::FileReader,
+        serialized_reader::{SerializedFileReader, SliceableCursor},
+    };
+    use std::io::Cursor;
+
+    fn batch() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![Some("a"), None])),
+                Arc::new(Int64Array::from(vec![10, 20])),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn encode(format: QueryOutputFormat, batches: &[RecordBatch]) -> Vec<u8> {
+        let mut encoder = format.encoder(batches[0].schema()).unwrap();
+        let mut bytes = vec![];
+        for batch in batches {
+            bytes.extend(encoder.encode(batch).unwrap());
+        }
+        bytes.extend(encoder.finish().unwrap());
+        bytes
+    }
+
+    #[test]
+    fn from_accept() {
+        use QueryOutputFormat::*;
+
+        assert_eq!(QueryOutputFormat::from_accept(None).unwrap(), Csv);
+        assert_eq!(QueryOutputFormat::from_accept(Some("*/*")).unwrap(), Csv);
+        assert_eq!(
+            QueryOutputFormat::from_accept(Some("text/plain")).unwrap(),
+            Pretty
+        );
+        assert_eq!(
+            QueryOutputFormat::from_accept(Some("text/csv; charset=utf-8")).unwrap(),
+            Csv
+        );
+        assert_eq!(
+            QueryOutputFormat::from_accept(Some("image/png, application/x-ndjson;q=0.9")).unwrap(),
+            Json
+        );
+        assert_eq!(
+            QueryOutputFormat::from_accept(Some("application/vnd.apache.arrow.stream")).unwrap(),
+            ArrowIpc
+        );
+        assert_eq!(
+            QueryOutputFormat::from_accept(Some("application/x-parquet")).unwrap(),
+            Parquet
+        );
+
+        let err = QueryOutputFormat::from_accept(Some("image/png")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "None of the requested formats 'image/png' is supported"
+        );
+    }
+
+    #[test]
+    fn encode_csv_and_json() {
+        let batches = vec![batch(), batch()];
+
+        let csv = encode(QueryOutputFormat::Csv, &batches);
+        // the header is only written once
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "host,time\na,10\n,20\na,10\n,20\n"
+        );
+
+        let json = String::from_utf8(encode(QueryOutputFormat::Json, &batches[..1])).unwrap();
+        let lines: Vec<_> = json.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], r#"{"host":"a","time":10}"#);
+    }
+
+    #[test]
+    fn encode_arrow_ipc() {
+        let batches = vec![batch(), batch()];
+        let bytes = encode(QueryOutputFormat::ArrowIpc, &batches);
+
+        let reader = StreamReader::try_new(Cursor::new(bytes)).unwrap();
+        let num_rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(num_rows, 4);
+    }
+
+    #[test]
+    fn encode_parquet() {
+        let bytes = encode(QueryOutputFormat::Parquet, &[batch()]);
+
+        assert_eq!(&bytes[..4], b"PAR1");
+        assert_eq!(&bytes[bytes.len() - 4..], b"PAR1");
+    }
+
+    #[test]
+    fn encode_parquet_row_groups_as_batches_arrive() {
+        let mut encoder = QueryOutputFormat::Parquet
+            .encoder(batch().schema())
+            .unwrap();
+
+        // each batch is written as a row group before the next arrives
+        let first = encoder.encode(&batch()).unwrap();
+        assert_eq!(&first[..4], b"PAR1");
+        let second = encoder.encode(&batch()).unwrap();
+        assert!(!second.is_empty());
+
+        let footer = encoder.finish().unwrap();
+        assert_eq!(&footer[footer.len() - 4..], b"PAR1");
+
+        let mut bytes = first;
+        bytes.extend(second);
+        bytes.extend(footer);
+        let reader = SerializedFileReader::new(SliceableCursor::new(Arc::new(bytes))).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 4);
+    }
+}
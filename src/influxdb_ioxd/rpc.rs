@@ -12,4 +12,5 @@ pub mod data;
 pub mod expr;
 pub mod id;
 pub mod input;
+pub mod replication;
 pub mod service;
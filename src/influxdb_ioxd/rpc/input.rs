@@ -2,8 +2,8 @@ use tonic::Status;
 
 use generated_types::{
     MeasurementFieldsRequest, MeasurementNamesRequest, MeasurementTagKeysRequest,
-    MeasurementTagValuesRequest, ReadFilterRequest, ReadGroupRequest, ReadSource,
-    ReadWindowAggregateRequest, TagKeysRequest, TagValuesRequest,
+    MeasurementTagValuesRequest, ReadFilterRequest, ReadGroupRequest, ReadSeriesCardinalityRequest,
+    ReadSource, ReadWindowAggregateRequest, TagKeysRequest, TagValuesRequest,
 };
 
 use super::id::ID;
@@ -96,6 +96,12 @@ impl GrpcInputs for MeasurementFieldsRequest {
     }
 }
 
+impl GrpcInputs for ReadSeriesCardinalityRequest {
+    fn read_source_field(&self) -> Option<&prost_types::Any> {
+        self.read_series_cardinality_source.as_ref()
+    }
+}
+
 impl GrpcInputs for ReadWindowAggregateRequest {
     fn read_source_field(&self) -> Option<&prost_types::Any> {
         self.read_source.as_ref()
@@ -0,0 +1,511 @@
+//! This module contains the gRPC service IOx servers use to replicate writes
+//! to each other, implemented in terms of `server::Server`
+
+use std::{collections::BTreeMap, fmt::Debug};
+
+use data_types::{
+    data::ReplicatedWrite,
+    database_rules::{MatchTables, Matcher, WriterId},
+};
+use generated_types::replication::v1::{
+    replication_server::Replication, write_matcher, ReplicateRequest, ReplicateResponse,
+    SubscribeRequest, SubscribeResponse, WriteMatcher,
+};
+use query::DatabaseStore;
+use server::{
+    subscription::{Error as SubscriptionError, WriteSubscription},
+    ConnectionManager, Error as ServerError, Server as AppServer,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Status;
+use tracing::{error, info, warn};
+
+use super::service::GrpcService;
+
+/// Something that can apply writes replicated to it from other servers, and
+/// stream the writes applied to it to subscribers
+#[tonic::async_trait]
+pub trait ReplicatedWriteSink: Send + Sync {
+    /// Applies `write` to the database named `db_name`
+    async fn write_replicated(&self, db_name: &str, write: ReplicatedWrite) -> Result<(), Status>;
+
+    /// Subscribes to the writes to the database named `db_name` that match
+    /// `matcher`, starting after the watermark `since`
+    fn subscribe(
+        &self,
+        db_name: &str,
+        since: BTreeMap<WriterId, u64>,
+        matcher: Matcher,
+    ) -> Result<WriteSubscription, Status>;
+}
+
+#[tonic::async_trait]
+impl<M> ReplicatedWriteSink for AppServer<M>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    async fn write_replicated(&self, db_name: &str, write: ReplicatedWrite) -> Result<(), Status> {
+        let result = self.write_replicated(db_name, write).await;
+
+        // the write was applied, and is queued for the host groups this server
+        // replicates to that missed it, so the sender mustn't send it elsewhere
+        if let Err(e @ ServerError::ReplicationPending { .. }) = &result {
+            warn!(error_message = ?e.to_string(), "Replicated write pending replication");
+            return Ok(());
+        }
+
+        result.map_err(|e| {
+            error!(error = ?e, error_message = ?e.to_string(), "Error applying replicated write");
+
+            match e {
+                ServerError::DatabaseNotFound { .. } => Status::not_found(e.to_string()),
+                ServerError::InvalidDatabaseName { .. } => Status::invalid_argument(e.to_string()),
+                ServerError::InvalidReplicatedWrite { .. } => {
+                    Status::invalid_argument(e.to_string())
+                }
+                ServerError::IdNotSet => Status::failed_precondition(e.to_string()),
+                ServerError::ReplicationQueueError { .. } => {
+                    Status::resource_exhausted(e.to_string())
+                }
+                // the sender retries these, as memory is freed as the
+                // database persists its data
+                ServerError::HardLimitReached { .. }
+                | ServerError::ServerMemoryLimitReached { .. } => {
+                    Status::unavailable(e.to_string())
+                }
+                _ => Status::internal(e.to_string()),
+            }
+        })
+    }
+
+    fn subscribe(
+        &self,
+        db_name: &str,
+        since: BTreeMap<WriterId, u64>,
+        matcher: Matcher,
+    ) -> Result<WriteSubscription, Status> {
+        self.subscribe(db_name, since, matcher).map_err(|e| {
+            error!(error = ?e, error_message = ?e.to_string(), "Error subscribing to writes");
+
+            match e {
+                ServerError::DatabaseNotFound { .. } => Status::not_found(e.to_string()),
+                ServerError::InvalidDatabaseName { .. } => Status::invalid_argument(e.to_string()),
+                ServerError::Subscribing { source } => subscription_error_to_status(source),
+                _ => Status::internal(e.to_string()),
+            }
+        })
+    }
+}
+
+fn subscription_error_to_status(e: SubscriptionError) -> Status {
+    match e {
+        SubscriptionError::NoWalBuffer { .. } => Status::failed_precondition(e.to_string()),
+        SubscriptionError::TooFarBehind { .. } => Status::out_of_range(e.to_string()),
+        SubscriptionError::InvalidMatcher { .. } => Status::invalid_argument(e.to_string()),
+    }
+}
+
+/// Converts the `WriteMatcher` of a subscribe request to a `Matcher`; an
+/// unset matcher matches every write
+fn matcher_from_proto(matcher: Option<WriteMatcher>) -> Matcher {
+    let WriteMatcher { tables, predicate } = matcher.unwrap_or_default();
+
+    let tables = match tables {
+        None => MatchTables::All,
+        Some(write_matcher::Tables::Table(table)) => MatchTables::Table(table),
+        Some(write_matcher::Tables::Regex(regex)) => MatchTables::Regex(regex),
+    };
+    let predicate = if predicate.is_empty() {
+        None
+    } else {
+        Some(predicate)
+    };
+
+    Matcher { tables, predicate }
+}
+
+#[tonic::async_trait]
+/// Implements the protobuf defined replication service for a store that
+/// accepts replicated writes
+impl<T> Replication for GrpcService<T>
+where
+    T: DatabaseStore + ReplicatedWriteSink + 'static,
+{
+    async fn replicate(
+        &self,
+        req: tonic::Request<ReplicateRequest>,
+    ) -> Result<tonic::Response<ReplicateResponse>, Status> {
+        let ReplicateRequest {
+            db_name,
+            replicated_write,
+        } = req.into_inner();
+
+        self.db_store
+            .write_replicated(
+                &db_name,
+                ReplicatedWrite {
+                    data: replicated_write,
+                },
+            )
+            .await?;
+
+        Ok(tonic::Response::new(ReplicateResponse {}))
+    }
+
+    type SubscribeStream = ReceiverStream<Result<SubscribeResponse, Status>>;
+
+    async fn subscribe(
+        &self,
+        req: tonic::Request<SubscribeRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeStream>, Status> {
+        let SubscribeRequest {
+            db_name,
+            writer_sequences,
+            matcher,
+        } = req.into_inner();
+
+        info!(
+            "subscribe for database {}, writer sequences: {:?}",
+            db_name, writer_sequences
+        );
+
+        let since = writer_sequences.into_iter().collect();
+        let mut subscription =
+            self.db_store
+                .subscribe(&db_name, since, matcher_from_proto(matcher))?;
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            loop {
+                let response = tokio::select! {
+                    result = subscription.next() => result
+                        .map(|write| SubscribeResponse {
+                            replicated_write: write.data,
+                        })
+                        .map_err(subscription_error_to_status),
+                    // stop waiting for writes once the subscriber goes away
+                    _ = tx.closed() => return,
+                };
+
+                let failed = response.is_err();
+                if tx.send(response).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::service::make_server;
+    use super::*;
+    use data_types::{
+        data::lines_to_replicated_write,
+        database_rules::{
+            DatabaseRules, LifecycleRules, PartitionTemplate, TemplatePart, WalBufferConfig,
+            WalBufferRollover,
+        },
+        DatabaseName,
+    };
+    use generated_types::replication::v1::replication_client::ReplicationClient;
+    use influxdb_line_protocol::parse_lines;
+    use object_store::{memory::InMemory, ObjectStore};
+    use query::Database;
+    use server::ConnectionManagerImpl;
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        sync::Arc,
+    };
+    use tonic::Code;
+
+    type AppServerImpl = AppServer<ConnectionManagerImpl>;
+
+    fn app_server(id: u32) -> AppServerImpl {
+        let server = AppServer::new(
+            ConnectionManagerImpl::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        );
+        server.set_id(id);
+        server
+    }
+
+    /// Starts a gRPC endpoint for `server` on a random localhost port,
+    /// returning its address
+    async fn start_grpc(server: Arc<AppServerImpl>) -> String {
+        // Get a random port from the kernel by asking for port 0.
+        let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
+        let socket = tokio::net::TcpListener::bind(bind_addr).await.unwrap();
+        let addr = format!("http://{}", socket.local_addr().unwrap());
+
+        tokio::task::spawn(make_server(socket, server));
+
+        addr
+    }
+
+    fn rules(replication: Vec<String>) -> DatabaseRules {
+        DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Table],
+            },
+            store_locally: true,
+            replication,
+            replication_count: 1,
+            ..Default::default()
+        }
+    }
+
+    fn buffered_rules(buffer_size: u64, segment_size: u64) -> DatabaseRules {
+        DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size,
+                segment_size,
+                buffer_rollover: WalBufferRollover::DropOldSegment,
+                store_segments: false,
+                close_segment_after: None,
+            }),
+            ..rules(vec![])
+        }
+    }
+
+    async fn write_lp(server: &AppServerImpl, db_name: &str, lp: &str) {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        server.write_lines(db_name, &lines).await.unwrap();
+    }
+
+    fn subscribe_request(writer_sequences: Vec<(u32, u64)>) -> SubscribeRequest {
+        SubscribeRequest {
+            db_name: "foo".to_string(),
+            writer_sequences: writer_sequences.into_iter().collect(),
+            matcher: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn replicates_between_servers() {
+        let mut server_a = app_server(1);
+        let server_b = Arc::new(app_server(2));
+        let server_b_addr = start_grpc(server_b.clone()).await;
+
+        server_a
+            .create_host_group("az1".to_string(), vec![server_b_addr])
+            .await
+            .unwrap();
+        server_a
+            .create_database("foo", rules(vec!["az1".to_string()]))
+            .await
+            .unwrap();
+        server_b
+            .create_database("foo", rules(vec![]))
+            .await
+            .unwrap();
+
+        let lines: Vec<_> = parse_lines("cpu bar=1 10\nmem used=2 10")
+            .map(|l| l.unwrap())
+            .collect();
+        server_a.write_lines("foo", &lines).await.unwrap();
+
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = server_b.db(&db_name).await.unwrap();
+        let mut partition_keys = db.partition_keys().await.unwrap();
+        partition_keys.sort();
+        assert_eq!(partition_keys, vec!["cpu", "mem"]);
+
+        // the receiving server doesn't have database "bar", so the write isn't
+        // acknowledged
+        server_a
+            .create_database("bar", rules(vec!["az1".to_string()]))
+            .await
+            .unwrap();
+        let err = server_a.write_lines("bar", &lines).await.unwrap_err();
+        assert!(matches!(err, ServerError::ReplicationPending { .. }));
+    }
+
+    #[tokio::test]
+    async fn replicate_to_unknown_database() {
+        let addr = start_grpc(Arc::new(app_server(1))).await;
+
+        let mut client = ReplicationClient::connect(addr).await.unwrap();
+        let status = client
+            .replicate(ReplicateRequest {
+                db_name: "foo".to_string(),
+                replicated_write: vec![],
+            })
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn replicate_invalid_write() {
+        let server = Arc::new(app_server(1));
+        server.create_database("foo", rules(vec![])).await.unwrap();
+        let addr = start_grpc(server.clone()).await;
+
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(2, 1, &lines, &rules(vec![])).unwrap();
+        // flips a byte of the payload, which no longer matches the checksum
+        let payload = write.to_fb().payload().unwrap();
+        let offset = payload.as_ptr() as usize - write.data.as_ptr() as usize;
+        let mut corrupt = write.data.clone();
+        corrupt[offset + payload.len() / 2] ^= 0xff;
+
+        let mut client = ReplicationClient::connect(addr).await.unwrap();
+        for replicated_write in vec![vec![], vec![1, 2, 3], write.data[1..].to_vec(), corrupt] {
+            let status = client
+                .replicate(ReplicateRequest {
+                    db_name: "foo".to_string(),
+                    replicated_write,
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+
+        let db = server.db(&DatabaseName::new("foo").unwrap()).await.unwrap();
+        assert!(db.partition_keys().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replicate_over_memory_limit() {
+        let server = Arc::new(app_server(1));
+        let rules = DatabaseRules {
+            lifecycle_rules: LifecycleRules {
+                buffer_size_hard: std::num::NonZeroUsize::new(1),
+                ..Default::default()
+            },
+            ..rules(vec![])
+        };
+        server.create_database("foo", rules.clone()).await.unwrap();
+        let addr = start_grpc(server.clone()).await;
+
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        let request = |sequence| ReplicateRequest {
+            db_name: "foo".to_string(),
+            replicated_write: lines_to_replicated_write(2, sequence, &lines, &rules)
+                .unwrap()
+                .data,
+        };
+
+        let mut client = ReplicationClient::connect(addr).await.unwrap();
+        client.replicate(request(1)).await.unwrap();
+
+        // the first write took the database over its hard limit, so the next
+        // is rejected as unavailable, which the sender retries
+        let status = client.replicate(request(2)).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn subscribe_streams_buffered_then_live_writes() {
+        let server = Arc::new(app_server(1));
+        server
+            .create_database("foo", buffered_rules(1 << 20, 1 << 20))
+            .await
+            .unwrap();
+        let addr = start_grpc(server.clone()).await;
+
+        write_lp(&server, "foo", "cpu bar=1 10").await;
+        write_lp(&server, "foo", "mem used=2 10").await;
+
+        let mut client = ReplicationClient::connect(addr).await.unwrap();
+        let request = SubscribeRequest {
+            matcher: Some(WriteMatcher {
+                tables: Some(write_matcher::Tables::Regex("^(cpu|disk)$".to_string())),
+                predicate: String::new(),
+            }),
+            ..subscribe_request(vec![(1, 0)])
+        };
+        let mut stream = client.subscribe(request).await.unwrap().into_inner();
+
+        let response = stream.message().await.unwrap().unwrap();
+        let write = ReplicatedWrite {
+            data: response.replicated_write,
+        };
+        assert_eq!(write.writer_and_sequence(), (1, 1));
+
+        // the write to mem doesn't match, the live write to disk does
+        write_lp(&server, "foo", "disk free=3 20").await;
+
+        let response = stream.message().await.unwrap().unwrap();
+        let write = ReplicatedWrite {
+            data: response.replicated_write,
+        };
+        assert_eq!(write.writer_and_sequence(), (1, 3));
+        let write = write.to_string();
+        assert!(write.contains("table:disk"), "{}", write);
+    }
+
+    #[tokio::test]
+    async fn subscribe_too_far_behind() {
+        let server = Arc::new(app_server(1));
+        server
+            .create_database("foo", buffered_rules(600, 1))
+            .await
+            .unwrap();
+        let addr = start_grpc(server.clone()).await;
+
+        // every write closes a segment, and the buffer only holds two of them
+        for _ in 0..3 {
+            write_lp(&server, "foo", "cpu val=1 10").await;
+        }
+
+        let mut client = ReplicationClient::connect(addr).await.unwrap();
+        let status = client
+            .subscribe(subscribe_request(vec![]))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::OutOfRange);
+
+        // a subscriber that has seen the dropped writes can still catch up
+        let mut stream = client
+            .subscribe(subscribe_request(vec![(1, 2)]))
+            .await
+            .unwrap()
+            .into_inner();
+        let response = stream.message().await.unwrap().unwrap();
+        let write = ReplicatedWrite {
+            data: response.replicated_write,
+        };
+        assert_eq!(write.writer_and_sequence(), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn subscribe_without_wal_buffer() {
+        let server = Arc::new(app_server(1));
+        server.create_database("foo", rules(vec![])).await.unwrap();
+        let addr = start_grpc(server).await;
+
+        let mut client = ReplicationClient::connect(addr).await.unwrap();
+        let status = client
+            .subscribe(subscribe_request(vec![]))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn subscribe_with_invalid_matcher() {
+        let server = Arc::new(app_server(1));
+        server
+            .create_database("foo", buffered_rules(1 << 20, 1 << 20))
+            .await
+            .unwrap();
+        let addr = start_grpc(server).await;
+
+        let mut client = ReplicationClient::connect(addr).await.unwrap();
+        let request = SubscribeRequest {
+            matcher: Some(WriteMatcher {
+                tables: Some(write_matcher::Tables::Regex("(".to_string())),
+                predicate: String::new(),
+            }),
+            ..subscribe_request(vec![])
+        };
+        let status = client.subscribe(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+}
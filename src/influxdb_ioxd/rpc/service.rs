@@ -2,10 +2,14 @@
 //! implemented in terms of the `query::Database` and
 //! `query::DatabaseStore`
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use generated_types::{
     i_ox_testing_server::{IOxTesting, IOxTestingServer},
+    replication::v1::replication_server::ReplicationServer,
     storage_server::{Storage, StorageServer},
     CapabilitiesResponse, Capability, Int64ValuesResponse, MeasurementFieldsRequest,
     MeasurementFieldsResponse, MeasurementNamesRequest, MeasurementTagKeysRequest,
@@ -14,6 +18,13 @@ use generated_types::{
     TagValuesRequest, TestErrorRequest, TestErrorResponse, TimestampRange,
 };
 
+use arrow_deps::{
+    arrow::array::{Array, StringArray},
+    datafusion::{
+        error::DataFusionError,
+        logical_plan::{col, LogicalPlan, LogicalPlanBuilder},
+    },
+};
 use data_types::error::ErrorLogger;
 
 use query::group_by::GroupByAndAggregate;
@@ -22,12 +33,16 @@ use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
 
 use super::expr::{self, AddRPCNode, Loggable, SpecialTagKeys};
 use super::input::GrpcInputs;
+use super::replication::ReplicatedWriteSink;
 use data_types::names::org_and_bucket_to_database;
 
-use data_types::DatabaseName;
+use data_types::{DatabaseName, MEASUREMENT_COLUMN_NAME};
 
 use query::{
-    exec::seriesset::{Error as SeriesSetError, SeriesSetItem},
+    exec::{
+        seriesset::{Error as SeriesSetError, SeriesSetItem},
+        Executor, SeriesSetPlan,
+    },
     predicate::PredicateBuilder,
     Database, DatabaseStore,
 };
@@ -140,6 +155,9 @@ pub enum Error {
     #[snafu(display("Error computing series: {}", source))]
     ComputingSeriesSet { source: SeriesSetError },
 
+    #[snafu(display("Tag columns of table '{}' are not strings", table_name))]
+    NonStringTagColumn { table_name: String },
+
     #[snafu(display("Error converting tag_key to UTF-8 in tag_values request, tag_key value '{}': {}", String::from_utf8_lossy(source.as_bytes()), source))]
     ConvertingTagKeyInTagValues { source: std::string::FromUtf8Error },
 
@@ -204,6 +222,7 @@ impl Error {
             Self::ConvertingReadGroupType { .. } => Status::invalid_argument(self.to_string()),
             Self::ConvertingWindowAggregate { .. } => Status::invalid_argument(self.to_string()),
             Self::ComputingSeriesSet { .. } => Status::invalid_argument(self.to_string()),
+            Self::NonStringTagColumn { .. } => Status::internal(self.to_string()),
             Self::ConvertingTagKeyInTagValues { .. } => Status::invalid_argument(self.to_string()),
             Self::ComputingGroupedSeriesSet { .. } => Status::invalid_argument(self.to_string()),
             Self::ConvertingSeriesSet { .. } => Status::invalid_argument(self.to_string()),
@@ -217,7 +236,7 @@ impl Error {
 
 #[derive(Debug)]
 pub struct GrpcService<T: DatabaseStore> {
-    db_store: Arc<T>,
+    pub(crate) db_store: Arc<T>,
 }
 
 impl<T> GrpcService<T>
@@ -466,10 +485,19 @@ where
             );
 
             if predicate.is_some() {
-                unimplemented!("tag_value for a measurement, with general predicate");
+                // find the tables with rows matching the predicate
+                tag_values_impl(
+                    self.db_store.clone(),
+                    db_name,
+                    MEASUREMENT_COLUMN_NAME.to_string(),
+                    measurement,
+                    range,
+                    predicate,
+                )
+                .await
+            } else {
+                measurement_name_impl(self.db_store.clone(), db_name, range).await
             }
-
-            measurement_name_impl(self.db_store.clone(), db_name, range).await
         } else if tag_key.is_field() {
             info!(
                 "tag_values with tag_key=[xff] (field name) for database {}, range: {:?}, predicate: {} --> returning fields",
@@ -523,9 +551,37 @@ where
 
     async fn read_series_cardinality(
         &self,
-        _req: tonic::Request<ReadSeriesCardinalityRequest>,
+        req: tonic::Request<ReadSeriesCardinalityRequest>,
     ) -> Result<tonic::Response<Self::ReadSeriesCardinalityStream>, Status> {
-        unimplemented!("read_series_cardinality not yet implemented");
+        let (tx, rx) = mpsc::channel(4);
+
+        let read_series_cardinality_request = req.into_inner();
+
+        let db_name = get_database_name(&read_series_cardinality_request)?;
+
+        let ReadSeriesCardinalityRequest {
+            read_series_cardinality_source: _read_source,
+            range,
+            predicate,
+        } = read_series_cardinality_request;
+
+        info!(
+            "read_series_cardinality for database {}, range: {:?}, predicate: {}",
+            db_name,
+            range,
+            predicate.loggable()
+        );
+
+        let response =
+            read_series_cardinality_impl(self.db_store.clone(), db_name, range, predicate)
+                .await
+                .map_err(|e| e.to_status());
+
+        tx.send(response)
+            .await
+            .expect("sending read_series_cardinality response to server");
+
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
     }
 
     async fn capabilities(
@@ -549,6 +605,7 @@ where
                 ],
             ),
             ("Group", vec!["First", "Last", "Min", "Max"]),
+            ("ReadSeriesCardinality", vec![]),
         ];
 
         // Turn it into the HashMap -> Capabiltity
@@ -939,6 +996,26 @@ where
     Ok(StringValuesResponse { values })
 }
 
+/// Returns a plan for the distinct values of `tag_columns`, one column per
+/// tag, in the rows produced by the series set plan `plan`. If there are no
+/// tag columns, the plan produces at most one row of `plan`.
+fn distinct_tags_plan(
+    plan: &LogicalPlan,
+    tag_columns: &[Arc<String>],
+) -> Result<LogicalPlan, DataFusionError> {
+    let plan_builder = LogicalPlanBuilder::from(plan);
+
+    if tag_columns.is_empty() {
+        return plan_builder.limit(1)?.build();
+    }
+
+    let group_exprs = tag_columns
+        .iter()
+        .map(|tag_name| col(tag_name.as_str()))
+        .collect();
+    plan_builder.aggregate(group_exprs, vec![])?.build()
+}
+
 /// Launch async tasks that send the result of executing read_filter to `tx`
 async fn read_filter_impl<'a, T>(
     tx: mpsc::Sender<Result<ReadResponse, Status>>,
@@ -1000,6 +1077,110 @@ where
     Ok(())
 }
 
+/// Counts the distinct series (measurement and tag set) that have data
+/// matching the predicate
+async fn read_series_cardinality_impl<T>(
+    db_store: Arc<T>,
+    db_name: DatabaseName<'static>,
+    range: Option<TimestampRange>,
+    rpc_predicate: Option<Predicate>,
+) -> Result<Int64ValuesResponse>
+where
+    T: DatabaseStore,
+{
+    let rpc_predicate_string = format!("{:?}", rpc_predicate);
+
+    let predicate = PredicateBuilder::default()
+        .set_range(range)
+        .rpc_predicate(rpc_predicate)
+        .context(ConvertingPredicate {
+            rpc_predicate_string,
+        })?
+        .build();
+
+    let db = db_store
+        .db(&db_name)
+        .await
+        .context(DatabaseNotFound { db_name: &*db_name })?;
+
+    let executor = db_store.executor();
+
+    let cardinality = series_cardinality(db.as_ref(), &executor, &db_name, predicate).await?;
+
+    Ok(Int64ValuesResponse {
+        values: vec![cardinality],
+    })
+}
+
+/// Counts the distinct series (measurement and tag set) in `db` that have
+/// data matching `predicate`
+async fn series_cardinality<D: Database>(
+    db: &D,
+    executor: &Executor,
+    db_name: &str,
+    predicate: query::predicate::Predicate,
+) -> Result<i64> {
+    let series_plans =
+        db.query_series(predicate)
+            .await
+            .map_err(|e| Error::PlanningFilteringSeries {
+                db_name: db_name.to_string(),
+                source: Box::new(e),
+            })?;
+
+    // Only the distinct tag values of each measurement are needed to count
+    // the series, so rather than converting the output of the series set
+    // plans to series sets, each is extended to group its rows by their
+    // tags. A series may still be split over several plans (for example, if
+    // its data is in more than one chunk), so they are deduped by
+    // measurement and tags.
+    let mut series = HashSet::new();
+    for series_plan in series_plans.plans {
+        let SeriesSetPlan {
+            table_name,
+            plan,
+            tag_columns,
+            ..
+        } = series_plan;
+
+        let plan = distinct_tags_plan(&plan, &tag_columns).map_err(|e| {
+            Error::PlanningFilteringSeries {
+                db_name: db_name.to_string(),
+                source: Box::new(e),
+            }
+        })?;
+        let batches =
+            executor
+                .run_logical_plan(plan)
+                .await
+                .map_err(|e| Error::FilteringSeries {
+                    db_name: db_name.to_string(),
+                    source: Box::new(e),
+                })?;
+
+        for batch in batches {
+            let tag_arrays = (0..tag_columns.len())
+                .map(|i| batch.column(i).as_any().downcast_ref::<StringArray>())
+                .collect::<Option<Vec<_>>>()
+                .context(NonStringTagColumn {
+                    table_name: table_name.as_str(),
+                })?;
+
+            for row in 0..batch.num_rows() {
+                let tags: Vec<_> = tag_columns
+                    .iter()
+                    .zip(&tag_arrays)
+                    .filter(|(_, array)| !array.is_null(row))
+                    .map(|(name, array)| (Arc::clone(name), array.value(row).to_string()))
+                    .collect();
+                series.insert((Arc::clone(&table_name), tags));
+            }
+        }
+    }
+
+    Ok(series.len() as i64)
+}
+
 /// Receives SeriesSets from rx, converts them to ReadResponse and
 /// and sends them to tx
 async fn convert_series_set(
@@ -1140,13 +1321,14 @@ where
 /// shutdown.
 pub async fn make_server<T>(socket: TcpListener, storage: Arc<T>) -> Result<()>
 where
-    T: DatabaseStore + 'static,
+    T: DatabaseStore + ReplicatedWriteSink + 'static,
 {
     let stream = TcpListenerStream::new(socket);
 
     tonic::transport::Server::builder()
         .add_service(IOxTestingServer::new(GrpcService::new(storage.clone())))
         .add_service(StorageServer::new(GrpcService::new(storage.clone())))
+        .add_service(ReplicationServer::new(GrpcService::new(storage.clone())))
         .serve_with_incoming(stream)
         .await
         .context(ServerError {})
@@ -1159,6 +1341,8 @@ mod tests {
 
     use super::*;
     use arrow_deps::arrow::datatypes::DataType;
+    use data_types::database_rules::DatabaseRules;
+    use mutable_buffer::MutableBufferDb;
     use panic_logging::SendPanicsToTracing;
     use query::{
         exec::fieldlist::{Field, FieldList},
@@ -1169,7 +1353,8 @@ mod tests {
         test::FieldColumnsRequest,
         test::QueryGroupsRequest,
         test::TestDatabaseStore,
-        test::{ColumnValuesRequest, QuerySeriesRequest, TestChunk},
+        test::{ColumnValuesRequest, QuerySeriesRequest, TestChunk, TestLPWriter},
+        PartitionChunk,
     };
     use std::{
         convert::TryFrom,
@@ -1188,6 +1373,8 @@ mod tests {
     };
 
     use prost::Message;
+    use read_buffer::Database as ReadBufferDb;
+    use server::db::Db;
 
     type IOxTestingClient = i_ox_testing_client::IOxTestingClient<tonic::transport::Channel>;
     type StorageClient = storage_client::StorageClient<tonic::transport::Channel>;
@@ -1210,6 +1397,8 @@ mod tests {
 
         expected_capabilities.insert("Group".into(), to_str_vec(&["First", "Last", "Min", "Max"]));
 
+        expected_capabilities.insert("ReadSeriesCardinality".into(), vec![]);
+
         assert_eq!(
             expected_capabilities,
             fixture.storage_client.capabilities().await?
@@ -1551,6 +1740,36 @@ mod tests {
             "unexpected tag values while getting tag values for measurement names"
         );
 
+        // ---
+        // test tag_key = _measurement with a predicate means listing the
+        // measurement names with matching rows
+        // ---
+        let request = TagValuesRequest {
+            tags_source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+            tag_key: [0].into(),
+        };
+
+        let expected_request = ColumnValuesRequest {
+            predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into(),
+            column_name: "_measurement".into(),
+        };
+
+        let tag_values = vec!["h2o"];
+        test_db.set_column_values(to_string_vec(&tag_values)).await;
+
+        let actual_tag_values = fixture.storage_client.tag_values(request).await.unwrap();
+        assert_eq!(
+            actual_tag_values, tag_values,
+            "unexpected tag values while getting tag values for measurement names with a predicate"
+        );
+        assert_eq!(
+            test_db.get_column_values_request().await,
+            Some(expected_request),
+            "unexpected request while getting tag values for measurement names with a predicate"
+        );
+
         // ---
         // test tag_key = _field means listing all field names
         // ---
@@ -1859,6 +2078,130 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_read_series_cardinality() -> Result<(), tonic::Status> {
+        // Start a test gRPC server on a randomally allocated port
+        let mut fixture = Fixture::new().await.expect("Connecting to test server");
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let partition_id = 1;
+
+        let test_db = fixture
+            .test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+
+        let source = Some(StorageClientWrapper::read_source(
+            db_info.org_id,
+            db_info.bucket_id,
+            partition_id,
+        ));
+
+        let request = ReadSeriesCardinalityRequest {
+            read_series_cardinality_source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+        };
+
+        let expected_request = QuerySeriesRequest {
+            predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into()
+        };
+
+        let dummy_series_set_plan = SeriesSetPlans::from(vec![]);
+        test_db.set_query_series_values(dummy_series_set_plan).await;
+
+        let cardinality = fixture
+            .storage_client
+            .read_series_cardinality(request)
+            .await?;
+
+        assert_eq!(cardinality, vec![0]);
+        assert_eq!(
+            test_db.get_query_series_request().await,
+            Some(expected_request),
+            "unexpected request to query_series",
+        );
+
+        // ---
+        // test error
+        // ---
+        let request = ReadSeriesCardinalityRequest {
+            read_series_cardinality_source: source.clone(),
+            range: None,
+            predicate: None,
+        };
+
+        // Note we don't set the response on the test database, so we expect an error
+        let response = fixture
+            .storage_client
+            .read_series_cardinality(request)
+            .await;
+        assert!(response.is_err());
+        let response_string = format!("{:?}", response);
+        let expected_error = "No saved query_series in TestDatabase";
+        assert!(
+            response_string.contains(expected_error),
+            "'{}' did not contain expected content '{}'",
+            response_string,
+            expected_error
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_series_cardinality_of_read_buffer_chunks() {
+        let db = Db::new(
+            DatabaseRules::default(),
+            Some(MutableBufferDb::new("cardinality")),
+            ReadBufferDb::new(),
+            None, // wal buffer
+        );
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,region=west,host=a user=1 100\n\
+                 cpu,region=west,host=a user=2 200\n\
+                 cpu,region=east,host=b user=3 300\n\
+                 mem free=10i 100",
+            )
+            .await
+            .unwrap();
+
+        // move the data to the read buffer only
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        // a series also written to the mutable buffer is only counted once
+        writer
+            .write_lp_string(&db, "cpu,region=west,host=a user=4 400")
+            .await
+            .unwrap();
+
+        let executor = Executor::new();
+        let predicate = PredicateBuilder::default().build();
+        let cardinality = series_cardinality(&db, &executor, "cardinality", predicate)
+            .await
+            .unwrap();
+        assert_eq!(cardinality, 3);
+
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(250, 350)
+            .build();
+        let cardinality = series_cardinality(&db, &executor, "cardinality", predicate)
+            .await
+            .unwrap();
+        assert_eq!(cardinality, 1);
+    }
+
     #[tokio::test]
     async fn test_read_group() -> Result<(), tonic::Status> {
         // Start a test gRPC server on a randomally allocated port
@@ -2473,6 +2816,23 @@ mod tests {
             Ok(vec![s])
         }
 
+        /// Make a request to read_series_cardinality and do the
+        /// required async dance to flatten the resulting stream
+        async fn read_series_cardinality(
+            &mut self,
+            request: ReadSeriesCardinalityRequest,
+        ) -> Result<Vec<i64>, tonic::Status> {
+            let responses: Vec<_> = self
+                .inner
+                .read_series_cardinality(request)
+                .await?
+                .into_inner()
+                .try_collect()
+                .await?;
+
+            Ok(responses.into_iter().flat_map(|r| r.values).collect())
+        }
+
         /// Make a request to query::query_groups and do the
         /// required async dance to flatten the resulting stream
         async fn read_group(
@@ -2582,6 +2942,26 @@ mod tests {
         Tonic { source: tonic::transport::Error },
     }
 
+    #[tonic::async_trait]
+    impl ReplicatedWriteSink for TestDatabaseStore {
+        async fn write_replicated(
+            &self,
+            _db_name: &str,
+            _write: data_types::data::ReplicatedWrite,
+        ) -> Result<(), Status> {
+            Err(Status::unimplemented("test database store"))
+        }
+
+        fn subscribe(
+            &self,
+            _db_name: &str,
+            _since: std::collections::BTreeMap<u32, u64>,
+            _matcher: data_types::database_rules::Matcher,
+        ) -> Result<server::subscription::WriteSubscription, Status> {
+            Err(Status::unimplemented("test database store"))
+        }
+    }
+
     // Wrapper around raw clients and test database
     struct Fixture {
         iox_client: IOxTestingClient,
@@ -20,6 +20,7 @@ mod commands {
     mod input;
     pub mod logging;
     pub mod stats;
+    pub mod wal;
 }
 pub mod influxdb_ioxd;
 
@@ -30,6 +31,7 @@ enum ReturnCode {
     MetadataDumpFailed = 2,
     StatsFailed = 3,
     ServerExitedAbnormally = 4,
+    WalCheckFailed = 5,
 }
 
 fn main() -> Result<(), std::io::Error> {
@@ -56,6 +58,12 @@ Examples:
 
     # Dumps storage statistics about out.parquet to stdout
     influxdb_iox stats out.parquet
+
+    # Checks the entries of the local WAL in the directory wal/mydb
+    influxdb_iox wal verify wal/mydb
+
+    # Truncates an entry left incomplete by a crash off the local WAL in wal/mydb
+    influxdb_iox wal repair wal/mydb
 "#;
     // load all environment variables from .env before doing anything
     load_dotenv();
@@ -120,6 +128,36 @@ Examples:
                         .long("per-file")
                         .help("Include detailed information per file")
                 ),
+        )
+        .subcommand(
+            SubCommand::with_name("wal")
+                .about("Check and repair the local WAL of a database")
+                .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("verify")
+                        .about("Check every entry of a WAL and report the damaged ones, without changing it")
+                        .arg(
+                            Arg::with_name("DIRECTORY")
+                                .help("The directory of the WAL")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("repair")
+                        .about("Truncate an entry left incomplete by a crash off the end of a WAL")
+                        .arg(
+                            Arg::with_name("DIRECTORY")
+                                .help("The directory of the WAL")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("skip-corrupt")
+                                .long("skip-corrupt")
+                                .help("Also remove corrupt entries before the end of the WAL, losing their data")
+                        ),
+                ),
         )
          .subcommand(
             commands::config::Config::clap(),
@@ -190,6 +228,29 @@ async fn dispatch_args(matches: ArgMatches<'_>) {
                 }
             }
         }
+        ("wal", Some(sub_matches)) => {
+            logging_level.setup_basic_logging();
+            let res = match sub_matches.subcommand() {
+                ("verify", Some(sub_matches)) => {
+                    let directory = sub_matches.value_of("DIRECTORY").unwrap();
+                    commands::wal::verify(std::path::Path::new(directory))
+                }
+                ("repair", Some(sub_matches)) => {
+                    let directory = sub_matches.value_of("DIRECTORY").unwrap();
+                    let skip_corrupt = sub_matches.is_present("skip-corrupt");
+                    commands::wal::repair(std::path::Path::new(directory), skip_corrupt)
+                }
+                _ => unreachable!("a wal subcommand is required"),
+            };
+
+            match res {
+                Ok(()) => debug!("WAL check completed successfully"),
+                Err(e) => {
+                    eprintln!("WAL check failed: {}", e);
+                    std::process::exit(ReturnCode::WalCheckFailed as _)
+                }
+            }
+        }
         // Handle the case where the user explicitly specified the server command
         ("server", Some(sub_matches)) => {
             // Note don't set up basic logging here, different logging rules appy in server
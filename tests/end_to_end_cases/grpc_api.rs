@@ -2,13 +2,14 @@ use crate::{create_database, substitute_nanos, Scenario};
 use futures::prelude::*;
 use generated_types::{
     aggregate::AggregateType,
-    node::{Comparison, Type as NodeType, Value},
+    node::{Comparison, Logical, Type as NodeType, Value},
     read_group_request::Group,
     read_response::{frame::Data, *},
     storage_client::StorageClient,
     Aggregate, MeasurementFieldsRequest, MeasurementNamesRequest, MeasurementTagKeysRequest,
     MeasurementTagValuesRequest, Node, Predicate, ReadFilterRequest, ReadGroupRequest,
-    ReadWindowAggregateRequest, Tag, TagKeysRequest, TagValuesRequest, TimestampRange,
+    ReadSeriesCardinalityRequest, ReadWindowAggregateRequest, Tag, TagKeysRequest,
+    TagValuesRequest, TimestampRange,
 };
 use std::str;
 use test_helpers::tag_key_bytes_to_strings;
@@ -17,8 +18,10 @@ use tonic::transport::Channel;
 pub async fn test(storage_client: &mut StorageClient<Channel>, scenario: &Scenario) {
     capabilities_endpoint(storage_client).await;
     read_filter_endpoint(storage_client, scenario).await;
+    read_series_cardinality_endpoint(storage_client, scenario).await;
     tag_keys_endpoint(storage_client, scenario).await;
     tag_values_endpoint(storage_client, scenario).await;
+    measurement_tag_values_with_predicate_endpoint(storage_client, scenario).await;
     measurement_names_endpoint(storage_client, scenario).await;
     measurement_tag_keys_endpoint(storage_client, scenario).await;
     measurement_tag_values_endpoint(storage_client, scenario).await;
@@ -31,7 +34,7 @@ async fn capabilities_endpoint(storage_client: &mut StorageClient<Channel>) {
     let capabilities_response = capabilities_response.into_inner();
     assert_eq!(
         capabilities_response.caps.len(),
-        2,
+        3,
         "Response: {:?}",
         capabilities_response
     );
@@ -85,6 +88,35 @@ async fn read_filter_endpoint(storage_client: &mut StorageClient<Channel>, scena
     );
 }
 
+async fn read_series_cardinality_endpoint(
+    storage_client: &mut StorageClient<Channel>,
+    scenario: &Scenario,
+) {
+    let read_source = scenario.read_source();
+    let range = scenario.timestamp_range();
+    let predicate = make_tag_predicate("host", "server01");
+    let predicate = Some(predicate);
+
+    let read_series_cardinality_request = tonic::Request::new(ReadSeriesCardinalityRequest {
+        read_series_cardinality_source: read_source,
+        range,
+        predicate,
+    });
+
+    let read_series_cardinality_response = storage_client
+        .read_series_cardinality(read_series_cardinality_request)
+        .await
+        .unwrap();
+    let responses: Vec<_> = read_series_cardinality_response
+        .into_inner()
+        .try_collect()
+        .await
+        .unwrap();
+
+    // three cpu_load_short regions and one swap disk for server01
+    assert_eq!(responses[0].values, vec![4]);
+}
+
 async fn tag_keys_endpoint(storage_client: &mut StorageClient<Channel>, scenario: &Scenario) {
     let read_source = scenario.read_source();
     let range = scenario.timestamp_range();
@@ -138,6 +170,55 @@ async fn tag_values_endpoint(storage_client: &mut StorageClient<Channel>, scenar
     assert_eq!(values, vec!["server01"]);
 }
 
+/// Validate tag_values for the measurement name (`_measurement`) combined
+/// with a general predicate, as sent by Grafana template variables
+async fn measurement_tag_values_with_predicate_endpoint(
+    storage_client: &mut StorageClient<Channel>,
+    scenario: &Scenario,
+) {
+    let cases = vec![
+        (
+            make_tag_predicate("host", "server01"),
+            vec!["cpu_load_short", "swap"],
+        ),
+        (
+            make_and_predicate(
+                make_measurement_predicate("cpu_load_short"),
+                make_tag_predicate("region", "us-west"),
+            ),
+            vec!["cpu_load_short"],
+        ),
+        (
+            make_and_predicate(
+                make_measurement_predicate("swap"),
+                make_tag_predicate("host", "server02"),
+            ),
+            vec![],
+        ),
+    ];
+
+    for (predicate, expected) in cases {
+        let tag_values_request = tonic::Request::new(TagValuesRequest {
+            tags_source: scenario.read_source(),
+            range: scenario.timestamp_range(),
+            predicate: Some(predicate),
+            tag_key: [0].to_vec(),
+        });
+
+        let tag_values_response = storage_client.tag_values(tag_values_request).await.unwrap();
+        let responses: Vec<_> = tag_values_response
+            .into_inner()
+            .try_collect()
+            .await
+            .unwrap();
+
+        let values = &responses[0].values;
+        let values: Vec<_> = values.iter().map(|s| str::from_utf8(s).unwrap()).collect();
+
+        assert_eq!(values, expected);
+    }
+}
+
 async fn measurement_names_endpoint(
     storage_client: &mut StorageClient<Channel>,
     scenario: &Scenario,
@@ -640,6 +721,25 @@ fn make_tag_predicate(tag_name: impl Into<String>, tag_value: impl Into<String>)
     }
 }
 
+/// Create a predicate representing _m=measurement_name in the horrible gRPC
+/// structs
+fn make_measurement_predicate(measurement_name: impl Into<String>) -> Predicate {
+    // the measurement name is encoded as the tag key [0x00]
+    make_tag_predicate("\0", measurement_name)
+}
+
+/// Create a predicate representing `left AND right` in the horrible gRPC
+/// structs
+fn make_and_predicate(left: Predicate, right: Predicate) -> Predicate {
+    Predicate {
+        root: Some(Node {
+            node_type: NodeType::LogicalExpression as i32,
+            children: vec![left.root.unwrap(), right.root.unwrap()],
+            value: Some(Value::Logical(Logical::And as _)),
+        }),
+    }
+}
+
 /// Create a predicate representing _f=field_name in the horrible gRPC structs
 fn make_field_predicate(field_name: impl Into<String>) -> Predicate {
     Predicate {
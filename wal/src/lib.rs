@@ -11,8 +11,9 @@
 //! This crate provides a local-disk based WAL tailored for InfluxDB
 //! IOx `Partition`s.
 //!
-//! It is not currently connected to anything, but the intent is to
-//! permit IOx running in standalone mode better durability.
+//! It gives IOx running in standalone mode better durability: the server
+//! appends the writes of databases with a `local_wal_config` to a WAL
+//! before acknowledging them, and replays it on startup.
 //!
 //! Work remaining:
 //!
@@ -32,6 +33,7 @@ use std::{
     io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
     iter, mem, num,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 /// WAL Writer and related utilties
@@ -129,6 +131,24 @@ enum InternalError {
         source: io::Error,
         path: PathBuf,
     },
+
+    UnableToTruncateFile {
+        source: io::Error,
+        path: PathBuf,
+    },
+
+    UnableToRenameFile {
+        source: io::Error,
+        src: PathBuf,
+        dst: PathBuf,
+    },
+
+    #[snafu(display("Corrupt entry in {:?} at offset {}: {}", path, offset, reason))]
+    CorruptEntryFound {
+        path: PathBuf,
+        offset: u64,
+        reason: String,
+    },
 }
 
 /// A specialized `Result` for WAL-related errors
@@ -141,6 +161,7 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub struct WalBuilder {
     root: PathBuf,
     file_rollover_size: u64,
+    sync_policy: SyncPolicy,
 }
 
 impl WalBuilder {
@@ -156,6 +177,7 @@ impl WalBuilder {
         Self {
             root,
             file_rollover_size: Self::DEFAULT_FILE_ROLLOVER_SIZE_BYTES,
+            sync_policy: SyncPolicy::default(),
         }
     }
 
@@ -173,6 +195,17 @@ impl WalBuilder {
         self
     }
 
+    /// Set when the WAL writer task started by
+    /// [writer::start_wal_sync_task] syncs appended entries to disk.
+    ///
+    /// A `Wal` used directly is only synced by calls to [Wal::sync_all].
+    ///
+    /// See [SyncPolicy]
+    pub fn sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = sync_policy;
+        self
+    }
+
     /// Consume the builder and create a `Wal`.
     ///
     /// # Asynchronous considerations
@@ -199,6 +232,25 @@ impl WalBuilder {
         Loader::load(self.file_locator())
     }
 
+    /// Consume the builder to check every entry of this WAL, returning a
+    /// report of the damage found.
+    ///
+    /// A damaged entry that extends to the end of the last file is a torn
+    /// write, left behind by a crash in the middle of an append; it is never
+    /// an error. Any other damaged entry is corrupt, and causes an error
+    /// unless [RecoveryOptions::skip_corrupt_entries] is set. With
+    /// [RecoveryOptions::repair] set, the torn write is truncated and the
+    /// files with skipped corrupt entries are rewritten without them, so
+    /// the WAL can be opened and read again.
+    ///
+    /// # Asynchronous considerations
+    ///
+    /// This method performs blocking IO and care should be taken when using
+    /// it in an asynchronous context.
+    pub fn recover(self, options: RecoveryOptions) -> Result<RecoveryReport> {
+        Loader::recover(&self.file_locator(), options)
+    }
+
     fn file_locator(self) -> FileLocator {
         FileLocator {
             root: self.root,
@@ -207,6 +259,32 @@ impl WalBuilder {
     }
 }
 
+/// When the WAL writer task syncs the entries appended since the last sync.
+///
+/// Whatever the policy, all the entries appended since the last sync share
+/// a single fsync, and their writers are notified once it completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync as soon as the writer task has appended every write queued up
+    /// while it was busy. Writes are never acknowledged before they are on
+    /// disk, but concurrent writes are still synced together.
+    EveryWrite,
+    /// Sync the entries appended since the last sync once the oldest of
+    /// them has waited for the given duration.
+    Interval(Duration),
+    /// Sync once at least `bytes` bytes have been appended since the last
+    /// sync, or once the oldest unsynced entry has waited for `max_latency`,
+    /// whichever comes first, so a write is never held back indefinitely
+    /// when few bytes are being written.
+    Bytes { bytes: u64, max_latency: Duration },
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        Self::EveryWrite
+    }
+}
+
 /// The main WAL type to interact with.
 ///
 /// For use in single-threaded synchronous contexts. For multi-threading or
@@ -489,11 +567,18 @@ impl Loader {
                     let data_len = i64::from(header.len);
                     file.seek(SeekFrom::Current(data_len)).unwrap();
 
-                    length_remaining -= Header::LEN + u64::from(header.len);
+                    // a torn entry at the end of the file can claim more
+                    // bytes than are left
+                    length_remaining =
+                        length_remaining.saturating_sub(Header::LEN + u64::from(header.len));
 
                     Some(Ok(header))
                 }
-                Err(e) => Some(Err(e)),
+                Err(e) => {
+                    // nothing after a damaged entry can be found
+                    length_remaining = 0;
+                    Some(Err(e))
+                }
             }
         })))
     }
@@ -527,12 +612,16 @@ impl Loader {
 
                     Some(Ok(entry))
                 }
-                Err(e) => Some(Err(e)),
+                Err(e) => {
+                    // nothing after a damaged entry can be found
+                    length_remaining = 0;
+                    Some(Err(e))
+                }
             }
         })))
     }
 
-    fn load_one(file: &mut File) -> Result<(Entry, u64)> {
+    fn load_one<R: Read>(file: &mut R) -> Result<(Entry, u64)> {
         let header = Header::read(&mut *file)?;
 
         let expected_len_us =
@@ -579,6 +668,128 @@ impl Loader {
 
         Ok((entry, bytes_read))
     }
+
+    fn recover(files: &FileLocator, options: RecoveryOptions) -> Result<RecoveryReport> {
+        let mut report = RecoveryReport::default();
+
+        let paths: Vec<_> = files.existing_filenames()?.collect();
+        let last_index = paths.len().saturating_sub(1);
+
+        for (index, path) in paths.iter().enumerate() {
+            Self::recover_file(path, index == last_index, options, &mut report)?;
+            report.files += 1;
+        }
+
+        Ok(report)
+    }
+
+    fn recover_file(
+        path: &Path,
+        is_last_file: bool,
+        options: RecoveryOptions,
+        report: &mut RecoveryReport,
+    ) -> Result<()> {
+        let mut data = vec![];
+        File::open(path)
+            .context(UnableToOpenFile { path })?
+            .read_to_end(&mut data)
+            .context(UnableToReadData)?;
+        let file_len = data.len() as u64;
+
+        // the byte ranges of the valid entries, kept to rewrite the file
+        // without its corrupt entries
+        let mut valid = vec![];
+        let mut corrupt = false;
+        let mut offset = 0;
+
+        while offset < file_len {
+            let mut remaining = &data[offset as usize..];
+            let error = match Self::load_one(&mut remaining) {
+                Ok((_, bytes_read)) => {
+                    valid.push(offset as usize..(offset + bytes_read) as usize);
+                    report.entries += 1;
+                    offset += bytes_read;
+                    continue;
+                }
+                Err(e) => e,
+            };
+
+            // how far the damaged entry claims to extend, if its header is
+            // intact enough to tell
+            let entry_end = Header::read(&data[offset as usize..])
+                .ok()
+                .map(|header| offset + Header::LEN + u64::from(header.len))
+                .filter(|&end| end < file_len);
+
+            if is_last_file && entry_end.is_none() {
+                if options.repair {
+                    Self::truncate(path, offset)?;
+                }
+                report.torn_tail = Some(TornTail {
+                    path: path.to_path_buf(),
+                    offset,
+                    len: file_len - offset,
+                    truncated: options.repair,
+                });
+                break;
+            }
+
+            ensure!(
+                options.skip_corrupt_entries,
+                CorruptEntryFound {
+                    path,
+                    offset,
+                    reason: error.to_string(),
+                }
+            );
+
+            // without a trustworthy length, nothing after the entry can be
+            // found, so the rest of the file is skipped
+            let end = entry_end.unwrap_or(file_len);
+            report.corrupt_entries.push(CorruptEntry {
+                path: path.to_path_buf(),
+                offset,
+                len: end - offset,
+                reason: error.to_string(),
+                removed: options.repair,
+            });
+            corrupt = true;
+            offset = end;
+        }
+
+        if corrupt && options.repair {
+            Self::rewrite(path, valid.into_iter().map(|range| &data[range]))?;
+        }
+
+        Ok(())
+    }
+
+    fn truncate(path: &Path, len: u64) -> Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .context(UnableToOpenFile { path })?;
+        file.set_len(len).context(UnableToTruncateFile { path })?;
+        file.sync_all().context(UnableToSync)?;
+        Ok(())
+    }
+
+    /// Replaces the file at `path` with one that only contains `entries`
+    fn rewrite<'a>(path: &Path, entries: impl Iterator<Item = &'a [u8]>) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+
+        let mut file = File::create(&tmp_path).context(UnableToCreateFile { path: &tmp_path })?;
+        for entry in entries {
+            file.write_all(entry).context(UnableToWriteData)?;
+        }
+        file.sync_all().context(UnableToSync)?;
+
+        fs::rename(&tmp_path, path).context(UnableToRenameFile {
+            src: &tmp_path,
+            dst: path,
+        })?;
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -642,6 +853,67 @@ impl Entry {
     }
 }
 
+/// How [WalBuilder::recover] handles damaged entries
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecoveryOptions {
+    /// Skip and report corrupt entries before the end of the WAL instead of
+    /// returning an error
+    pub skip_corrupt_entries: bool,
+    /// Truncate a torn final entry and rewrite the files that have skipped
+    /// corrupt entries; otherwise the WAL is only read
+    pub repair: bool,
+}
+
+/// What [WalBuilder::recover] found in a WAL
+#[derive(Debug, Default)]
+pub struct RecoveryReport {
+    /// The number of files checked
+    pub files: usize,
+    /// The number of valid entries
+    pub entries: usize,
+    /// The incomplete entry at the end of the last file, if any
+    pub torn_tail: Option<TornTail>,
+    /// The corrupt entries that were skipped
+    pub corrupt_entries: Vec<CorruptEntry>,
+}
+
+impl RecoveryReport {
+    /// Whether the WAL had no damaged entries
+    pub fn is_clean(&self) -> bool {
+        self.torn_tail.is_none() && self.corrupt_entries.is_empty()
+    }
+}
+
+/// An incomplete entry at the end of the WAL, as left behind by a crash
+/// during an append
+#[derive(Debug, Clone)]
+pub struct TornTail {
+    /// The file containing the entry
+    pub path: PathBuf,
+    /// The offset of the entry in the file
+    pub offset: u64,
+    /// The number of bytes from the offset to the end of the file
+    pub len: u64,
+    /// Whether the file was truncated to remove the entry
+    pub truncated: bool,
+}
+
+/// A damaged entry before the end of the WAL
+#[derive(Debug, Clone)]
+pub struct CorruptEntry {
+    /// The file containing the entry
+    pub path: PathBuf,
+    /// The offset of the entry in the file
+    pub offset: u64,
+    /// The number of bytes skipped. This is the rest of the file if the
+    /// length in the entry's header can't be trusted.
+    pub len: u64,
+    /// Why the entry couldn't be read
+    pub reason: String,
+    /// Whether the entry was removed from the file
+    pub removed: bool,
+}
+
 /// A single write to append to the WAL file
 #[derive(Debug)]
 pub struct WritePayload {
@@ -4,15 +4,24 @@
     clippy::explicit_iter_loop,
     clippy::use_self
 )]
-use crate::{Error as WalError, SequenceNumber, WalBuilder, WritePayload};
+use crate::{Error as WalError, SequenceNumber, SyncPolicy, Wal, WalBuilder, WritePayload};
 
-use futures::{channel::mpsc, SinkExt, StreamExt};
+use futures::{
+    channel::{mpsc, oneshot},
+    SinkExt, StreamExt,
+};
 use snafu::{ResultExt, Snafu};
 
 use serde::{Deserialize, Serialize};
 use tracing::{error, info};
 
-use std::path::PathBuf;
+use std::{
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::time::Instant;
 
 #[derive(Debug, Snafu)]
 /// Error type
@@ -31,6 +40,9 @@ pub enum Error {
         metadata_path: PathBuf,
         source: std::io::Error,
     },
+
+    #[snafu(display("Error syncing WAL: {}", message))]
+    SyncingWal { message: String },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -39,13 +51,42 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub struct WalDetails {
     pub metadata_path: PathBuf,
     pub metadata: WalMetadata,
-    pub write_tx: mpsc::Sender<WalWrite>,
+    request_tx: mpsc::Sender<WalRequest>,
 }
 
 #[derive(Debug)]
 pub struct WalWrite {
     payload: WritePayload,
-    notify_tx: mpsc::Sender<Result<SequenceNumber, WalError>>,
+    notify_tx: oneshot::Sender<Result<SequenceNumber>>,
+}
+
+/// The requests handled by the WAL thread
+#[derive(Debug)]
+enum WalRequest {
+    Write(WalWrite),
+    DeleteUpToEntry {
+        entry_number: SequenceNumber,
+        notify_tx: mpsc::Sender<Result<(), WalError>>,
+    },
+}
+
+/// A write that has been queued for the WAL thread. It resolves to the
+/// sequence number of the write's entry once that entry has been synced to
+/// disk, or to the error that prevented it.
+#[derive(Debug)]
+#[must_use = "a DurableWrite does nothing unless polled"]
+pub struct DurableWrite {
+    notify_rx: oneshot::Receiver<Result<SequenceNumber>>,
+}
+
+impl Future for DurableWrite {
+    type Output = Result<SequenceNumber>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.notify_rx).poll(cx).map(|result| {
+            result.expect("The WAL thread should always be running to send a response.")
+        })
+    }
 }
 
 impl WalDetails {
@@ -60,25 +101,49 @@ impl WalDetails {
         })?)
     }
 
-    pub async fn write_and_sync(&self, data: Vec<u8>) -> Result<()> {
+    /// Queues `data` to be appended to the WAL, returning a future that
+    /// resolves once the new entry is durable. Entries are appended in the
+    /// order the calls to `append` complete, and concurrent writes share
+    /// fsyncs according to the WAL's `SyncPolicy`.
+    pub async fn append(&self, data: Vec<u8>) -> Result<DurableWrite> {
         let payload = WritePayload::new(data).context(UnderlyingWalError {})?;
 
-        let (notify_tx, mut notify_rx) = mpsc::channel(1);
+        let (notify_tx, notify_rx) = oneshot::channel();
 
         let write = WalWrite { payload, notify_tx };
 
-        let mut tx = self.write_tx.clone();
-        tx.send(write)
+        let mut tx = self.request_tx.clone();
+        tx.send(WalRequest::Write(write))
             .await
             .expect("The WAL thread should always be running to receive a write");
 
-        let _ = notify_rx
+        Ok(DurableWrite { notify_rx })
+    }
+
+    /// Appends `data` to the WAL and waits for it to be synced to disk,
+    /// returning the sequence number of the new entry
+    pub async fn write_and_sync(&self, data: Vec<u8>) -> Result<SequenceNumber> {
+        self.append(data).await?.await
+    }
+
+    /// Deletes the WAL files before the one that contains the entry
+    /// `entry_number` (see `Wal::delete_up_to_entry`)
+    pub async fn delete_up_to_entry(&self, entry_number: SequenceNumber) -> Result<()> {
+        let (notify_tx, mut notify_rx) = mpsc::channel(1);
+
+        let mut tx = self.request_tx.clone();
+        tx.send(WalRequest::DeleteUpToEntry {
+            entry_number,
+            notify_tx,
+        })
+        .await
+        .expect("The WAL thread should always be running to receive a delete");
+
+        notify_rx
             .next()
             .await
             .expect("The WAL thread should always be running to send a response.")
-            .context(UnderlyingWalError {})?;
-
-        Ok(())
+            .context(UnderlyingWalError {})
     }
 }
 
@@ -104,7 +169,79 @@ pub enum WalFormat {
     Unknown,
 }
 
+/// The entries appended since the last sync, whose writers are waiting for
+/// them to be durable
+#[derive(Debug, Default)]
+struct UnsyncedEntries {
+    waiting: Vec<(SequenceNumber, oneshot::Sender<Result<SequenceNumber>>)>,
+    bytes: u64,
+    oldest: Option<Instant>,
+}
+
+impl UnsyncedEntries {
+    fn push(
+        &mut self,
+        sequence_number: SequenceNumber,
+        bytes: u64,
+        notify_tx: oneshot::Sender<Result<SequenceNumber>>,
+    ) {
+        self.waiting.push((sequence_number, notify_tx));
+        self.bytes += bytes;
+        self.oldest.get_or_insert_with(Instant::now);
+    }
+
+    /// Whether `policy` calls for a sync right away
+    fn sync_due(&self, policy: SyncPolicy) -> bool {
+        match policy {
+            _ if self.waiting.is_empty() => false,
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::Interval(_) => false,
+            SyncPolicy::Bytes { bytes, .. } => self.bytes >= bytes,
+        }
+    }
+
+    /// When `policy` calls for a sync if no other request arrives first
+    fn sync_deadline(&self, policy: SyncPolicy) -> Option<Instant> {
+        match policy {
+            SyncPolicy::Interval(interval)
+            | SyncPolicy::Bytes {
+                max_latency: interval,
+                ..
+            } => self.oldest.map(|oldest| oldest + interval),
+            SyncPolicy::EveryWrite => None,
+        }
+    }
+
+    /// Syncs the WAL with a single fsync and notifies every waiting writer
+    fn sync(&mut self, wal: &mut Wal) {
+        let waiting = std::mem::take(&mut self.waiting);
+        self.bytes = 0;
+        self.oldest = None;
+
+        if waiting.is_empty() {
+            return;
+        }
+
+        // the error can't be cloned, so each writer gets its description
+        let result = wal.sync_all().map_err(|e| e.to_string());
+
+        for (sequence_number, notify_tx) in waiting {
+            let result = match &result {
+                Ok(()) => Ok(sequence_number),
+                Err(message) => SyncingWal { message }.fail(),
+            };
+
+            // the writer may have stopped waiting, which is fine
+            let _ = notify_tx.send(result);
+        }
+    }
+}
+
+/// Starts the task that owns the WAL. Writes are appended in the order they
+/// are received, and every write queued while the task was busy is synced
+/// with the same fsync, according to the builder's `SyncPolicy`.
 pub async fn start_wal_sync_task(wal_builder: WalBuilder) -> Result<WalDetails> {
+    let sync_policy = wal_builder.sync_policy;
     let mut wal = wal_builder.wal().context(UnderlyingWalError)?;
 
     let metadata = tokio::fs::read_to_string(wal.metadata_path())
@@ -115,29 +252,47 @@ pub async fn start_wal_sync_task(wal_builder: WalBuilder) -> Result<WalDetails>
         .unwrap_or_default();
     let metadata_path = wal.metadata_path();
 
-    let (write_tx, mut write_rx) = mpsc::channel::<WalWrite>(100);
+    let (request_tx, mut request_rx) = mpsc::channel::<WalRequest>(100);
 
     tokio::spawn({
         async move {
+            let mut unsynced = UnsyncedEntries::default();
+
             loop {
-                match write_rx.next().await {
-                    Some(write) => {
-                        let payload = write.payload;
-                        let mut tx = write.notify_tx;
-
-                        let result = wal.append(payload).and_then(|seq| {
-                            wal.sync_all()?;
-                            Ok(seq)
-                        });
-
-                        if let Err(e) = tx.send(result).await {
-                            error!("error sending result back to writer {:?}", e);
+                let request = match unsynced.sync_deadline(sync_policy) {
+                    Some(deadline) => tokio::select! {
+                        request = request_rx.next() => request,
+                        _ = tokio::time::sleep_until(deadline) => {
+                            unsynced.sync(&mut wal);
+                            continue;
                         }
-                    }
+                    },
+                    None => request_rx.next().await,
+                };
+
+                let mut request = match request {
+                    Some(request) => request,
                     None => {
+                        unsynced.sync(&mut wal);
                         info!("shutting down WAL for {:?}", wal.metadata_path());
                         return;
                     }
+                };
+
+                // handle everything queued up while the WAL was busy before
+                // deciding whether to sync, so concurrent writers share it
+                loop {
+                    handle_request(&mut wal, &mut unsynced, request).await;
+
+                    match request_rx.try_next() {
+                        Ok(Some(next)) => request = next,
+                        // closed or empty: the outer loop handles both
+                        Ok(None) | Err(_) => break,
+                    }
+                }
+
+                if unsynced.sync_due(sync_policy) {
+                    unsynced.sync(&mut wal);
                 }
             }
         }
@@ -146,14 +301,142 @@ pub async fn start_wal_sync_task(wal_builder: WalBuilder) -> Result<WalDetails>
     Ok(WalDetails {
         metadata_path,
         metadata,
-        write_tx,
+        request_tx,
     })
 }
 
+async fn handle_request(wal: &mut Wal, unsynced: &mut UnsyncedEntries, request: WalRequest) {
+    match request {
+        WalRequest::Write(write) => {
+            let size_before = wal.total_size();
+
+            match wal.append(write.payload) {
+                Ok(sequence_number) => unsynced.push(
+                    sequence_number,
+                    wal.total_size() - size_before,
+                    write.notify_tx,
+                ),
+                Err(e) => {
+                    if write
+                        .notify_tx
+                        .send(Err(e).context(UnderlyingWalError))
+                        .is_err()
+                    {
+                        error!("error sending result back to writer");
+                    }
+                }
+            }
+        }
+        WalRequest::DeleteUpToEntry {
+            entry_number,
+            mut notify_tx,
+        } => {
+            let result = wal.delete_up_to_entry(entry_number);
+
+            if let Err(e) = notify_tx.send(result).await {
+                error!("error sending result back to deleter {:?}", e);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    #[test]
-    fn it_works_but_has_no_tests() {
-        // :thinking_face:
+    use super::*;
+    use std::time::Duration;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    fn entries(builder: WalBuilder) -> Result<Vec<(SequenceNumber, Vec<u8>)>> {
+        Ok(builder
+            .entries()?
+            .map(|entry| entry.map(|e| (e.sequence_number(), e.into_data())))
+            .collect::<Result<_, _>>()?)
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_are_durable() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let builder = WalBuilder::new(dir.as_ref());
+        let details = start_wal_sync_task(builder.clone()).await?;
+
+        // queue all the writes before awaiting any so they are synced together
+        let mut writes = vec![];
+        for i in 0..10 {
+            writes.push(details.append(format!("write {}", i).into_bytes()).await?);
+        }
+        let sequence_numbers = futures::future::try_join_all(writes).await?;
+        assert_eq!(sequence_numbers, (0..10).collect::<Vec<_>>());
+
+        let entries = entries(builder)?;
+        assert_eq!(entries.len(), 10);
+        for (i, (sequence_number, data)) in entries.into_iter().enumerate() {
+            assert_eq!(sequence_number, i as u64);
+            assert_eq!(data, format!("write {}", i).into_bytes());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn interval_sync_policy() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let builder = WalBuilder::new(dir.as_ref())
+            .sync_policy(SyncPolicy::Interval(Duration::from_millis(10)));
+        let details = start_wal_sync_task(builder.clone()).await?;
+
+        let first = details.append(b"first".to_vec()).await?;
+        let second = details.append(b"second".to_vec()).await?;
+        assert_eq!(first.await?, 0);
+        assert_eq!(second.await?, 1);
+
+        assert_eq!(details.write_and_sync(b"third".to_vec()).await?, 2);
+        assert_eq!(entries(builder)?.len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bytes_sync_policy() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let builder = WalBuilder::new(dir.as_ref()).sync_policy(SyncPolicy::Bytes {
+            bytes: 1024,
+            max_latency: Duration::from_secs(60),
+        });
+        let details = start_wal_sync_task(builder).await?;
+
+        let mut small = details.append(b"small".to_vec()).await?;
+        let waited = tokio::time::timeout(Duration::from_millis(50), &mut small).await;
+        assert!(waited.is_err(), "synced before enough bytes were appended");
+
+        // pseudo-random bytes so compression keeps the entry over the threshold
+        let large: Vec<u8> = (0..4096_u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let large = details.append(large).await?;
+
+        assert_eq!(small.await?, 0);
+        assert_eq!(large.await?, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bytes_sync_policy_max_latency() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let builder = WalBuilder::new(dir.as_ref()).sync_policy(SyncPolicy::Bytes {
+            bytes: 1024 * 1024,
+            max_latency: Duration::from_millis(10),
+        });
+        let details = start_wal_sync_task(builder.clone()).await?;
+
+        // a single small write is synced once it has waited for max_latency
+        let small = details.append(b"small".to_vec()).await?;
+        let synced = tokio::time::timeout(Duration::from_secs(5), small).await;
+        assert_eq!(synced.expect("write was never synced")?, 0);
+        assert_eq!(entries(builder)?.len(), 1);
+
+        Ok(())
     }
 }
@@ -0,0 +1,119 @@
+use std::fs::{self, OpenOptions};
+use wal::{RecoveryOptions, WalBuilder, WritePayload};
+
+#[macro_use]
+mod helpers;
+use crate::helpers::*;
+
+// The size of an entry header: sequence number, checksum and length
+const HEADER_LEN: u64 = 16;
+
+#[test]
+fn torn_tail_is_truncated() -> Result {
+    let dir = test_helpers::tmp_dir()?;
+    let builder = WalBuilder::new(dir.as_ref());
+
+    {
+        let mut wal = builder.clone().wal()?;
+        create_and_sync_batch!(wal, ["first", "second", "third"]);
+    }
+
+    // Simulate a crash in the middle of appending the last entry
+    let path = &wal_paths(&dir.as_ref())[0];
+    let len = fs::metadata(path)?.len();
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(len - 3)?;
+
+    // Reading the WAL fails
+    assert!(all_entries(&builder).is_err());
+
+    // Verifying reports the torn entry without changing the file
+    let report = builder.clone().recover(RecoveryOptions::default())?;
+    assert_eq!(report.entries, 2);
+    assert!(report.corrupt_entries.is_empty());
+    let torn_tail = report.torn_tail.expect("torn tail should be detected");
+    assert!(!torn_tail.truncated);
+    assert_eq!(torn_tail.len, len - 3 - torn_tail.offset);
+    assert_eq!(fs::metadata(path)?.len(), len - 3);
+
+    // Repairing truncates the file to the last valid entry
+    let report = builder.clone().recover(RecoveryOptions {
+        repair: true,
+        ..Default::default()
+    })?;
+    let torn_tail = report.torn_tail.expect("torn tail should be detected");
+    assert!(torn_tail.truncated);
+    assert_eq!(fs::metadata(path)?.len(), torn_tail.offset);
+
+    let wal_entries = all_entries(&builder)?;
+    assert_eq!(2, wal_entries.len());
+    assert_entry!(wal_entries[0], 0, b"first");
+    assert_entry!(wal_entries[1], 1, b"second");
+
+    // The torn entry's sequence number is reused
+    let mut wal = builder.clone().wal()?;
+    create_and_sync_batch!(wal, ["third again"]);
+    let wal_entries = all_entries(&builder)?;
+    assert_entry!(wal_entries[2], 2, b"third again");
+
+    let report = builder.recover(RecoveryOptions::default())?;
+    assert!(report.is_clean());
+
+    Ok(())
+}
+
+#[test]
+fn corrupt_entries_are_skipped_when_allowed() -> Result {
+    let dir = test_helpers::tmp_dir()?;
+    let builder = WalBuilder::new(dir.as_ref()).file_rollover_size(1);
+
+    {
+        let mut wal = builder.clone().wal()?;
+        create_and_sync_batch!(wal, ["first", "second"]);
+        create_and_sync_batch!(wal, ["third"]);
+    }
+    assert_filenames_for_sequence_numbers!(dir, [0, 2]);
+
+    // Flip a bit in the data of the first entry
+    let path = &wal_paths(&dir.as_ref())[0];
+    let mut contents = fs::read(path)?;
+    contents[HEADER_LEN as usize] ^= 1;
+    fs::write(path, contents)?;
+
+    // Corrupt entries are an error unless they can be skipped
+    let err = builder
+        .clone()
+        .recover(RecoveryOptions::default())
+        .unwrap_err();
+    assert!(err.to_string().contains("offset 0"), "{}", err);
+
+    let report = builder.clone().recover(RecoveryOptions {
+        skip_corrupt_entries: true,
+        ..Default::default()
+    })?;
+    assert_eq!(report.files, 2);
+    assert_eq!(report.entries, 2);
+    assert!(report.torn_tail.is_none());
+    assert_eq!(report.corrupt_entries.len(), 1);
+    let corrupt = &report.corrupt_entries[0];
+    assert_eq!(&corrupt.path, path);
+    assert_eq!(corrupt.offset, 0);
+    assert!(!corrupt.removed);
+
+    // Repairing removes the corrupt entry
+    let report = builder.clone().recover(RecoveryOptions {
+        skip_corrupt_entries: true,
+        repair: true,
+    })?;
+    assert!(report.corrupt_entries[0].removed);
+    assert_filenames_for_sequence_numbers!(dir, [0, 2]);
+
+    let wal_entries = all_entries(&builder)?;
+    assert_eq!(2, wal_entries.len());
+    assert_entry!(wal_entries[0], 1, b"second");
+    assert_entry!(wal_entries[1], 2, b"third");
+
+    Ok(())
+}